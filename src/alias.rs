@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::sync::OnceLock;
+
+// Short forms of downloading, which is what manga-cli does without a
+// command: `manga-cli d "series"` is `manga-cli "series"`. Alone they are
+// the series searched for, so `manga-cli d` still looks up "d".
+const DOWNLOAD_FORMS: &[&str] = &["download", "d"];
+
+static ARGS: OnceLock<Vec<OsString>> = OnceLock::new();
+
+pub enum Origin {
+    BuiltIn,
+    Config,
+}
+
+// A name that stands for other words when it is the first argument.
+pub struct Alias {
+    pub name: String,
+    pub expansion: String,
+    pub origin: Origin,
+}
+
+// The aliases in effect: the built-in short forms, with those `configured`
+// replacing them. `short_forms` are clap's aliases of the commands by
+// command name. Configured aliases named like a command are left out and
+// returned apart, since commands can't be redefined.
+pub fn effective(
+    configured: &BTreeMap<String, String>,
+    commands: &[String],
+    short_forms: &[(String, String)],
+) -> (Vec<Alias>, Vec<String>) {
+    let mut aliases: BTreeMap<String, Alias> = BTreeMap::new();
+    let built_in = DOWNLOAD_FORMS
+        .iter()
+        .map(|name| (name.to_string(), String::new()))
+        .chain(short_forms.iter().cloned());
+    for (name, expansion) in built_in {
+        let origin = Origin::BuiltIn;
+        aliases.insert(
+            name.clone(),
+            Alias {
+                name,
+                expansion,
+                origin,
+            },
+        );
+    }
+    let mut shadowing = Vec::new();
+    for (name, expansion) in configured {
+        if commands.contains(name) {
+            shadowing.push(name.clone());
+            continue;
+        }
+        aliases.insert(
+            name.clone(),
+            Alias {
+                name: name.clone(),
+                expansion: expansion.clone(),
+                origin: Origin::Config,
+            },
+        );
+    }
+    (aliases.into_values().collect(), shadowing)
+}
+
+// Replaces the first argument after the program name while it names an
+// alias, leaving the rest as the shell passed them. An expansion's words are
+// split like a shell would, so quotes in it group words. An alias that comes
+// back to itself is an error.
+pub fn expand(mut args: Vec<OsString>, aliases: &[Alias]) -> Result<Vec<OsString>, String> {
+    let mut seen: Vec<String> = Vec::new();
+    while let Some(first) = args.get(1).and_then(|arg| arg.to_str()).map(str::to_string) {
+        let Some(alias) = aliases.iter().find(|alias| alias.name == first) else {
+            break;
+        };
+        if matches!(alias.origin, Origin::BuiltIn) {
+            // Clap expands its own aliases.
+            if !alias.expansion.is_empty() {
+                break;
+            }
+            // A download form with nothing after it is a title.
+            if args.len() == 2 {
+                break;
+            }
+        }
+        if seen.contains(&first) {
+            seen.push(first);
+            return Err(format!(
+                "The alias {} expands to itself: {}",
+                seen[0],
+                seen.join(" -> ")
+            ));
+        }
+        let words = shlex::split(&alias.expansion)
+            .ok_or_else(|| format!("The alias {} has unbalanced quotes", first))?;
+        seen.push(first);
+        let expanded = !words.is_empty();
+        args.splice(1..2, words.into_iter().map(OsString::from));
+        // Only words an alias put first are expanded again.
+        if !expanded {
+            break;
+        }
+    }
+    Ok(args)
+}
+
+// Records the command line with aliases expanded, for the commands that
+// pass their arguments on.
+pub fn set_args(args: Vec<OsString>) {
+    let _ = ARGS.set(args);
+}
+
+// The command line, with aliases expanded.
+pub fn args() -> Vec<OsString> {
+    ARGS.get()
+        .cloned()
+        .unwrap_or_else(|| env::args_os().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn effective_with(configured: &[(&str, &str)]) -> (Vec<Alias>, Vec<String>) {
+        let configured = configured
+            .iter()
+            .map(|(name, expansion)| (name.to_string(), expansion.to_string()))
+            .collect();
+        let commands = ["update".to_string(), "list".to_string()];
+        let short_forms = [("ls".to_string(), "list".to_string())];
+        effective(&configured, &commands, &short_forms)
+    }
+
+    fn expand_words(words: &[&str], aliases: &[Alias]) -> Result<Vec<String>, String> {
+        let args = std::iter::once("manga-cli")
+            .chain(words.iter().copied())
+            .map(OsString::from)
+            .collect();
+        expand(args, aliases).map(|args| {
+            args.into_iter()
+                .skip(1)
+                .map(|arg| arg.into_string().unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn expands_the_first_argument_again_while_it_is_an_alias() {
+        let (aliases, _) = effective_with(&[
+            ("up", "update --all"),
+            ("weekly", "up --jobs '2'"),
+            ("quoted", "\"one piece\" -c 1"),
+        ]);
+        assert_eq!(
+            expand_words(&["weekly", "--dry-run", "up"], &aliases).unwrap(),
+            ["update", "--all", "--jobs", "2", "--dry-run", "up"]
+        );
+        assert_eq!(
+            expand_words(&["quoted"], &aliases).unwrap(),
+            ["one piece", "-c", "1"]
+        );
+        // Only the first argument is looked at.
+        assert_eq!(
+            expand_words(&["--plain", "up"], &aliases).unwrap(),
+            ["--plain", "up"]
+        );
+    }
+
+    #[test]
+    fn recursive_aliases_are_an_error() {
+        let (aliases, _) = effective_with(&[("a", "b --x"), ("b", "c"), ("c", "a"), ("me", "me")]);
+        assert_eq!(
+            expand_words(&["a"], &aliases).unwrap_err(),
+            "The alias a expands to itself: a -> b -> c -> a"
+        );
+        assert_eq!(
+            expand_words(&["me"], &aliases).unwrap_err(),
+            "The alias me expands to itself: me -> me"
+        );
+        let (aliases, _) = effective_with(&[("open", "'unbalanced")]);
+        assert!(expand_words(&["open"], &aliases).is_err());
+    }
+
+    #[test]
+    fn configured_aliases_shadow_built_in_ones_but_not_commands() {
+        let (aliases, shadowing) = effective_with(&[("d", "update"), ("list", "update")]);
+        assert_eq!(shadowing, ["list"]);
+        assert_eq!(expand_words(&["d"], &aliases).unwrap(), ["update"]);
+        assert_eq!(expand_words(&["list"], &aliases).unwrap(), ["list"]);
+        // Clap's own short forms are left to clap.
+        assert_eq!(expand_words(&["ls", "-v"], &aliases).unwrap(), ["ls", "-v"]);
+    }
+
+    #[test]
+    fn download_forms_need_a_title_after_them() {
+        let (aliases, _) = effective_with(&[]);
+        assert_eq!(
+            expand_words(&["d", "one piece"], &aliases).unwrap(),
+            ["one piece"]
+        );
+        assert_eq!(
+            expand_words(&["download", "-c", "1", "x"], &aliases).unwrap(),
+            ["-c", "1", "x"]
+        );
+        // Alone, they are what's searched for.
+        assert_eq!(expand_words(&["d"], &aliases).unwrap(), ["d"]);
+        assert_eq!(expand_words(&["download"], &aliases).unwrap(), ["download"]);
+        // An alias ending in one still downloads what follows.
+        let (aliases, _) = effective_with(&[("get", "d --format pdf")]);
+        assert_eq!(
+            expand_words(&["get", "x"], &aliases).unwrap(),
+            ["--format", "pdf", "x"]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use crate::chapter_range::parse_chapter_range;
+use crate::report::Report;
+use serde::Serialize;
+use std::fs;
+
+// One download from a batch file. Lines read
+//   https://m.manganelo.com/manga-aa951409
+//   https://m.manganelo.com/manga-aa951409 :: 100-
+//   Berserk :: 1-20,25
+// and lines starting with '#' are comments. A URL without chapters means all
+// of them.
+pub struct Entry {
+    pub line: usize,
+    pub spec: String,
+    // A manga URL, or a title to search for.
+    pub target: String,
+    // Chapter ranges as written, each downloaded as its own run.
+    pub ranges: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct BatchReport {
+    pub success: bool,
+    pub lines: Vec<LineReport>,
+    pub elapsed_seconds: f64,
+}
+
+#[derive(Serialize)]
+pub struct LineReport {
+    pub line: usize,
+    pub spec: String,
+    pub status: LineStatus,
+    pub error: Option<String>,
+    // One report per chapter range of the line.
+    pub runs: Vec<Report>,
+}
+
+#[derive(Serialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LineStatus {
+    Downloaded,
+    // Every output already existed.
+    Skipped,
+    Failed,
+    // The line couldn't be parsed.
+    Invalid,
+}
+
+pub fn load(path: &str) -> Result<(Vec<Entry>, Vec<LineReport>), Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read batch file {}: {}", path, e))?;
+    Ok(parse(&contents))
+}
+
+// Splits a batch file into entries and reports for the lines that don't
+// parse.
+pub fn parse(contents: &str) -> (Vec<Entry>, Vec<LineReport>) {
+    let mut entries = Vec::new();
+    let mut invalid = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let spec = line.trim();
+        if spec.is_empty() || spec.starts_with('#') {
+            continue;
+        }
+        match parse_line(spec) {
+            Ok((target, ranges)) => entries.push(Entry {
+                line: i + 1,
+                spec: spec.to_string(),
+                target,
+                ranges,
+            }),
+            Err(e) => invalid.push(LineReport {
+                line: i + 1,
+                spec: spec.to_string(),
+                status: LineStatus::Invalid,
+                error: Some(format!("line {}: {}", i + 1, e)),
+                runs: Vec::new(),
+            }),
+        }
+    }
+    (entries, invalid)
+}
+
+fn parse_line(spec: &str) -> Result<(String, Vec<String>), String> {
+    let (target, chapters) = match spec.split_once("::") {
+        Some((target, chapters)) => (target.trim(), Some(chapters.trim())),
+        None => (spec, None),
+    };
+    if target.is_empty() {
+        return Err("missing the series before \"::\"".to_string());
+    }
+    let Some(chapters) = chapters else {
+        if !is_url(target) {
+            return Err(format!(
+                "expected a URL or \"title :: chapters\", got \"{}\"",
+                spec
+            ));
+        }
+        return Ok((target.to_string(), vec!["0-".to_string()]));
+    };
+    let ranges: Vec<String> = chapters
+        .split(',')
+        .map(|range| range.trim().to_string())
+        .collect();
+    for range in &ranges {
+        parse_chapter_range(range).map_err(|e| format!("invalid chapters \"{}\": {}", range, e))?;
+    }
+    Ok((target.to_string(), ranges))
+}
+
+pub fn is_url(value: &str) -> bool {
+    value.starts_with("https://") || value.starts_with("http://")
+}
+
+impl BatchReport {
+    pub fn print_summary(&self) {
+        println!();
+        println!("Batch summary");
+        for line in &self.lines {
+            let status = match line.status {
+                LineStatus::Downloaded => "downloaded",
+                LineStatus::Skipped => "skipped",
+                LineStatus::Failed => "failed",
+                LineStatus::Invalid => "invalid",
+            };
+            println!("  line {:<4} {:<10} {}", line.line, status, line.spec);
+            if let Some(error) = &line.error {
+                println!("    {}", error);
+            }
+        }
+        let failed = self
+            .lines
+            .iter()
+            .filter(|line| matches!(line.status, LineStatus::Failed | LineStatus::Invalid))
+            .count();
+        println!(
+            "  {} line(s), {} failed, {:.1}s",
+            self.lines.len(),
+            failed,
+            self.elapsed_seconds
+        );
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use crate::http::{self, Exchange};
+use crate::tar;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use regex::Regex;
+use reqwest::Url;
+use serde_json::{json, Value};
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Every file of the bundle sits in this directory of the archive.
+const ROOT: &str = "manga-cli-bug-report";
+const REDACTED: &str = "[redacted]";
+// Header and query parameter names containing one of these carry credentials.
+const SENSITIVE: &[&str] = &[
+    "cookie",
+    "authorization",
+    "token",
+    "session",
+    "api_key",
+    "apikey",
+    "api-key",
+    "secret",
+    "password",
+    "signature",
+];
+
+// Writes a .tar.gz with the recorded page requests, the arguments, the version
+// and the chain of `error`, ready to attach to an issue. `attachments` are
+// extra files such as the doctor results. Everything is scrubbed first:
+// credentials in headers, URLs and text are replaced and the home directory
+// is shortened to "~".
+pub fn write(
+    path: &Path,
+    args: &[OsString],
+    error: Option<&(dyn std::error::Error + 'static)>,
+    attachments: &[(&str, String)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut requests = Vec::new();
+    for (index, exchange) in http::recorded().into_iter().enumerate() {
+        let file = (!exchange.body.is_empty()).then(|| format!("pages/{:03}.html", index + 1));
+        requests.push(request_entry(&exchange, file.as_deref()));
+        if let Some(file) = file {
+            files.push((file, scrub(&String::from_utf8_lossy(&exchange.body))));
+        }
+    }
+
+    let mut chain = Vec::new();
+    let mut current = error;
+    while let Some(error) = current {
+        chain.push(scrub(&error.to_string()));
+        current = error.source();
+    }
+    let manifest = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "os": env::consts::OS,
+        "arch": env::consts::ARCH,
+        "args": args
+            .iter()
+            .map(|arg| scrub(&arg.to_string_lossy()))
+            .collect::<Vec<_>>(),
+        "error_chain": chain,
+        "requests": requests,
+    });
+    files.insert(
+        0,
+        (
+            "report.json".to_string(),
+            serde_json::to_string_pretty(&manifest)?,
+        ),
+    );
+    for (name, data) in attachments {
+        files.push((name.to_string(), scrub(data)));
+    }
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut archive = GzEncoder::new(File::create(path)?, Compression::default());
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    for (name, data) in &files {
+        tar::entry(
+            &mut archive,
+            &format!("{}/{}", ROOT, name),
+            data.as_bytes(),
+            mtime,
+        )?;
+    }
+    tar::finish(&mut archive)?;
+    archive.finish()?;
+    Ok(())
+}
+
+fn request_entry(exchange: &Exchange, file: Option<&str>) -> Value {
+    json!({
+        "url": scrub_url(&exchange.url),
+        "status": exchange.status,
+        "cached": exchange.cached,
+        "error": exchange.error.as_deref().map(scrub),
+        "request_headers": scrub_headers(&exchange.request_headers),
+        "response_headers": scrub_headers(&exchange.response_headers),
+        "file": file,
+    })
+}
+
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_lowercase();
+    SENSITIVE.iter().any(|word| name.contains(word))
+}
+
+fn scrub_headers(headers: &[(String, String)]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_sensitive(name) {
+                REDACTED.to_string()
+            } else {
+                scrub(value)
+            };
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+fn scrub_url(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return scrub(url);
+    };
+    let _ = parsed.set_password(None);
+    let pairs: Vec<(String, String)> = parsed
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if is_sensitive(&name) {
+                REDACTED.to_string()
+            } else {
+                value.into_owned()
+            };
+            (name.into_owned(), value)
+        })
+        .collect();
+    if !pairs.is_empty() {
+        parsed.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    parsed.to_string()
+}
+
+// Replaces `name=value` and `name: value` pairs whose name looks like a
+// credential, and the home directory, anywhere in `text`.
+fn scrub(text: &str) -> String {
+    static PAIR: OnceLock<Regex> = OnceLock::new();
+    let pair = PAIR.get_or_init(|| {
+        Regex::new(&format!(
+            r#"(?i)([\w-]*(?:{})[\w-]*)(["']?\s*[=:]\s*["']?)[^\s&;"'<>,]+"#,
+            SENSITIVE.join("|")
+        ))
+        .unwrap()
+    });
+    let text = pair.replace_all(text, format!("${{1}}${{2}}{}", REDACTED));
+    match env::var("HOME") {
+        Ok(home) if home.len() > 1 => text.replace(&home, "~"),
+        _ => text.into_owned(),
+    }
+}
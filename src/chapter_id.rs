@@ -0,0 +1,236 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+// A chapter's number the way sites write it: "12", "12.5", "12 extra".
+// Leading zeros and trailing decimal zeros are dropped, so "012" and "12.50"
+// are the same chapters as "12" and "12.5". Sorts 10 < 10 extra < 10.5 < 11.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChapterId {
+    whole: u64,
+    // Digits after the decimal point, without trailing zeros.
+    fraction: Option<String>,
+    // Lowercase word after the number, like "extra" or "omake".
+    suffix: Option<String>,
+}
+
+impl ChapterId {
+    pub fn whole(whole: u64) -> ChapterId {
+        ChapterId {
+            whole,
+            fraction: None,
+            suffix: None,
+        }
+    }
+
+    // The first chapter numbered after this one's whole number: 13 for 12.5.
+    pub fn next_whole(&self) -> ChapterId {
+        ChapterId::whole(self.whole + 1)
+    }
+}
+
+impl FromStr for ChapterId {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<ChapterId, String> {
+        let invalid = || format!("\"{}\" is not a chapter number", value.trim());
+        let text = value.trim().to_lowercase();
+        let digits = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        let whole = text[..digits].parse().map_err(|_| invalid())?;
+        let mut rest = &text[digits..];
+
+        let mut fraction = None;
+        if let Some(after_point) = rest.strip_prefix('.') {
+            let digits = after_point
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(after_point.len());
+            let trimmed = after_point[..digits].trim_end_matches('0');
+            fraction = (!trimmed.is_empty()).then(|| trimmed.to_string());
+            rest = &after_point[digits..];
+        }
+
+        let word = rest.trim_start_matches([' ', '-', '_']);
+        let suffix = match word.chars().next() {
+            None => None,
+            Some(first)
+                if first.is_ascii_alphabetic()
+                    && word.chars().all(|c| c.is_ascii_alphanumeric()) =>
+            {
+                Some(word.to_string())
+            }
+            Some(_) => return Err(invalid()),
+        };
+        Ok(ChapterId {
+            whole,
+            fraction,
+            suffix,
+        })
+    }
+}
+
+impl fmt::Display for ChapterId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.whole)?;
+        if let Some(fraction) = &self.fraction {
+            write!(f, ".{}", fraction)?;
+        }
+        if let Some(suffix) = &self.suffix {
+            write!(f, " {}", suffix)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for ChapterId {
+    fn cmp(&self, other: &ChapterId) -> Ordering {
+        // Without trailing zeros, decimal digits compare like strings: "05" <
+        // "25" < "5".
+        (self.whole, &self.fraction, &self.suffix).cmp(&(
+            other.whole,
+            &other.fraction,
+            &other.suffix,
+        ))
+    }
+}
+
+impl PartialOrd for ChapterId {
+    fn partial_cmp(&self, other: &ChapterId) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Stored as the displayed text, like the plain strings used before.
+impl Serialize for ChapterId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChapterId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ChapterId, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(text: &str) -> ChapterId {
+        text.parse().unwrap()
+    }
+
+    // Every combination of a few whole numbers, fractions and suffixes as
+    // sites write them, with how each is displayed.
+    fn spellings() -> Vec<(String, String)> {
+        let wholes = [("0", "0"), ("7", "7"), ("0010", "10"), ("1043", "1043")];
+        let fractions = [
+            ("", ""),
+            (".5", ".5"),
+            (".50", ".5"),
+            (".05", ".05"),
+            (".0", ""),
+            (".", ""),
+        ];
+        let suffixes = [
+            ("", ""),
+            (" extra", " extra"),
+            ("-Omake", " omake"),
+            ("_side2", " side2"),
+            ("extra", " extra"),
+        ];
+        let mut spellings = Vec::new();
+        for (whole, whole_shown) in wholes {
+            for (fraction, fraction_shown) in fractions {
+                for (suffix, suffix_shown) in suffixes {
+                    spellings.push((
+                        format!("{}{}{}", whole, fraction, suffix),
+                        format!("{}{}{}", whole_shown, fraction_shown, suffix_shown),
+                    ));
+                }
+            }
+        }
+        spellings
+    }
+
+    #[test]
+    fn parse_display_round_trips() {
+        for (text, canonical) in spellings() {
+            let parsed = id(&text);
+            assert_eq!(parsed.to_string(), canonical, "{:?}", text);
+            assert_eq!(id(&parsed.to_string()), parsed, "{:?}", text);
+            let json = serde_json::to_string(&parsed).unwrap();
+            assert_eq!(serde_json::from_str::<ChapterId>(&json).unwrap(), parsed);
+        }
+    }
+
+    #[test]
+    fn equal_spellings_are_one_chapter() {
+        assert_eq!(id("12.50"), id("12.5"));
+        assert_eq!(id("012"), id("12"));
+        assert_eq!(id("12."), id("12"));
+        assert_eq!(id("12.0"), id("12"));
+        assert_eq!(id(" 12 Extra "), id("12-extra"));
+        assert_ne!(id("12.05"), id("12.5"));
+    }
+
+    #[test]
+    fn orders_decimals_between_wholes_and_suffixes_after_their_base() {
+        let sorted = [
+            "1",
+            "9",
+            "10",
+            "10 extra",
+            "10.05",
+            "10.5",
+            "10.5 omake",
+            "10.55",
+            "11",
+            "100",
+        ];
+        for pair in sorted.windows(2) {
+            assert!(id(pair[0]) < id(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+        let mut shuffled: Vec<ChapterId> = sorted.iter().rev().map(|text| id(text)).collect();
+        shuffled.sort();
+        let names: Vec<String> = shuffled.iter().map(ChapterId::to_string).collect();
+        assert_eq!(names, sorted);
+    }
+
+    #[test]
+    fn decimal_order_matches_the_numbers() {
+        // Chapters 0 to 20 in thousandths, compared in pairs.
+        let value = |thousandths: u64| {
+            let text = format!("{}.{:03}", thousandths / 1000, thousandths % 1000);
+            id(&text)
+        };
+        let mut seed = 0x2545_f491u32;
+        for _ in 0..2000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let (a, b) = ((seed % 20_000) as u64, ((seed >> 16) % 20_000) as u64);
+            assert_eq!(value(a).cmp(&value(b)), a.cmp(&b), "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_number() {
+        for bad in [
+            "",
+            "abc",
+            "-1",
+            "1.5.2",
+            "12 extra!",
+            "18446744073709551616",
+        ] {
+            assert!(bad.parse::<ChapterId>().is_err(), "{:?}", bad);
+        }
+        assert_eq!(id("12.5").next_whole(), id("13"));
+    }
+}
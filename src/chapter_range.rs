@@ -0,0 +1,161 @@
+use crate::chapter_id::ChapterId;
+use std::fmt;
+
+// Chapter selections given on the command line, resolved against the chapter
+// list once it has been fetched.
+
+#[derive(Clone)]
+pub enum ChapterSpec {
+    Latest,
+    Number(ChapterId),
+}
+
+#[derive(Clone)]
+pub enum ChapterRange {
+    // "A-B", or just "A".
+    Between(ChapterId, ChapterId),
+    // "A-": from A to the newest chapter.
+    From(ChapterId),
+    // "-N": the N most recent chapters.
+    Last(usize),
+}
+
+impl ChapterRange {
+    pub fn contains(&self, number: &ChapterId) -> bool {
+        match self {
+            ChapterRange::Between(first, last) => first <= number && number <= last,
+            ChapterRange::From(first) => first <= number,
+            ChapterRange::Last(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for ChapterRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChapterRange::Between(first, last) if first == last => write!(f, "{}", first),
+            ChapterRange::Between(first, last) => write!(f, "{}-{}", first, last),
+            ChapterRange::From(first) => write!(f, "{}-", first),
+            ChapterRange::Last(count) => write!(f, "-{}", count),
+        }
+    }
+}
+
+pub fn parse_chapter(value: &str) -> Result<ChapterSpec, String> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("latest") {
+        return Ok(ChapterSpec::Latest);
+    }
+    Ok(ChapterSpec::Number(parse_number(value)?))
+}
+
+pub fn parse_chapter_range(value: &str) -> Result<ChapterRange, String> {
+    let value = value.trim();
+    if let Some(count) = value.strip_prefix('-') {
+        return match count.trim().parse::<usize>() {
+            Ok(count) if count > 0 => Ok(ChapterRange::Last(count)),
+            _ => Err(format!("\"-{}\" must be a positive chapter count", count)),
+        };
+    }
+    match value.split_once('-') {
+        Some((first, "")) => Ok(ChapterRange::From(parse_number(first)?)),
+        Some((first, last)) => {
+            let (first, last) = (parse_number(first)?, parse_number(last)?);
+            if first > last {
+                return Err(format!("range starts after it ends: {}", value));
+            }
+            Ok(ChapterRange::Between(first, last))
+        }
+        None => {
+            let number = parse_number(value)?;
+            Ok(ChapterRange::Between(number.clone(), number))
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Result<ChapterId, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "\"{}\" is not a chapter number; expected N, A-B, A- or -N",
+            value.trim()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(text: &str) -> ChapterId {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn range_grammar() {
+        for (value, shown) in [
+            ("5", "5"),
+            ("10-20", "10-20"),
+            (" 10 - 20 ", "10-20"),
+            ("010-12.50", "10-12.5"),
+            ("12.5-12.5", "12.5"),
+            ("100-", "100-"),
+            ("-10", "-10"),
+            ("- 3", "-3"),
+        ] {
+            let range = parse_chapter_range(value).unwrap_or_else(|e| panic!("{:?}: {}", value, e));
+            assert_eq!(range.to_string(), shown, "{:?}", value);
+            // What is shown reads back as the same range.
+            assert_eq!(parse_chapter_range(shown).unwrap().to_string(), shown);
+        }
+        assert!(matches!(
+            parse_chapter_range("100-"),
+            Ok(ChapterRange::From(_))
+        ));
+        assert!(matches!(
+            parse_chapter_range("-10"),
+            Ok(ChapterRange::Last(10))
+        ));
+    }
+
+    #[test]
+    fn rejected_ranges() {
+        for value in [
+            "-0", "-", "--5", "-x", "20-10", "10.5-10", "a-b", "1-2-3", "", "latest", "1,2",
+        ] {
+            assert!(parse_chapter_range(value).is_err(), "{:?}", value);
+        }
+        let reversed = parse_chapter_range("20-10").err().unwrap();
+        assert!(reversed.contains("starts after it ends"), "{}", reversed);
+        let garbage = parse_chapter_range("abc").err().unwrap();
+        assert!(garbage.contains("expected N, A-B, A- or -N"), "{}", garbage);
+    }
+
+    #[test]
+    fn ranges_contain_their_chapters() {
+        let between = parse_chapter_range("10-11").unwrap();
+        for (number, inside) in [
+            ("9", false),
+            ("10", true),
+            ("10.5", true),
+            ("11", true),
+            ("11 extra", false),
+        ] {
+            assert_eq!(between.contains(&id(number)), inside, "{}", number);
+        }
+        let from = parse_chapter_range("100-").unwrap();
+        assert!(from.contains(&id("100")) && from.contains(&id("5000")));
+        assert!(!from.contains(&id("99.9")));
+    }
+
+    #[test]
+    fn single_chapters() {
+        assert!(matches!(parse_chapter("latest"), Ok(ChapterSpec::Latest)));
+        assert!(matches!(parse_chapter(" LATEST "), Ok(ChapterSpec::Latest)));
+        assert!(parse_chapter("12,5").is_err());
+        match parse_chapter("012.50") {
+            Ok(ChapterSpec::Number(number)) => assert_eq!(number, id("12.5")),
+            _ => panic!("012.50 isn't a chapter"),
+        }
+        assert!(parse_chapter("newest").is_err());
+    }
+}
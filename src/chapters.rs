@@ -0,0 +1,66 @@
+use crate::source::Manga;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Chapter lists are reused for this long unless the config sets
+// `chapter_list_hours`.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+// A series' parsed chapter list, with what it was fetched for. A list fetched
+// from another source or for other languages is never reused.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    source: String,
+    manga_url: String,
+    languages: Vec<String>,
+    fetched_at: u64,
+    manga: Manga,
+}
+
+// The cached chapter list of `manga_url`, if it's younger than `ttl`.
+pub fn load(
+    dir: &Path,
+    source: &str,
+    manga_url: &str,
+    languages: &[String],
+    ttl: Duration,
+) -> Option<Manga> {
+    let data = fs::read_to_string(entry_path(dir, source, manga_url)).ok()?;
+    let entry: Entry = serde_json::from_str(&data).ok()?;
+    let fresh = now().saturating_sub(entry.fetched_at) < ttl.as_secs();
+    let same =
+        entry.source == source && entry.manga_url == manga_url && entry.languages == languages;
+    (fresh && same).then_some(entry.manga)
+}
+
+pub fn store(dir: &Path, source: &str, manga_url: &str, languages: &[String], manga: &Manga) {
+    let entry = Entry {
+        source: source.to_string(),
+        manga_url: manga_url.to_string(),
+        languages: languages.to_vec(),
+        fetched_at: now(),
+        manga: manga.clone(),
+    };
+    let saved = fs::create_dir_all(dir).and_then(|_| {
+        let data = serde_json::to_string(&entry).unwrap_or_default();
+        fs::write(entry_path(dir, source, manga_url), data)
+    });
+    if let Err(e) = saved {
+        log::debug!("Failed to cache the chapter list of {}: {}", manga_url, e);
+    }
+}
+
+fn entry_path(dir: &Path, source: &str, manga_url: &str) -> PathBuf {
+    let key = Sha256::digest(format!("{}\n{}", source, manga_url).as_bytes());
+    dir.join(format!("{:x}.json", key))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
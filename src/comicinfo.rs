@@ -0,0 +1,86 @@
+use crate::chapter_id::ChapterId;
+
+// Minimal ComicInfo.xml (the Anansi schema read by Komga, Kavita and most CBZ
+// readers) embedded into CBZ archives.
+pub struct ComicInfo {
+    pub series: String,
+    // Title of the chapter, for single-chapter archives.
+    pub title: Option<String>,
+    pub number: Option<ChapterId>,
+    pub volume: Option<String>,
+    pub page_count: usize,
+    // Zero-based page index and label of each chapter start.
+    pub bookmarks: Vec<(usize, String)>,
+}
+
+impl ComicInfo {
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+            "<ComicInfo xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" ",
+            "xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n",
+        ));
+        push_element(&mut xml, "Series", &self.series);
+        if let Some(title) = &self.title {
+            push_element(&mut xml, "Title", title);
+        }
+        if let Some(number) = &self.number {
+            push_element(&mut xml, "Number", &number.to_string());
+        }
+        if let Some(volume) = &self.volume {
+            push_element(&mut xml, "Volume", volume);
+        }
+        push_element(&mut xml, "PageCount", &self.page_count.to_string());
+
+        if !self.bookmarks.is_empty() {
+            xml.push_str("  <Pages>\n");
+            for (image, bookmark) in &self.bookmarks {
+                xml.push_str(&format!(
+                    "    <Page Image=\"{}\" Bookmark=\"{}\" />\n",
+                    image,
+                    escape(bookmark)
+                ));
+            }
+            xml.push_str("  </Pages>\n");
+        }
+
+        xml.push_str("</ComicInfo>\n");
+        xml
+    }
+}
+
+// The series and chapter number recorded in a ComicInfo.xml, such as one
+// written by to_xml().
+pub fn series_and_number(xml: &str) -> (Option<String>, Option<ChapterId>) {
+    let element = |name: &str| {
+        let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+        let end = start + xml[start..].find(&format!("</{}>", name))?;
+        Some(unescape(xml[start..end].trim()))
+    };
+    (
+        element("Series").filter(|series| !series.is_empty()),
+        element("Number").and_then(|number| number.parse().ok()),
+    )
+}
+
+fn push_element(xml: &mut String, name: &str, value: &str) {
+    xml.push_str(&format!("  <{0}>{1}</{0}>\n", name, escape(value)));
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,98 @@
+use crate::decode;
+use crate::error::Error;
+use crate::exec::{self, Tool};
+use clap::ArgEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat};
+use std::fs;
+
+type CompatResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Quality of pages transcoded to JPEG; high since most sources are already
+// lossy and every generation loses detail.
+const JPEG_QUALITY: u8 = 90;
+
+// Target of --compat-format. JPEG and PNG pages are kept as they are, anything
+// else (WebP, AVIF, GIF, ...) is transcoded to the target.
+#[derive(ArgEnum, Clone, Copy)]
+pub enum CompatFormat {
+    Jpeg,
+    Png,
+}
+
+// Rewrites the page at `path` in the target format when its content is in a
+// format old readers can't show. Returns whether it was transcoded.
+pub fn transcode(path: &str, target: CompatFormat) -> CompatResult<bool> {
+    let data = fs::read(path)?;
+    let img = match content_format(&data) {
+        Some(ImageFormat::Jpeg) | Some(ImageFormat::Png) => return Ok(false),
+        Some(ImageFormat::Avif) => decode_avif(path, &data)?,
+        _ => decode::from_memory(path, &data)?,
+    };
+
+    let mut encoded = Vec::new();
+    match target {
+        CompatFormat::Jpeg => {
+            let img = DynamicImage::ImageRgb8(img.to_rgb8());
+            JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY).encode_image(&img)?;
+        }
+        CompatFormat::Png => img.write_to(&mut encoded, image::ImageOutputFormat::Png)?,
+    }
+    fs::write(path, encoded)?;
+    Ok(true)
+}
+
+// The format of a page by its content; pages are stored under .jpg names
+// whatever the site served.
+pub fn content_format(data: &[u8]) -> Option<ImageFormat> {
+    // AVIF is an ISO-BMFF file whose "ftyp" box names the avif brand.
+    if data.len() >= 12 && &data[4..8] == b"ftyp" && matches!(&data[8..12], b"avif" | b"avis") {
+        return Some(ImageFormat::Avif);
+    }
+    image::guess_format(data).ok()
+}
+
+// File extension matching a page's content, "jpg" when it's not recognized.
+pub fn extension(path: &str) -> &'static str {
+    let mut header = [0u8; 32];
+    let read = fs::File::open(path)
+        .and_then(|mut file| std::io::Read::read(&mut file, &mut header))
+        .unwrap_or(0);
+    extension_of(&header[..read])
+}
+
+// extension() for a page still in memory.
+pub fn extension_of(data: &[u8]) -> &'static str {
+    match content_format(data) {
+        Some(ImageFormat::Png) => "png",
+        Some(ImageFormat::WebP) => "webp",
+        Some(ImageFormat::Gif) => "gif",
+        Some(ImageFormat::Avif) => "avif",
+        _ => "jpg",
+    }
+}
+
+// The image crate is built without an AVIF decoder, so AVIF pages go through
+// libavif's avifdec when it's installed.
+fn decode_avif(path: &str, data: &[u8]) -> CompatResult<DynamicImage> {
+    decode::check(path, data)?;
+    if let Ok(img) = decode::from_memory_with_format(path, data, ImageFormat::Avif) {
+        return Ok(img);
+    }
+    let decoded = format!("{}.avifdec.png", path);
+    Tool::new("avifdec")
+        .arg(exec::path_arg(path))
+        .arg(exec::path_arg(&decoded))
+        .hint("AVIF pages need libavif's avifdec")
+        .run()
+        .map_err(|e| match e {
+            Error::Tool { tool, message } => Error::Tool {
+                tool,
+                message: format!("{} for {}", message, path),
+            },
+            e => e,
+        })?;
+    let img = decode::reader(&decoded).and_then(|reader| Ok(reader.decode()?));
+    let _ = fs::remove_file(&decoded);
+    img
+}
@@ -0,0 +1,197 @@
+use crate::source::parse_chapter_url_template;
+use crate::template::Template;
+use crate::ui;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const CONFIG_FILE: &str = "config.toml";
+
+// User defaults read from $XDG_CONFIG_HOME/manga-cli/config.toml. Flags given
+// on the command line take precedence.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    // Preferred chapter languages, most preferred first.
+    pub lang: Vec<String>,
+    // Extra SHA-256 digests of pages to treat as promotions.
+    pub promo_hashes: Vec<String>,
+    // Regexes matching image URLs of app ads appended to chapters.
+    #[serde(deserialize_with = "regexes")]
+    pub ad_url_patterns: Vec<Regex>,
+    // Daily check for a newer release; `update_check = false` turns it off.
+    pub update_check: Option<bool>,
+    // Mirror to always use, by source name.
+    pub mirror: HashMap<String, String>,
+    // Request headers by source name, replacing the source's own.
+    pub headers: HashMap<String, SourceHeaders>,
+    // Milliseconds waited between requests to the same host, by source name.
+    // --delay takes precedence.
+    pub delay: HashMap<String, u64>,
+    // How long series' chapter lists are reused, in hours; 0 always fetches
+    // them.
+    pub chapter_list_hours: Option<u64>,
+    // Largest image decoded, in pixels per side and in total.
+    pub max_image_side: Option<u32>,
+    pub max_image_pixels: Option<u64>,
+    // Layout of chapter URLs guessed for chapters missing from the chapter
+    // list, e.g. "{manga_url}/c{chapter}".
+    #[serde(deserialize_with = "chapter_url_template")]
+    pub chapter_url_template: Option<Template>,
+    // Record each run's source, timings and failures locally for `stats`.
+    pub usage_stats: bool,
+    // Password `serve` asks browsers for; any user name is accepted.
+    pub serve_password: Option<String>,
+    // Bytes a calendar month may transfer before new chapters are refused.
+    pub monthly_cap: Option<u64>,
+    // Longest absolute path for outputs and cache folders; longer names are
+    // shortened. Defaults to 260 on Windows and 4096 elsewhere.
+    pub max_path_length: Option<usize>,
+    // Seconds ImageMagick, avifdec, --upscale-cmd and --post-cmd may run
+    // before they're killed; an hour by default.
+    pub tool_timeout: Option<u64>,
+    // Flags used when not given on the command line, e.g. `format = "cbz"` or
+    // `jobs = 8`. Per-series settings take precedence.
+    pub defaults: BTreeMap<String, toml::Value>,
+    // Words a first argument stands for, e.g. `dl = "--format cbz"` makes
+    // `manga-cli dl "series"` run `manga-cli --format cbz "series"`.
+    pub alias: BTreeMap<String, String>,
+}
+
+// A source's [headers.<source>.page] and [headers.<source>.image] tables;
+// page headers go with HTML and API requests.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct SourceHeaders {
+    pub page: BTreeMap<String, String>,
+    pub image: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn path() -> PathBuf {
+        config_dir().join(CONFIG_FILE)
+    }
+
+    pub fn load() -> Config {
+        let path = Config::path();
+        let Ok(data) = fs::read_to_string(&path) else {
+            return Config::default();
+        };
+        toml::from_str(&data).unwrap_or_else(|e| {
+            ui::error(&format!(
+                "Ignoring invalid config {}: {}",
+                path.display(),
+                e
+            ));
+            Config::default()
+        })
+    }
+
+    // The [defaults] table as flag values: lists become comma-separated.
+    pub fn defaults(&self) -> BTreeMap<String, String> {
+        self.defaults
+            .iter()
+            .map(|(key, value)| (key.replace('_', "-"), setting_value(value)))
+            .collect()
+    }
+}
+
+// Sets `key` in the config file's [`table`] to `value`, leaving the rest of
+// the file, comments included, as it was. Nothing is written when the result
+// wouldn't load, e.g. when the table is also written with dotted keys.
+pub fn set_value(
+    table: &str,
+    key: &str,
+    value: toml::Value,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = Config::path();
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let mut lines: Vec<String> = data.lines().map(str::to_string).collect();
+    let setting = format!("{} = {}", key, value);
+    let header = format!("[{}]", table);
+    match lines.iter().position(|line| line.trim() == header) {
+        Some(start) => {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|line| line.trim_start().starts_with('['))
+                .map_or(lines.len(), |i| start + 1 + i);
+            let existing = lines[start + 1..end]
+                .iter()
+                .position(|line| line.split('=').next().map(str::trim) == Some(key));
+            match existing {
+                Some(i) => lines[start + 1 + i] = setting,
+                None => lines.insert(start + 1, setting),
+            }
+        }
+        None => {
+            if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(header);
+            lines.push(setting);
+        }
+    }
+    let edited = lines.join("\n") + "\n";
+    toml::from_str::<Config>(&edited).map_err(|e| {
+        format!(
+            "Setting {}.{} would leave {} invalid ({}); set it by hand",
+            table,
+            key,
+            path.display(),
+            e
+        )
+    })?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, edited)?;
+    Ok(path)
+}
+
+pub fn setting_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(setting_value)
+            .collect::<Vec<_>>()
+            .join(","),
+        value => value.to_string(),
+    }
+}
+
+fn chapter_url_template<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Template>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_chapter_url_template(&value)
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("invalid chapter_url_template: {}", e)))
+}
+
+fn regexes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Regex>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|e| {
+                serde::de::Error::custom(format!("invalid ad_url_patterns entry: {}", e))
+            })
+        })
+        .collect()
+}
+
+pub fn config_dir() -> PathBuf {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("manga-cli")
+}
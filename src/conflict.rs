@@ -0,0 +1,118 @@
+use crate::prompt;
+use clap::ArgEnum;
+use std::io::{self, IsTerminal};
+use std::sync::Mutex;
+
+// What a run does when its output already exists (--on-conflict).
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+pub enum OnConflict {
+    Overwrite,
+    Skip,
+    // Saves under the first free "name (1)", "name (2)", ...
+    Rename,
+    Fail,
+}
+
+// An "overwrite all" or "skip all" answer, kept for the rest of the process
+// so a batch asks once.
+static ANSWER_FOR_ALL: Mutex<Option<OnConflict>> = Mutex::new(None);
+
+// What to do about `name` already existing: `chosen` when given, otherwise
+// the user's answer, or overwriting when there's no one to ask.
+pub fn resolve(name: &str, chosen: Option<OnConflict>) -> Result<OnConflict, String> {
+    if let Some(action) = chosen {
+        return Ok(action);
+    }
+    if let Some(action) = *ANSWER_FOR_ALL.lock().unwrap() {
+        return Ok(action);
+    }
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Ok(OnConflict::Overwrite);
+    }
+    let (action, for_all) = prompt::ask(
+        &format!(
+            "{} already exists. [o]verwrite, [s]kip, [r]ename, overwrite [a]ll, skip a[l]l? ",
+            name
+        ),
+        None,
+        parse_answer,
+    )?;
+    if for_all {
+        *ANSWER_FOR_ALL.lock().unwrap() = Some(action);
+    }
+    Ok(action)
+}
+
+fn parse_answer(answer: &str) -> Result<(OnConflict, bool), String> {
+    match answer.trim().to_lowercase().as_str() {
+        "o" | "overwrite" => Ok((OnConflict::Overwrite, false)),
+        "s" | "skip" => Ok((OnConflict::Skip, false)),
+        "r" | "rename" => Ok((OnConflict::Rename, false)),
+        "a" | "overwrite all" => Ok((OnConflict::Overwrite, true)),
+        "l" | "skip all" => Ok((OnConflict::Skip, true)),
+        _ => Err("please answer o, s, r, a or l".to_string()),
+    }
+}
+
+// `name` with the lowest " (n)" suffix that isn't `taken`.
+pub fn free_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    (1..)
+        .map(|n| format!("{} ({})", name, n))
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free_among(name: &str, taken: &[&str]) -> String {
+        free_name(name, |candidate| taken.contains(&candidate))
+    }
+
+    #[test]
+    fn rename_takes_the_lowest_free_number() {
+        assert_eq!(free_among("Series c1", &[]), "Series c1 (1)");
+        assert_eq!(free_among("Series c1", &["Series c1 (1)"]), "Series c1 (2)");
+        assert_eq!(
+            free_among(
+                "Series c1",
+                &["Series c1 (1)", "Series c1 (2)", "Series c1 (3)"]
+            ),
+            "Series c1 (4)"
+        );
+    }
+
+    #[test]
+    fn rename_fills_gaps() {
+        let taken = ["Series c1 (1)", "Series c1 (3)"];
+        assert_eq!(free_among("Series c1", &taken), "Series c1 (2)");
+        assert_eq!(free_among("Series c1", &["Series c1 (2)"]), "Series c1 (1)");
+    }
+
+    #[test]
+    fn rename_numbers_only_the_name_asked_about() {
+        // Other series' and nested numbers don't count.
+        let taken = ["Series c2 (1)", "Series c1 (1) (1)", "Series c1(1)"];
+        assert_eq!(free_among("Series c1", &taken), "Series c1 (1)");
+        assert_eq!(free_among("Series c1 (1)", &taken), "Series c1 (1) (2)");
+    }
+
+    #[test]
+    fn answers() {
+        for (answer, expected) in [
+            ("o", Some((OnConflict::Overwrite, false))),
+            (" S ", Some((OnConflict::Skip, false))),
+            ("rename", Some((OnConflict::Rename, false))),
+            ("a", Some((OnConflict::Overwrite, true))),
+            ("skip all", Some((OnConflict::Skip, true))),
+            ("x", None),
+            ("", None),
+        ] {
+            let parsed = parse_answer(answer).ok();
+            assert!(parsed == expected, "{:?}", answer);
+        }
+        // A choice on the command line is never asked about.
+        assert!(resolve("Series c1", Some(OnConflict::Fail)) == Ok(OnConflict::Fail));
+    }
+}
@@ -0,0 +1,300 @@
+use time::format_description::well_known::Rfc3339;
+use time::{Date, Duration, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+// Month name prefixes by language; the first one a word starts with wins, so
+// longer forms ("marzo", "março") match too.
+const MONTHS: &[[&str; 12]] = &[
+    [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ],
+    [
+        "ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sep", "oct", "nov", "dic",
+    ],
+    [
+        "jan", "fev", "mar", "abr", "mai", "jun", "jul", "ago", "set", "out", "nov", "dez",
+    ],
+];
+
+// Words for units of time in relative dates: English, Spanish, Portuguese and
+// Japanese, in the singular.
+const UNITS: &[(&str, i64)] = &[
+    ("sec", 1),
+    ("second", 1),
+    ("segundo", 1),
+    ("秒", 1),
+    ("min", 60),
+    ("minute", 60),
+    ("minuto", 60),
+    ("分", 60),
+    ("hour", 3600),
+    ("hora", 3600),
+    ("時間", 3600),
+    ("day", 86400),
+    ("día", 86400),
+    ("dia", 86400),
+    ("日", 86400),
+    ("week", 7 * 86400),
+    ("semana", 7 * 86400),
+    ("週間", 7 * 86400),
+    ("month", 30 * 86400),
+    ("mes", 30 * 86400),
+    ("mês", 30 * 86400),
+    ("ヶ月", 30 * 86400),
+    ("か月", 30 * 86400),
+    ("ヵ月", 30 * 86400),
+    ("year", 365 * 86400),
+    ("año", 365 * 86400),
+    ("ano", 365 * 86400),
+    ("年", 365 * 86400),
+];
+
+// Words for "a"/"an" in "an hour ago", "hace un día", "há uma hora".
+const ONE: &[&str] = &["a", "an", "un", "una", "um", "uma"];
+
+const NOW: &[&str] = &[
+    "just now",
+    "now",
+    "justo ahora",
+    "ahora mismo",
+    "ahora",
+    "agora mesmo",
+    "agora",
+    "たった今",
+    "今",
+];
+const YESTERDAY: &[&str] = &["yesterday", "ayer", "ontem", "昨日"];
+
+// Best-effort parsing of the upload dates shown in chapter lists, either
+// relative ("2 days ago", "hace 2 días", "há 2 dias", "3時間前"), absolute
+// ("Mar 06,2023 04:12", "6 de marzo de 2023", "2023年3月6日") or the RFC 3339
+// timestamps APIs return. Mirrors in other languages render dates their own
+// way, so every language is tried; their words don't overlap.
+pub fn parse_release_date(text: &str, now: OffsetDateTime) -> Option<OffsetDateTime> {
+    if let Ok(date) = OffsetDateTime::parse(text.trim(), &Rfc3339) {
+        return Some(date);
+    }
+    let text = text.trim().to_lowercase();
+    let text = text
+        .strip_prefix("updated ")
+        .or_else(|| text.strip_prefix("actualizado "))
+        .or_else(|| text.strip_prefix("atualizado "))
+        .unwrap_or(&text)
+        .trim();
+    if text.is_empty() {
+        return None;
+    }
+    if NOW.contains(&text) {
+        return Some(now);
+    }
+    if YESTERDAY.contains(&text) {
+        return Some(now - Duration::days(1));
+    }
+    let relative = text
+        .strip_suffix(" ago")
+        .or_else(|| text.strip_prefix("hace "))
+        .or_else(|| text.strip_prefix("há "))
+        .or_else(|| text.strip_suffix(" atrás"))
+        .or_else(|| text.strip_suffix('前'));
+    match relative {
+        Some(relative) => parse_relative(relative.trim(), now),
+        None => parse_absolute(text),
+    }
+}
+
+// Lengths of time such as "24h", "90m", "3d" or "1w".
+pub fn parse_window(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("\"{}\" isn't a length of time like 24h or 3d", value))?;
+    match unit {
+        "m" => Ok(Duration::minutes(amount)),
+        "h" => Ok(Duration::hours(amount)),
+        "d" => Ok(Duration::days(amount)),
+        "w" => Ok(Duration::weeks(amount)),
+        _ => Err(format!(
+            "\"{}\" needs a unit: m, h, d or w, as in 24h",
+            value
+        )),
+    }
+}
+
+fn parse_relative(text: &str, now: OffsetDateTime) -> Option<OffsetDateTime> {
+    // Japanese writes no space: "3時間".
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .filter(|&split| split > 0 && !text[split..].starts_with(' '));
+    let (amount, unit) = match split {
+        Some(split) => text.split_at(split),
+        None => text.split_once(' ')?,
+    };
+    let amount: i64 = match amount {
+        amount if ONE.contains(&amount) => 1,
+        amount => amount.parse().ok()?,
+    };
+    let unit = unit.trim();
+    // "días", "meses", but "mes" and "mês" are singular.
+    let seconds = [Some(unit), unit.strip_suffix('s'), unit.strip_suffix("es")]
+        .into_iter()
+        .flatten()
+        .find_map(|unit| {
+            UNITS
+                .iter()
+                .find(|(name, _)| *name == unit)
+                .map(|(_, seconds)| seconds)
+        })?;
+    Some(now - Duration::seconds(amount * seconds))
+}
+
+fn parse_absolute(text: &str) -> Option<OffsetDateTime> {
+    if let Some(date) = parse_numeric(text) {
+        return Some(date);
+    }
+    let normalized = text.replace([',', '.'], " ");
+    // "6 de marzo de 2023".
+    let mut tokens: Vec<&str> = normalized
+        .split_whitespace()
+        .filter(|token| *token != "de")
+        .collect();
+    // Day first, as Spanish and Portuguese mirrors write it.
+    if tokens.len() >= 2 && tokens[0].parse::<u8>().is_ok() {
+        tokens.swap(0, 1);
+    }
+    let mut tokens = tokens.into_iter();
+
+    let month = tokens.next()?;
+    let month = MONTHS
+        .iter()
+        .find_map(|names| names.iter().position(|name| month.starts_with(name)))?
+        as u8
+        + 1;
+    let day: u8 = tokens.next()?.parse().ok()?;
+    let year: i32 = match tokens.next()? {
+        year if year.len() == 2 => 2000 + year.parse::<i32>().ok()?,
+        year => year.parse().ok()?,
+    };
+    let time = match tokens.next().filter(|time| time.contains(':')) {
+        Some(time) => parse_time(time)?,
+        None => Time::MIDNIGHT,
+    };
+
+    let date = Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()?;
+    Some(PrimitiveDateTime::new(date, time).assume_utc())
+}
+
+// Year first and in digits: "2023-03-06", "2023/03/06 04:12", "2023.03.06"
+// or "2023年3月6日".
+fn parse_numeric(text: &str) -> Option<OffsetDateTime> {
+    let (date, time) = match text.split_once(' ') {
+        Some((date, time)) => (date, Some(time.trim())),
+        None => (text, None),
+    };
+    let mut parts = date
+        .split(['-', '/', '.', '年', '月', '日'])
+        .filter(|part| !part.is_empty());
+    let year: i32 = parts.next()?.parse().ok()?;
+    let month: u8 = parts.next()?.parse().ok()?;
+    let day: u8 = parts.next()?.parse().ok()?;
+    if year < 1000 || parts.next().is_some() {
+        return None;
+    }
+    let time = match time {
+        Some(time) => parse_time(time)?,
+        None => Time::MIDNIGHT,
+    };
+    let date = Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()?;
+    Some(PrimitiveDateTime::new(date, time).assume_utc())
+}
+
+// "04:12".
+fn parse_time(text: &str) -> Option<Time> {
+    let (hour, minute) = text.split_once(':')?;
+    Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    const NOW: OffsetDateTime = datetime!(2024-05-10 12:00 UTC);
+
+    fn ago(text: &str) -> Option<Duration> {
+        parse_release_date(text, NOW).map(|date| NOW - date)
+    }
+
+    #[test]
+    fn relative_dates_in_every_language() {
+        let cases = [
+            ("2 days ago", Duration::days(2)),
+            ("an hour ago", Duration::hours(1)),
+            ("Updated 5 mins ago", Duration::minutes(5)),
+            ("hace 2 días", Duration::days(2)),
+            ("hace un mes", Duration::days(30)),
+            ("hace 3 meses", Duration::days(90)),
+            ("Actualizado hace 1 semana", Duration::weeks(1)),
+            ("há 2 dias", Duration::days(2)),
+            ("há uma hora", Duration::hours(1)),
+            ("3 horas atrás", Duration::hours(3)),
+            ("1 mês atrás", Duration::days(30)),
+            ("3時間前", Duration::hours(3)),
+            ("1か月前", Duration::days(30)),
+            ("2週間前", Duration::weeks(2)),
+            ("10秒前", Duration::seconds(10)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(ago(text), Some(expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn now_and_yesterday_in_every_language() {
+        for text in ["just now", "ahora", "agora mesmo", "たった今"] {
+            assert_eq!(ago(text), Some(Duration::ZERO), "{}", text);
+        }
+        for text in ["Yesterday", "ayer", "ontem", "昨日"] {
+            assert_eq!(ago(text), Some(Duration::days(1)), "{}", text);
+        }
+    }
+
+    #[test]
+    fn absolute_dates_in_every_language() {
+        let cases = [
+            ("Mar 06,2023 04:12", datetime!(2023-03-06 04:12 UTC)),
+            ("Mar 06,23", datetime!(2023-03-06 0:00 UTC)),
+            ("6 de marzo de 2023", datetime!(2023-03-06 0:00 UTC)),
+            ("06 dic 2022", datetime!(2022-12-06 0:00 UTC)),
+            ("6 de março de 2023", datetime!(2023-03-06 0:00 UTC)),
+            ("15 fev. 2021", datetime!(2021-02-15 0:00 UTC)),
+            ("2023/03/06", datetime!(2023-03-06 0:00 UTC)),
+            ("2023.03.06 04:12", datetime!(2023-03-06 04:12 UTC)),
+            ("2023-03-06", datetime!(2023-03-06 0:00 UTC)),
+            ("2023年3月6日", datetime!(2023-03-06 0:00 UTC)),
+            ("2023-03-06T04:12:00+02:00", datetime!(2023-03-06 04:12 +2)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(parse_release_date(text, NOW), Some(expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn anything_else_is_unknown() {
+        for text in [
+            "",
+            "updated",
+            "soon",
+            "hace mucho",
+            "3 fortnights ago",
+            "2023/13/06",
+            "31 feb 2023",
+            "06/03/2023",
+            "Chapter 12",
+        ] {
+            assert_eq!(parse_release_date(text, NOW), None, "{}", text);
+        }
+    }
+}
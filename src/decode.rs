@@ -0,0 +1,192 @@
+use crate::error::Error;
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, ImageFormat};
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+use std::sync::OnceLock;
+
+type DecodeResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Images claiming to be larger than this are refused before decoding, so a
+// crafted header can't make the decoder allocate gigabytes. Long webtoon
+// strips are tall but narrow, hence the generous side and the pixel budget.
+#[derive(Clone, Copy)]
+pub struct DecodeLimits {
+    pub max_side: u32,
+    pub max_pixels: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> DecodeLimits {
+        DecodeLimits {
+            max_side: 40_000,
+            max_pixels: 120_000_000,
+        }
+    }
+}
+
+static LIMITS: OnceLock<DecodeLimits> = OnceLock::new();
+
+// Replaces the default limits for the rest of the run (config file).
+pub fn set_limits(limits: DecodeLimits) {
+    let _ = LIMITS.set(limits);
+}
+
+fn limits() -> DecodeLimits {
+    LIMITS.get().copied().unwrap_or_default()
+}
+
+// Checks the size `data` declares in its header against the limits. `name`,
+// usually the page URL, identifies the image in the error. Headers that can't
+// be read are left for the decoder to reject.
+pub fn check(name: &str, data: &[u8]) -> Result<(), Error> {
+    match dimensions(data) {
+        Some((width, height)) => check_dimensions(name, width, height),
+        None => Ok(()),
+    }
+}
+
+// Width and height from the image's header, without decoding it.
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok())
+}
+
+// An image reader for the file at `path`, after checking its declared size.
+pub fn reader(path: impl AsRef<Path>) -> DecodeResult<ImageReader<BufReader<File>>> {
+    let path = path.as_ref();
+    let (width, height) = ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_dimensions()?;
+    check_dimensions(&path.to_string_lossy(), width, height)?;
+    Ok(ImageReader::open(path)?.with_guessed_format()?)
+}
+
+pub fn from_memory(name: &str, data: &[u8]) -> DecodeResult<DynamicImage> {
+    check(name, data)?;
+    Ok(image::load_from_memory(data)?)
+}
+
+pub fn from_memory_with_format(
+    name: &str,
+    data: &[u8],
+    format: ImageFormat,
+) -> DecodeResult<DynamicImage> {
+    check(name, data)?;
+    Ok(image::load_from_memory_with_format(data, format)?)
+}
+
+fn check_dimensions(name: &str, width: u32, height: u32) -> Result<(), Error> {
+    let limits = limits();
+    if width.max(height) > limits.max_side || width as u64 * height as u64 > limits.max_pixels {
+        return Err(Error::TooLarge {
+            name: name.to_string(),
+            width,
+            height,
+            max_side: limits.max_side,
+            max_pixels: limits.max_pixels,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+    use std::fs;
+
+    // A PNG of a few dozen bytes whose header declares `width` x `height`,
+    // with no image data.
+    fn crafted_png(width: u32, height: u32) -> Vec<u8> {
+        let mut header = width.to_be_bytes().to_vec();
+        header.extend(height.to_be_bytes());
+        // 8-bit RGB, no interlacing.
+        header.extend([8, 2, 0, 0, 0]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in [(b"IHDR", &header[..]), (b"IDAT", &[]), (b"IEND", &[])] {
+            let chunk = [&kind[..], data].concat();
+            png.extend((data.len() as u32).to_be_bytes());
+            png.extend(&chunk);
+            png.extend(crc32(&chunk).to_be_bytes());
+        }
+        png
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for byte in data {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    crc >> 1 ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn crafted_headers_are_refused_before_decoding() {
+        let url = "https://a.test/chapter-1/2.png";
+        let bomb = crafted_png(100_000, 100_000);
+        assert!(bomb.len() < 64);
+        assert_eq!(dimensions(&bomb), Some((100_000, 100_000)));
+
+        let error = check(url, &bomb).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::TooLarge {
+                width: 100_000,
+                height: 100_000,
+                ..
+            }
+        ));
+        assert!(error.to_string().starts_with(url), "{}", error);
+
+        // Decoding would fail anyway, but only after allocating the picture.
+        let error = from_memory(url, &bomb).unwrap_err();
+        assert!(error.downcast_ref::<Error>().is_some(), "{}", error);
+        let error = from_memory_with_format(url, &bomb, ImageFormat::Png).unwrap_err();
+        assert!(error.downcast_ref::<Error>().is_some(), "{}", error);
+    }
+
+    #[test]
+    fn each_limit_is_enforced_alone() {
+        let limits = DecodeLimits::default();
+        // A long strip within the pixel budget but over the side.
+        let strip = crafted_png(100, limits.max_side + 1);
+        assert!(check("strip", &strip).is_err());
+        // Within the side but over the budget.
+        let side = limits.max_side;
+        let square = crafted_png(side, (limits.max_pixels / side as u64) as u32 + 1);
+        assert!(check("square", &square).is_err());
+        // Just within both.
+        let strip = crafted_png(limits.max_pixels as u32 / side, side);
+        assert!(check("strip", &strip).is_ok());
+    }
+
+    #[test]
+    fn files_on_disk_are_checked_too() {
+        let dir = TestDir::new("decode");
+        let bomb = dir.join("bomb.png");
+        fs::write(&bomb, crafted_png(60_000, 60_000)).unwrap();
+        let error = reader(&bomb).err().unwrap();
+        assert!(error.to_string().contains("bomb.png"), "{}", error);
+
+        let page = dir.join("page.png");
+        image::RgbImage::new(8, 12).save(&page).unwrap();
+        assert_eq!(reader(&page).unwrap().into_dimensions().unwrap(), (8, 12));
+    }
+
+    #[test]
+    fn unreadable_headers_are_left_to_the_decoder() {
+        assert!(check("garbage", b"<html>not an image</html>").is_ok());
+        assert!(from_memory("garbage", b"<html>not an image</html>").is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use crate::process::original_path;
+use std::fs;
+
+// Query parameters sites add to defeat caches; URLs differing only in these
+// name the same image.
+const CACHE_BUSTERS: &[&str] = &[
+    "_",
+    "cache",
+    "cb",
+    "nocache",
+    "r",
+    "rand",
+    "rnd",
+    "t",
+    "time",
+    "timestamp",
+    "ts",
+    "v",
+    "ver",
+    "version",
+];
+
+// Drops repeated image URLs, keeping the first of each, as when a reader page
+// both shows and preloads an image. Returns how many were dropped.
+pub fn unique_urls(urls: &mut Vec<String>) -> usize {
+    let before = urls.len();
+    let mut seen = Vec::new();
+    urls.retain(|url| {
+        let key = without_cache_busters(url);
+        if seen.contains(&key) {
+            return false;
+        }
+        seen.push(key);
+        true
+    });
+    before - urls.len()
+}
+
+// The URL without cache-busting query parameters or a fragment.
+fn without_cache_busters(url: &str) -> String {
+    let url = url.split('#').next().unwrap_or(url);
+    let Some((base, query)) = url.split_once('?') else {
+        return url.to_string();
+    };
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split('=').next().unwrap_or(pair);
+            !pair.is_empty() && !CACHE_BUSTERS.contains(&name.to_lowercase().as_str())
+        })
+        .collect();
+    if kept.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}?{}", base, kept.join("&"))
+    }
+}
+
+// Indices of downloaded pages whose bytes equal the page before them.
+pub fn repeated_pages(pages: &[String]) -> Vec<usize> {
+    let mut repeated = Vec::new();
+    let mut previous: Option<Vec<u8>> = None;
+    for (i, page) in pages.iter().enumerate() {
+        // Compare the downloads, not what processing made of them.
+        let data = fs::read(original_path(page))
+            .or_else(|_| fs::read(page))
+            .ok();
+        if data.is_some() && data == previous {
+            repeated.push(i);
+        }
+        previous = data;
+    }
+    repeated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::ORIGINALS_DIR;
+    use crate::testdir::TestDir;
+
+    fn unique(urls: &[&str]) -> (Vec<String>, usize) {
+        let mut urls: Vec<String> = urls.iter().map(|url| url.to_string()).collect();
+        let dropped = unique_urls(&mut urls);
+        (urls, dropped)
+    }
+
+    #[test]
+    fn cache_busters_dont_make_a_new_image() {
+        let (urls, dropped) = unique(&[
+            "https://cdn.test/1.jpg",
+            "https://cdn.test/1.jpg?t=1",
+            "https://cdn.test/1.jpg?T=2&cb=3",
+            "https://cdn.test/1.jpg#retina",
+            "https://cdn.test/2.jpg?token=a&v=1",
+            "https://cdn.test/2.jpg?v=2&token=a",
+            "https://cdn.test/2.jpg?token=b",
+            "https://cdn.test/3.jpg?",
+            "https://cdn.test/3.jpg",
+        ]);
+        assert_eq!(
+            urls,
+            [
+                "https://cdn.test/1.jpg",
+                "https://cdn.test/2.jpg?token=a&v=1",
+                "https://cdn.test/2.jpg?token=b",
+                "https://cdn.test/3.jpg?",
+            ]
+        );
+        assert_eq!(dropped, 5);
+    }
+
+    #[test]
+    fn order_is_kept() {
+        let urls = [
+            "https://cdn.test/3.jpg",
+            "https://cdn.test/1.jpg",
+            "https://cdn.test/2.jpg",
+        ];
+        assert_eq!(unique(&urls), (urls.map(String::from).to_vec(), 0));
+        // A page shown again later is still a repeat.
+        let (urls, dropped) = unique(&[
+            "https://cdn.test/1.jpg",
+            "https://cdn.test/2.jpg",
+            "https://cdn.test/1.jpg?_=9",
+        ]);
+        assert_eq!(urls.len(), 2);
+        assert_eq!(dropped, 1);
+    }
+
+    #[test]
+    fn only_identical_neighbours_are_repeated_pages() {
+        let dir = TestDir::new("dedupe");
+        let page = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            fs::write(&path, data).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let pages = [
+            page("1.jpg", b"first"),
+            page("2.jpg", b"second"),
+            page("3.jpg", b"second"),
+            page("4.jpg", b"second"),
+            page("5.jpg", b"first"),
+            dir.join("6.jpg").to_string_lossy().into_owned(),
+            dir.join("7.jpg").to_string_lossy().into_owned(),
+        ];
+        // Missing pages aren't repeats of each other.
+        assert_eq!(repeated_pages(&pages), [2, 3]);
+    }
+
+    #[test]
+    fn processed_pages_are_compared_by_their_download() {
+        let dir = TestDir::new("dedupe-originals");
+        fs::create_dir(dir.join(ORIGINALS_DIR)).unwrap();
+        let mut pages = Vec::new();
+        for (name, original, processed) in [
+            ("1.jpg", "a", "x"),
+            ("2.jpg", "b", "x"),
+            ("3.jpg", "b", "y"),
+        ] {
+            fs::write(dir.join(ORIGINALS_DIR).join(name), original).unwrap();
+            fs::write(dir.join(name), processed).unwrap();
+            pages.push(dir.join(name).to_string_lossy().into_owned());
+        }
+        assert_eq!(repeated_pages(&pages), [2]);
+    }
+}
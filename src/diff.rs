@@ -0,0 +1,211 @@
+use crate::decode;
+use crate::http::{self, Kind};
+use crate::report::format_bytes;
+use crate::scheduler::{host_of, Scheduler};
+use crate::source::{Chapter, Source};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Requests in flight against one image host at a time.
+const MAX_REQUESTS_PER_HOST: usize = 2;
+
+const PAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif", "avif"];
+
+// How a page of the cached copy compares with the one on the site now.
+pub enum PageStatus {
+    Same,
+    // With what differs.
+    Changed(String),
+    // Only on the site.
+    Added,
+    // Only in the cached copy.
+    Removed,
+    // On both, but the pages weren't compared (no --pages).
+    Unchecked,
+    // The site's page couldn't be fetched.
+    Failed(String),
+}
+
+pub struct PageDiff {
+    pub page: usize,
+    pub status: PageStatus,
+}
+
+// The page images of a cached chapter folder, in page order.
+pub fn cached_pages(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut pages: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    PAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+                })
+        })
+        .collect();
+    // Numeric names sort by value so "10.jpg" follows "9.jpg".
+    pages.sort_by_key(|path| {
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("");
+        (stem.parse::<u64>().unwrap_or(u64::MAX), path.clone())
+    });
+    Ok(pages)
+}
+
+// Compares the `cached` pages of `chapter` with the `images` the site lists
+// now. Without `fetch` only the page counts are compared. With it, each page
+// is first compared by the size a HEAD request reports and only downloaded
+// when the sizes match or are unknown. Copies re-encoded for --low-data
+// (`reencoded`) can only be compared by their dimensions.
+pub fn compare(
+    source: &dyn Source,
+    chapter: &Chapter,
+    cached: &[PathBuf],
+    images: &[String],
+    fetch: bool,
+    reencoded: bool,
+    jobs: usize,
+) -> Vec<PageDiff> {
+    let shared = cached.len().min(images.len());
+    let mut diffs: Vec<PageDiff> = if fetch {
+        let headers = source.image_headers(chapter);
+        let scheduler = Scheduler::new(jobs, MAX_REQUESTS_PER_HOST);
+        let tasks = (0..shared).map(|i| (host_of(&images[i]), i)).collect();
+        scheduler
+            .run(tasks, |i| {
+                compare_page(&cached[i], &images[i], &headers, reencoded)
+            })
+            .into_iter()
+            .enumerate()
+            .map(|(i, status)| PageDiff {
+                page: i + 1,
+                status,
+            })
+            .collect()
+    } else {
+        (0..shared)
+            .map(|i| PageDiff {
+                page: i + 1,
+                status: PageStatus::Unchecked,
+            })
+            .collect()
+    };
+    diffs.extend((shared..images.len()).map(|i| PageDiff {
+        page: i + 1,
+        status: PageStatus::Added,
+    }));
+    diffs.extend((shared..cached.len()).map(|i| PageDiff {
+        page: i + 1,
+        status: PageStatus::Removed,
+    }));
+    diffs
+}
+
+fn compare_page(
+    cached: &Path,
+    url: &str,
+    headers: &[(String, String)],
+    reencoded: bool,
+) -> PageStatus {
+    let local = match fs::read(cached) {
+        Ok(data) => data,
+        Err(e) => return PageStatus::Changed(format!("cached copy unreadable: {}", e)),
+    };
+    if !reencoded {
+        match http::content_length(url, headers) {
+            Ok(Some(length)) if length != local.len() as u64 => {
+                let (before, after) = (format_bytes(local.len() as u64), format_bytes(length));
+                // Close sizes round to the same text.
+                return PageStatus::Changed(if before == after {
+                    format!("{} B -> {} B", local.len(), length)
+                } else {
+                    format!("{} -> {}", before, after)
+                });
+            }
+            Ok(_) => {}
+            Err(e) => log::debug!("No size for {}: {}", url, e),
+        }
+    }
+
+    let response = match http::get(url, headers, Kind::Image) {
+        Ok(response) => response,
+        Err(e) => return PageStatus::Failed(e.to_string()),
+    };
+    if let Err(e) = response.check_status(url) {
+        return PageStatus::Failed(e.to_string());
+    }
+    let remote = response.body;
+    if Sha256::digest(&local) == Sha256::digest(&remote) {
+        return PageStatus::Same;
+    }
+    match (decode::dimensions(&local), decode::dimensions(&remote)) {
+        (Some(before), Some(after)) if before != after => PageStatus::Changed(format!(
+            "{}x{} -> {}x{}",
+            before.0, before.1, after.0, after.1
+        )),
+        (Some(_), Some(_)) if reencoded => PageStatus::Same,
+        _ => PageStatus::Changed("different image data".to_string()),
+    }
+}
+
+// Whether the site's copy differs from the cached one in anything compared.
+pub fn differs(diffs: &[PageDiff]) -> bool {
+    diffs.iter().any(|diff| {
+        matches!(
+            diff.status,
+            PageStatus::Changed(_) | PageStatus::Added | PageStatus::Removed
+        )
+    })
+}
+
+// One line per page that isn't known to be the same, then the totals.
+pub fn print(diffs: &[PageDiff]) {
+    let (mut same, mut changed, mut added, mut removed, mut unchecked, mut failed) =
+        (0, 0, 0, 0, 0, 0);
+    for diff in diffs {
+        let line = match &diff.status {
+            PageStatus::Same => {
+                same += 1;
+                continue;
+            }
+            PageStatus::Unchecked => {
+                unchecked += 1;
+                continue;
+            }
+            PageStatus::Changed(detail) => {
+                changed += 1;
+                format!("changed ({})", detail)
+            }
+            PageStatus::Added => {
+                added += 1;
+                "added".to_string()
+            }
+            PageStatus::Removed => {
+                removed += 1;
+                "removed".to_string()
+            }
+            PageStatus::Failed(error) => {
+                failed += 1;
+                format!("not compared: {}", error)
+            }
+        };
+        println!("  page {:>3}  {}", diff.page, line);
+    }
+    let mut totals = vec![
+        format!("{} same", same),
+        format!("{} changed", changed),
+        format!("{} added", added),
+        format!("{} removed", removed),
+    ];
+    if unchecked > 0 {
+        totals.push(format!("{} not compared (use --pages)", unchecked));
+    }
+    if failed > 0 {
+        totals.push(format!("{} failed", failed));
+    }
+    println!("{}", totals.join(", "));
+}
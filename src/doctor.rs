@@ -0,0 +1,226 @@
+use crate::bug_report;
+use crate::http::{self, Kind};
+use crate::rate_limit::{self, Observed};
+use crate::source::{source, Source, SourceKind};
+use crate::ui;
+use clap::ArgEnum;
+use reqwest::blocking::Client;
+use reqwest::Url;
+use serde::Serialize;
+use std::env;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+
+#[derive(Serialize)]
+pub struct CheckResult {
+    pub source: &'static str,
+    pub check: &'static str,
+    pub passed: bool,
+    // Failing critical checks make `doctor` exit non-zero.
+    pub critical: bool,
+    pub detail: String,
+}
+
+// Runs every check against every registered source. Returns whether all
+// critical checks passed. With `bug_report`, a failed check writes the pages
+// fetched and the results to that file.
+pub fn run(
+    languages: &[String],
+    json: bool,
+    bug_report: Option<&str>,
+    apply_suggestion: bool,
+) -> bool {
+    let mut results = Vec::new();
+    let observed: Vec<Observed> = rate_limit::load().into_values().collect();
+    for kind in SourceKind::value_variants() {
+        let source = source(*kind, languages, false);
+        check_source(source.as_ref(), &mut results);
+        check_rate_limits(source.name(), &observed, &mut results);
+    }
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&results).unwrap_or_default()
+        );
+    } else {
+        print_table(&results);
+    }
+    let passed = results
+        .iter()
+        .all(|result| result.passed || !result.critical);
+    if let Some(path) = bug_report.filter(|_| results.iter().any(|result| !result.passed)) {
+        let attachment = serde_json::to_string_pretty(&results).unwrap_or_default();
+        let args: Vec<_> = env::args_os().collect();
+        match bug_report::write(Path::new(path), &args, None, &[("doctor.json", attachment)]) {
+            Ok(()) => println!("Wrote a bug report to {}.", path),
+            Err(e) => ui::error(&format!("Failed to write bug report {}: {}", path, e)),
+        }
+    }
+    if apply_suggestion {
+        for done in rate_limit::apply(&observed) {
+            // Keeps the JSON on stdout whole.
+            if json {
+                eprintln!("{}", done);
+            } else {
+                println!("{}", done);
+            }
+        }
+    }
+    passed
+}
+
+// How often the source's hosts limited the latest run that requested from
+// them, never critical. Sources no run has used yet aren't listed.
+fn check_rate_limits(source: &'static str, observed: &[Observed], results: &mut Vec<CheckResult>) {
+    let hosts: Vec<&Observed> = observed
+        .iter()
+        .filter(|observed| observed.source == source)
+        .collect();
+    if hosts.is_empty() {
+        return;
+    }
+    let hints: Vec<String> = hosts
+        .iter()
+        .filter_map(|observed| observed.hint())
+        .collect();
+    let worst = hosts
+        .iter()
+        .map(|observed| observed.limited_share())
+        .fold(0.0, f64::max);
+    results.push(CheckResult {
+        source,
+        check: "limits",
+        passed: hints.is_empty(),
+        critical: false,
+        detail: if hints.is_empty() {
+            format!(
+                "{} host(s), at most {:.1}% of requests limited",
+                hosts.len(),
+                worst * 100.0
+            )
+        } else {
+            hints.join("; ")
+        },
+    });
+}
+
+fn check_source(source: &dyn Source, results: &mut Vec<CheckResult>) {
+    let expected = source.health_check();
+    let mut record = |check: &'static str, critical: bool, outcome: Result<String, String>| {
+        let passed = outcome.is_ok();
+        results.push(CheckResult {
+            source: source.name(),
+            check,
+            passed,
+            critical,
+            detail: outcome.unwrap_or_else(|e| e),
+        });
+        passed
+    };
+
+    let host = Url::parse(expected.site_url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .unwrap_or_default();
+    let resolved = (host.as_str(), 443)
+        .to_socket_addrs()
+        .map(|addrs| format!("{} resolves to {} address(es)", host, addrs.count()))
+        .map_err(|e| format!("{}: {}", host, e));
+    if !record("dns", true, resolved) {
+        return;
+    }
+
+    let reachable = Client::new()
+        .get(expected.site_url)
+        .header("User-Agent", "Mozilla/5.0")
+        .send()
+        .map_err(|e| e.to_string())
+        .and_then(|response| match response.status() {
+            status if status.is_server_error() => Err(format!("HTTP {}", status)),
+            status => Ok(format!("HTTP {}", status)),
+        });
+    if !record("http", true, reachable) {
+        return;
+    }
+
+    let found = source
+        .search(expected.query)
+        .map_err(|e| e.to_string())
+        .and_then(|results| {
+            results
+                .into_iter()
+                .find(|result| {
+                    result
+                        .title
+                        .to_lowercase()
+                        .contains(&expected.expected_title.to_lowercase())
+                })
+                .ok_or_else(|| format!("no result titled \"{}\"", expected.expected_title))
+        });
+    let manga_url = found.as_ref().map(|result| result.url.clone()).ok();
+    if !record(
+        "search",
+        true,
+        found.map(|result| format!("found {}", result.url)),
+    ) {
+        return;
+    }
+
+    let manga = source
+        .manga(&manga_url.unwrap_or_default())
+        .map_err(|e| e.to_string())
+        .and_then(|manga| {
+            if manga.chapters.is_empty() {
+                Err("chapter list is empty".to_string())
+            } else {
+                Ok(manga)
+            }
+        });
+    let chapter = manga
+        .as_ref()
+        .ok()
+        .and_then(|manga| manga.chapters.last().cloned());
+    if !record(
+        "chapters",
+        true,
+        manga.map(|manga| format!("{} chapters", manga.chapters.len())),
+    ) {
+        return;
+    }
+
+    let chapter = chapter.unwrap();
+    let image = source
+        .pages(&chapter)
+        .map_err(|e| e.to_string())
+        .and_then(|pages| pages.into_iter().next().ok_or("no page images".to_string()))
+        .and_then(|url| {
+            let response = http::get(&url, &source.image_headers(&chapter), Kind::Image)
+                .map_err(|e| e.to_string())?;
+            response.check_status(&url).map_err(|e| e.to_string())?;
+            image::guess_format(&response.body)
+                .map(|format| format!("{:?} image from {}", format, url))
+                .map_err(|_| format!("{} did not return an image", url))
+        });
+    // Image hosts fail transiently more often than the site itself.
+    record("images", false, image);
+}
+
+fn print_table(results: &[CheckResult]) {
+    let source_width = results.iter().map(|r| r.source.len()).max().unwrap_or(0);
+    for result in results {
+        let status = match (result.passed, result.critical) {
+            (true, _) => "PASS",
+            (false, true) => "FAIL",
+            (false, false) => "WARN",
+        };
+        println!(
+            "{:source_width$}  {:8}  {}  {}",
+            result.source,
+            result.check,
+            status,
+            result.detail,
+            source_width = source_width
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use crate::report::format_bytes;
+use std::io;
+
+// Exit codes telling scripts what kind of failure stopped the run. clap
+// already exits with 2 for usage errors.
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_NETWORK: i32 = 3;
+pub const EXIT_HTTP: i32 = 4;
+pub const EXIT_PARSE: i32 = 5;
+pub const EXIT_FILESYSTEM: i32 = 6;
+pub const EXIT_TOOL: i32 = 7;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Network error fetching {url}: {source}. Check your connection and try again.")]
+    Network {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("HTTP {status} for {url}{}", http_hint(*.status))]
+    Http { url: String, status: u16 },
+    #[error("Couldn't find {what} on {url}; the site's layout may have changed.")]
+    Parse { url: String, what: &'static str },
+    #[error("{}", filesystem_message(.path, .source, *.needed))]
+    Filesystem {
+        path: String,
+        #[source]
+        source: io::Error,
+        // Bytes that were being written, if known.
+        needed: Option<u64>,
+    },
+    #[error(
+        "Not enough space for {path}: {} needed, {} free ({} short). Free up space, or pass --no-space-check if the numbers are wrong.",
+        format_bytes(*.needed),
+        format_bytes(*.available),
+        format_bytes(.needed - .available)
+    )]
+    NoSpace {
+        path: String,
+        needed: u64,
+        available: u64,
+    },
+    #[error(
+        "{name} declares a {width}x{height} image, over the decoding limit of {max_side} pixels a side or {max_pixels} pixels in total."
+    )]
+    TooLarge {
+        name: String,
+        width: u32,
+        height: u32,
+        max_side: u32,
+        max_pixels: u64,
+    },
+    #[error("Chapter {number} not found.")]
+    MissingChapter { number: String },
+    #[error("{url} is not in the offline fixtures.")]
+    NotInFixtures { url: String },
+    #[error("{tool} failed: {message}")]
+    Tool { tool: String, message: String },
+    #[error("{tool} is needed for {purpose} but wasn't found; {hint}.")]
+    MissingTool {
+        tool: String,
+        purpose: String,
+        hint: String,
+    },
+}
+
+impl Error {
+    pub fn network(url: &str, source: reqwest::Error) -> Error {
+        Error::Network {
+            url: url.to_string(),
+            // The URL is already part of the message.
+            source: source.without_url(),
+        }
+    }
+
+    pub fn filesystem(path: &str, source: io::Error) -> Error {
+        Error::Filesystem {
+            path: path.to_string(),
+            source,
+            needed: None,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Network { .. } | Error::NotInFixtures { .. } => EXIT_NETWORK,
+            Error::Http { .. } => EXIT_HTTP,
+            Error::Parse { .. } => EXIT_PARSE,
+            Error::Filesystem { .. } | Error::NoSpace { .. } => EXIT_FILESYSTEM,
+            Error::Tool { .. } | Error::MissingTool { .. } => EXIT_TOOL,
+            Error::TooLarge { .. } | Error::MissingChapter { .. } => EXIT_FAILURE,
+        }
+    }
+
+    // Failures that trying again won't fix: the site says the page is gone,
+    // or it never had it.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Error::Http { status, .. } => matches!(status, 404 | 410),
+            Error::Parse { .. } | Error::TooLarge { .. } | Error::MissingChapter { .. } => true,
+            _ => false,
+        }
+    }
+
+    // Connection failures and timeouts, as opposed to a site answering with
+    // something unexpected.
+    pub fn is_offline(&self) -> bool {
+        matches!(self, Error::Network { source, .. } if source.is_connect() || source.is_timeout())
+    }
+}
+
+fn http_hint(status: u16) -> &'static str {
+    match status {
+        404 | 410 => "; the page may have moved or been removed.",
+        401 | 403 => "; the site refused the request.",
+        429 => "; the site is rate limiting, try again later or with fewer --jobs.",
+        500..=599 => "; the site is having problems, try again later.",
+        _ => "",
+    }
+}
+
+fn filesystem_message(path: &str, source: &io::Error, needed: Option<u64>) -> String {
+    match (source.kind(), needed) {
+        (io::ErrorKind::StorageFull, Some(needed)) => format!(
+            "No space left writing {} ({} bytes needed). Free up space and try again.",
+            path, needed
+        ),
+        (io::ErrorKind::StorageFull, None) => format!(
+            "No space left writing {}. Free up space and try again.",
+            path
+        ),
+        (io::ErrorKind::PermissionDenied, _) => format!(
+            "Permission denied for {}. Check the directory's permissions.",
+            path
+        ),
+        _ => format!("Failed to write {}: {}", path, source),
+    }
+}
+
+// Wraps an error in a message of its own while keeping it as the source, so
+// the exit code still reflects what went wrong underneath.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct Context {
+    message: String,
+    #[source]
+    source: Box<dyn std::error::Error>,
+}
+
+pub fn context(message: String, source: Box<dyn std::error::Error>) -> Box<dyn std::error::Error> {
+    Box::new(Context { message, source })
+}
+
+// Name of the kind of error, for the usage records.
+pub fn class(error: &(dyn std::error::Error + 'static)) -> &'static str {
+    match exit_code(error) {
+        EXIT_NETWORK => "network",
+        EXIT_HTTP => "http",
+        EXIT_PARSE => "parse",
+        EXIT_FILESYSTEM => "filesystem",
+        EXIT_TOOL => "tool",
+        _ => "other",
+    }
+}
+
+// Whether the first classified error in the chain is permanent. Anything
+// unclassified may well work next time.
+pub fn is_permanent(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(error) = error.downcast_ref::<Error>() {
+            return error.is_permanent();
+        }
+        current = error.source();
+    }
+    false
+}
+
+// Finds the first classified error in the chain. Unclassified library errors
+// are sorted by type.
+pub fn exit_code(error: &(dyn std::error::Error + 'static)) -> i32 {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(error) = error.downcast_ref::<Error>() {
+            return error.exit_code();
+        }
+        if error.is::<reqwest::Error>() {
+            return EXIT_NETWORK;
+        }
+        if error.is::<io::Error>() {
+            return EXIT_FILESYSTEM;
+        }
+        current = error.source();
+    }
+    EXIT_FAILURE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type BoxError = Box<dyn std::error::Error>;
+
+    // A reqwest error without touching the network.
+    fn reqwest_error() -> reqwest::Error {
+        reqwest::blocking::get("not a url").unwrap_err()
+    }
+
+    fn io_error(kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, "simulated")
+    }
+
+    fn every_class() -> Vec<(Error, i32)> {
+        vec![
+            (
+                Error::network("https://a.test/", reqwest_error()),
+                EXIT_NETWORK,
+            ),
+            (
+                Error::NotInFixtures {
+                    url: "https://a.test/".to_string(),
+                },
+                EXIT_NETWORK,
+            ),
+            (
+                Error::Http {
+                    url: "https://a.test/".to_string(),
+                    status: 503,
+                },
+                EXIT_HTTP,
+            ),
+            (
+                Error::Parse {
+                    url: "https://a.test/".to_string(),
+                    what: "the chapter list",
+                },
+                EXIT_PARSE,
+            ),
+            (
+                Error::filesystem("out/a.cbz", io_error(io::ErrorKind::PermissionDenied)),
+                EXIT_FILESYSTEM,
+            ),
+            (
+                Error::NoSpace {
+                    path: "out/a.cbz".to_string(),
+                    needed: 2048,
+                    available: 1024,
+                },
+                EXIT_FILESYSTEM,
+            ),
+            (
+                Error::Tool {
+                    tool: "magick".to_string(),
+                    message: "exit status 1".to_string(),
+                },
+                EXIT_TOOL,
+            ),
+            (
+                Error::MissingTool {
+                    tool: "magick".to_string(),
+                    purpose: "PDF".to_string(),
+                    hint: "install ImageMagick".to_string(),
+                },
+                EXIT_TOOL,
+            ),
+            (
+                Error::TooLarge {
+                    name: "1.png".to_string(),
+                    width: 100_000,
+                    height: 10,
+                    max_side: 30_000,
+                    max_pixels: 200_000_000,
+                },
+                EXIT_FAILURE,
+            ),
+            (
+                Error::MissingChapter {
+                    number: "7".to_string(),
+                },
+                EXIT_FAILURE,
+            ),
+        ]
+    }
+
+    #[test]
+    fn every_class_has_its_exit_code() {
+        for (error, code) in every_class() {
+            let message = error.to_string();
+            assert_eq!(error.exit_code(), code, "{}", message);
+            let error: BoxError = Box::new(error);
+            assert_eq!(exit_code(error.as_ref()), code, "{}", message);
+        }
+    }
+
+    #[test]
+    fn exit_code_walks_the_context_chain() {
+        for (error, code) in every_class() {
+            let message = error.to_string();
+            let error = context("Chapter 3 failed".to_string(), Box::new(error));
+            let error = context("Downloading failed".to_string(), error);
+            assert_eq!(exit_code(error.as_ref()), code, "{}", message);
+        }
+        let error = context(
+            "Parsing failed".to_string(),
+            Box::new(Error::Parse {
+                url: "https://a.test/".to_string(),
+                what: "pages",
+            }),
+        );
+        assert_eq!(class(error.as_ref()), "parse");
+        assert!(is_permanent(error.as_ref()));
+    }
+
+    #[test]
+    fn library_errors_are_sorted_by_type() {
+        let error = context(
+            "Saving failed".to_string(),
+            Box::new(io_error(io::ErrorKind::StorageFull)),
+        );
+        assert_eq!(exit_code(error.as_ref()), EXIT_FILESYSTEM);
+        assert_eq!(class(error.as_ref()), "filesystem");
+
+        let error = context("Fetching failed".to_string(), Box::new(reqwest_error()));
+        assert_eq!(exit_code(error.as_ref()), EXIT_NETWORK);
+        // Unclassified failures may work next time.
+        assert!(!is_permanent(error.as_ref()));
+    }
+
+    #[test]
+    fn anything_else_is_a_plain_failure() {
+        let error: BoxError = "Nothing matched the search.".into();
+        assert_eq!(exit_code(error.as_ref()), EXIT_FAILURE);
+        assert_eq!(class(error.as_ref()), "other");
+        let error = context("Outer".to_string(), error);
+        assert_eq!(exit_code(error.as_ref()), EXIT_FAILURE);
+    }
+}
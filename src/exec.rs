@@ -0,0 +1,339 @@
+use crate::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long a tool may run unless the config file's tool_timeout says
+// otherwise. Generous, since upscalers can take minutes a page.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+// How often a running tool is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+// Sets how long tools may run for the rest of the run.
+pub fn set_timeout(timeout: Duration) {
+    let _ = TIMEOUT.set(timeout);
+}
+
+fn timeout() -> Duration {
+    TIMEOUT.get().copied().unwrap_or(DEFAULT_TIMEOUT)
+}
+
+// An external program with its arguments, each passed as one argument and
+// never through a shell, so a title with spaces, quotes or ";" in it stays
+// text. Every program manga-cli starts goes through here.
+pub struct Tool {
+    program: OsString,
+    args: Vec<OsString>,
+    dir: Option<PathBuf>,
+    // Added to the message when the program can't be started.
+    hint: Option<String>,
+}
+
+// What a finished tool printed.
+pub struct Output {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Tool {
+    pub fn new(program: impl AsRef<OsStr>) -> Tool {
+        Tool {
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            dir: None,
+            hint: None,
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Tool {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    pub fn args<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(mut self, args: I) -> Tool {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_os_string()));
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> Tool {
+        self.dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn hint(mut self, hint: &str) -> Tool {
+        self.hint = Some(hint.to_string());
+        self
+    }
+
+    fn name(&self) -> String {
+        self.program.to_string_lossy().into_owned()
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    fn start_error(&self, e: io::Error) -> Error {
+        Error::Tool {
+            tool: self.name(),
+            message: match &self.hint {
+                Some(hint) => format!("{}; {}", e, hint),
+                None => e.to_string(),
+            },
+        }
+    }
+
+    // Runs the tool and waits for it, killing it once it runs past the
+    // timeout. What it prints is captured and goes to the debug log; exiting
+    // non-zero fails with the last line of its stderr.
+    pub fn run(self) -> Result<Output, Error> {
+        log::debug!("Running {:?} {:?}", self.program, self.args);
+        let mut child = self
+            .command()
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| self.start_error(e))?;
+        // Read on their own threads, so a tool filling one pipe doesn't stall.
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+        let status = wait(&mut child, timeout());
+        let output = Output {
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        };
+        let name = self.name();
+        for (stream, data) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            for line in String::from_utf8_lossy(data).lines() {
+                log::debug!("{} {}: {}", name, stream, line);
+            }
+        }
+        let status = match status {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                return Err(Error::Tool {
+                    tool: name,
+                    message: format!(
+                        "killed after running for {}s; raise tool_timeout in the config file if it needs longer",
+                        timeout().as_secs()
+                    ),
+                })
+            }
+            Err(e) => {
+                return Err(Error::Tool {
+                    tool: name,
+                    message: e.to_string(),
+                })
+            }
+        };
+        if !status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let last = stderr.lines().rev().find(|line| !line.trim().is_empty());
+            return Err(Error::Tool {
+                tool: name,
+                message: match last {
+                    Some(line) => format!("exited with {}: {}", status, line.trim()),
+                    None => format!("exited with {}", status),
+                },
+            });
+        }
+        Ok(output)
+    }
+
+    // Starts the tool on this terminal without waiting for it, for programs
+    // the user works with, like viewers.
+    pub fn spawn(self) -> Result<Child, Error> {
+        log::debug!("Starting {:?} {:?}", self.program, self.args);
+        self.command().spawn().map_err(|e| self.start_error(e))
+    }
+
+    // Starts the tool in the background with its output going to `log`.
+    pub fn spawn_logged(self, log: File) -> Result<Child, Error> {
+        log::debug!(
+            "Starting {:?} {:?} in the background",
+            self.program,
+            self.args
+        );
+        let stdout = log.try_clone().map_err(|e| self.start_error(e))?;
+        self.command()
+            .stdin(Stdio::null())
+            .stdout(stdout)
+            .stderr(log)
+            .spawn()
+            .map_err(|e| self.start_error(e))
+    }
+}
+
+fn drain<R: Read + Send + 'static>(stream: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        if let Some(mut stream) = stream {
+            let _ = stream.read_to_end(&mut data);
+        }
+        data
+    })
+}
+
+// The child's exit status, or None when it was killed for running past
+// `timeout`.
+fn wait(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// A path as an argument: a relative one starting with "-" gets "./" in front,
+// so the program can't take it for an option.
+pub fn path_arg(path: &str) -> String {
+    if path.starts_with('-') {
+        format!("./{}", path)
+    } else {
+        path.to_string()
+    }
+}
+
+// `word` with each of the `substitutions`' keys replaced by its value in one
+// pass, so a value holding another key, like a title with "{output}" in it,
+// is left as it is.
+pub fn fill(word: &str, substitutions: &[(&str, &str)]) -> String {
+    let mut filled = String::new();
+    let mut rest = word;
+    while !rest.is_empty() {
+        let next = substitutions
+            .iter()
+            .filter_map(|(key, value)| rest.find(key).map(|at| (at, *key, *value)))
+            .min_by_key(|(at, key, _)| (*at, usize::MAX - key.len()));
+        let Some((at, key, value)) = next else {
+            filled.push_str(rest);
+            break;
+        };
+        filled.push_str(&rest[..at]);
+        filled.push_str(value);
+        rest = &rest[at + key.len()..];
+    }
+    filled
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+    use std::fs;
+
+    const HOSTILE: &[&str] = &[
+        "--help; rm -rf .",
+        "-rf",
+        "$(touch injected)",
+        "`touch injected`",
+        "a && touch injected",
+        "quotes \" ' and \\ backslash",
+        "{output}",
+        "",
+    ];
+
+    // The arguments `tool` received, as it printed them back through
+    // printf.
+    fn received(output: Output) -> Vec<String> {
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut args: Vec<String> = stdout.split('\0').map(str::to_string).collect();
+        assert_eq!(args.pop().as_deref(), Some(""));
+        args
+    }
+
+    fn echo_args(dir: &TestDir) -> Tool {
+        Tool::new("printf").arg("%s\\0").current_dir(&dir.path)
+    }
+
+    #[test]
+    fn hostile_titles_arrive_as_one_literal_argument_each() {
+        let dir = TestDir::new("exec-hostile");
+        let output = echo_args(&dir).args(HOSTILE).run().unwrap();
+        assert_eq!(received(output), HOSTILE);
+        assert!(!dir.join("injected").exists());
+    }
+
+    #[test]
+    fn path_arg_keeps_a_dash_title_from_being_read_as_an_option() {
+        let dir = TestDir::new("exec-path-arg");
+        let name = "--help; rm -rf .";
+        fs::write(dir.join(name), b"page").unwrap();
+        assert_eq!(path_arg(name), "./--help; rm -rf .");
+        assert_eq!(path_arg("/tmp/-x.cbz"), "/tmp/-x.cbz");
+        let output = Tool::new("cat")
+            .arg(path_arg(name))
+            .current_dir(&dir.path)
+            .run()
+            .unwrap();
+        assert_eq!(output.stdout, b"page");
+        assert!(dir.join(name).exists());
+    }
+
+    #[test]
+    fn filled_templates_keep_each_word_one_argument() {
+        let dir = TestDir::new("exec-fill");
+        let words = shlex::split("upscale -i {input} -o {output} --name={input}").unwrap();
+        for title in HOSTILE {
+            let input = format!("{}/{}.png", dir.str(), title);
+            let substitutions = [("{input}", input.as_str()), ("{output}", "out.png")];
+            let args: Vec<String> = words
+                .iter()
+                .map(|word| fill(word, &substitutions))
+                .collect();
+            let output = echo_args(&dir).args(&args).run().unwrap();
+            assert_eq!(
+                received(output),
+                [
+                    "upscale".to_string(),
+                    "-i".to_string(),
+                    input.clone(),
+                    "-o".to_string(),
+                    "out.png".to_string(),
+                    format!("--name={}", input),
+                ]
+            );
+        }
+        assert!(!dir.join("injected").exists());
+    }
+
+    #[test]
+    fn values_holding_a_key_are_not_filled_again() {
+        let filled = fill(
+            "{input}:{output}",
+            &[("{input}", "{output}"), ("{output}", "x")],
+        );
+        assert_eq!(filled, "{output}:x");
+    }
+
+    #[test]
+    fn tools_running_past_the_timeout_are_killed() {
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let started = Instant::now();
+        assert!(wait(&mut child, Duration::from_millis(100))
+            .unwrap()
+            .is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,243 @@
+use image::DynamicImage;
+
+const ORIENTATION_TAG: u16 = 0x0112;
+const SHORT: u16 = 3;
+
+// The EXIF orientation of a JPEG or PNG, when it asks for the pixels to be
+// turned or mirrored (2-8). The image crate ignores the tag, and so do many
+// readers, so phone-photographed scans come out sideways.
+pub fn orientation(data: &[u8]) -> Option<u16> {
+    let tiff = jpeg_exif(data).or_else(|| png_exif(data))?;
+    tiff_orientation(tiff).filter(|orientation| (2..=8).contains(orientation))
+}
+
+// Turns and mirrors `img` the way `orientation` says it should be shown.
+pub fn apply(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        // Mirrored along the diagonal.
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// The TIFF structure of a JPEG's "Exif" APP1 segment.
+fn jpeg_exif(data: &[u8]) -> Option<&[u8]> {
+    let mut rest = data.strip_prefix(&[0xFF, 0xD8])?;
+    while rest.len() >= 4 && rest[0] == 0xFF {
+        let marker = rest[1];
+        // Start of scan or end of image: no more metadata.
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        let length = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        let segment = rest.get(4..2 + length)?;
+        if marker == 0xE1 {
+            if let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+                return Some(tiff);
+            }
+        }
+        rest = &rest[2 + length..];
+    }
+    None
+}
+
+// The TIFF structure of a PNG's eXIf chunk.
+fn png_exif(data: &[u8]) -> Option<&[u8]> {
+    let mut rest = data.strip_prefix(b"\x89PNG\r\n\x1a\n")?;
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let kind = &rest[4..8];
+        // The chunk has to come before the image data.
+        if kind == b"IDAT" {
+            return None;
+        }
+        let chunk = rest.get(8..8 + length)?;
+        if kind == b"eXIf" {
+            return Some(chunk);
+        }
+        rest = rest.get(12 + length..)?;
+    }
+    None
+}
+
+// The orientation entry of the first image file directory.
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |offset: usize| {
+        let bytes: [u8; 4] = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+
+    if u16_at(2)? != 42 {
+        return None;
+    }
+    let directory = u32_at(4)? as usize;
+    let entries = u16_at(directory)? as usize;
+    (0..entries)
+        .map(|i| directory + 2 + i * 12)
+        .find(|&entry| u16_at(entry) == Some(ORIENTATION_TAG))
+        .filter(|&entry| u16_at(entry + 2) == Some(SHORT))
+        .and_then(|entry| u16_at(entry + 8))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{GenericImageView, Rgb, RgbImage};
+
+    // A TIFF directory with just the orientation.
+    fn tiff(orientation: u16, big_endian: bool) -> Vec<u8> {
+        let u16_bytes = |value: u16| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut tiff = if big_endian {
+            b"MM".to_vec()
+        } else {
+            b"II".to_vec()
+        };
+        tiff.extend(u16_bytes(42));
+        tiff.extend(u32_bytes(8));
+        tiff.extend(u16_bytes(1));
+        tiff.extend(u16_bytes(ORIENTATION_TAG));
+        tiff.extend(u16_bytes(SHORT));
+        tiff.extend(u32_bytes(1));
+        tiff.extend(u16_bytes(orientation));
+        tiff.extend([0, 0]);
+        // No next directory.
+        tiff.extend([0; 4]);
+        tiff
+    }
+
+    // `img` as a JPEG whose EXIF says `orientation`, as a phone writes it.
+    pub fn tagged_jpeg(img: &RgbImage, orientation: u16, big_endian: bool) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 95)
+            .encode_image(&DynamicImage::ImageRgb8(img.clone()))
+            .unwrap();
+        let mut segment = b"Exif\0\0".to_vec();
+        segment.extend(tiff(orientation, big_endian));
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend((segment.len() as u16 + 2).to_be_bytes());
+        app1.extend(segment);
+        jpeg.splice(2..2, app1);
+        jpeg
+    }
+
+    #[test]
+    fn orientation_is_read_from_jpegs_either_byte_order() {
+        let img = RgbImage::new(8, 8);
+        for orientation in 1..=8 {
+            for big_endian in [true, false] {
+                let jpeg = tagged_jpeg(&img, orientation, big_endian);
+                let expected = (orientation > 1).then_some(orientation);
+                assert_eq!(super::orientation(&jpeg), expected, "{}", orientation);
+                // Still a JPEG to the decoder.
+                assert!(image::load_from_memory(&jpeg).is_ok());
+            }
+        }
+        // Out of range, missing or cut short.
+        assert_eq!(super::orientation(&tagged_jpeg(&img, 9, true)), None);
+        let mut untagged = Vec::new();
+        JpegEncoder::new(&mut untagged)
+            .encode_image(&DynamicImage::ImageRgb8(img.clone()))
+            .unwrap();
+        assert_eq!(super::orientation(&untagged), None);
+        let jpeg = tagged_jpeg(&img, 6, true);
+        assert_eq!(super::orientation(&jpeg[..20]), None);
+        assert_eq!(super::orientation(b"not an image"), None);
+    }
+
+    #[test]
+    fn orientation_is_read_from_png_exif_chunks() {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let data = tiff(8, false);
+        png.extend((data.len() as u32).to_be_bytes());
+        png.extend(b"eXIf");
+        png.extend(&data);
+        // CRCs aren't checked.
+        png.extend([0; 4]);
+        let iend = [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0, 0, 0, 0];
+        png.extend(iend);
+        assert_eq!(super::orientation(&png), Some(8));
+        // Only a chunk before the image data counts.
+        let mut late = b"\x89PNG\r\n\x1a\n".to_vec();
+        late.extend([0, 0, 0, 0, b'I', b'D', b'A', b'T', 0, 0, 0, 0]);
+        late.extend(&png[8..]);
+        assert_eq!(super::orientation(&late), None);
+    }
+
+    #[test]
+    fn every_orientation_comes_out_upright() {
+        // Each pixel of a 3x2 image is its own colour.
+        let (width, height) = (3u32, 2u32);
+        let stored = RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 0]));
+        // Where the stored pixel (x, y) is shown, per the EXIF specification.
+        let (w, h) = (width - 1, height - 1);
+        let shown = |orientation: u16, x: u32, y: u32| match orientation {
+            1 => (x, y),
+            2 => (w - x, y),
+            3 => (w - x, h - y),
+            4 => (x, h - y),
+            5 => (y, x),
+            6 => (h - y, x),
+            7 => (h - y, w - x),
+            8 => (y, w - x),
+            _ => unreachable!(),
+        };
+        for orientation in 1..=8 {
+            let upright = apply(DynamicImage::ImageRgb8(stored.clone()), orientation);
+            let turned = orientation >= 5;
+            let size = if turned {
+                (height, width)
+            } else {
+                (width, height)
+            };
+            assert_eq!(upright.dimensions(), size, "{}", orientation);
+            for (x, y, pixel) in stored.enumerate_pixels() {
+                let (shown_x, shown_y) = shown(orientation, x, y);
+                assert_eq!(
+                    upright.to_rgb8().get_pixel(shown_x, shown_y),
+                    pixel,
+                    "orientation {} pixel {},{}",
+                    orientation,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use sha2::{Digest, Sha256};
+use std::path::{self, Path};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static TRANSLITERATE: AtomicBool = AtomicBool::new(false);
+
+// Longest absolute path names are fitted into (max_path_length in
+// config.toml): MAX_PATH on Windows, PATH_MAX elsewhere.
+const DEFAULT_MAX_PATH: usize = if cfg!(windows) { 260 } else { 4096 };
+static MAX_PATH: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_PATH);
+// Longest file or folder name most filesystems take, in bytes.
+const MAX_NAME: usize = 255;
+// A shortened detail keeps at least this much, or is dropped.
+const MIN_DETAIL: usize = 12;
+// Hex digits of the title's hash appended to a shortened title.
+const HASH_DIGITS: usize = 8;
+
+// Makes sanitize() spell names in ASCII for the rest of the run
+// (--transliterate-filenames), for filesystems and devices that mangle UTF-8.
+pub fn set_transliterate(transliterate: bool) {
+    TRANSLITERATE.store(transliterate, Ordering::Relaxed);
+}
+
+pub fn set_max_path(max: usize) {
+    MAX_PATH.store(max, Ordering::Relaxed);
+}
+
+pub fn max_path() -> usize {
+    MAX_PATH.load(Ordering::Relaxed)
+}
+
+// A file or folder name from a title. Characters some filesystems refuse
+// become '_'; everything else, CJK included, is kept as UTF-8 unless
+// transliteration was asked for.
+pub fn sanitize(name: &str) -> String {
+    let name = if TRANSLITERATE.load(Ordering::Relaxed) {
+        deunicode::deunicode(name)
+    } else {
+        name.to_string()
+    };
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+// The pieces of a name, by how much they matter. Joined as `title`, `number`,
+// then `separator` and `detail` when there is a detail.
+pub struct NameParts<'a> {
+    // Shortened last, then ends in a hash of the whole title so different
+    // titles sharing a start still get different names.
+    pub title: &'a str,
+    // Never shortened, like " c12" or " v03".
+    pub number: &'a str,
+    pub separator: &'a str,
+    // Shortened first, like a chapter title; dropped when too little of it
+    // would be left.
+    pub detail: Option<&'a str>,
+}
+
+impl NameParts<'_> {
+    pub fn full(&self) -> String {
+        self.join(self.detail)
+    }
+
+    fn join(&self, detail: Option<&str>) -> String {
+        match detail {
+            Some(detail) => format!("{}{}{}{}", self.title, self.number, self.separator, detail),
+            None => format!("{}{}", self.title, self.number),
+        }
+    }
+}
+
+// The name `parts` make, shortened when needed so that a file or folder of
+// that name in each of `dirs`, plus `reserve` bytes for an extension or the
+// files inside it, stays within the path limit. The second value tells
+// whether it was shortened.
+pub fn fit(dirs: &[&Path], parts: &NameParts, reserve: usize) -> (String, bool) {
+    let room = dirs
+        .iter()
+        .map(|dir| room(dir, reserve))
+        .min()
+        .unwrap_or(MAX_NAME);
+    let full = parts.full();
+    if full.len() <= room {
+        return (full, false);
+    }
+
+    if let Some(detail) = parts.detail {
+        let kept = parts.title.len() + parts.number.len() + parts.separator.len();
+        if room >= kept + MIN_DETAIL {
+            let detail = truncate(detail, room - kept).trim_end();
+            return (parts.join(Some(detail)), true);
+        }
+        let without_detail = parts.join(None);
+        if without_detail.len() <= room {
+            return (without_detail, true);
+        }
+    }
+
+    let hash = format!(
+        "~{}",
+        &format!("{:x}", Sha256::digest(parts.title))[..HASH_DIGITS]
+    );
+    let title_room = room.saturating_sub(parts.number.len() + hash.len());
+    let title = truncate(parts.title, title_room).trim_end();
+    (format!("{}{}{}", title, hash, parts.number), true)
+}
+
+// Bytes left for a name in `dir`.
+fn room(dir: &Path, reserve: usize) -> usize {
+    let dir_length = path::absolute(dir)
+        .map(|dir| dir.as_os_str().len())
+        .unwrap_or_else(|_| dir.as_os_str().len());
+    let max_path = max_path();
+    // One more for the separator before the name.
+    max_path
+        .saturating_sub(dir_length + 1 + reserve)
+        .min(MAX_NAME.saturating_sub(reserve))
+}
+
+// The longest start of `text` within `max` bytes that ends on a character
+// boundary.
+fn truncate(text: &str, max: usize) -> &str {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Leaves `room` bytes for the name in a short directory.
+    fn fit_in(room: usize, parts: &NameParts) -> (String, bool) {
+        fit(&[Path::new("/out")], parts, MAX_NAME - room)
+    }
+
+    fn parts<'a>(title: &'a str, detail: Option<&'a str>) -> NameParts<'a> {
+        NameParts {
+            title,
+            number: " c12",
+            separator: " - ",
+            detail,
+        }
+    }
+
+    #[test]
+    fn names_that_fit_are_kept() {
+        let name = parts("Series", Some("The Beginning"));
+        assert_eq!(
+            fit_in(100, &name),
+            ("Series c12 - The Beginning".to_string(), false)
+        );
+    }
+
+    #[test]
+    fn chapter_title_is_shortened_first() {
+        let detail = "A Very Long Chapter Title That Goes On";
+        let (name, shortened) = fit_in(40, &parts("Series", Some(detail)));
+        assert!(shortened);
+        assert_eq!(name, "Series c12 - A Very Long Chapter Title T");
+        assert!(name.len() <= 40);
+    }
+
+    #[test]
+    fn chapter_title_is_dropped_before_the_series_title_is_touched() {
+        // Fewer than MIN_DETAIL bytes of the chapter title would be left.
+        let (name, shortened) = fit_in(20, &parts("Series", Some("A Very Long Chapter Title")));
+        assert!(shortened);
+        assert_eq!(name, "Series c12");
+    }
+
+    #[test]
+    fn series_title_is_shortened_last_and_keeps_the_number() {
+        let title = "An Extremely Long Official Series Title That Never Seems To End";
+        let (name, shortened) = fit_in(40, &parts(title, Some("Chapter Title")));
+        assert!(shortened);
+        assert!(name.len() <= 40, "{}", name);
+        assert!(name.starts_with("An Extremely Long"), "{}", name);
+        let hash = &format!("{:x}", Sha256::digest(title))[..HASH_DIGITS];
+        assert!(name.ends_with(&format!("~{} c12", hash)), "{}", name);
+    }
+
+    #[test]
+    fn shortened_titles_sharing_a_start_stay_apart() {
+        let start = "The Same Long Beginning Shared By Several Series Titles, ".repeat(3);
+        let names: Vec<String> = ["Part One", "Part Two", "Part Three"]
+            .iter()
+            .map(|end| fit_in(60, &parts(&format!("{}{}", start, end), None)).0)
+            .collect();
+        assert!(names.iter().all(|name| name.len() <= 60));
+        assert_ne!(names[0], names[1]);
+        assert_ne!(names[1], names[2]);
+        assert_ne!(names[0], names[2]);
+        // The same title always gets the same name.
+        let again = fit_in(60, &parts(&format!("{}Part One", start), None)).0;
+        assert_eq!(again, names[0]);
+    }
+
+    #[test]
+    fn shortening_keeps_characters_whole() {
+        let title = "進撃の巨人".repeat(10);
+        let (name, shortened) = fit_in(40, &parts(&title, None));
+        assert!(shortened);
+        assert!(name.len() <= 40);
+        assert!(name.starts_with("進撃の"));
+    }
+
+    #[test]
+    fn the_deepest_directory_decides() {
+        let deep = format!("/{}", "folder/".repeat(580));
+        let room = max_path() - path::absolute(&deep).unwrap().as_os_str().len() - 1;
+        let title = "T".repeat(room + 10);
+        let (name, shortened) = fit(
+            &[Path::new("/out"), Path::new(&deep)],
+            &parts(&title, None),
+            0,
+        );
+        assert!(shortened);
+        assert!(name.len() <= room);
+        let (name, shortened) = fit(&[Path::new("/out")], &parts("Short", None), 0);
+        assert_eq!((name.as_str(), shortened), ("Short c12", false));
+    }
+}
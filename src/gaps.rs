@@ -0,0 +1,295 @@
+use crate::chapter_id::ChapterId;
+use crate::library;
+use crate::manifest::Manifest;
+use crate::report::format_bytes;
+use crate::source::Chapter;
+use crate::ui;
+use regex::{Captures, Regex};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Files holding chapters, whichever tool wrote them.
+const ARCHIVE_EXTENSIONS: &[&str] = &["cbz", "cbr", "cb7", "zip", "pdf", "epub"];
+
+// What a file's name says it holds.
+enum Holds {
+    // The chapters from the first to the last number.
+    Chapters(ChapterId, ChapterId),
+    Volume(String),
+}
+
+pub struct Gaps {
+    // Numbered chapters the source lists, one per number.
+    pub listed: usize,
+    // Those with neither a file nor a cached copy, in reading order.
+    pub missing: Vec<ChapterId>,
+    // The same as runs of chapters next to each other in the list, from the
+    // first to the last.
+    pub spans: Vec<(ChapterId, ChapterId)>,
+    // Chapters named by files on disk that the source doesn't list.
+    pub local_only: Vec<ChapterId>,
+    // Chapters the source lists without a number, which can't be compared.
+    pub unnumbered: usize,
+    // Average file size per chapter on disk, to estimate the missing ones.
+    pub chapter_bytes: Option<u64>,
+}
+
+// Compares the chapters of the series `title` the source lists, every
+// version of them, with the files in `library_dir` and the chapter folders in
+// the series' cache folder `cache_dir`. Files count when they sit in a folder
+// named after the series or their name starts with its title; their names
+// give the chapters they hold, like "c12", "c12.5", "c12 extra", "c1-5",
+// "v03" or a bare number at the end. Cached chapters count by the URLs their
+// manifests record.
+pub fn find(chapters: &[Chapter], title: &str, library_dir: &Path, cache_dir: &Path) -> Gaps {
+    let mut numbers: Vec<ChapterId> = chapters
+        .iter()
+        .filter_map(|chapter| chapter.number.clone())
+        .collect();
+    numbers.sort();
+    numbers.dedup();
+    let mut held = vec![false; numbers.len()];
+    let index = |number: &ChapterId| numbers.binary_search(number).ok();
+
+    let mut local_only = Vec::new();
+    let mut bytes = 0;
+    let mut counted = 0;
+    for (holds, size) in files(library_dir, title) {
+        let covered: Vec<usize> = match &holds {
+            Holds::Chapters(first, last) => (0..numbers.len())
+                .filter(|&i| *first <= numbers[i] && numbers[i] <= *last)
+                .collect(),
+            Holds::Volume(volume) => chapters
+                .iter()
+                .filter(|chapter| {
+                    chapter
+                        .volume
+                        .as_deref()
+                        .is_some_and(|v| same_volume(v, volume))
+                })
+                .filter_map(|chapter| chapter.number.as_ref().and_then(index))
+                .collect(),
+        };
+        if let Holds::Chapters(first, last) = holds {
+            for end in [first, last] {
+                if index(&end).is_none() && !local_only.contains(&end) {
+                    local_only.push(end);
+                }
+            }
+        }
+        if covered.is_empty() {
+            continue;
+        }
+        bytes += size;
+        counted += covered.len() as u64;
+        for i in covered {
+            held[i] = true;
+        }
+    }
+    local_only.sort();
+
+    for manifest in cached(cache_dir) {
+        let numbers = chapters
+            .iter()
+            .filter(|chapter| manifest.chapter_urls.contains(&chapter.url))
+            .filter_map(|chapter| chapter.number.as_ref().and_then(index));
+        for i in numbers {
+            held[i] = true;
+        }
+    }
+
+    let mut missing = Vec::new();
+    let mut spans: Vec<(ChapterId, ChapterId)> = Vec::new();
+    for (i, number) in numbers.iter().enumerate() {
+        if held[i] {
+            continue;
+        }
+        missing.push(number.clone());
+        match spans.last_mut() {
+            Some((_, last)) if i > 0 && !held[i - 1] => *last = number.clone(),
+            _ => spans.push((number.clone(), number.clone())),
+        }
+    }
+
+    Gaps {
+        listed: numbers.len(),
+        missing,
+        spans,
+        local_only,
+        unnumbered: chapters
+            .iter()
+            .filter(|chapter| chapter.number.is_none())
+            .count(),
+        chapter_bytes: (counted > 0).then(|| bytes / counted),
+    }
+}
+
+pub fn print(title: &str, gaps: &Gaps) {
+    println!(
+        "{}: {} of {} chapters on disk",
+        ui::bold(title),
+        gaps.listed - gaps.missing.len(),
+        gaps.listed
+    );
+    if gaps.missing.is_empty() {
+        println!("{}", ui::success("Nothing missing."));
+    } else {
+        let spans: Vec<String> = gaps
+            .spans
+            .iter()
+            .map(|(first, last)| {
+                if first == last {
+                    format!("c{}", first)
+                } else {
+                    format!("c{}-{}", first, last)
+                }
+            })
+            .collect();
+        println!(
+            "Missing ({}): {}",
+            gaps.missing.len(),
+            ui::caution(&spans.join(", "))
+        );
+        match gaps.chapter_bytes {
+            Some(bytes) => println!(
+                "  about {}, going by the chapters on disk",
+                format_bytes(bytes * gaps.missing.len() as u64)
+            ),
+            None => println!("  size unknown; no chapter files to go by"),
+        }
+    }
+    if !gaps.local_only.is_empty() {
+        let numbers: Vec<String> = gaps
+            .local_only
+            .iter()
+            .map(|number| format!("c{}", number))
+            .collect();
+        println!(
+            "On disk but not on the source ({}): {}",
+            gaps.local_only.len(),
+            numbers.join(", ")
+        );
+    }
+    if gaps.unnumbered > 0 {
+        println!(
+            "{}",
+            ui::dim(&format!(
+                "{} chapter(s) without a number weren't compared.",
+                gaps.unnumbered
+            ))
+        );
+    }
+}
+
+// The series' chapter files in `library_dir` with their sizes.
+fn files(library_dir: &Path, title: &str) -> Vec<(Holds, u64)> {
+    let key = library::normalize(title);
+    let own = |path: &Path| {
+        path.file_name()
+            .is_some_and(|name| library::normalize(&name.to_string_lossy()) == key)
+    };
+    let mut dirs = vec![(library_dir.to_path_buf(), own(library_dir))];
+    dirs.extend(
+        fs::read_dir(library_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir() && own(path))
+            .map(|path| (path, true)),
+    );
+    let mut files = Vec::new();
+    for (dir, own) in dirs {
+        for path in archives(&dir) {
+            let stem = path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            let Some((prefix, holds)) = parse_name(&stem) else {
+                continue;
+            };
+            if own || library::normalize(prefix) == key {
+                let size = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+                files.push((holds, size));
+            }
+        }
+    }
+    files
+}
+
+fn archives(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path.extension().is_some_and(|extension| {
+                    let extension = extension.to_string_lossy().to_lowercase();
+                    ARCHIVE_EXTENSIONS.contains(&extension.as_str())
+                })
+        })
+        .collect()
+}
+
+// The title part of a file name and the chapters the rest names.
+fn parse_name(stem: &str) -> Option<(&str, Holds)> {
+    static CHAPTERS: OnceLock<Regex> = OnceLock::new();
+    static VOLUME: OnceLock<Regex> = OnceLock::new();
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    let chapters = CHAPTERS.get_or_init(|| {
+        Regex::new(
+            r"(?i)(?:^|[^a-z])(?:c|ch|chap|chapter)[ ._]*(\d+(?:\.\d+)?(?: ?(?:extra|omake|special|bonus))?)(?:-(\d+(?:\.\d+)?))?(?:$|[^a-z\d.])",
+        )
+        .unwrap()
+    });
+    let volume = VOLUME.get_or_init(|| {
+        Regex::new(r"(?i)(?:^|[^a-z])(?:v|vol|volume)[ ._]*(\d+)(?:$|[^a-z\d])").unwrap()
+    });
+    let number = NUMBER.get_or_init(|| Regex::new(r"(\d+(?:\.\d+)?)\s*$").unwrap());
+    let start = |captures: &Captures| captures.get(0).map_or(0, |found| found.start());
+
+    // A title can hold a "c" and digits too, so the last match counts.
+    if let Some(captures) = chapters.captures_iter(stem).last() {
+        let first: ChapterId = captures[1].parse().ok()?;
+        let last = match captures.get(2) {
+            Some(last) => last.as_str().parse().ok().filter(|last| *last >= first),
+            None => None,
+        };
+        let last = last.unwrap_or_else(|| first.clone());
+        return Some((&stem[..start(&captures)], Holds::Chapters(first, last)));
+    }
+    if let Some(captures) = volume.captures_iter(stem).last() {
+        return Some((
+            &stem[..start(&captures)],
+            Holds::Volume(captures[1].to_string()),
+        ));
+    }
+    let captures = number.captures(stem)?;
+    let number: ChapterId = captures[1].parse().ok()?;
+    Some((
+        &stem[..start(&captures)],
+        Holds::Chapters(number.clone(), number),
+    ))
+}
+
+fn same_volume(a: &str, b: &str) -> bool {
+    a.trim()
+        .trim_start_matches('0')
+        .eq_ignore_ascii_case(b.trim().trim_start_matches('0'))
+}
+
+// Manifests of the chapters in a series' cache folder.
+fn cached(cache_dir: &Path) -> Vec<Manifest> {
+    fs::read_dir(cache_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .filter_map(|path| Manifest::load(&path.to_string_lossy()))
+        .collect()
+}
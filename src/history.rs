@@ -0,0 +1,139 @@
+use crate::chapter_id::ChapterId;
+use crate::journal::{self, Store};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+// Chapters that were read, recorded when the viewer they were handed to
+// exits. Kept as a journal::Store, so a download marking chapters read while
+// `watch` runs loses neither's.
+#[derive(Serialize, Deserialize, Default)]
+pub struct HistoryStore {
+    // By manga URL.
+    series: BTreeMap<String, SeriesHistory>,
+    // The series as loaded or last saved, to tell what changed.
+    #[serde(skip)]
+    saved: BTreeMap<String, SeriesHistory>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+struct SeriesHistory {
+    title: String,
+    // By chapter number: when it was read, RFC 3339.
+    read: BTreeMap<String, String>,
+}
+
+// A series' history as one save left it.
+#[derive(Serialize, Deserialize)]
+pub struct HistoryChange {
+    url: String,
+    #[serde(flatten)]
+    history: SeriesHistory,
+}
+
+impl Store for HistoryStore {
+    const NAME: &'static str = "history";
+    type Change = HistoryChange;
+
+    // Reads only add up: a chapter read in either is read, at the later time.
+    fn apply(&mut self, change: HistoryChange) {
+        let ours = self.series.entry(change.url).or_default();
+        if !change.history.title.is_empty() {
+            ours.title = change.history.title;
+        }
+        for (number, read) in change.history.read {
+            if ours
+                .read
+                .get(&number)
+                .is_none_or(|ours| is_later(&read, ours))
+            {
+                ours.read.insert(number, read);
+            }
+        }
+    }
+}
+
+impl HistoryStore {
+    pub fn load() -> HistoryStore {
+        let mut store: HistoryStore = journal::load();
+        store.saved = store.series.clone();
+        store
+    }
+
+    // Saves the series changed since loading.
+    pub fn save(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let changes = self
+            .series
+            .iter()
+            .filter(|(url, history)| self.saved.get(*url) != Some(history))
+            .map(|(url, history)| HistoryChange {
+                url: url.clone(),
+                history: history.clone(),
+            })
+            .collect();
+        journal::save::<HistoryStore>(changes)?;
+        self.saved = self.series.clone();
+        Ok(())
+    }
+
+    pub fn mark_read(&mut self, manga_url: &str, title: &str, numbers: &[ChapterId]) {
+        let now = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+        let series = self.series.entry(manga_url.to_string()).or_default();
+        series.title = title.to_string();
+        for number in numbers {
+            series.read.insert(number.to_string(), now.clone());
+        }
+    }
+
+    // Adds the chapters read according to `other`, an imported store. When
+    // both have read a chapter, the later time is kept. Returns how many
+    // chapters were new or newer.
+    pub fn merge(&mut self, other: HistoryStore) -> usize {
+        let mut merged = 0;
+        for (url, theirs) in other.series {
+            let ours = self.series.entry(url).or_default();
+            if ours.title.is_empty() {
+                ours.title = theirs.title;
+            }
+            for (number, read) in theirs.read {
+                if ours
+                    .read
+                    .get(&number)
+                    .is_none_or(|ours| is_later(&read, ours))
+                {
+                    ours.read.insert(number, read);
+                    merged += 1;
+                }
+            }
+        }
+        merged
+    }
+
+    // The chapters of a series that were read, in reading order.
+    pub fn read(&self, manga_url: &str) -> Vec<ChapterId> {
+        let mut read: Vec<ChapterId> = self
+            .series
+            .get(manga_url)
+            .into_iter()
+            .flat_map(|series| series.read.keys())
+            .filter_map(|number| number.parse().ok())
+            .collect();
+        read.sort();
+        read
+    }
+}
+
+// Whether RFC 3339 time `a` is after `b`; one that can't be read never is.
+pub fn is_later(a: &str, b: &str) -> bool {
+    match (
+        OffsetDateTime::parse(a, &Rfc3339),
+        OffsetDateTime::parse(b, &Rfc3339),
+    ) {
+        (Ok(a), Ok(b)) => a > b,
+        (Ok(_), Err(_)) => true,
+        _ => false,
+    }
+}
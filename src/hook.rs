@@ -0,0 +1,52 @@
+use crate::error::Error;
+use crate::exec::Tool;
+use crate::template::Template;
+use clap::ArgEnum;
+
+const FIELDS: &[&str] = &["file", "series", "chapter", "format"];
+
+// --post-cmd, split into words before the placeholders are filled in, so a
+// file name with spaces stays one argument.
+#[derive(Clone)]
+pub struct PostCommand {
+    words: Vec<Template>,
+}
+
+// What a post command exiting non-zero does to the run.
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+pub enum PostCommandFailures {
+    Abort,
+    Warn,
+}
+
+pub fn parse_post_command(value: &str) -> Result<PostCommand, String> {
+    let words = shlex::split(value).ok_or(format!("unbalanced quotes in \"{}\"", value))?;
+    if words.is_empty() {
+        return Err("the command is empty".to_string());
+    }
+    let words = words
+        .iter()
+        .map(|word| Template::parse(word, FIELDS))
+        .collect::<Result<_, _>>()?;
+    Ok(PostCommand { words })
+}
+
+impl PostCommand {
+    // Runs the command with `values` for the placeholders and waits for it.
+    // Each word stays one argument whatever the values hold. What it prints
+    // goes to the debug log.
+    pub fn run(&self, values: &[(&str, &str)]) -> Result<(), Error> {
+        let args: Vec<String> = self.words.iter().map(|word| word.render(values)).collect();
+        Tool::new(&args[0])
+            .args(&args[1..])
+            .run()
+            .map_err(|e| match e {
+                Error::Tool { tool, message } => Error::Tool {
+                    tool,
+                    message: format!("post command {}", message),
+                },
+                e => e,
+            })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::fs;
+use std::path::Path;
+
+pub struct HtmlOptions {
+    // Embed every page as a data: URI into one self-contained file.
+    pub single_file: bool,
+    // Right-to-left reading: the left arrow key moves to the next page.
+    pub rtl: bool,
+}
+
+const STYLE: &str = "\
+body { margin: 0; background: #111; color: #ccc; font-family: sans-serif; }
+h1 { font-size: 1rem; font-weight: normal; text-align: center; padding: 0.5rem; }
+main { display: flex; flex-direction: column; align-items: center; gap: 4px; }
+img { display: block; max-width: 100%; height: auto; }
+";
+
+// Arrow keys and j/k jump between pages, Home/End to the first/last one.
+const SCRIPT: &str = "\
+(function () {
+  var pages = Array.prototype.slice.call(document.querySelectorAll('main img'));
+  var rtl = document.documentElement.dir === 'rtl';
+  function current() {
+    var y = window.scrollY + 1;
+    for (var i = pages.length - 1; i >= 0; i--) {
+      if (pages[i].offsetTop <= y) { return i; }
+    }
+    return 0;
+  }
+  function go(index) {
+    index = Math.max(0, Math.min(pages.length - 1, index));
+    window.scrollTo(0, pages[index].offsetTop);
+  }
+  document.addEventListener('keydown', function (event) {
+    var next = rtl ? 'ArrowLeft' : 'ArrowRight';
+    var prev = rtl ? 'ArrowRight' : 'ArrowLeft';
+    if (event.key === next || event.key === 'j' || event.key === ' ') { go(current() + 1); }
+    else if (event.key === prev || event.key === 'k') { go(current() - 1); }
+    else if (event.key === 'Home') { go(0); }
+    else if (event.key === 'End') { go(pages.length - 1); }
+    else { return; }
+    event.preventDefault();
+  });
+})();
+";
+
+// Writes `<dir>/<name>/index.html` next to copies of the pages, or
+// `<dir>/<name>.html` with the pages embedded. Returns the path written.
+pub fn create_html(
+    pages: &[String],
+    dir: &str,
+    name: &str,
+    title: &str,
+    options: &HtmlOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let width = pages.len().to_string().len().max(3);
+    let out_dir = Path::new(dir).join(name);
+    if !options.single_file {
+        fs::create_dir_all(&out_dir)?;
+    }
+
+    let mut images = String::new();
+    for (i, page) in pages.iter().enumerate() {
+        let data = fs::read(page)?;
+        let (mime, extension) = image_type(&data);
+        let src = if options.single_file {
+            format!("data:{};base64,{}", mime, STANDARD.encode(&data))
+        } else {
+            let file_name = format!("{:0width$}.{}", i + 1, extension, width = width);
+            fs::write(out_dir.join(&file_name), &data)?;
+            file_name
+        };
+        images.push_str(&format!(
+            "<img src=\"{}\" alt=\"Page {}\" loading=\"lazy\">\n",
+            src,
+            i + 1
+        ));
+    }
+
+    let html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\" dir=\"{dir}\">\n<head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n<style>\n{style}</style>\n</head>\n<body>\n\
+         <h1>{title}</h1>\n<main>\n{images}</main>\n<script>\n{script}</script>\n\
+         </body>\n</html>\n",
+        dir = if options.rtl { "rtl" } else { "ltr" },
+        title = escape(title),
+        style = STYLE,
+        images = images,
+        script = SCRIPT,
+    );
+
+    let path = if options.single_file {
+        Path::new(dir).join(format!("{}.html", name))
+    } else {
+        out_dir.join("index.html")
+    };
+    fs::write(&path, html)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+fn image_type(data: &[u8]) -> (&'static str, &'static str) {
+    match image::guess_format(data) {
+        Ok(image::ImageFormat::Png) => ("image/png", "png"),
+        Ok(image::ImageFormat::Gif) => ("image/gif", "gif"),
+        Ok(image::ImageFormat::WebP) => ("image/webp", "webp"),
+        _ => ("image/jpeg", "jpg"),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
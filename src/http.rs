@@ -0,0 +1,557 @@
+use crate::config::SourceHeaders;
+use crate::error::Error;
+use crate::rate_limit;
+use crate::scheduler::host_of;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// How long cached responses are used: pages and API responses change when
+// chapters are added, image URLs point at immutable files.
+const PAGE_TTL: Duration = Duration::from_secs(60 * 60);
+const IMAGE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Page,
+    Image,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+    // None when answered from the cache.
+    pub timing: Option<Timing>,
+}
+
+// From sending the request until the headers arrived, and from there until
+// the body was read.
+pub struct Timing {
+    pub first_byte: Duration,
+    pub transfer: Duration,
+}
+
+impl Response {
+    pub fn check_status(&self, url: &str) -> Result<(), Error> {
+        if self.status >= 400 {
+            return Err(Error::Http {
+                url: url.to_string(),
+                status: self.status,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+struct Cache {
+    dir: PathBuf,
+    // Skip cached entries but still store fresh responses.
+    refresh: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    status: u16,
+    fetched_at: u64,
+}
+
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+// Response bytes received from the network so far; cache hits and fixtures
+// don't count.
+static TRANSFERRED: AtomicU64 = AtomicU64::new(0);
+
+const FIXTURES_FILE: &str = "fixtures.toml";
+
+// Canned responses standing in for the network (--offline-fixtures), listed
+// in the directory's fixtures.toml as
+//   [[fixture]]
+//   url = "https://api.mangadex.org/manga/ID"
+//   file = "manga.json"
+//   status = 200  # optional
+#[derive(Deserialize)]
+struct FixtureList {
+    #[serde(default)]
+    fixture: Vec<Fixture>,
+}
+
+#[derive(Deserialize)]
+struct Fixture {
+    url: String,
+    file: PathBuf,
+    #[serde(default = "ok_status")]
+    status: u16,
+}
+
+fn ok_status() -> u16 {
+    200
+}
+
+struct Fixtures {
+    dir: PathBuf,
+    // By fixture_key() of the URL.
+    responses: Vec<(String, Fixture)>,
+}
+
+static FIXTURES: OnceLock<Fixtures> = OnceLock::new();
+
+// The config file's [headers.<source>] tables, by source name.
+static CONFIGURED_HEADERS: OnceLock<HashMap<String, SourceHeaders>> = OnceLock::new();
+// --print-request.
+static PRINT_FAILED: AtomicBool = AtomicBool::new(false);
+
+// A page request and what came back, kept for --bug-report. `status` is None
+// when no response arrived.
+pub struct Exchange {
+    pub url: String,
+    pub request_headers: Vec<(String, String)>,
+    pub status: Option<u16>,
+    pub response_headers: Vec<(String, String)>,
+    pub cached: bool,
+    pub error: Option<String>,
+    pub body: Vec<u8>,
+}
+
+static RECORDED: OnceLock<Mutex<Vec<Exchange>>> = OnceLock::new();
+
+// Keeps every page and API request of the rest of the run. Images aren't kept.
+pub fn record_pages() {
+    let _ = RECORDED.set(Mutex::new(Vec::new()));
+}
+
+// The requests recorded so far, oldest first.
+pub fn recorded() -> Vec<Exchange> {
+    RECORDED
+        .get()
+        .map(|recorded| std::mem::take(&mut *recorded.lock().unwrap()))
+        .unwrap_or_default()
+}
+
+fn record(exchange: Exchange) {
+    if let Some(recorded) = RECORDED.get() {
+        recorded.lock().unwrap().push(exchange);
+    }
+}
+
+// Turns on the response cache for the rest of the run (--http-cache).
+pub fn enable_cache(dir: &Path, refresh: bool) {
+    let _ = CACHE.set(Cache {
+        dir: dir.to_path_buf(),
+        refresh,
+    });
+}
+
+// Answers every request of the rest of the run from the fixtures in `dir`
+// instead of the network. Requests for URLs without a fixture fail with
+// Error::NotInFixtures.
+pub fn use_fixtures(dir: &Path) -> Result<(), String> {
+    let path = dir.join(FIXTURES_FILE);
+    let data = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let list: FixtureList =
+        toml::from_str(&data).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+    let responses = list
+        .fixture
+        .into_iter()
+        .map(|fixture| (fixture_key(&fixture.url), fixture))
+        .collect();
+    let _ = FIXTURES.set(Fixtures {
+        dir: dir.to_path_buf(),
+        responses,
+    });
+    Ok(())
+}
+
+pub fn transferred() -> u64 {
+    TRANSFERRED.load(Ordering::Relaxed)
+}
+
+pub fn configure_headers(headers: HashMap<String, SourceHeaders>) {
+    let _ = CONFIGURED_HEADERS.set(headers);
+}
+
+// Prints the headers of every request that fails for the rest of the run.
+pub fn print_failed_requests() {
+    PRINT_FAILED.store(true, Ordering::Relaxed);
+}
+
+// The headers a request sends: the source's `defaults` for requests of its
+// kind, then those the config file sets for the source, then the request's
+// own `extra` ones such as a Referer.
+pub fn headers(
+    source: &str,
+    kind: Kind,
+    defaults: &[(&str, &str)],
+    extra: &[(String, String)],
+) -> Vec<(String, String)> {
+    let configured = CONFIGURED_HEADERS
+        .get()
+        .and_then(|configured| configured.get(source))
+        .map(|headers| match kind {
+            Kind::Page => &headers.page,
+            Kind::Image => &headers.image,
+        });
+    merge_headers(defaults, configured, extra)
+}
+
+// A header given again replaces the earlier one wherever it was, names
+// compared case-insensitively; an empty value drops it.
+fn merge_headers(
+    defaults: &[(&str, &str)],
+    configured: Option<&BTreeMap<String, String>>,
+    extra: &[(String, String)],
+) -> Vec<(String, String)> {
+    let mut merged: Vec<(String, String)> = Vec::new();
+    let layers = defaults
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .chain(
+            configured
+                .into_iter()
+                .flatten()
+                .map(|(n, v)| (n.clone(), v.clone())),
+        )
+        .chain(extra.iter().cloned());
+    for (name, value) in layers {
+        match merged
+            .iter_mut()
+            .find(|(merged, _)| merged.eq_ignore_ascii_case(&name))
+        {
+            Some(header) => header.1 = value,
+            None => merged.push((name, value)),
+        }
+    }
+    merged.retain(|(_, value)| !value.is_empty());
+    merged
+}
+
+// Whether requests are answered from fixtures rather than the network.
+pub fn offline() -> bool {
+    FIXTURES.get().is_some()
+}
+
+// The URL with its query decoded and sorted, so fixtures can be written
+// without percent-encoding and in any parameter order.
+fn fixture_key(url: &str) -> String {
+    let Ok(mut parsed) = reqwest::Url::parse(url) else {
+        return url.to_string();
+    };
+    let mut pairs: Vec<(String, String)> = parsed.query_pairs().into_owned().collect();
+    pairs.sort();
+    parsed.set_query(None);
+    parsed.set_fragment(None);
+    let query: Vec<String> = pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    if query.is_empty() {
+        parsed.to_string()
+    } else {
+        format!("{}?{}", parsed, query.join("&"))
+    }
+}
+
+impl Fixtures {
+    fn fixture(&self, url: &str) -> Result<&Fixture, Error> {
+        let key = fixture_key(url);
+        self.responses
+            .iter()
+            .find(|(fixture_key, _)| *fixture_key == key)
+            .map(|(_, fixture)| fixture)
+            .ok_or_else(|| Error::NotInFixtures {
+                url: url.to_string(),
+            })
+    }
+
+    fn response(&self, url: &str) -> Result<Response, Error> {
+        let fixture = self.fixture(url)?;
+        let path = self.dir.join(&fixture.file);
+        let body = fs::read(&path).map_err(|e| Error::filesystem(&path.to_string_lossy(), e))?;
+        log::debug!("Answered {} from {}", url, path.display());
+        Ok(Response {
+            status: fixture.status,
+            body,
+            timing: None,
+        })
+    }
+}
+
+// GETs `url`, answering from the cache when it's enabled and holds a fresh
+// enough response for the same URL and headers.
+pub fn get(url: &str, headers: &[(String, String)], kind: Kind) -> Result<Response, Error> {
+    let result = get_recorded(url, headers, kind);
+    let failed = match &result {
+        Ok(response) => response.status >= 400,
+        Err(_) => true,
+    };
+    if failed && PRINT_FAILED.load(Ordering::Relaxed) {
+        // What reqwest adds on its own, Host and a missing Accept, isn't shown.
+        eprintln!("GET {}", url);
+        for (name, value) in headers {
+            eprintln!("  {}: {}", name, value);
+        }
+    }
+    result
+}
+
+fn get_recorded(url: &str, headers: &[(String, String)], kind: Kind) -> Result<Response, Error> {
+    if RECORDED.get().is_none() || !matches!(kind, Kind::Page) {
+        return fetch(url, headers, kind, &mut Vec::new()).map(|(response, _)| response);
+    }
+    let mut response_headers = Vec::new();
+    let result = fetch(url, headers, kind, &mut response_headers);
+    let (status, cached, error, body) = match &result {
+        Ok((response, cached)) => (Some(response.status), *cached, None, response.body.clone()),
+        Err(e) => (None, false, Some(e.to_string()), Vec::new()),
+    };
+    record(Exchange {
+        url: url.to_string(),
+        request_headers: headers.to_vec(),
+        status,
+        response_headers,
+        cached,
+        error,
+        body,
+    });
+    result.map(|(response, _)| response)
+}
+
+// Size of the file at `url` from a HEAD request's Content-Length, None when
+// the server doesn't say. Never cached or recorded.
+pub fn content_length(url: &str, headers: &[(String, String)]) -> Result<Option<u64>, Error> {
+    if let Some(fixtures) = FIXTURES.get() {
+        let path = fixtures.dir.join(&fixtures.fixture(url)?.file);
+        return Ok(fs::metadata(&path).ok().map(|metadata| metadata.len()));
+    }
+    let mut request = Client::new().head(url);
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let response = request.send().map_err(|e| Error::network(url, e))?;
+    if response.status().as_u16() >= 400 {
+        return Err(Error::Http {
+            url: url.to_string(),
+            status: response.status().as_u16(),
+        });
+    }
+    Ok(response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse().ok()))
+}
+
+// Also fills in the response headers and tells whether the cache answered.
+fn fetch(
+    url: &str,
+    headers: &[(String, String)],
+    kind: Kind,
+    response_headers: &mut Vec<(String, String)>,
+) -> Result<(Response, bool), Error> {
+    if let Some(fixtures) = FIXTURES.get() {
+        return fixtures.response(url).map(|response| (response, false));
+    }
+    let cache = CACHE.get();
+    let key = cache_key(url, headers);
+    if let Some(cache) = cache.filter(|cache| !cache.refresh) {
+        let ttl = match kind {
+            Kind::Page => PAGE_TTL,
+            Kind::Image => IMAGE_TTL,
+        };
+        if let Some(response) = cache.load(&key, ttl) {
+            log::debug!("HTTP cache hit for {}", url);
+            return Ok((response, true));
+        }
+    }
+
+    let mut request = Client::new().get(url);
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let host = host_of(url);
+    rate_limit::wait(&host);
+    let started = Instant::now();
+    let response = request.send().map_err(|e| Error::network(url, e))?;
+    let first_byte = started.elapsed();
+    let status = response.status();
+    rate_limit::observe(&host, status.as_u16());
+    *response_headers = response
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect();
+    let body = response
+        .bytes()
+        .map_err(|e| Error::network(url, e))?
+        .to_vec();
+    TRANSFERRED.fetch_add(body.len() as u64, Ordering::Relaxed);
+    let response = Response {
+        status: status.as_u16(),
+        body,
+        timing: Some(Timing {
+            first_byte,
+            transfer: started.elapsed() - first_byte,
+        }),
+    };
+
+    if let Some(cache) = cache {
+        if cacheable(response.status) {
+            if let Err(e) = cache.store(&key, url, &response) {
+                log::warn!("Failed to cache {}: {}", url, e);
+            }
+        }
+    }
+    Ok((response, false))
+}
+
+// Only successful responses are kept. Anything else may be rate limiting,
+// including the 403 some sites send for it, or an outage, and is asked for
+// again next time.
+fn cacheable(status: u16) -> bool {
+    (200..300).contains(&status)
+}
+
+fn cache_key(url: &str, headers: &[(String, String)]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(url.as_bytes());
+    for (name, value) in headers {
+        hasher.update(b"\n");
+        hasher.update(name.to_lowercase().as_bytes());
+        hasher.update(b":");
+        hasher.update(value.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+impl Cache {
+    fn load(&self, key: &str, ttl: Duration) -> Option<Response> {
+        let data = fs::read_to_string(self.dir.join(format!("{}.json", key))).ok()?;
+        let entry: Entry = serde_json::from_str(&data).ok()?;
+        // Older versions kept error responses too.
+        if !cacheable(entry.status) || now().saturating_sub(entry.fetched_at) > ttl.as_secs() {
+            return None;
+        }
+        let body = fs::read(self.dir.join(format!("{}.body", key))).ok()?;
+        Some(Response {
+            status: entry.status,
+            body,
+            timing: None,
+        })
+    }
+
+    fn store(&self, key: &str, url: &str, response: &Response) -> std::io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.dir.join(format!("{}.body", key)), &response.body)?;
+        let entry = Entry {
+            url: url.to_string(),
+            status: response.status,
+            fetched_at: now(),
+        };
+        fs::write(
+            self.dir.join(format!("{}.json", key)),
+            serde_json::to_string(&entry)?,
+        )
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn response(status: u16) -> Response {
+        Response {
+            status,
+            body: b"body".to_vec(),
+            timing: None,
+        }
+    }
+
+    #[test]
+    fn cache_answers_only_with_successful_responses() {
+        let dir = TestDir::new("http-cache");
+        let cache = Cache {
+            dir: dir.path.clone(),
+            refresh: false,
+        };
+        cache
+            .store("ok", "https://example.com/1.jpg", &response(200))
+            .unwrap();
+        let cached = cache.load("ok", IMAGE_TTL).unwrap();
+        assert_eq!((cached.status, cached.body), (200, b"body".to_vec()));
+
+        // As stored by versions that kept errors: asked for again.
+        for status in [403, 404, 429, 500] {
+            let key = status.to_string();
+            cache
+                .store(&key, "https://example.com/2.jpg", &response(status))
+                .unwrap();
+            assert!(cache.load(&key, IMAGE_TTL).is_none(), "{}", status);
+            assert!(!cacheable(status));
+        }
+    }
+
+    fn pairs(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn later_header_layers_win() {
+        let defaults = [
+            ("User-Agent", "Mozilla/5.0"),
+            ("Accept", "image/*"),
+            ("Accept-Language", "en"),
+        ];
+        let configured: BTreeMap<String, String> = pairs(&[
+            ("user-agent", "manga-cli"),
+            ("Accept-Language", ""),
+            ("DNT", "1"),
+        ])
+        .into_iter()
+        .collect();
+        let extra = pairs(&[("Referer", "https://site.test/"), ("ACCEPT", "image/png")]);
+
+        assert_eq!(merge_headers(&defaults, None, &[]), pairs(&defaults));
+        // Replaced in place under the earlier name, dropped when emptied.
+        assert_eq!(
+            merge_headers(&defaults, Some(&configured), &extra),
+            pairs(&[
+                ("User-Agent", "manga-cli"),
+                ("Accept", "image/png"),
+                ("DNT", "1"),
+                ("Referer", "https://site.test/"),
+            ])
+        );
+        // The request's own headers win over the config file.
+        let dropped = pairs(&[("user-agent", "")]);
+        assert_eq!(
+            merge_headers(&defaults, Some(&configured), &dropped),
+            pairs(&[("Accept", "image/*"), ("DNT", "1")])
+        );
+    }
+}
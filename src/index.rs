@@ -0,0 +1,198 @@
+use crate::chapter_id::ChapterId;
+use crate::manifest::Manifest;
+use crate::migrate;
+use crate::series_json;
+use crate::source::Details;
+use crate::workdir::UNFINISHED_SUFFIX;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const INDEX_FILE: &str = "index.json";
+// Raised when a field changes meaning or goes away. Fields are only ever
+// added within a version, so readers should ignore those they don't know.
+pub const INDEX_VERSION: u32 = 1;
+
+// index.json in a series folder of the cache: the series' description and
+// tags with every cached chapter, in one file for library frontends to
+// search. Fields a newer manga-cli added are kept as they are.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SeriesIndex {
+    pub version: u32,
+    pub title: String,
+    pub url: Option<String>,
+    pub description: Option<String>,
+    pub genres: Vec<String>,
+    pub authors: Vec<String>,
+    pub alt_titles: Vec<String>,
+    // As the site words it, e.g. "Ongoing".
+    pub status: Option<String>,
+    // In reading order.
+    pub chapters: Vec<ChapterEntry>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ChapterEntry {
+    // The chapter's folder in the series folder.
+    pub folder: String,
+    pub number: Option<ChapterId>,
+    pub volume: Option<String>,
+    pub name: Option<String>,
+    pub title: Option<String>,
+    // The scanlation group that uploaded it.
+    pub group: Option<String>,
+    pub language: Option<String>,
+    // RFC 3339.
+    pub release_date: String,
+    pub pages: usize,
+    pub url: Option<String>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+// Writes index.json for the series folder `dir` from its chapters'
+// manifests. The series' metadata comes from `details` when the site was
+// asked, else from the index there already or series.json. Nothing is
+// fetched.
+pub fn update(
+    dir: &Path,
+    details: Option<&Details>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = dir.join(INDEX_FILE);
+    let old = match fs::read_to_string(&path) {
+        Ok(data) => serde_json::from_str::<SeriesIndex>(&data)
+            .map_err(|e| format!("{} can't be read ({}); not replacing it", path.display(), e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => SeriesIndex::default(),
+        Err(e) => return Err(e.into()),
+    };
+    if old.version > INDEX_VERSION {
+        return Err(format!(
+            "{} is version {}, from a newer manga-cli; not replacing it",
+            path.display(),
+            old.version
+        )
+        .into());
+    }
+    let index = build(dir, old, details);
+    let data = serde_json::to_string_pretty(&index)?;
+    let unfinished = dir.join(format!("{}{}", INDEX_FILE, UNFINISHED_SUFFIX));
+    let mut file = fs::File::create(&unfinished)?;
+    file.write_all(data.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&unfinished, &path)?;
+    Ok(path)
+}
+
+fn build(dir: &Path, mut old: SeriesIndex, details: Option<&Details>) -> SeriesIndex {
+    let old_chapters = std::mem::take(&mut old.chapters);
+    let mut index = SeriesIndex {
+        version: INDEX_VERSION,
+        chapters: Vec::new(),
+        ..old
+    };
+    if let Some(details) = details {
+        index.description = details.description.clone();
+        index.genres = details.genres.clone();
+        index.authors = details.authors.clone();
+        index.alt_titles = details.alt_titles.clone();
+        index.status = details.status.clone();
+    } else if index.description.is_none() && index.genres.is_empty() {
+        from_series_json(dir, &mut index);
+    }
+
+    // Chapter fields a newer manga-cli added stay with their chapter.
+    let mut extras: Map<String, Value> = old_chapters
+        .into_iter()
+        .map(|chapter| (chapter.folder, Value::Object(chapter.other)))
+        .collect();
+    let mut folders: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    folders.sort();
+    for folder in folders {
+        let Some(manifest) = Manifest::load(&folder.to_string_lossy()) else {
+            continue;
+        };
+        let name = folder
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if let Some(title) = &manifest.series_title {
+            index.title = title.clone();
+        }
+        index.url = Some(manifest.manga_url.clone());
+        let url = manifest.chapter_urls.first().cloned();
+        let other = match extras.remove(&name) {
+            Some(Value::Object(other)) => other,
+            _ => Map::new(),
+        };
+        index.chapters.push(ChapterEntry {
+            // Manifests from before numbers were kept.
+            number: manifest
+                .number
+                .or_else(|| url.as_deref().and_then(migrate::guess_number)),
+            folder: name,
+            volume: manifest.volume,
+            name: manifest.chapter_name,
+            title: manifest.chapter_title,
+            group: manifest.group,
+            language: manifest.language,
+            release_date: manifest.release_date,
+            pages: manifest.pages,
+            url,
+            other,
+        });
+    }
+    // Unnumbered chapters go last, by folder.
+    index.chapters.sort_by(|a, b| {
+        (a.number.is_none(), &a.number, &a.folder).cmp(&(b.number.is_none(), &b.number, &b.folder))
+    });
+    if index.title.is_empty() {
+        index.title = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+    }
+    index
+}
+
+// Offline, series.json holds what --series-json last fetched.
+fn from_series_json(dir: &Path, index: &mut SeriesIndex) {
+    let Some(metadata) = series_json::metadata(dir) else {
+        return;
+    };
+    let text = |key: &str| {
+        metadata
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    let list = |key: &str| -> Vec<String> {
+        metadata
+            .get(key)
+            .and_then(Value::as_array)
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    index.description = text("description_text");
+    index.genres = list("genres");
+    index.authors = list("authors");
+    index.alt_titles = list("alt_titles");
+    index.status = text("status");
+}
@@ -0,0 +1,106 @@
+use crate::http;
+use crate::report::format_bytes;
+use crate::scheduler::{host_of, Scheduler};
+use crate::source::{Chapter, Source};
+use crate::ui;
+use serde::Serialize;
+
+// Columns the chapter names are cut or padded to.
+const CHAPTER_COLUMN: usize = 32;
+
+// HEAD requests in flight against one image host at a time.
+const MAX_REQUESTS_PER_HOST: usize = 2;
+
+#[derive(Serialize)]
+pub struct ChapterInfo {
+    pub chapter: String,
+    pub url: String,
+    // None when the page list couldn't be fetched.
+    pub pages: Option<usize>,
+    // Sum of the page sizes the image host reported, with --sizes.
+    pub bytes: Option<u64>,
+    // Pages whose size the host didn't report.
+    pub unknown_sizes: usize,
+    pub error: Option<String>,
+}
+
+// Prints the page count of each chapter, and with `sizes` the size of its
+// pages from HEAD requests, `jobs` at a time. Nothing is downloaded or cached.
+pub fn print(source: &dyn Source, chapters: &[Chapter], sizes: bool, json: bool, jobs: usize) {
+    let scheduler = Scheduler::new(jobs, MAX_REQUESTS_PER_HOST);
+    let mut infos = Vec::new();
+    for chapter in chapters {
+        let mut info = ChapterInfo {
+            chapter: chapter.name.clone(),
+            url: chapter.url.clone(),
+            pages: None,
+            bytes: None,
+            unknown_sizes: 0,
+            error: None,
+        };
+        match source.pages(chapter) {
+            Ok(images) => {
+                info.pages = Some(images.len());
+                if sizes {
+                    let headers = source.image_headers(chapter);
+                    let tasks = images.iter().map(|url| (host_of(url), url)).collect();
+                    let lengths = scheduler.run(tasks, |url| {
+                        http::content_length(url, &headers).unwrap_or_else(|e| {
+                            log::debug!("No size for {}: {}", url, e);
+                            None
+                        })
+                    });
+                    info.unknown_sizes = lengths.iter().filter(|length| length.is_none()).count();
+                    info.bytes = (info.unknown_sizes < lengths.len())
+                        .then(|| lengths.iter().flatten().sum());
+                }
+            }
+            Err(e) => info.error = Some(e.to_string()),
+        }
+        if !json {
+            print_row(&info, sizes);
+        }
+        infos.push(info);
+    }
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&infos).unwrap_or_default()
+        );
+        return;
+    }
+    let pages: usize = infos.iter().filter_map(|info| info.pages).sum();
+    let total = ChapterInfo {
+        chapter: format!("{} chapter(s)", infos.len()),
+        url: String::new(),
+        pages: Some(pages),
+        bytes: infos
+            .iter()
+            .filter_map(|info| info.bytes)
+            .reduce(|a, b| a + b),
+        unknown_sizes: infos.iter().map(|info| info.unknown_sizes).sum(),
+        error: None,
+    };
+    print_row(&total, sizes);
+}
+
+// Rows are printed as chapters finish, so long listings show progress.
+fn print_row(info: &ChapterInfo, sizes: bool) {
+    let chapter = ui::pad(&ui::truncate(&info.chapter, CHAPTER_COLUMN), CHAPTER_COLUMN);
+    let pages = match (info.pages, &info.error) {
+        (Some(pages), _) => format!("{} pages", pages),
+        (None, Some(error)) => format!("failed: {}", error),
+        (None, None) => "unknown".to_string(),
+    };
+    if !sizes || info.pages.is_none() {
+        println!("{}  {}", chapter, pages);
+        return;
+    }
+    let size = match (info.bytes, info.unknown_sizes) {
+        (None, _) => "unknown".to_string(),
+        (Some(bytes), 0) => format_bytes(bytes),
+        (Some(bytes), unknown) => format!("{} + {} unknown", format_bytes(bytes), unknown),
+    };
+    println!("{}  {:>10}  {}", chapter, pages, size);
+}
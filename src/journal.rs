@@ -0,0 +1,260 @@
+use crate::series::data_dir;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Change files past which a save folds them into the snapshot.
+const COMPACT_AFTER: usize = 64;
+// A lock left this long belongs to a compaction that crashed.
+const STALE_LOCK: Duration = Duration::from_secs(10 * 60);
+// Loads retried when a compaction removed changes while they were read.
+const LOAD_ATTEMPTS: usize = 5;
+
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+// A store that several manga-cli processes write at once, like `watch` and a
+// download run side by side. It is kept as a snapshot, "<name>.json", plus
+// one file per save in "<name>.d", each holding only what that save changed
+// and written under a temporary name first. Saving never rewrites what
+// another process wrote, so no process loses another's changes.
+pub trait Store: Serialize + DeserializeOwned + Default {
+    const NAME: &'static str;
+    type Change: Serialize + DeserializeOwned;
+
+    // Applies a change on top of the store. Changes are applied in the order
+    // they were saved, and may be applied again after a compaction.
+    fn apply(&mut self, change: Self::Change);
+}
+
+fn snapshot_path<S: Store>() -> PathBuf {
+    data_dir().join(format!("{}.json", S::NAME))
+}
+
+fn changes_dir<S: Store>() -> PathBuf {
+    data_dir().join(format!("{}.d", S::NAME))
+}
+
+fn lock_path<S: Store>() -> PathBuf {
+    data_dir().join(format!("{}.lock", S::NAME))
+}
+
+// The snapshot with every saved change applied. The snapshot is what the
+// single file of earlier versions was, so their stores load as they are.
+pub fn load<S: Store>() -> S {
+    for _ in 0..LOAD_ATTEMPTS {
+        if let Some(store) = try_load::<S>() {
+            return store;
+        }
+    }
+    log::warn!("{} kept changing while it was read", S::NAME);
+    try_load::<S>().unwrap_or_default()
+}
+
+// None when a change listed went missing, which means a compaction folded it
+// into a snapshot newer than the one read.
+fn try_load<S: Store>() -> Option<S> {
+    // Listed before the snapshot is read: a change compacted in between is
+    // then either in the snapshot or still there to read.
+    load_changes(change_files::<S>())
+}
+
+fn load_changes<S: Store>(changes: Vec<PathBuf>) -> Option<S> {
+    let mut store: S = fs::read_to_string(snapshot_path::<S>())
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default();
+    for path in &changes {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                log::warn!("Skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        match serde_json::from_str::<Vec<S::Change>>(&data) {
+            Ok(saved) => saved.into_iter().for_each(|change| store.apply(change)),
+            Err(e) => log::warn!("Skipping {}: {}", path.display(), e),
+        }
+    }
+    Some(store)
+}
+
+// The change files in the order they were saved.
+fn change_files<S: Store>() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(changes_dir::<S>())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+// Saves `changes` as one change file, compacting the store once enough have
+// piled up.
+pub fn save<S: Store>(changes: Vec<S::Change>) -> Result<(), Box<dyn std::error::Error>> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let dir = changes_dir::<S>();
+    fs::create_dir_all(&dir)?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or(0);
+    // Fixed-width, so the names sort in the order they were saved.
+    let name = format!(
+        "{:032x}-{:08x}-{:08x}",
+        nanos,
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    );
+    write_atomic(
+        &dir.join(format!("{}.json", name)),
+        &serde_json::to_string(&changes)?,
+    )?;
+    if change_files::<S>().len() > COMPACT_AFTER {
+        if let Err(e) = compact::<S>() {
+            log::warn!("Failed to compact {}: {}", S::NAME, e);
+        }
+    }
+    Ok(())
+}
+
+// Folds the change files into the snapshot and removes them. Only one process
+// compacts at a time; the others leave it be.
+pub fn compact<S: Store>() -> io::Result<()> {
+    let Some(_lock) = Lock::take(lock_path::<S>())? else {
+        return Ok(());
+    };
+    let changes = change_files::<S>();
+    let Some(store) = load_changes::<S>(changes.clone()) else {
+        return Ok(());
+    };
+    let data = serde_json::to_string_pretty(&store).map_err(io::Error::other)?;
+    write_atomic(&snapshot_path::<S>(), &data)?;
+    // Changes saved since the listing stay for the next load to apply.
+    for path in changes {
+        let _ = fs::remove_file(path);
+    }
+    Ok(())
+}
+
+fn write_atomic(path: &Path, data: &str) -> io::Result<()> {
+    let mut unfinished = path.as_os_str().to_os_string();
+    unfinished.push(crate::workdir::UNFINISHED_SUFFIX);
+    let unfinished = PathBuf::from(unfinished);
+    let mut file = fs::File::create(&unfinished)?;
+    file.write_all(data.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&unfinished, path)
+}
+
+// A file only one process at a time can create; removed when dropped.
+struct Lock {
+    path: PathBuf,
+}
+
+impl Lock {
+    fn take(path: PathBuf) -> io::Result<Option<Lock>> {
+        let stale = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().unwrap_or_default() > STALE_LOCK);
+        if stale {
+            let _ = fs::remove_file(&path);
+        }
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => Ok(Some(Lock { path })),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_id::ChapterId;
+    use crate::history::HistoryStore;
+    use crate::series::SeriesStore;
+    use crate::testdir::TestDir;
+    use std::thread;
+
+    const WRITERS: usize = 6;
+    const SAVES: usize = 25;
+
+    // Writers each load, change and save a store many times over, all at
+    // once, compacting as they go. Every change must still be there.
+    #[test]
+    fn concurrent_writers_lose_no_records() {
+        let dir = TestDir::new("journal-stress");
+        thread::scope(|scope| {
+            for writer in 0..WRITERS {
+                let dir = &dir;
+                scope.spawn(move || {
+                    dir.use_as_data_dir();
+                    for save in 0..SAVES {
+                        let mut history = HistoryStore::load();
+                        let number: ChapterId =
+                            (writer * SAVES + save).to_string().parse().unwrap();
+                        history.mark_read("https://example.com/manga/shared", "Shared", &[number]);
+                        history.save().unwrap();
+
+                        // Every writer sets flags of one shared series, and
+                        // the title of a series of its own last.
+                        let mut series = SeriesStore::load();
+                        series
+                            .get_mut("https://example.com/manga/shared")
+                            .overrides
+                            .insert(format!("flag-{}-{}", writer, save), save.to_string());
+                        let own = series.get_mut(&format!("https://example.com/manga/{}", writer));
+                        own.title = Some(format!("Writer {} save {}", writer, save));
+                        own.followed = true;
+                        series.save().unwrap();
+                    }
+                });
+            }
+        });
+
+        dir.use_as_data_dir();
+        // Compaction ran along the way; the rest folds in now.
+        compact::<HistoryStore>().unwrap();
+        compact::<SeriesStore>().unwrap();
+        assert!(change_files::<SeriesStore>().is_empty());
+
+        let history = HistoryStore::load();
+        let read = history.read("https://example.com/manga/shared");
+        assert_eq!(read.len(), WRITERS * SAVES);
+        let series = SeriesStore::load();
+        let shared = series.get("https://example.com/manga/shared");
+        assert_eq!(shared.overrides.len(), WRITERS * SAVES);
+        for writer in 0..WRITERS {
+            let own = series.get(&format!("https://example.com/manga/{}", writer));
+            assert_eq!(
+                own.title,
+                Some(format!("Writer {} save {}", writer, SAVES - 1))
+            );
+            assert!(own.followed);
+        }
+    }
+}
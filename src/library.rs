@@ -0,0 +1,287 @@
+use crate::conflict;
+use crate::diff;
+use crate::manifest::Manifest;
+use crate::reflink;
+use crate::series_json;
+use crate::workdir::UNFINISHED_SUFFIX;
+use filetime::FileTime;
+use sha2::{digest, Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub type Hash = digest::Output<Sha256>;
+
+// A title reduced to what tells series apart: transliterated, lower case,
+// letters and digits only. "Jujutsu Kaisen" and "Jujutsu-Kaisen" come out
+// the same.
+pub fn normalize(title: &str) -> String {
+    deunicode::deunicode(title)
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// A series folder with the titles it goes by: its own name, the titles its
+// chapter manifests record and those in its series.json.
+pub struct SeriesFolder {
+    pub path: PathBuf,
+    pub name: String,
+    pub chapters: usize,
+    keys: Vec<String>,
+}
+
+impl SeriesFolder {
+    fn read(path: PathBuf) -> SeriesFolder {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut titles = vec![name.clone()];
+        titles.extend(series_json::titles(&path));
+        let mut chapters = 0;
+        for entry in fs::read_dir(&path).into_iter().flatten().flatten() {
+            if !entry.path().is_dir() {
+                continue;
+            }
+            chapters += 1;
+            let manifest = Manifest::load(&entry.path().to_string_lossy());
+            if let Some(title) = manifest.and_then(|manifest| manifest.series_title) {
+                if !titles.contains(&title) {
+                    titles.push(title);
+                }
+            }
+        }
+        let mut keys: Vec<String> = titles
+            .iter()
+            .map(|title| normalize(title))
+            .filter(|key| !key.is_empty())
+            .collect();
+        keys.sort();
+        keys.dedup();
+        SeriesFolder {
+            path,
+            name,
+            chapters,
+            keys,
+        }
+    }
+
+    // Whether any of `titles` names this series.
+    pub fn matches(&self, titles: &[String]) -> bool {
+        titles
+            .iter()
+            .map(|title| normalize(title))
+            .any(|key| !key.is_empty() && self.keys.contains(&key))
+    }
+}
+
+// Every series folder in `root`, by name.
+pub fn folders(root: &Path) -> Vec<SeriesFolder> {
+    let mut folders: Vec<SeriesFolder> = fs::read_dir(root)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .map(SeriesFolder::read)
+        .collect();
+    folders.sort_by(|a, b| a.name.cmp(&b.name));
+    folders
+}
+
+// Another folder in `root` that looks like the series `titles` name, when the
+// series has no folder of its own named `own` yet.
+pub fn lookalike(root: &Path, own: &str, titles: &[String]) -> Option<SeriesFolder> {
+    if root.join(own).exists() {
+        return None;
+    }
+    folders(root)
+        .into_iter()
+        .find(|folder| folder.name != own && folder.matches(titles))
+}
+
+// Folders that look like the same series, each group sorted with the folder
+// holding the most chapters first.
+pub fn duplicates(root: &Path) -> Vec<Vec<SeriesFolder>> {
+    let mut groups: Vec<Vec<SeriesFolder>> = Vec::new();
+    for folder in folders(root) {
+        let group = groups.iter_mut().find(|group| {
+            group
+                .iter()
+                .any(|other| other.keys.iter().any(|key| folder.keys.contains(key)))
+        });
+        match group {
+            Some(group) => group.push(folder),
+            None => groups.push(vec![folder]),
+        }
+    }
+    groups.retain(|group| group.len() > 1);
+    for group in &mut groups {
+        group.sort_by(|a, b| b.chapters.cmp(&a.chapters).then(a.name.cmp(&b.name)));
+    }
+    groups
+}
+
+// Moves everything in `from` into `into`. Nothing is deleted: a name already
+// taken in `into` gets the first free " (n)" before its extension, and
+// `from` is only removed once empty. Returns the entries that were renamed.
+pub fn merge(from: &Path, into: &Path) -> io::Result<Vec<(String, String)>> {
+    let mut renamed = Vec::new();
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let mut target = into.join(&name);
+        if target.exists() {
+            let path = Path::new(&name);
+            // Chapter folders have no extension, and a dot in their name
+            // isn't one.
+            let (stem, extension) = match (entry.path().is_file(), path.extension()) {
+                (true, Some(extension)) => (
+                    path.file_stem().unwrap().to_string_lossy().into_owned(),
+                    format!(".{}", extension.to_string_lossy()),
+                ),
+                _ => (name.clone(), String::new()),
+            };
+            let free = conflict::free_name(&stem, |candidate| {
+                into.join(format!("{}{}", candidate, extension)).exists()
+            });
+            let free = format!("{}{}", free, extension);
+            target = into.join(&free);
+            renamed.push((name, free));
+        }
+        fs::rename(entry.path(), &target)?;
+    }
+    fs::remove_dir(from)?;
+    Ok(renamed)
+}
+
+// The pages of the chapter folders in `root` that are as they were
+// downloaded: with a manifest whose page count they match. Others are left
+// out, since checking the chapter would take them for changed.
+pub fn intact_pages(root: &Path) -> Vec<PathBuf> {
+    let mut pages = Vec::new();
+    for folder in folders(root) {
+        for entry in fs::read_dir(&folder.path).into_iter().flatten().flatten() {
+            let dir = entry.path();
+            let Some(manifest) = Manifest::load(&dir.to_string_lossy()) else {
+                continue;
+            };
+            match diff::cached_pages(&dir) {
+                Ok(found) if found.len() == manifest.pages => pages.extend(found),
+                _ => log::info!("Leaving {} alone: its pages changed", dir.display()),
+            }
+        }
+    }
+    pages
+}
+
+// Files with the same content, in the order they were given.
+pub struct SameFiles {
+    pub paths: Vec<PathBuf>,
+    pub size: u64,
+    pub hash: Hash,
+}
+
+// The files among `files` that hold the same content as another. Names of
+// one file, like hard links, count once, and empty files not at all.
+pub fn identical(files: &[PathBuf]) -> io::Result<Vec<SameFiles>> {
+    let mut seen = HashSet::new();
+    let mut by_size: HashMap<u64, Vec<&PathBuf>> = HashMap::new();
+    for path in files {
+        let metadata = fs::metadata(path)?;
+        if metadata.len() > 0 && seen.insert(file_id(&metadata, path)) {
+            by_size.entry(metadata.len()).or_default().push(path);
+        }
+    }
+    let mut groups: Vec<SameFiles> = Vec::new();
+    for (size, paths) in by_size.into_iter().filter(|(_, paths)| paths.len() > 1) {
+        let mut by_hash: Vec<SameFiles> = Vec::new();
+        for path in paths {
+            let hash = hash_file(path)?;
+            match by_hash.iter_mut().find(|group| group.hash == hash) {
+                Some(group) => group.paths.push(path.clone()),
+                None => by_hash.push(SameFiles {
+                    paths: vec![path.clone()],
+                    size,
+                    hash,
+                }),
+            }
+        }
+        groups.extend(by_hash.into_iter().filter(|group| group.paths.len() > 1));
+    }
+    groups.sort_by(|a, b| a.paths[0].cmp(&b.paths[0]));
+    Ok(groups)
+}
+
+// Replaces `duplicate` with a copy-on-write clone of `original`, both holding
+// the content hashed as `hash`. The clone is made beside it and checked
+// before it takes the duplicate's name, modification time and permissions,
+// so a file either stays as it was or ends up with the same content.
+pub fn share(original: &Path, duplicate: &Path, hash: &Hash) -> io::Result<()> {
+    let mut staged = duplicate.as_os_str().to_os_string();
+    staged.push(UNFINISHED_SUFFIX);
+    let staged = PathBuf::from(staged);
+    reflink::clone(original, &staged)?;
+    let shared = (|| {
+        // Either may have been written to since it was hashed.
+        if hash_file(&staged)? != *hash || hash_file(duplicate)? != *hash {
+            return Err(io::Error::other("changed while optimizing"));
+        }
+        let metadata = fs::metadata(duplicate)?;
+        fs::set_permissions(&staged, metadata.permissions())?;
+        filetime::set_file_mtime(&staged, FileTime::from_last_modification_time(&metadata))?;
+        fs::rename(&staged, duplicate)
+    })();
+    if shared.is_err() {
+        let _ = fs::remove_file(&staged);
+    }
+    shared
+}
+
+// The pages stored inside the CBZs among `archives` that repeat a file hashed
+// into `pages`, and the bytes they take. A page inside an archive doesn't
+// start on a block of its own, so it can't share storage with the file.
+pub fn archived_copies(archives: &[PathBuf], pages: &HashSet<Hash>) -> (usize, u64) {
+    let (mut count, mut bytes) = (0, 0);
+    for path in archives {
+        let Ok(file) = fs::File::open(path) else {
+            continue;
+        };
+        let Ok(mut archive) = zip::ZipArchive::new(file) else {
+            continue;
+        };
+        for i in 0..archive.len() {
+            let Ok(mut entry) = archive.by_index(i) else {
+                continue;
+            };
+            let mut hasher = Sha256::new();
+            let copied = entry.is_file() && io::copy(&mut entry, &mut hasher).is_ok();
+            if copied && pages.contains(&hasher.finalize()) {
+                count += 1;
+                bytes += entry.size();
+            }
+        }
+    }
+    (count, bytes)
+}
+
+pub fn hash_file(path: &Path) -> io::Result<Hash> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+// What tells files apart: the inode where there is one, the path elsewhere.
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata, _path: &Path) -> (u64, u64, PathBuf) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino(), PathBuf::new())
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata, path: &Path) -> (u64, u64, PathBuf) {
+    (0, 0, path.to_path_buf())
+}
@@ -1,184 +1,6023 @@
-use clap::{ArgEnum, Parser, Subcommand};
-use reqwest::blocking::Client;
-use reqwest::header::USER_AGENT;
-use select::document::Document;
-use select::node::Node;
-use select::predicate::Name;
+mod alias;
+mod batch;
+mod bug_report;
+mod chapter_id;
+mod chapter_range;
+mod chapters;
+mod comicinfo;
+mod compat;
+mod config;
+mod conflict;
+mod dates;
+mod decode;
+mod dedupe;
+mod diff;
+mod doctor;
+mod error;
+mod exec;
+mod exif;
+mod filename;
+mod gaps;
+mod history;
+mod hook;
+mod html;
+mod http;
+mod index;
+mod info;
+mod journal;
+mod library;
+mod manifest;
+mod migrate;
+mod mirrors;
+mod overrides;
+mod pdf;
+mod policy;
+mod prefetch;
+mod process;
+mod profile;
+mod project;
+mod promo;
+mod prompt;
+mod quarantine;
+mod rate_limit;
+mod reflink;
+mod report;
+mod schedule;
+mod scheduler;
+mod series;
+mod series_json;
+#[cfg(feature = "serve")]
+mod serve;
+mod source;
+mod space;
+mod stamp;
+mod state;
+mod stats;
+mod tar;
+mod template;
+#[cfg(test)]
+mod testdir;
+mod tools;
+mod transfer;
+mod ui;
+mod update;
+mod upscale;
+mod wizard;
+mod workdir;
+
+use batch::{BatchReport, LineReport, LineStatus};
+use chapter_id::ChapterId;
+use chapter_range::{parse_chapter, parse_chapter_range, ChapterRange, ChapterSpec};
+use clap::{
+    ArgEnum, ArgMatches, CommandFactory, ErrorKind, FromArgMatches, Parser, Subcommand, ValueSource,
+};
+use comicinfo::ComicInfo;
+use compat::CompatFormat;
+use config::Config;
+use conflict::OnConflict;
+use dates::{parse_release_date, parse_window};
+use decode::DecodeLimits;
+use error::{context, Error};
+use filename::NameParts;
+use filetime::FileTime;
+use history::HistoryStore;
+use hook::{parse_post_command, PostCommand, PostCommandFailures};
+use html::{create_html, HtmlOptions};
+use http::Kind;
+use manifest::Manifest;
+use pdf::{
+    page_args, parse_margin, parse_max_side, parse_page_size, split_oversize, PageSize, PdfOptions,
+};
+use policy::{ChapterPolicy, PagePolicy, ParsePolicy};
+use process::{
+    fit_to_size, process_pages, recompress_pages, Encoding, Levels, LevelsOptions, PageProcessor,
+    ProcessOptions, TrimOptions, LOW_DATA_QUALITY,
+};
+use profile::{millis, PageTiming, Profile};
+use promo::{suspicious_pages, AdBlocklist, PromoOptions};
+use quarantine::FailureStore;
+use regex::Regex;
+use report::{ChapterReport, ChapterStatus, FailedPage, FlaggedPage, LowData, Report};
+use schedule::{parse_schedule, Schedule};
+use scheduler::{host_of, Scheduler};
+use series::{LastSelection, SeriesMeta, SeriesStore};
+use series_json::SeriesMetadata;
+use sha2::{Digest, Sha256};
+use source::{
+    clean_chapter_title, kind_for_url, kind_named, normalize_number, parse_source_choice, source,
+    title_from_url, Chapter, Details, Manga, SearchResult, Source, SourceChoice, SourceKind,
+    SourceResult,
+};
+use stamp::{stamp_pages, Corner, StampOptions};
+use stats::UsageRecord;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::io::{self, Write};
-use std::path::PathBuf;
-use zip::{write::FileOptions, ZipWriter};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use template::Template;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use ui::Progress;
+use upscale::{upscale_pages, UpscaleOptions};
+use workdir::{longest_work_dir, series_dir, series_root, WorkDir, UNFINISHED_SUFFIX};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+#[derive(Parser)]
+#[clap(name = "manga-cli")]
+#[clap(about = "A command-line manga downloader.")]
+#[clap(subcommand_negates_reqs = true)]
+#[allow(clippy::upper_case_acronyms)]
+struct CLI {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(
+        short,
+        long,
+        arg_enum,
+        multiple_occurrences(true),
+        use_value_delimiter(true)
+    )]
+    format: Vec<Format>,
+
+    #[clap(long, arg_enum, value_name = "FORMAT")]
+    fallback_format: Option<Format>,
+
+    #[clap(long, conflicts_with_all = &["from-dir", "export-urls"])]
+    stream_cbz: bool,
+
+    #[clap(long, value_name = "TEMPLATE", parse(try_from_str = parse_post_command))]
+    post_cmd: Option<PostCommand>,
+
+    #[clap(long, arg_enum, value_name = "ACTION", default_value = "warn")]
+    post_cmd_failures: PostCommandFailures,
+
+    #[clap(short, long)]
+    clear: bool,
+
+    #[clap(long, requires = "clear")]
+    stale: bool,
+
+    #[clap(long, requires = "clear")]
+    then_download: bool,
+
+    #[clap(long, arg_enum, value_name = "FORMAT")]
+    compat_format: Option<CompatFormat>,
+
+    #[clap(long, value_name = "URL", parse(try_from_str = parse_mirror))]
+    mirror: Option<String>,
+
+    #[clap(long, value_name = "TEMPLATE", parse(try_from_str = parse_entry_template))]
+    entry_template: Option<Template>,
+
+    #[clap(short, long)]
+    viewer: Option<String>,
+
+    #[clap(long)]
+    no_mark_read: bool,
+
+    /// While the viewer is open, download the chapter after it in the background
+    #[clap(long)]
+    prefetch: bool,
+
+    // Set on the download --prefetch starts.
+    #[clap(long, hide = true)]
+    prefetching: bool,
+
+    #[clap(long, value_name = "DIR")]
+    output_dir: Option<String>,
+
+    #[clap(long)]
+    no_wizard: bool,
+
+    #[clap(long, arg_enum, default_value = "oldest")]
+    order: Order,
+
+    #[clap(short, long, default_value = "4")]
+    jobs: usize,
+
+    /// Milliseconds to wait between requests to the same host
+    #[clap(long, value_name = "MS")]
+    delay: Option<u64>,
+
+    /// Save the --delay suggested after rate-limited requests in config.toml
+    #[clap(long)]
+    apply_suggestion: bool,
+
+    #[clap(long, value_name = "N")]
+    process_jobs: Option<usize>,
+
+    #[clap(long)]
+    trim_margins: bool,
+
+    #[clap(long, default_value = "1.0")]
+    trim_safety_margin: f64,
+
+    #[clap(long, default_value = "15.0")]
+    trim_max_crop: f64,
+
+    #[clap(long)]
+    autocontrast: bool,
+
+    #[clap(long, value_name = "BLACK,WHITE", parse(try_from_str = parse_levels))]
+    levels: Option<(u8, u8)>,
+
+    #[clap(long, default_value = "1.0", parse(try_from_str = parse_gamma))]
+    gamma: f64,
+
+    #[clap(long)]
+    adjust_color_pages: bool,
+
+    #[clap(long, value_name = "TEMPLATE")]
+    upscale_cmd: Option<String>,
+
+    #[clap(long, default_value = "1")]
+    upscale_jobs: usize,
+
+    #[clap(long)]
+    upscale_fallback: bool,
+
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "CORNER",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "bottom-right"
+    )]
+    stamp_pages: Option<Corner>,
+
+    #[clap(long, requires = "stamp-pages")]
+    stamp_chapter: bool,
+
+    #[clap(long, value_name = "PERCENT", default_value = "80", parse(try_from_str = parse_stamp_opacity))]
+    stamp_opacity: f64,
+
+    #[clap(long, value_name = "PERCENT", default_value = "2", parse(try_from_str = parse_stamp_size))]
+    stamp_size: f64,
+
+    #[clap(long, value_name = "N|none")]
+    volume: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "N|latest",
+        parse(try_from_str = parse_chapter),
+        conflicts_with_all = &["chapters", "volume"]
+    )]
+    chapter: Option<ChapterSpec>,
+
+    #[clap(
+        long,
+        value_name = "A-B|A-|-N",
+        parse(try_from_str = parse_chapter_range),
+        allow_hyphen_values = true,
+        conflicts_with = "volume"
+    )]
+    chapters: Option<ChapterRange>,
+
+    /// Split the --chapters range into outputs of N chapters each
+    #[clap(
+        long,
+        value_name = "N",
+        requires = "chapters",
+        conflicts_with = "volume",
+        parse(try_from_str = parse_bundle_size)
+    )]
+    bundle_every: Option<usize>,
+
+    /// Site to search, or "all" to search every site at once
+    #[clap(
+        short,
+        long,
+        value_name = "SOURCE",
+        default_value = "manganelo",
+        parse(try_from_str = parse_source_choice)
+    )]
+    source: SourceChoice,
+
+    #[clap(short, long)]
+    group: Option<String>,
+
+    #[clap(long)]
+    lenient: bool,
+
+    #[clap(long, value_name = "CODE", multiple_occurrences(true))]
+    lang: Vec<String>,
+
+    #[clap(long)]
+    verbose: bool,
+
+    #[clap(long)]
+    ignore_monthly_cap: bool,
+
+    #[clap(long)]
+    plain: bool,
+
+    #[clap(long)]
+    no_color: bool,
+
+    #[clap(long)]
+    print_request: bool,
+
+    #[clap(long)]
+    transliterate_filenames: bool,
+
+    #[clap(long)]
+    no_space_check: bool,
+
+    #[clap(long)]
+    profile_run: bool,
+
+    #[clap(long)]
+    strict: bool,
+
+    #[clap(long)]
+    skip_existing: bool,
+
+    #[clap(long, arg_enum, value_name = "ACTION")]
+    on_conflict: Option<OnConflict>,
+
+    #[clap(long, arg_enum, value_name = "ACTION")]
+    on_page_failure: Option<PagePolicy>,
+
+    #[clap(long, arg_enum, value_name = "ACTION")]
+    on_chapter_failure: Option<ChapterPolicy>,
+
+    #[clap(long, arg_enum, value_name = "ACTION")]
+    on_parse_failure: Option<ParsePolicy>,
+
+    #[clap(long)]
+    series_json: bool,
+
+    /// Keep index.json in the series folder of the cache up to date: the
+    /// series' description and tags with each cached chapter's details
+    #[clap(long)]
+    index: bool,
+
+    #[clap(long)]
+    single_file: bool,
+
+    #[clap(long)]
+    rtl: bool,
+
+    #[clap(long, value_name = "FILE")]
+    export_urls: Option<String>,
+
+    #[clap(long, value_name = "DIR", conflicts_with = "export-urls")]
+    from_dir: Option<String>,
+
+    #[clap(long, value_name = "FILE")]
+    report: Option<String>,
+
+    #[clap(long, value_name = "FILE")]
+    bug_report: Option<String>,
+
+    #[clap(long)]
+    skip_promo_pages: bool,
+
+    /// Keep the site's app ads at the end of chapters
+    #[clap(long)]
+    keep_ads: bool,
+
+    /// Keep repeated image URLs and identical neighboring pages
+    #[clap(long)]
+    no_dedupe: bool,
+
+    #[clap(long)]
+    no_exif_rotate: bool,
+
+    #[clap(long)]
+    no_backup: bool,
+
+    #[clap(long)]
+    low_data: bool,
+
+    #[clap(long, requires = "low-data")]
+    always_reencode: bool,
+
+    /// Re-encode each chapter's pages at lower quality and size until it
+    /// fits into SIZE, e.g. 80M
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_target_size))]
+    target_size: Option<u64>,
+
+    #[clap(long, value_name = "N", default_value = "1")]
+    retry_passes: usize,
+
+    #[clap(long = "match", value_name = "REGEX", parse(try_from_str = Regex::new))]
+    match_pattern: Option<Regex>,
+
+    #[clap(long)]
+    reproducible: bool,
+
+    #[clap(long)]
+    http_cache: bool,
+
+    #[clap(long, value_name = "DIR")]
+    offline_fixtures: Option<PathBuf>,
+
+    #[clap(long, value_name = "original|a4|b5|WxH", default_value = "original", parse(try_from_str = parse_page_size))]
+    pdf_page_size: PageSize,
+
+    #[clap(long, value_name = "MM", default_value = "0", parse(try_from_str = parse_margin))]
+    pdf_margin: f64,
+
+    #[clap(long, value_name = "PX", default_value = "14000", parse(try_from_str = parse_max_side))]
+    pdf_max_side: u32,
+
+    #[clap(long)]
+    no_split_oversize: bool,
+
+    #[clap(long)]
+    refresh: bool,
+
+    #[clap(long, conflicts_with = "last-selection")]
+    again: bool,
+
+    #[clap(long)]
+    last_selection: bool,
+
+    // Required unless --from-dir, --again, --last-selection or --clear is
+    // given or a project file names the series.
+    manga_name: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that every source can still be searched and downloaded from
+    Doctor {
+        #[clap(long)]
+        json: bool,
+
+        /// Write the fetched pages and the results to FILE (.tar.gz) when a check fails
+        #[clap(long, value_name = "FILE")]
+        bug_report: Option<String>,
+
+        /// Save the --delay suggested for rate-limited sources in config.toml
+        #[clap(long)]
+        apply_suggestion: bool,
+    },
+    /// Follow a series and edit the settings stored for it
+    Follow {
+        #[clap(short, long, arg_enum, default_value = "manganelo")]
+        source: SourceKind,
+
+        #[clap(long, value_name = "KEY=VALUE", multiple_occurrences(true))]
+        set: Vec<String>,
+
+        #[clap(long, value_name = "KEY", multiple_occurrences(true))]
+        unset: Vec<String>,
+
+        /// When `watch` checks this series instead of its --schedule, e.g.
+        /// "0 9 * * 0" for Sundays at 9; remove it with --unset schedule
+        #[clap(long, value_name = "CRON|TIME", parse(try_from_str = parse_schedule_text))]
+        schedule: Option<String>,
+
+        manga_name: String,
+    },
+    /// List a series' chapters with their page counts, without downloading
+    Info {
+        #[clap(short, long, arg_enum, default_value = "manganelo")]
+        source: SourceKind,
+
+        /// Only list these chapters
+        #[clap(
+            long,
+            value_name = "A-B|A-|-N",
+            parse(try_from_str = parse_chapter_range),
+            allow_hyphen_values = true
+        )]
+        chapters: Option<ChapterRange>,
+
+        /// Also add up the size of each chapter's pages, asking the image host
+        #[clap(long)]
+        sizes: bool,
+
+        #[clap(long)]
+        json: bool,
+
+        /// Size requests to run at once
+        #[clap(short, long, default_value = "4")]
+        jobs: usize,
+
+        manga_name: String,
+    },
+    /// Compare a cached chapter with the one on the site now, page by page
+    Diff {
+        #[clap(short, long, arg_enum, default_value = "manganelo")]
+        source: SourceKind,
+
+        /// Also compare the pages themselves, asking for their sizes first
+        /// and downloading only those that could still be the same
+        #[clap(long)]
+        pages: bool,
+
+        /// Download the chapter again when it differs, replacing the cached
+        /// copy and the output
+        #[clap(long)]
+        update_if_changed: bool,
+
+        /// Page requests to run at once
+        #[clap(short, long, default_value = "4")]
+        jobs: usize,
+
+        manga_name: String,
+
+        #[clap(parse(try_from_str = prompt::parse_chapter_number))]
+        chapter: ChapterId,
+    },
+    /// List the chapters of a series missing from the library, going by the
+    /// file names there and the cache, and those only the library has
+    Gaps {
+        #[clap(short, long, arg_enum, default_value = "manganelo")]
+        source: SourceKind,
+
+        /// Where the series' files are; defaults to --output-dir
+        #[clap(long, value_name = "DIR")]
+        library_dir: Option<String>,
+
+        /// Download the missing chapters, one file each
+        #[clap(long)]
+        fill: bool,
+
+        manga_name: String,
+    },
+    /// Download every series listed in a file, one per line
+    Batch {
+        /// Don't start when a line can't be parsed
+        #[clap(long)]
+        strict: bool,
+
+        file: String,
+    },
+    /// Download the chapters of followed series released recently, skipping
+    /// older missing ones
+    Fresh {
+        /// How far back a release counts as fresh, e.g. 12h, 3d or 1w
+        #[clap(long, value_name = "TIME", default_value = "24h", parse(try_from_str = parse_window))]
+        since: time::Duration,
+    },
+    /// Keep running and download releases of followed series whenever the
+    /// schedule says to check
+    Watch {
+        /// A cron expression in local time, e.g. "0 7,19 * * *" for 7am and
+        /// 7pm, or an interval such as 6h
+        #[clap(long, value_name = "CRON|TIME", default_value = "6h", parse(try_from_str = parse_schedule))]
+        schedule: Schedule,
+
+        /// How far back the first check of each series looks
+        #[clap(long, value_name = "TIME", default_value = "24h", parse(try_from_str = parse_window))]
+        since: time::Duration,
+
+        /// Stop trying a chapter after this many permanent failures in a row
+        /// (missing pages, chapters gone from the site)
+        #[clap(long, value_name = "N", default_value = "3", parse(try_from_str = parse_quarantine_after))]
+        quarantine_after: u32,
+    },
+    /// Show followed series, when `watch` checks them, and the chapters it
+    /// failed to download or quarantined
+    Status,
+    /// Take a chapter out of quarantine so `watch` tries it again
+    Retry {
+        /// The series' URL or part of its title
+        series: String,
+
+        #[clap(parse(try_from_str = prompt::parse_chapter_number))]
+        chapter: ChapterId,
+    },
+    /// Update manga-cli to the latest release
+    SelfUpdate,
+    /// Show how reliable each source and mirror has been, from the usage
+    /// recorded with `usage_stats = true`
+    Stats {
+        /// Only count runs of the last N days
+        #[clap(long, value_name = "N", default_value = "30")]
+        days: u64,
+
+        /// Break the numbers down by week
+        #[clap(long)]
+        weekly: bool,
+    },
+    /// Serve the finished CBZ and PDF files read-only over HTTP
+    #[cfg(feature = "serve")]
+    Serve {
+        #[clap(long, value_name = "DIR", default_value = IMAGE_DIR)]
+        library_dir: String,
+
+        #[clap(long, default_value = "8080")]
+        port: u16,
+
+        /// Address to listen on; 127.0.0.1 keeps the library off the network
+        #[clap(long, default_value = "0.0.0.0")]
+        address: std::net::IpAddr,
+    },
+    /// Tidy up the series folders in the cache
+    Library {
+        #[clap(subcommand)]
+        command: LibraryCommand,
+    },
+    /// Sort pages and outputs left in the cache by older versions into the
+    /// series folders
+    MigrateCache,
+    /// Show how much manga-cli transferred this month
+    Usage {
+        /// Start this month's count from zero again
+        #[clap(long)]
+        reset: bool,
+    },
+    /// Write the followed series, their settings, the reading history and
+    /// the failed chapters into FILE (.tar.gz), to import on another machine
+    ExportState { file: PathBuf },
+    /// Merge a file written by `export-state` into the state here, keeping
+    /// what's here where both differ
+    ImportState { file: PathBuf },
+    /// Pin the series and settings of the folder manga-cli runs in
+    Project {
+        #[clap(subcommand)]
+        command: ProjectCommand,
+    },
+    /// Maintain the index.json files --index keeps in the series folders
+    Index {
+        #[clap(subcommand)]
+        command: IndexCommand,
+    },
+    /// Show the aliases set with [alias] in the config file and the
+    /// built-in short forms
+    Alias {
+        #[clap(subcommand)]
+        command: AliasCommand,
+    },
+    /// List the sources and their mirrors
+    Sources {
+        /// Measure how fast each mirror answers
+        #[clap(long)]
+        probe: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum LibraryCommand {
+    /// Find series folders that look like the same series, e.g. one from
+    /// each source, and offer to merge them
+    Dedupe,
+    /// Share the storage of identical pages and outputs through copy-on-write
+    /// clones (btrfs, XFS, APFS), or say what that would save
+    Optimize {
+        /// Folders holding outputs to include besides the cache's
+        dirs: Vec<PathBuf>,
+
+        /// Only report what could be saved
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Write index.json for every series folder from the chapters'
+    /// manifests, without going online
+    Rebuild {
+        /// The folder of series folders; defaults to the cache's
+        #[clap(long)]
+        library_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AliasCommand {
+    /// List the aliases in effect and where each comes from
+    List,
+}
+
+#[derive(Subcommand)]
+enum ProjectCommand {
+    /// Search for the series and write manga-cli.toml here, with the source,
+    /// languages and the per-series flags given before `project`
+    Init {
+        #[clap(short, long, arg_enum, default_value = "manganelo")]
+        source: SourceKind,
+
+        manga_name: String,
+    },
+}
+
+#[derive(ArgEnum, Clone, PartialEq)]
+enum Format {
+    Pdf,
+    Cbz,
+    Html,
+}
+
+// Which end of a multi-chapter request is downloaded first. Outputs keep
+// reading order either way.
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+enum Order {
+    Oldest,
+    Newest,
+}
+
+// What a run produces: the output file name (without extension) and the
+// metadata embedded into it.
+struct Output {
+    name: String,
+    info: ComicInfo,
+    // Index of each chapter's first page and the chapter's number.
+    chapter_starts: Vec<(usize, Option<ChapterId>)>,
+}
+
+// Settings that apply to every chapter of a run.
+struct DownloadOptions {
+    // In the order given, without repeats.
+    formats: Vec<Format>,
+    // Where finished files are published.
+    output_dir: String,
+    // Names of the pages inside archives and the series cache.
+    entry_template: Option<Template>,
+    // Format to convert pages old readers can't show to.
+    compat_format: Option<CompatFormat>,
+    jobs: usize,
+    process: ProcessOptions,
+    process_jobs: usize,
+    upscale: Option<UpscaleOptions>,
+    // Page numbers drawn on copies of the pages (--stamp-pages).
+    stamp: Option<StampOptions>,
+    html: HtmlOptions,
+    pdf: PdfOptions,
+    promo: PromoOptions,
+    // Drop repeated image URLs and pages identical to the one before.
+    dedupe: bool,
+    // Trade image quality for smaller transfers.
+    low_data: bool,
+    // Keep re-encoded pages even when they came out larger.
+    always_reencode: bool,
+    // Bytes each chapter's pages may add up to (--target-size).
+    target_size: Option<u64>,
+    // Extra passes over chapters that failed, or over pages with
+    // --stream-cbz.
+    retry_passes: usize,
+    // What's done about failures the retries don't fix; asked when unset.
+    on_page_failure: Option<PagePolicy>,
+    on_chapter_failure: Option<ChapterPolicy>,
+    reproducible: bool,
+    // Check for free space before each chapter and before conversion.
+    space_check: bool,
+    // Record where each page's time goes (--profile-run).
+    profile: bool,
+    // Refuse chapters whose image count differs from the declared one.
+    strict: bool,
+    order: Order,
+    // Write pages straight into the CBZ as they arrive (--stream-cbz).
+    stream: bool,
+    // Keep outputs replaced by newer ones as <name>.bak (no --no-backup).
+    backup: bool,
+    // Run for every output published (--post-cmd).
+    post_command: Option<PostCommand>,
+    post_command_failures: PostCommandFailures,
+    // Bytes a month may transfer before no more chapters are started.
+    monthly_cap: Option<u64>,
+}
+
+// A chapter's image URLs and where each page goes in the page sequence.
+struct ChapterPages {
+    images: Vec<String>,
+    paths: Vec<String>,
+    // Number of the chapter's first page in the sequence.
+    first_page: usize,
+    done: Vec<bool>,
+}
+
+// Where download_chapter puts pages: the work directory, and the processing
+// queue when pages are processed.
+struct PageStage<'a> {
+    work: &'a WorkDir,
+    jobs: usize,
+    compat_format: Option<CompatFormat>,
+    processor: Option<&'a PageProcessor>,
+    profile: Option<&'a Mutex<Vec<PageTiming>>>,
+    strict: bool,
+    dedupe: bool,
+}
+
+const IMAGE_DIR: &str = ".cache/manga-cli";
+const MAX_REQUESTS_PER_HOST: usize = 2;
+// Room kept beside names fitted to the path limit: an output's extension and
+// ".tmp", or an HTML reader's page files; a chapter folder and its manifest
+// in a series folder; page files in a chapter folder.
+const OUTPUT_NAME_RESERVE: usize = 32;
+const SERIES_FOLDER_RESERVE: usize = 64;
+const CHAPTER_FOLDER_RESERVE: usize = 32;
+// Under IMAGE_DIR, used by --http-cache.
+const HTTP_CACHE_DIR: &str = "http";
+// Under IMAGE_DIR, pages refused by the decoding limits, kept for inspection.
+const REJECTED_DIR: &str = "rejected";
+// Under IMAGE_DIR, parsed chapter lists by series.
+const CHAPTER_LIST_DIR: &str = "chapters";
+// Added to the name of a --stream-cbz archive missing pages.
+const PARTIAL_SUFFIX: &str = ".partial";
+// Wait before retry pass N is N times this, and N times RETRY_CHAPTER_DELAY
+// between the chapters of that pass.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+const RETRY_CHAPTER_DELAY: Duration = Duration::from_secs(2);
+// Longest sleep between looks at the clock in `watch`.
+const WATCH_NAP: Duration = Duration::from_secs(60);
+// Page size assumed by the space check until this run has downloaded pages.
+const ASSUMED_PAGE_BYTES: u64 = 2 * 1024 * 1024;
+// Space checks ask for this multiple of the estimate, leaving room for
+// processed copies and outputs being built next to the pages.
+const SPACE_MARGIN: u64 = 2;
+// Search results whose manga page is fetched for alternative names, and how
+// many of those names the selection list shows.
+const ALT_TITLE_LOOKUPS: usize = 5;
+const ALT_TITLES_SHOWN: usize = 3;
+
+fn main() {
+    let mut config = Config::load();
+    let (aliases, shadowing) = aliases(&config);
+    let args: Vec<OsString> = env::args_os().collect();
+    if let Some(name) = args.get(1).and_then(|arg| arg.to_str()) {
+        if shadowing.iter().any(|shadowed| shadowed == name) {
+            ui::warn(&format!(
+                "the alias {} in {} is ignored: {} is a command.",
+                name,
+                Config::path().display(),
+                name
+            ));
+        }
+    }
+    let args = alias::expand(args, &aliases).unwrap_or_else(|e| {
+        ui::error(&e);
+        std::process::exit(2);
+    });
+    alias::set_args(args.clone());
+    let matches = CLI::command().get_matches_from(args);
+    let cli = CLI::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    ui::set_plain(cli.plain);
+    ui::set_no_color(cli.no_color);
+    ui::install_panic_hook();
+    filename::set_transliterate(cli.transliterate_filenames);
+
+    if cli.clear {
+        check_clear_args(&cli, &matches);
+        if cli.stale {
+            clear_stale();
+        } else {
+            clear_cache();
+        }
+        if !cli.then_download {
+            return;
+        }
+    }
+
+    env_logger::Builder::new()
+        .filter_level(if cli.verbose {
+            log::LevelFilter::Debug
+        } else {
+            log::LevelFilter::Warn
+        })
+        .format_timestamp(None)
+        .write_style(if ui::plain() {
+            env_logger::WriteStyle::Never
+        } else {
+            env_logger::WriteStyle::Auto
+        })
+        .parse_default_env()
+        .init();
+
+    if cli.command.is_none() && !cli.no_wizard && wizard::wanted() {
+        match wizard::run(IMAGE_DIR) {
+            Ok(()) => config = Config::load(),
+            Err(e) => println!("Setup stopped ({}); continuing without a config file.", e),
+        }
+    }
+    let config = config;
+    let mut pinned = config.mirror.clone();
+    if let (Some(mirror), Some(kind)) = (&cli.mirror, cli.source.kind()) {
+        pinned.insert(source(kind, &[], false).name().to_string(), mirror.clone());
+    }
+    mirrors::pin(pinned);
+    http::configure_headers(config.headers.clone());
+    if let Some(seconds) = config.tool_timeout {
+        exec::set_timeout(Duration::from_secs(seconds));
+    }
+    if cli.print_request {
+        http::print_failed_requests();
+    }
+    if let Some(template) = &config.chapter_url_template {
+        source::set_chapter_url_template(template.clone());
+    }
+    let defaults = DecodeLimits::default();
+    decode::set_limits(DecodeLimits {
+        max_side: config.max_image_side.unwrap_or(defaults.max_side),
+        max_pixels: config.max_image_pixels.unwrap_or(defaults.max_pixels),
+    });
+    if let Some(max) = config.max_path_length {
+        filename::set_max_path(max);
+    }
+    // `info` must leave the cache as it was.
+    if cli.http_cache && !matches!(cli.command, Some(Command::Info { .. })) {
+        http::enable_cache(&Path::new(IMAGE_DIR).join(HTTP_CACHE_DIR), cli.refresh);
+    }
+    if let Some(dir) = &cli.offline_fixtures {
+        if let Err(e) = http::use_fixtures(dir) {
+            ui::error(&e.to_string());
+            std::process::exit(1);
+        }
+    }
+    if cli.bug_report.is_some()
+        || matches!(
+            cli.command,
+            Some(Command::Doctor {
+                bug_report: Some(_),
+                ..
+            })
+        )
+    {
+        http::record_pages();
+    }
+
+    match &cli.command {
+        Some(Command::Doctor {
+            json,
+            bug_report,
+            apply_suggestion,
+        }) => {
+            let languages = languages(&cli, &config);
+            if !doctor::run(&languages, *json, bug_report.as_deref(), *apply_suggestion) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Follow {
+            source: kind,
+            set,
+            unset,
+            schedule,
+            manga_name,
+        }) => {
+            let source = source(*kind, &languages(&cli, &config), false);
+            let edits = FollowEdits {
+                set,
+                unset,
+                schedule: schedule.as_deref(),
+            };
+            if let Err(e) = follow(source.as_ref(), manga_name, &edits) {
+                ui::error(&e.to_string());
+                std::process::exit(error::exit_code(e.as_ref()));
+            }
+            return;
+        }
+        Some(Command::Info {
+            source: kind,
+            chapters,
+            sizes,
+            json,
+            jobs,
+            manga_name,
+        }) => {
+            let languages = languages(&cli, &config);
+            // A manga URL picks its own source.
+            let kind = kind_for_url(manga_name).unwrap_or(*kind);
+            let source = source(kind, &languages, false);
+            let listed = list_chapters(source.as_ref(), manga_name, chapters.as_ref(), &languages);
+            match listed {
+                Ok(chapters) => info::print(source.as_ref(), &chapters, *sizes, *json, *jobs),
+                Err(e) => {
+                    ui::error(&e.to_string());
+                    std::process::exit(error::exit_code(e.as_ref()));
+                }
+            }
+            return;
+        }
+        #[cfg(feature = "serve")]
+        Some(Command::Serve {
+            library_dir,
+            port,
+            address,
+        }) => {
+            let options = serve::ServeOptions {
+                library_dir: library_dir.into(),
+                address: std::net::SocketAddr::new(*address, *port),
+                password: config.serve_password.clone(),
+            };
+            if let Err(e) = serve::run(&options) {
+                ui::error(&format!("Failed to serve {}: {}", library_dir, e));
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Index {
+            command: IndexCommand::Rebuild { library_dir },
+        }) => {
+            let root = library_dir
+                .clone()
+                .unwrap_or_else(|| series_root(IMAGE_DIR));
+            if let Err(e) = rebuild_indexes(&root) {
+                ui::error(&e.to_string());
+                std::process::exit(error::exit_code(e.as_ref()));
+            }
+            return;
+        }
+        Some(Command::Alias {
+            command: AliasCommand::List,
+        }) => {
+            list_aliases(&config);
+            return;
+        }
+        Some(Command::Sources { probe }) => {
+            list_sources(*probe);
+            return;
+        }
+        Some(Command::Stats { days, weekly }) => {
+            stats::print(*days, *weekly);
+            return;
+        }
+        Some(Command::Status) => {
+            print_status();
+            return;
+        }
+        Some(Command::Retry { series, chapter }) => {
+            let mut failures = FailureStore::load();
+            let released = failures.release(series, chapter).and_then(|title| {
+                failures.save().map_err(|e| e.to_string())?;
+                Ok(title)
+            });
+            match released {
+                Ok(title) => println!(
+                    "{} chapter {} is tried again at the next check.",
+                    title, chapter
+                ),
+                Err(e) => {
+                    ui::error(&e.to_string());
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Library {
+            command: LibraryCommand::Dedupe,
+        }) => {
+            if let Err(e) = library_dedupe() {
+                ui::error(&e.to_string());
+                std::process::exit(error::exit_code(e.as_ref()));
+            }
+            return;
+        }
+        Some(Command::Library {
+            command: LibraryCommand::Optimize { dirs, dry_run },
+        }) => {
+            if let Err(e) = library_optimize(dirs, *dry_run) {
+                ui::error(&e.to_string());
+                std::process::exit(error::exit_code(e.as_ref()));
+            }
+            return;
+        }
+        Some(Command::Project {
+            command: ProjectCommand::Init { source, manga_name },
+        }) => {
+            if let Err(e) = init_project(&cli, &matches, &config, *source, manga_name) {
+                ui::error(&format!("Failed to set up the project: {}", e));
+                std::process::exit(error::exit_code(e.as_ref()));
+            }
+            return;
+        }
+        Some(Command::MigrateCache) => {
+            if let Err(e) = migrate_cache() {
+                ui::error(&format!("Failed to migrate the cache: {}", e));
+                std::process::exit(error::exit_code(e.as_ref()));
+            }
+            return;
+        }
+        Some(Command::Usage { reset }) => {
+            if let Err(e) = transfer::print(config.monthly_cap, *reset) {
+                ui::error(&format!("Failed to reset this month's transfer: {}", e));
+                std::process::exit(error::exit_code(e.as_ref()));
+            }
+            return;
+        }
+        Some(Command::ExportState { file }) => {
+            if let Err(e) = state::export(file) {
+                ui::error(&format!("Failed to export the state: {}", e));
+                std::process::exit(error::exit_code(e.as_ref()));
+            }
+            return;
+        }
+        Some(Command::ImportState { file }) => {
+            if let Err(e) = state::import(file) {
+                ui::error(&format!("Failed to import the state: {}", e));
+                std::process::exit(error::exit_code(e.as_ref()));
+            }
+            return;
+        }
+        Some(Command::SelfUpdate) => {
+            if let Err(e) = update::self_update() {
+                ui::error(&format!("Self-update failed: {}", e));
+                std::process::exit(error::exit_code(e.as_ref()));
+            }
+            return;
+        }
+        Some(
+            Command::Batch { .. }
+            | Command::Fresh { .. }
+            | Command::Watch { .. }
+            | Command::Diff { .. }
+            | Command::Gaps { .. },
+        )
+        | None => {}
+    }
+
+    if config.update_check != Some(false) && !http::offline() && !cli.prefetching {
+        update::check_for_update();
+    }
+
+    if let Some(Command::Batch { strict, file }) = &cli.command {
+        std::process::exit(run_batch(&cli, &config, file, *strict));
+    }
+    if let Some(Command::Fresh { since }) = &cli.command {
+        std::process::exit(run_fresh(&cli, &config, *since));
+    }
+    if let Some(Command::Watch {
+        schedule,
+        since,
+        quarantine_after,
+    }) = &cli.command
+    {
+        let options = WatchOptions {
+            schedule: schedule.clone(),
+            since: *since,
+            quarantine_after: *quarantine_after,
+        };
+        std::process::exit(run_watch(&cli, &config, &options));
+    }
+    if let Some(Command::Diff {
+        source: kind,
+        pages,
+        update_if_changed,
+        jobs,
+        manga_name,
+        chapter,
+    }) = &cli.command
+    {
+        let options = DiffOptions {
+            pages: *pages,
+            update: *update_if_changed,
+            jobs: *jobs,
+        };
+        std::process::exit(run_diff(
+            &cli, &config, *kind, manga_name, chapter, &options,
+        ));
+    }
+
+    if let Some(Command::Gaps {
+        source: kind,
+        library_dir,
+        fill,
+        manga_name,
+    }) = &cli.command
+    {
+        std::process::exit(run_gaps(
+            &cli,
+            &config,
+            *kind,
+            manga_name,
+            library_dir.as_deref(),
+            *fill,
+        ));
+    }
+
+    offer_cache_migration();
+
+    let started = Instant::now();
+    let mut report = Report::new(
+        cli.manga_name
+            .as_deref()
+            .or(cli.from_dir.as_deref())
+            .unwrap_or_default(),
+    );
+    let args: Vec<OsString> = alias::args();
+    let result = run(&cli, &matches, &args, &config, &mut report);
+    report.finish(
+        started.elapsed(),
+        result.as_ref().err().map(|e| e.to_string()),
+    );
+    record_usage(
+        &config,
+        &mut report,
+        result.as_ref().err().map(|e| e.as_ref()),
+    );
+    report.print_summary();
+    apply_suggestion(&cli, &report);
+    let viewer = cli
+        .viewer
+        .clone()
+        .or_else(|| config.defaults().remove("viewer"));
+    if let (Ok(()), Some(viewer), Some(output)) = (&result, viewer, report.outputs.first()) {
+        let prefetch = cli.prefetch
+            || config
+                .defaults()
+                .get("prefetch")
+                .is_some_and(|value| value == "true");
+        let prefetch = match (&report.read, &report.next_chapter) {
+            (Some((manga_url, _)), Some(next)) if prefetch => {
+                start_prefetch(&matches, manga_url, next)
+            }
+            _ => None,
+        };
+        let mark = report
+            .read
+            .as_ref()
+            .filter(|(_, numbers)| !cli.no_mark_read && !numbers.is_empty());
+        // Waiting tells when the chapter was read and when to stop prefetching.
+        let closed = open_in_viewer(&viewer, output, mark.is_some() || prefetch.is_some());
+        if let Some(prefetch) = prefetch {
+            prefetch.finish();
+        }
+        if let (true, Some((manga_url, numbers))) = (closed, mark) {
+            mark_read(manga_url, &report.manga, numbers);
+        }
+    }
+    if let Some(path) = &cli.report {
+        if let Err(e) = report.save(path) {
+            ui::error(&format!("Failed to write report {}: {}", path, e));
+        }
+    }
+    if let (Err(e), Some(path)) = (&result, &cli.bug_report) {
+        match bug_report::write(Path::new(path), &args, Some(e.as_ref()), &[]) {
+            Ok(()) => println!(
+                "Wrote a bug report to {}; attach it to an issue after looking it over.",
+                path
+            ),
+            Err(e) => ui::error(&format!("Failed to write bug report {}: {}", path, e)),
+        }
+    }
+    if let Err(e) = result {
+        ui::error(&e.to_string());
+        std::process::exit(error::exit_code(e.as_ref()));
+    }
+}
+
+// With --apply-suggestion, saves the delays the run's summary suggested.
+fn apply_suggestion(cli: &CLI, report: &Report) {
+    if cli.apply_suggestion {
+        for done in rate_limit::apply(&report.rate_limits) {
+            println!("{}", done);
+        }
+    }
+}
+
+// Spaces requests to the same host by --delay, else by the source's delay
+// in the config file.
+fn configure_requests(cli: &CLI, config: &Config, source: &str) {
+    let delay = cli
+        .delay
+        .or_else(|| config.delay.get(source).copied())
+        .unwrap_or(0);
+    rate_limit::configure(
+        source,
+        Duration::from_millis(delay),
+        cli.jobs.min(MAX_REQUESTS_PER_HOST),
+    );
+}
+
+// Chapter languages to accept, most preferred first.
+fn languages(cli: &CLI, config: &Config) -> Vec<String> {
+    if !cli.lang.is_empty() {
+        cli.lang.clone()
+    } else if !config.lang.is_empty() {
+        config.lang.clone()
+    } else {
+        vec!["en".to_string()]
+    }
+}
+
+fn download_options(cli: &CLI, config: &Config) -> DownloadOptions {
+    DownloadOptions {
+        formats: if cli.stream_cbz {
+            vec![Format::Cbz]
+        } else {
+            cli.format.iter().fold(Vec::new(), |mut formats, format| {
+                if !formats.contains(format) {
+                    formats.push(format.clone());
+                }
+                formats
+            })
+        },
+        output_dir: cli
+            .output_dir
+            .clone()
+            .unwrap_or_else(|| IMAGE_DIR.to_string()),
+        entry_template: cli.entry_template.clone(),
+        compat_format: cli.compat_format,
+        jobs: cli.jobs,
+        process_jobs: cli.process_jobs.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|cores| cores.get())
+                .unwrap_or(1)
+        }),
+        process: ProcessOptions {
+            trim: cli.trim_margins.then_some(TrimOptions {
+                safety_margin: cli.trim_safety_margin,
+                max_crop: cli.trim_max_crop,
+            }),
+            levels: level_options(cli),
+            exif_rotate: !cli.no_exif_rotate,
+        },
+        upscale: cli.upscale_cmd.clone().map(|template| UpscaleOptions {
+            template,
+            jobs: cli.upscale_jobs,
+            fallback: cli.upscale_fallback,
+        }),
+        stamp: cli.stamp_pages.map(|corner| StampOptions {
+            corner,
+            chapter: cli.stamp_chapter,
+            opacity: cli.stamp_opacity / 100.0,
+            size: cli.stamp_size,
+        }),
+        html: HtmlOptions {
+            single_file: cli.single_file,
+            rtl: cli.rtl,
+        },
+        pdf: PdfOptions {
+            page_size: cli.pdf_page_size,
+            margin: cli.pdf_margin,
+            max_side: (!cli.no_split_oversize).then_some(cli.pdf_max_side),
+        },
+        promo: PromoOptions {
+            skip: cli.skip_promo_pages,
+            known_hashes: config.promo_hashes.clone(),
+            keep_ads: cli.keep_ads,
+            ad_url_patterns: config.ad_url_patterns.clone(),
+        },
+        dedupe: !cli.no_dedupe,
+        low_data: cli.low_data,
+        always_reencode: cli.always_reencode,
+        target_size: cli.target_size,
+        retry_passes: cli.retry_passes,
+        on_page_failure: cli.on_page_failure,
+        on_chapter_failure: cli.on_chapter_failure,
+        reproducible: cli.reproducible,
+        space_check: !cli.no_space_check,
+        profile: cli.profile_run,
+        strict: cli.strict,
+        order: cli.order,
+        stream: cli.stream_cbz,
+        backup: !cli.no_backup,
+        post_command: cli.post_cmd.clone(),
+        post_command_failures: cli.post_cmd_failures,
+        monthly_cap: config.monthly_cap.filter(|_| !cli.ignore_monthly_cap),
+    }
+}
+
+// PDF output needs ImageMagick. It is looked for before downloading, so a
+// missing install doesn't waste the transfer; --fallback-format builds a
+// format without external tools in its place instead of failing.
+fn check_tools(
+    cli: &CLI,
+    options: &mut DownloadOptions,
+    report: &mut Report,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !options.formats.contains(&Format::Pdf) || tools::imagemagick().is_some() {
+        return Ok(());
+    }
+    let fallback = match &cli.fallback_format {
+        Some(Format::Pdf) => {
+            return Err(
+                "--fallback-format needs a format without external tools, cbz or html.".into(),
+            )
+        }
+        Some(fallback) => fallback.clone(),
+        None => {
+            return Err(Error::MissingTool {
+                tool: "ImageMagick (magick or convert)".to_string(),
+                purpose: "PDF output".to_string(),
+                hint: "install it from https://imagemagick.org or pass --fallback-format cbz"
+                    .to_string(),
+            }
+            .into())
+        }
+    };
+    let substitution = format!(
+        "{} instead of {}, ImageMagick wasn't found",
+        format_label(&fallback),
+        format_label(&Format::Pdf)
+    );
+    println!("Creating {}.", substitution);
+    let mut formats = Vec::new();
+    for format in options.formats.drain(..) {
+        let format = if format == Format::Pdf {
+            fallback.clone()
+        } else {
+            format
+        };
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+    options.formats = formats;
+    report.format_substitutions.push(substitution);
+    Ok(())
+}
+
+fn run(
+    cli: &CLI,
+    matches: &ArgMatches,
+    args: &[OsString],
+    config: &Config,
+    report: &mut Report,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Config defaults apply first, then per-series settings, then the command
+    // line.
+    let mut defaults = config.defaults();
+    overrides::check_defaults(&defaults)
+        .map_err(|e| format!("Invalid [defaults] in {}: {}", Config::path().display(), e))?;
+    // A project file's settings go over the config file's.
+    let project = project::find()?;
+    let project_settings = match &project {
+        Some((path, project)) => {
+            let settings = project.settings(path);
+            overrides::check_project(&settings)
+                .map_err(|e| format!("Invalid settings in {}: {}", path.display(), e))?;
+            log::info!("Using the project file {}", path.display());
+            log_overridden(&settings, &defaults, "the project", "config.toml");
+            for key in settings.keys() {
+                if matches.value_source(key) == Some(ValueSource::CommandLine) {
+                    log::info!("--{} on the command line overrides the project's", key);
+                }
+            }
+            settings
+        }
+        None => BTreeMap::new(),
+    };
+    defaults.extend(project_settings.clone());
+    let project_url = project.and_then(|(_, project)| project.url);
+    let defaulted;
+    let cli = if defaults.is_empty() {
+        cli
+    } else {
+        defaulted = overrides::apply(args, matches, &defaults)?;
+        &defaulted
+    };
+    // Checked again once per-series settings apply, but failing here spares
+    // the search.
+    check_stream_args(cli)?;
+    let mut options = download_options(cli, config);
+    let mut languages = languages(cli, config);
+    remove_unfinished_outputs(&options.output_dir);
+    if let Some(dir) = &cli.from_dir {
+        check_tools(cli, &mut options, report)?;
+        return package_directory(dir, &options, report)
+            .map_err(|e| context(format!("Failed to package images: {}", e), e));
+    }
+
+    // A manga URL picks its own source.
+    let named = cli.manga_name.as_ref().or(project_url.as_ref());
+    let choice = match named.and_then(|name| kind_for_url(name)) {
+        Some(kind) => SourceChoice::One(kind),
+        None => cli.source,
+    };
+    // Searching is spaced too; per-series settings may change the delay later.
+    if let SourceChoice::One(kind) = choice {
+        configure_requests(cli, config, source(kind, &[], false).name());
+    }
+    let last = if cli.again || cli.last_selection {
+        LastSelection::load().filter(|last| match choice {
+            SourceChoice::One(kind) => last.source == source(kind, &[], false).name(),
+            SourceChoice::All => kind_named(&last.source).is_some(),
+        })
+    } else {
+        None
+    };
+    let last = match last {
+        Some(last) if cli.last_selection => Some(last),
+        Some(last) if prompt::confirm(&format!("Last time you picked {}, use it?", last.title)) => {
+            Some(last)
+        }
+        _ => None,
+    };
+    let query =
+        match (&cli.manga_name, &last, &project_url) {
+            (Some(name), _, _) => name.clone(),
+            (None, Some(last), _) => last.query.clone(),
+            (None, None, Some(url)) => url.clone(),
+            (None, None, None) if cli.again || cli.last_selection => {
+                return Err("No previous selection to reuse; give a manga name.".into())
+            }
+            (None, None, None) => return Err(
+                "No manga given; name one, or run inside a project (see `manga-cli project init`)."
+                    .into(),
+            ),
+        };
+    // With --source all, the picked result binds the rest of the run to its
+    // source. The result's other names help spot the series in the library.
+    let (manga_link, kind, alt_titles) = match (&last, choice) {
+        (Some(last), _) => (
+            last.manga_url.clone(),
+            kind_named(&last.source).ok_or("Last selection names an unknown source.")?,
+            Vec::new(),
+        ),
+        (None, SourceChoice::One(kind)) if batch::is_url(&query) => {
+            (query.clone(), kind, Vec::new())
+        }
+        (None, SourceChoice::All) if batch::is_url(&query) => {
+            return Err(format!("No source knows {}; pick one with --source.", query).into())
+        }
+        (None, SourceChoice::One(kind)) => {
+            let source = source(kind, &languages, cli.low_data);
+            let result = find_manga(source.as_ref(), &query, cli.match_pattern.as_ref())?;
+            (result.url, kind, result.alt_titles)
+        }
+        (None, SourceChoice::All) => {
+            let (kind, result) = find_manga_everywhere(
+                &query,
+                &languages,
+                cli.low_data,
+                cli.match_pattern.as_ref(),
+            )?;
+            (result.url, kind, result.alt_titles)
+        }
+    };
+    let mut source = source(kind, &languages, cli.low_data);
+    report.source = source.name().to_string();
+
+    // Stored per-series settings apply unless the flag was given explicitly.
+    let mut store = SeriesStore::load();
+    let series_overrides = store.get(&manga_link).overrides;
+    let overridden;
+    let cli = if series_overrides.is_empty() {
+        cli
+    } else {
+        let mut overrides = defaults;
+        log_overridden(
+            &project_settings,
+            &series_overrides,
+            "the project",
+            "the series' settings",
+        );
+        overrides.extend(series_overrides);
+        overrides.extend(project_settings);
+        overridden = overrides::apply(args, matches, &overrides)?;
+        options = download_options(&overridden, config);
+        languages = self::languages(&overridden, config);
+        source = self::source(kind, &languages, overridden.low_data);
+        report.source = source.name().to_string();
+        &overridden
+    };
+    let manga_link = &manga_link;
+    configure_requests(cli, config, source.name());
+    check_stream_args(cli)?;
+    check_tools(cli, &mut options, report)?;
+    // Flags from the command line the source would otherwise quietly ignore.
+    let unsupported =
+        source::unsupported_flags(source.as_ref(), |flag| matches.occurrences_of(flag) > 0);
+    if !unsupported.is_empty() {
+        let message = format!(
+            "{} doesn't support {}",
+            source.name(),
+            unsupported.join(", ")
+        );
+        if !cli.lenient {
+            return Err(format!("{}; pass --lenient to go on without them.", message).into());
+        }
+        report.warnings.push(format!("{}; ignored.", message));
+    }
+
+    // The chapter list mostly adds metadata to single-chapter downloads, so
+    // failing to get it shouldn't stop them.
+    // Picking the next or latest chapter needs the current list, or new
+    // chapters would be missed until the cached one expires.
+    let current =
+        cli.refresh || cli.last_selection || matches!(cli.chapter, Some(ChapterSpec::Latest));
+    let manga = match (
+        chapter_list(source.as_ref(), manga_link, &languages, config, current),
+        &last,
+    ) {
+        // A remembered series that no longer loads has probably moved. Keep
+        // it when the network is merely unreachable.
+        (Err(e), Some(_)) => {
+            let offline = e.downcast_ref::<Error>().is_some_and(Error::is_offline);
+            if !offline {
+                LastSelection::clear();
+            }
+            return Err(context(
+                format!("Last selection {} doesn't load: {}", manga_link, e),
+                e,
+            ));
+        }
+        (Ok(manga), Some(_)) if manga.chapters.is_empty() => {
+            LastSelection::clear();
+            return Err(format!(
+                "Last selection {} no longer lists any chapters; search for it again.",
+                manga_link
+            )
+            .into());
+        }
+        (Ok(manga), _) => manga,
+        (Err(e), None) => {
+            // A site that changed its layout, rather than one that's down.
+            if error::class(e.as_ref()) == "parse" {
+                let problem = format!("The chapter list of {} couldn't be read", manga_link);
+                let (policy, _) = policy::decide(
+                    &problem,
+                    cli.on_parse_failure,
+                    ParsePolicy::Fallback,
+                    &mut report.decisions,
+                );
+                if policy == ParsePolicy::Abort {
+                    return Err(e);
+                }
+            }
+            Manga {
+                title: title_from_url(manga_link),
+                chapters: Vec::new(),
+            }
+        }
+    };
+    report.manga = manga.title.clone();
+
+    let preferred_group = cli.group.clone().or_else(|| store.get(manga_link).group);
+
+    let (chapters, output) = match (&cli.volume, &cli.chapters) {
+        (Some(volume), _) => {
+            let chapters = pick_versions(
+                chapters_in_volume(&manga, volume),
+                preferred_group.as_deref(),
+                &languages,
+            );
+            if chapters.is_empty() {
+                return Err(format!("No chapters found for volume {}.", volume).into());
+            }
+            let output = volume_output(&manga, volume, &options.output_dir, report);
+            (chapters, output)
+        }
+        (None, Some(range)) => {
+            let mut chapters = pick_versions(
+                chapters_in_range(&manga, range),
+                preferred_group.as_deref(),
+                &languages,
+            );
+            if let ChapterRange::From(first) = range {
+                chapters = follow_next_links(source.as_ref(), manga_link, &manga, chapters, first);
+            }
+            let (Some(first), Some(last)) = (chapters.first(), chapters.last()) else {
+                return Err(format!("No chapters match {}.", range).into());
+            };
+            println!(
+                "Chapters {}: {} chapter(s), {} to {}",
+                range,
+                chapters.len(),
+                first.name,
+                last.name
+            );
+            let output = range_output(&manga, &chapters, &options.output_dir, report);
+            (chapters, output)
+        }
+        (None, None) => {
+            let number = match &cli.chapter {
+                Some(ChapterSpec::Latest) => {
+                    let number = latest_number(&manga).ok_or("The chapter list is empty.")?;
+                    println!("latest is chapter {}", number);
+                    number
+                }
+                Some(ChapterSpec::Number(number)) => number.clone(),
+                None => {
+                    let next = last.as_ref().and_then(|last| last.next_chapter());
+                    match next {
+                        Some(next) if cli.last_selection => next,
+                        Some(next) => prompt::ask(
+                            &format!("Enter chapter number [{}]: ", next),
+                            Some(next),
+                            prompt::parse_chapter_number,
+                        )?,
+                        None => prompt::ask(
+                            "Enter chapter number: ",
+                            None,
+                            prompt::parse_chapter_number,
+                        )?,
+                    }
+                }
+            };
+            let chapter = select_chapter(
+                source.as_ref(),
+                manga_link,
+                &manga,
+                &number,
+                preferred_group.as_deref(),
+                &languages,
+            )
+            .ok_or(Error::MissingChapter {
+                number: number.to_string(),
+            })?;
+            let mut output = range_output(
+                &manga,
+                std::slice::from_ref(&chapter),
+                &options.output_dir,
+                report,
+            );
+            output.info.number = Some(number);
+            output.info.volume = chapter.volume.clone();
+            (vec![chapter], output)
+        }
+    };
+
+    if let Some(group) = &preferred_group {
+        for chapter in &chapters {
+            let used = chapter.group.as_deref().unwrap_or("another group");
+            if !used.eq_ignore_ascii_case(group) {
+                report.warnings.push(format!(
+                    "{} has no release of {}, used {}.",
+                    group, chapter.name, used
+                ));
+            }
+        }
+    }
+
+    if let Some(file) = &cli.export_urls {
+        return export_urls(source.as_ref(), &chapters, file, options.dedupe)
+            .map_err(|e| context(format!("Failed to export image URLs: {}", e), e));
+    }
+
+    // --bundle-every splits the range into outputs of that many chapters.
+    let bundles: Vec<(&[Chapter], Output)> = match cli.bundle_every {
+        Some(size) => chapters
+            .chunks(size)
+            .map(|bundle| {
+                let output = range_output(&manga, bundle, &options.output_dir, report);
+                (bundle, output)
+            })
+            .collect(),
+        None => vec![(&chapters[..], output)],
+    };
+    // A bundle is only published once complete, so one that exists is kept
+    // unless asked otherwise.
+    let on_conflict = cli
+        .on_conflict
+        .or(cli.bundle_every.map(|_| OnConflict::Skip));
+    let mut pending = Vec::new();
+    for (bundle, mut output) in bundles {
+        if cli.skip_existing && outputs_exist(&output, &options) {
+            println!("{} already exists, skipping.", output.name);
+            continue;
+        }
+        // Decided before downloading, so a skipped output costs no transfer.
+        if !existing_outputs(&output.name, &options).is_empty() {
+            match conflict::resolve(&output.name, on_conflict)? {
+                OnConflict::Overwrite => {}
+                OnConflict::Skip => {
+                    println!("{} already exists, skipping.", output.name);
+                    continue;
+                }
+                OnConflict::Rename => {
+                    let name = conflict::free_name(&output.name, |name| {
+                        !existing_outputs(name, &options).is_empty()
+                    });
+                    println!("{} already exists, saving as {}.", output.name, name);
+                    output.name = name;
+                }
+                OnConflict::Fail => {
+                    return Err(format!(
+                        "{} already exists; pass --on-conflict overwrite, skip or rename.",
+                        output.name
+                    )
+                    .into())
+                }
+            }
+        }
+        pending.push((bundle, output));
+    }
+    if pending.is_empty() {
+        report.skipped = true;
+        return Ok(());
+    }
+
+    offer_merge(&mut store, manga_link, &manga.title, &alt_titles);
+
+    for (bundle, output) in pending {
+        if options.stream {
+            stream_cbz(source.as_ref(), bundle, output, &options, report)
+        } else {
+            download_chapters(
+                source.as_ref(),
+                manga_link,
+                bundle,
+                output,
+                &options,
+                report,
+            )
+        }
+        .map_err(|e| context(format!("Failed to download chapter: {}", e), e))?;
+        // The viewer gets the first output.
+        report.read.get_or_insert_with(|| {
+            (
+                manga_link.clone(),
+                bundle
+                    .iter()
+                    .filter_map(|chapter| chapter.number.clone())
+                    .collect(),
+            )
+        });
+    }
+
+    if cli.series_json || cli.index {
+        let details = source
+            .details(manga_link)
+            .map_err(|e| {
+                report
+                    .warnings
+                    .push(format!("Failed to fetch the series details: {}", e))
+            })
+            .ok();
+        let dir = series_dir(IMAGE_DIR, &series_folder(&manga.title, manga_link).0);
+        if let (true, Some(details)) = (cli.series_json, &details) {
+            if let Err(e) = write_series_json(&dir, manga_link, &manga, details) {
+                report
+                    .warnings
+                    .push(format!("Failed to update series.json: {}", e));
+            }
+        }
+        // Without details the chapters are still added.
+        if cli.index {
+            if let Err(e) = index::update(&dir, details.as_ref()) {
+                report
+                    .warnings
+                    .push(format!("Failed to update index.json: {}", e));
+            }
+        }
+    }
+
+    // The listed chapter after those the viewer gets.
+    if let Some((_, read)) = &report.read {
+        report.next_chapter = read.iter().max().and_then(|last| {
+            manga
+                .chapters
+                .iter()
+                .filter_map(|chapter| chapter.number.as_ref())
+                .filter(|number| *number > last)
+                .min()
+                .cloned()
+        });
+    }
+
+    let selection = LastSelection {
+        source: source.name().to_string(),
+        query,
+        manga_url: manga_link.clone(),
+        title: manga.title.clone(),
+        chapter: chapters.last().and_then(|chapter| chapter.number.clone()),
+    };
+    // A prefetched chapter hasn't been picked yet, so --last-selection still
+    // goes on from the one being read.
+    if !cli.prefetching {
+        if let Err(e) = selection.save() {
+            report
+                .warnings
+                .push(format!("Failed to save last selection: {}", e));
+        }
+    }
+
+    // Remember the group so later downloads of the series stay consistent.
+    if let Some(group) = chapters.iter().find_map(|chapter| chapter.group.clone()) {
+        store.get_mut(manga_link).group = Some(group);
+        if let Err(e) = store.save() {
+            report
+                .warnings
+                .push(format!("Failed to save series settings: {}", e));
+        }
+    }
+    Ok(())
+}
+
+// Logs for --verbose the settings of `over` that replace different ones of
+// `under`, so a surprising value can be traced to where it came from.
+fn log_overridden(
+    over: &BTreeMap<String, String>,
+    under: &BTreeMap<String, String>,
+    over_name: &str,
+    under_name: &str,
+) {
+    for (key, value) in over {
+        if let Some(old) = under.get(key).filter(|old| *old != value) {
+            log::info!(
+                "{} = {} from {} overrides {} from {}",
+                key,
+                value,
+                over_name,
+                old,
+                under_name
+            );
+        }
+    }
+}
+
+// Refreshes series.json in the series cache folder `dir` (--series-json)
+// with the site's current metadata.
+fn write_series_json(
+    dir: &Path,
+    manga_link: &str,
+    manga: &Manga,
+    details: &Details,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let now = OffsetDateTime::now_utc();
+    let year = manga
+        .chapters
+        .iter()
+        .filter_map(|chapter| chapter.uploaded.as_deref())
+        .filter_map(|uploaded| parse_release_date(uploaded, now))
+        .min()
+        .unwrap_or(now)
+        .year();
+    let metadata = SeriesMetadata {
+        kind: "comicSeries".to_string(),
+        publisher: String::new(),
+        name: manga.title.clone(),
+        comicid: manga_link
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        year,
+        description_text: details.description.clone(),
+        booktype: "Print".to_string(),
+        total_issues: manga.chapters.len(),
+        publication_run: String::new(),
+        status: series_json::status(details.status.as_deref()),
+        alt_titles: details.alt_titles.clone(),
+        authors: details.authors.clone(),
+        genres: details.genres.clone(),
+        source_url: manga_link.to_string(),
+        locked: Vec::new(),
+    };
+    series_json::update(dir, &metadata)
+}
+
+// The series' chapter list, from the cache unless `current` asks for a fresh
+// one.
+fn chapter_list(
+    source: &dyn Source,
+    manga_url: &str,
+    languages: &[String],
+    config: &Config,
+    current: bool,
+) -> SourceResult<Manga> {
+    let dir = Path::new(IMAGE_DIR).join(CHAPTER_LIST_DIR);
+    let ttl = config
+        .chapter_list_hours
+        .map(|hours| Duration::from_secs(hours * 60 * 60))
+        .unwrap_or(chapters::DEFAULT_TTL);
+    if !current {
+        if let Some(manga) = chapters::load(&dir, source.name(), manga_url, languages, ttl) {
+            log::debug!("Using the cached chapter list of {}", manga_url);
+            return Ok(manga);
+        }
+    }
+    let manga = source.manga(manga_url)?;
+    // An empty list is more likely a parsing problem than a new series.
+    if !manga.chapters.is_empty() {
+        chapters::store(&dir, source.name(), manga_url, languages, &manga);
+    }
+    Ok(manga)
+}
+
+// Starts `viewer`, a command line such as "zathura --fork", on `output`,
+// waiting for it to exit when `wait` is set. Returns whether it started and,
+// when waited for, exited cleanly.
+fn open_in_viewer(viewer: &str, output: &str, wait: bool) -> bool {
+    let Some(mut words) = shlex::split(viewer).filter(|words| !words.is_empty()) else {
+        ui::warn(&format!("can't run viewer \"{}\".", viewer));
+        return false;
+    };
+    let program = words.remove(0);
+    let mut child = match exec::Tool::new(&program)
+        .args(words)
+        .arg(exec::path_arg(output))
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            ui::warn(&e.to_string());
+            return false;
+        }
+    };
+    !wait || child.wait().is_ok_and(|status| status.success())
+}
+
+// Starts downloading chapter `number` in the background with this run's
+// flags, less those choosing the chapters or handling the result.
+fn start_prefetch(
+    matches: &ArgMatches,
+    manga_url: &str,
+    number: &ChapterId,
+) -> Option<prefetch::Prefetch> {
+    let args = overrides::command_line(
+        matches,
+        &[
+            "chapter",
+            "chapters",
+            "volume",
+            "bundle-every",
+            "viewer",
+            "no-mark-read",
+            "prefetch",
+            "skip-existing",
+            "again",
+            "last-selection",
+            "export-urls",
+            "report",
+            "bug-report",
+            "profile-run",
+        ],
+    );
+    match prefetch::start(Path::new(IMAGE_DIR), args, manga_url, number.clone()) {
+        Ok(prefetch) => {
+            println!("Prefetching chapter {} while you read.", number);
+            Some(prefetch)
+        }
+        Err(e) => {
+            ui::warn(&format!("failed to start prefetching: {}", e));
+            None
+        }
+    }
+}
+
+// Records chapters as read after their viewer exits. Viewers that fork
+// return at once, so this is a best guess rather than proof of reading.
+fn mark_read(manga_url: &str, title: &str, numbers: &[ChapterId]) {
+    let mut history = HistoryStore::load();
+    history.mark_read(manga_url, title, numbers);
+    if let Err(e) = history.save() {
+        ui::warn(&format!("failed to record chapters as read: {}", e));
+    }
+}
+
+// Appends the run to the usage records when the config file asks for them.
+// Runs that never got to a source, like --from-dir, aren't recorded.
+fn record_usage(
+    config: &Config,
+    report: &mut Report,
+    error: Option<&(dyn std::error::Error + 'static)>,
+) {
+    report.mirror = mirrors::used(&report.source);
+    // What the hosts limited is kept for `doctor`.
+    if let Err(e) = rate_limit::save(&report.rate_limits) {
+        report
+            .warnings
+            .push(format!("Failed to record rate limiting: {}", e));
+    }
+    // The monthly transfer is kept whether or not usage is recorded.
+    match transfer::record() {
+        Ok(month) => report.month_transferred = Some(month),
+        Err(e) => report
+            .warnings
+            .push(format!("Failed to record this month's transfer: {}", e)),
+    }
+    if !config.usage_stats || report.source.is_empty() {
+        return;
+    }
+    let record = UsageRecord::new(report, error.map(error::class));
+    if let Err(e) = record.append() {
+        report
+            .warnings
+            .push(format!("Failed to record usage: {}", e));
+    }
+}
+
+// The expanded command line up to its command: the program and the flags
+// given before it, which commands that download pass on.
+fn global_args() -> Vec<OsString> {
+    let mut args = alias::args();
+    let command = command_position(&CLI::command(), &args).unwrap_or(args.len().min(1));
+    args.truncate(command);
+    args
+}
+
+// Where the command is in `args`, found the way clap reads the flags before
+// it, so a flag's value that reads like a command, as in `--output-dir
+// batch`, stays a value. None without a command.
+fn command_position(command: &clap::Command, args: &[OsString]) -> Option<usize> {
+    let takes_value = |arg: Option<&clap::Arg>| {
+        arg.is_some_and(|arg| arg.is_takes_value_set() && !arg.is_require_equals_set())
+    };
+    let mut i = 1;
+    while i < args.len() {
+        let word = args[i].to_string_lossy();
+        if word == "--" {
+            return None;
+        }
+        if let Some(long) = word.strip_prefix("--") {
+            let named = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long));
+            if takes_value(named) {
+                i += 1;
+            }
+        } else if let Some(shorts) = word.strip_prefix('-').filter(|shorts| !shorts.is_empty()) {
+            // Bundled flags; the first taking a value takes the rest of the
+            // word, or the next one when nothing is left.
+            for (at, short) in shorts.char_indices() {
+                let named = command
+                    .get_arguments()
+                    .find(|arg| arg.get_short() == Some(short));
+                if takes_value(named) {
+                    if at + short.len_utf8() == shorts.len() {
+                        i += 1;
+                    }
+                    break;
+                }
+            }
+        } else {
+            return command.find_subcommand(word.as_ref()).map(|_| i);
+        }
+        i += 1;
+    }
+    None
+}
+
+// Downloads the entries of a batch file one after another and returns the
+// exit code: that of the first failure, if any.
+fn run_batch(cli: &CLI, config: &Config, file: &str, strict: bool) -> i32 {
+    let started = Instant::now();
+    let (entries, mut invalid) = match batch::load(file) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            ui::error(&e.to_string());
+            return error::EXIT_FAILURE;
+        }
+    };
+    for line in &invalid {
+        ui::error(line.error.as_deref().unwrap_or_default());
+    }
+    if strict && !invalid.is_empty() {
+        ui::error("Not starting the batch because of --strict.");
+        return error::EXIT_FAILURE;
+    }
+
+    // Every entry runs with the flags given before `batch`.
+    let mut global: Vec<OsString> = global_args();
+    if !global.iter().any(|arg| arg == "--skip-existing") {
+        global.push("--skip-existing".into());
+    }
+    let mut exit_code = 0;
+    let mut lines = Vec::new();
+    for entry in &entries {
+        println!();
+        println!("Line {}: {}", entry.line, entry.spec);
+        let mut line = LineReport {
+            line: entry.line,
+            spec: entry.spec.clone(),
+            status: LineStatus::Downloaded,
+            error: None,
+            runs: Vec::new(),
+        };
+        for range in &entry.ranges {
+            let mut args = global.clone();
+            args.push(format!("--chapters={}", range).into());
+            // Titles are picked without asking, by exact name.
+            if !batch::is_url(&entry.target) && !global.iter().any(|arg| arg == "--match") {
+                args.push(format!("--match=(?i)^{}$", regex::escape(&entry.target)).into());
+            }
+            args.push(entry.target.clone().into());
+
+            let run_started = Instant::now();
+            let mut report = Report::new(&entry.target);
+            let result = CLI::command()
+                .try_get_matches_from(&args)
+                .map_err(|e| e.to_string().into())
+                .and_then(|matches| {
+                    let cli = CLI::from_arg_matches(&matches)?;
+                    run(&cli, &matches, &args, config, &mut report)
+                });
+            report.finish(
+                run_started.elapsed(),
+                result.as_ref().err().map(|e| e.to_string()),
+            );
+            record_usage(
+                config,
+                &mut report,
+                result.as_ref().err().map(|e| e.as_ref()),
+            );
+            report.print_summary();
+            apply_suggestion(cli, &report);
+            if let Err(e) = &result {
+                line.status = LineStatus::Failed;
+                line.error.get_or_insert(e.to_string());
+                if exit_code == 0 {
+                    exit_code = error::exit_code(e.as_ref());
+                }
+            }
+            line.runs.push(report);
+        }
+        if line.status != LineStatus::Failed && line.runs.iter().all(|run| run.skipped) {
+            line.status = LineStatus::Skipped;
+        }
+        lines.push(line);
+    }
+    if exit_code == 0 && !invalid.is_empty() {
+        exit_code = error::EXIT_FAILURE;
+    }
+
+    lines.append(&mut invalid);
+    lines.sort_by_key(|line| line.line);
+    let report = BatchReport {
+        success: exit_code == 0,
+        lines,
+        elapsed_seconds: started.elapsed().as_secs_f64(),
+    };
+    report.print_summary();
+    if let Some(path) = &cli.report {
+        if let Err(e) = report.save(path) {
+            ui::error(&format!("Failed to write report {}: {}", path, e));
+        }
+    }
+    exit_code
+}
+
+struct DiffOptions {
+    // Compare the pages, not only how many there are.
+    pages: bool,
+    // Download the chapter again when it differs.
+    update: bool,
+    jobs: usize,
+}
+
+// Compares a cached chapter with the site's current one and, when asked,
+// downloads it again if they differ. Returns the exit code.
+fn run_diff(
+    cli: &CLI,
+    config: &Config,
+    kind: SourceKind,
+    manga_name: &str,
+    number: &ChapterId,
+    options: &DiffOptions,
+) -> i32 {
+    let compared = diff_chapter(cli, config, kind, manga_name, number, options);
+    let (manga_url, differs) = match compared {
+        Ok(compared) => compared,
+        Err(e) => {
+            ui::error(&e.to_string());
+            return error::exit_code(e.as_ref());
+        }
+    };
+    if !differs || !options.update {
+        return 0;
+    }
+
+    println!();
+    println!("Downloading chapter {} again", number);
+    // The download runs with the flags given before `diff`.
+    let mut args: Vec<OsString> = global_args();
+    args.push(format!("--chapter={}", number).into());
+    args.push(manga_url.into());
+    let started = Instant::now();
+    let mut report = Report::new(manga_name);
+    let result = CLI::command()
+        .try_get_matches_from(&args)
+        .map_err(|e| e.to_string().into())
+        .and_then(|matches| {
+            let cli = CLI::from_arg_matches(&matches)?;
+            run(&cli, &matches, &args, config, &mut report)
+        });
+    report.finish(
+        started.elapsed(),
+        result.as_ref().err().map(|e| e.to_string()),
+    );
+    record_usage(
+        config,
+        &mut report,
+        result.as_ref().err().map(|e| e.as_ref()),
+    );
+    report.print_summary();
+    apply_suggestion(cli, &report);
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            ui::error(&e.to_string());
+            error::exit_code(e.as_ref())
+        }
+    }
+}
+
+// Prints how the cached copy of chapter `number` differs from the site's.
+// Returns the series URL and whether anything differs.
+fn diff_chapter(
+    cli: &CLI,
+    config: &Config,
+    kind: SourceKind,
+    manga_name: &str,
+    number: &ChapterId,
+    options: &DiffOptions,
+) -> Result<(String, bool), Box<dyn std::error::Error>> {
+    let languages = languages(cli, config);
+    // A manga URL picks its own source.
+    let kind = kind_for_url(manga_name).unwrap_or(kind);
+    let source = source(kind, &languages, false);
+    let manga_url = if batch::is_url(manga_name) {
+        manga_name.to_string()
+    } else {
+        find_manga(source.as_ref(), manga_name, None)?.url
+    };
+    let manga = source
+        .manga(&manga_url)
+        .map_err(|e| context(format!("Failed to fetch the chapter list: {}", e), e))?;
+    let range = ChapterRange::Between(number.clone(), number.clone());
+    let chapter = pick_versions(chapters_in_range(&manga, &range), None, &languages)
+        .into_iter()
+        .next()
+        .ok_or(format!("Chapter {} not found.", number))?;
+    let (folder, manifest) = cached_chapter(&manga.title, &manga_url, &chapter).ok_or(format!(
+        "{} isn't in the cache; download it before comparing.",
+        chapter.name
+    ))?;
+    let cached =
+        diff::cached_pages(&folder).map_err(|e| Error::filesystem(&folder.to_string_lossy(), e))?;
+    let images = page_urls(source.as_ref(), &chapter, true)
+        .map_err(|e| context(format!("Failed to fetch the page list: {}", e), e))?;
+
+    println!(
+        "{}: {} pages cached, {} on the site",
+        chapter.name,
+        cached.len(),
+        images.len()
+    );
+    let diffs = diff::compare(
+        source.as_ref(),
+        &chapter,
+        &cached,
+        &images,
+        options.pages,
+        manifest.low_data,
+        options.jobs,
+    );
+    diff::print(&diffs);
+    Ok((manga_url, diff::differs(&diffs)))
+}
+
+// Lists the chapters of a series missing on disk and, with `fill`, downloads
+// each of them. Returns the exit code.
+fn run_gaps(
+    cli: &CLI,
+    config: &Config,
+    kind: SourceKind,
+    manga_name: &str,
+    library_dir: Option<&str>,
+    fill: bool,
+) -> i32 {
+    let languages = languages(cli, config);
+    // A manga URL picks its own source.
+    let kind = kind_for_url(manga_name).unwrap_or(kind);
+    let source = source(kind, &languages, false);
+    let found = if batch::is_url(manga_name) {
+        Ok(manga_name.to_string())
+    } else {
+        find_manga(source.as_ref(), manga_name, None).map(|result| result.url)
+    };
+    let listed = found.and_then(|manga_url| {
+        let manga = chapter_list(source.as_ref(), &manga_url, &languages, config, true)
+            .map_err(|e| context(format!("Failed to fetch the chapter list: {}", e), e))?;
+        Ok((manga_url, manga))
+    });
+    let (manga_url, manga) = match listed {
+        Ok(listed) => listed,
+        Err(e) => {
+            ui::error(&e.to_string());
+            return error::exit_code(e.as_ref());
+        }
+    };
+    let output_dir = cli.output_dir.as_deref().unwrap_or(IMAGE_DIR);
+    let library_dir = library_dir.unwrap_or(output_dir);
+    let cache_dir = series_dir(IMAGE_DIR, &series_folder(&manga.title, &manga_url).0);
+    let gaps = gaps::find(
+        &manga.chapters,
+        &manga.title,
+        Path::new(library_dir),
+        &cache_dir,
+    );
+    gaps::print(&manga.title, &gaps);
+    if !fill || gaps.missing.is_empty() {
+        return 0;
+    }
+
+    // Each chapter runs with the flags given before `gaps`, into the library
+    // unless --output-dir says otherwise.
+    let mut global: Vec<OsString> = global_args();
+    if cli.output_dir.is_none() {
+        global.push(format!("--output-dir={}", library_dir).into());
+    }
+    let mut exit_code = 0;
+    let mut failed = Vec::new();
+    for number in &gaps.missing {
+        println!();
+        println!("{} chapter {}", manga.title, number);
+        let mut args = global.clone();
+        // A range of one names the file after the chapter, so the next
+        // `gaps` finds it.
+        args.push(format!("--chapters={}-{}", number, number).into());
+        args.push(manga_url.clone().into());
+        let started = Instant::now();
+        let mut report = Report::new(&manga.title);
+        let result = CLI::command()
+            .try_get_matches_from(&args)
+            .map_err(|e| e.to_string().into())
+            .and_then(|matches| {
+                let cli = CLI::from_arg_matches(&matches)?;
+                run(&cli, &matches, &args, config, &mut report)
+            });
+        report.finish(
+            started.elapsed(),
+            result.as_ref().err().map(|e| e.to_string()),
+        );
+        record_usage(
+            config,
+            &mut report,
+            result.as_ref().err().map(|e| e.as_ref()),
+        );
+        if let Err(e) = result {
+            ui::error(&e.to_string());
+            if exit_code == 0 {
+                exit_code = error::exit_code(e.as_ref());
+            }
+            failed.push(format!("c{}", number));
+        }
+    }
+    println!();
+    if failed.is_empty() {
+        println!(
+            "{}",
+            ui::success(&format!("Filled {} chapter(s).", gaps.missing.len()))
+        );
+    } else {
+        println!(
+            "{}",
+            ui::failure(&format!(
+                "Filled {} of {} chapter(s); failed {}.",
+                gaps.missing.len() - failed.len(),
+                gaps.missing.len(),
+                failed.join(", ")
+            ))
+        );
+    }
+    exit_code
+}
+
+// The cache folder holding `chapter` of the series `title`, found by the
+// chapter URL its manifest records.
+fn cached_chapter(title: &str, manga_url: &str, chapter: &Chapter) -> Option<(PathBuf, Manifest)> {
+    let dir = series_dir(IMAGE_DIR, &series_folder(title, manga_url).0);
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .find_map(|path| {
+            let manifest = Manifest::load(&path.to_string_lossy())?;
+            manifest
+                .chapter_urls
+                .contains(&chapter.url)
+                .then_some((path, manifest))
+        })
+}
+
+// Downloads the chapters of followed series whose release date falls within
+// `since`, one run per chapter with the series' stored settings. Chapters
+// missing from before the window are left alone. Returns the exit code.
+fn run_fresh(cli: &CLI, config: &Config, since: time::Duration) -> i32 {
+    let followed = SeriesStore::load().followed();
+    if followed.is_empty() {
+        println!("No followed series; add some with `manga-cli follow`.");
+        return 0;
+    }
+    let cutoff = OffsetDateTime::now_utc() - since;
+    // Every chapter runs with the flags given before `fresh`.
+    let global: Vec<OsString> = global_args();
+    let mut exit_code = 0;
+    let mut lines = Vec::new();
+    for (manga_url, meta) in &followed {
+        let (line, code) = download_fresh(cli, config, &global, manga_url, meta, cutoff, None);
+        if exit_code == 0 {
+            exit_code = code;
+        }
+        lines.push(line);
+    }
+
+    println!();
+    println!("Releases in the last {}:", format_window(since));
+    print_fresh(&lines);
+    exit_code
+}
+
+// Downloads the chapters of one followed series released since `cutoff`.
+// With `quarantine_after`, failed chapters are remembered and tried again by
+// later calls until that many permanent failures in a row quarantine them.
+// Returns its title with the outcome, and the exit code of the first
+// failure.
+fn download_fresh(
+    cli: &CLI,
+    config: &Config,
+    global: &[OsString],
+    manga_url: &str,
+    meta: &SeriesMeta,
+    cutoff: OffsetDateTime,
+    quarantine_after: Option<u32>,
+) -> ((String, String), i32) {
+    let now = OffsetDateTime::now_utc();
+    let title = meta
+        .title
+        .clone()
+        .unwrap_or_else(|| title_from_url(manga_url));
+    let Some(kind) = kind_for_url(manga_url) else {
+        return ((title, "no source for this URL".to_string()), 0);
+    };
+    // A series' own languages decide what counts as a release of it.
+    let languages = match meta.overrides.get("lang") {
+        Some(lang) => lang
+            .split(',')
+            .map(|lang| lang.trim().to_string())
+            .collect(),
+        None => languages(cli, config),
+    };
+    let source = source(kind, &languages, false);
+    let manga = match chapter_list(source.as_ref(), manga_url, &languages, config, true) {
+        Ok(manga) => manga,
+        Err(e) => {
+            let code = error::exit_code(e.as_ref());
+            return ((title, format!("failed: {}", e)), code);
+        }
+    };
+    let mut numbers: Vec<ChapterId> = Vec::new();
+    for chapter in manga.chapters.iter().rev() {
+        let released = chapter
+            .uploaded
+            .as_deref()
+            .and_then(|uploaded| parse_release_date(uploaded, now));
+        let wanted =
+            language_rank(chapter, &languages) < languages.len() || chapter.language.is_none();
+        match (&chapter.number, released) {
+            (Some(number), Some(released))
+                if released >= cutoff && wanted && !numbers.contains(number) =>
+            {
+                numbers.push(number.clone())
+            }
+            _ => {}
+        }
+    }
+    let mut failures = quarantine_after.map(|_| FailureStore::load());
+    let mut benched = Vec::new();
+    if let Some(failures) = &failures {
+        let (pending, quarantined) = failures.chapters(manga_url);
+        benched = numbers
+            .iter()
+            .filter(|number| quarantined.contains(number))
+            .map(|number| format!("c{}", number))
+            .collect();
+        numbers.retain(|number| !quarantined.contains(number));
+        for number in pending {
+            if !numbers.contains(&number) {
+                numbers.push(number);
+            }
+        }
+        numbers.sort();
+    }
+    if numbers.is_empty() {
+        let outcome = if benched.is_empty() {
+            "nothing new".to_string()
+        } else {
+            format!("skipped quarantined {}", benched.join(", "))
+        };
+        return ((title, outcome), 0);
+    }
+
+    let mut exit_code = 0;
+    let mut done = Vec::new();
+    let mut failed = Vec::new();
+    let mut quarantined = Vec::new();
+    for number in &numbers {
+        println!();
+        println!("{} chapter {}", title, number);
+        let mut args = global.to_vec();
+        args.push(format!("--chapter={}", number).into());
+        args.push(manga_url.into());
+        let run_started = Instant::now();
+        let mut report = Report::new(&title);
+        let result = CLI::command()
+            .try_get_matches_from(&args)
+            .map_err(|e| e.to_string().into())
+            .and_then(|matches| {
+                let cli = CLI::from_arg_matches(&matches)?;
+                run(&cli, &matches, &args, config, &mut report)
+            });
+        report.finish(
+            run_started.elapsed(),
+            result.as_ref().err().map(|e| e.to_string()),
+        );
+        record_usage(
+            config,
+            &mut report,
+            result.as_ref().err().map(|e| e.as_ref()),
+        );
+        match result {
+            Ok(()) => {
+                if let Some(failures) = &mut failures {
+                    failures.succeeded(manga_url, number);
+                }
+                done.push(format!("c{}", number));
+            }
+            Err(e) => {
+                println!("{}", e);
+                if exit_code == 0 {
+                    exit_code = error::exit_code(e.as_ref());
+                }
+                let quarantine = match (&mut failures, quarantine_after) {
+                    (Some(failures), Some(limit)) => failures.failed(
+                        manga_url,
+                        &title,
+                        number,
+                        &e.to_string(),
+                        error::is_permanent(e.as_ref()),
+                        limit,
+                    ),
+                    _ => false,
+                };
+                if quarantine {
+                    println!(
+                        "Quarantined {} chapter {} after {} permanent failures; `manga-cli retry` tries it again.",
+                        title,
+                        number,
+                        quarantine_after.unwrap_or_default()
+                    );
+                    quarantined.push(format!("c{}", number));
+                } else {
+                    failed.push(format!("c{}", number));
+                }
+            }
+        }
+    }
+    if let Some(failures) = failures {
+        if let Err(e) = failures.save() {
+            log::warn!("Failed to save the failed chapters: {}", e);
+        }
+    }
+    let mut outcome = Vec::new();
+    if !done.is_empty() {
+        outcome.push(format!("downloaded {}", done.join(", ")));
+    }
+    if !failed.is_empty() {
+        outcome.push(format!("failed {}", failed.join(", ")));
+    }
+    if !quarantined.is_empty() {
+        outcome.push(format!("quarantined {}", quarantined.join(", ")));
+    }
+    if !benched.is_empty() {
+        outcome.push(format!("skipped quarantined {}", benched.join(", ")));
+    }
+    ((title, outcome.join("; ")), exit_code)
+}
+
+// One line per series: its title and what became of its releases.
+fn print_fresh(lines: &[(String, String)]) {
+    // Long titles give way to the outcomes on narrow terminals.
+    let mut width = lines
+        .iter()
+        .map(|(title, _)| ui::width(title))
+        .max()
+        .unwrap_or(0);
+    if let Some(columns) = ui::terminal_width() {
+        width = width.min(columns / 2);
+    }
+    for (title, outcome) in lines {
+        println!(
+            "  {}  {}",
+            ui::pad(&ui::truncate(title, width), width),
+            outcome
+        );
+    }
+}
+
+struct WatchOptions {
+    schedule: Schedule,
+    since: time::Duration,
+    quarantine_after: u32,
+}
+
+// Followed series with their schedules, then the chapters `watch` couldn't
+// download.
+fn print_status() {
+    let followed = SeriesStore::load().followed();
+    if followed.is_empty() {
+        println!("No followed series; add some with `manga-cli follow`.");
+    }
+    let history = HistoryStore::load();
+    for (manga_url, meta) in &followed {
+        let title = meta
+            .title
+            .clone()
+            .unwrap_or_else(|| title_from_url(manga_url));
+        let read = match history.read(manga_url).last() {
+            Some(number) => format!("read to c{}", number),
+            None => "unread".to_string(),
+        };
+        match &meta.schedule {
+            Some(schedule) => println!("{}  {}  (checked {})", title, read, schedule),
+            None => println!("{}  {}", title, read),
+        }
+    }
+
+    let failures = FailureStore::load();
+    let mut failed: Vec<_> = failures.all().collect();
+    if failed.is_empty() {
+        return;
+    }
+    failed.sort_by_key(|(_, _, failures)| !failures.quarantined);
+    println!();
+    println!("Failed chapters:");
+    for (_, number, failures) in failed {
+        let state = if failures.quarantined {
+            "quarantined".to_string()
+        } else {
+            format!("retrying, {} permanent failures", failures.permanent)
+        };
+        println!(
+            "  {} c{}  {} since {}: {}",
+            failures.title, number, state, failures.last_failed, failures.last_error
+        );
+    }
+}
+
+// Checks the followed series for releases whenever their schedule (their
+// own, or `schedule`) fires, and downloads them like `fresh`. The first check
+// of a series looks back `since`, later ones back to the previous check.
+// Runs until interrupted.
+fn run_watch(cli: &CLI, config: &Config, options: &WatchOptions) -> i32 {
+    let WatchOptions {
+        schedule,
+        since,
+        quarantine_after,
+    } = options;
+    let global: Vec<OsString> = global_args();
+    let started = OffsetDateTime::now_utc();
+    // A plain interval checks right away, a cron schedule at its first time.
+    let first_check = |schedule: &Schedule| match schedule {
+        Schedule::Every(_) => Some(started),
+        Schedule::Cron(_) => schedule.next_after(started),
+    };
+    // By manga URL: when it's next checked, and releases since when count.
+    let mut checks: BTreeMap<String, (Option<OffsetDateTime>, OffsetDateTime)> = BTreeMap::new();
+    let mut last_now = started;
+    let mut announced = None;
+    loop {
+        let now = OffsetDateTime::now_utc();
+        let followed = SeriesStore::load().followed();
+        if followed.is_empty() {
+            println!("No followed series; add some with `manga-cli follow`.");
+            return 0;
+        }
+        let series_schedule = |meta: &SeriesMeta| match meta.schedule.as_deref().map(parse_schedule)
+        {
+            Some(Ok(own)) => own,
+            Some(Err(e)) => {
+                log::warn!("Ignoring the stored schedule of {:?}: {}", meta.title, e);
+                schedule.clone()
+            }
+            None => schedule.clone(),
+        };
+        // When the clock went back, work the next checks out again rather
+        // than wait for times that moved away.
+        let clock_went_back = now < last_now;
+        last_now = now;
+        for (manga_url, meta) in &followed {
+            let check = checks
+                .entry(manga_url.clone())
+                .or_insert_with(|| (first_check(&series_schedule(meta)), now - *since));
+            if clock_went_back {
+                check.0 = series_schedule(meta).next_after(now);
+            }
+        }
+
+        let due: Vec<&(String, SeriesMeta)> = followed
+            .iter()
+            .filter(|(manga_url, _)| checks[manga_url].0.is_some_and(|next| next <= now))
+            .collect();
+        if !due.is_empty() {
+            println!();
+            println!("Checking at {}:", schedule::format_local(now));
+            let mut lines = Vec::new();
+            for (manga_url, meta) in due {
+                let cutoff = checks[manga_url].1;
+                let (line, _) = download_fresh(
+                    cli,
+                    config,
+                    &global,
+                    manga_url,
+                    meta,
+                    cutoff,
+                    Some(*quarantine_after),
+                );
+                lines.push(line);
+                // From when this check started, so nothing released while it
+                // ran is missed. Missed times (the computer slept) fold into
+                // this one check.
+                let next = series_schedule(meta).next_after(OffsetDateTime::now_utc());
+                checks.insert(manga_url.clone(), (next, now));
+            }
+            println!();
+            print_fresh(&lines);
+            announced = None;
+            continue;
+        }
+
+        let next = followed
+            .iter()
+            .filter_map(|(manga_url, _)| checks[manga_url].0)
+            .min();
+        let Some(next) = next else {
+            println!("No schedule fires again; stopping.");
+            return 0;
+        };
+        if announced != Some(next) {
+            println!("Next check: {}", schedule::format_local(next));
+            announced = Some(next);
+        }
+        // Short naps, so waking from sleep or a clock change is noticed
+        // within a minute.
+        thread::sleep(
+            (next - now)
+                .unsigned_abs()
+                .clamp(Duration::from_secs(1), WATCH_NAP),
+        );
+    }
+}
+
+// "24h", "3d", the way --since takes it.
+fn format_window(window: time::Duration) -> String {
+    if window.whole_days() > 0 && window.whole_hours() % 24 == 0 {
+        format!("{}d", window.whole_days())
+    } else if window.whole_hours() > 0 && window.whole_minutes() % 60 == 0 {
+        format!("{}h", window.whole_hours())
+    } else {
+        format!("{}m", window.whole_minutes())
+    }
+}
+
+// Whether every requested format of `output` is already in the output
+// directory.
+fn outputs_exist(output: &Output, options: &DownloadOptions) -> bool {
+    !options.formats.is_empty()
+        && existing_outputs(&output.name, options).len() == options.formats.len()
+}
+
+// The requested formats already in the output directory under `name`.
+fn existing_outputs(name: &str, options: &DownloadOptions) -> Vec<PathBuf> {
+    let dir = Path::new(&options.output_dir);
+    options
+        .formats
+        .iter()
+        .map(|format| match format {
+            Format::Pdf => dir.join(format!("{}.pdf", name)),
+            Format::Cbz => dir.join(format!("{}.cbz", name)),
+            Format::Html if options.html.single_file => dir.join(format!("{}.html", name)),
+            Format::Html => dir.join(name).join("index.html"),
+        })
+        .filter(|path| path.exists())
+        .collect()
+}
+
+fn list_sources(probe: bool) {
+    for kind in SourceKind::value_variants() {
+        let source = source(*kind, &[], false);
+        let mirrors = source.mirrors();
+        if mirrors.len() < 2 {
+            println!("{}: single site", source.name());
+            continue;
+        }
+        println!("{}:", source.name());
+        let latencies = probe.then(|| mirrors::measure(source.name(), mirrors));
+        for mirror in mirrors {
+            match latencies.as_ref().map(|latencies| latencies.get(*mirror)) {
+                Some(Some(Some(latency))) => println!("  {}  {} ms", mirror, latency),
+                Some(_) => println!("  {}  no answer", mirror),
+                None => println!("  {}", mirror),
+            }
+        }
+    }
+}
+
+// Searches for `name` and lets the user pick a result, or picks the one
+// matching `pattern`.
+fn find_manga(
+    source: &dyn Source,
+    name: &str,
+    pattern: Option<&Regex>,
+) -> Result<SearchResult, Box<dyn std::error::Error>> {
+    let mut results = search(source, name)
+        .map_err(|e| context(format!("Failed to fetch manga IDs: {}", e), e))?;
+    if pattern.is_some() || !io::stdin().is_terminal() {
+        let index = pick_result(&results, &[], pattern)?;
+        return Ok(results.swap_remove(index));
+    }
+
+    // On a terminal the list can grow: by the source's next page of results,
+    // or by searching again with other words. Results already listed keep
+    // their numbers and aren't listed twice.
+    print_results(&results, &[], 0);
+    let mut query = name.to_string();
+    let mut page = 1;
+    loop {
+        let answer = prompt::ask(
+            "Enter number, m for more results, or other words to search for: ",
+            None,
+            parse_pick,
+        )?;
+        let found = match answer {
+            Pick::Number(number) if (1..=results.len()).contains(&number) => {
+                return Ok(results.swap_remove(number - 1));
+            }
+            Pick::Number(number) => {
+                println!("No manga numbered {}.", number);
+                continue;
+            }
+            Pick::More => {
+                println!("{}", ui::dim("searching…"));
+                page += 1;
+                source.search_page(&query, page)
+            }
+            Pick::Search(words) => {
+                println!("{}", ui::dim("searching…"));
+                let found = search(source, &words);
+                if found.is_ok() {
+                    query = words;
+                    page = 1;
+                }
+                found
+            }
+        };
+        let found = match found {
+            Ok(found) => found,
+            Err(e) => {
+                ui::warn(&format!("searching failed: {}", e));
+                continue;
+            }
+        };
+        let listed = results.len();
+        for result in found {
+            if !results.iter().any(|known| known.url == result.url) {
+                results.push(result);
+            }
+        }
+        if results.len() == listed {
+            println!("No new results; {} listed.", listed);
+        } else {
+            print_results(&results, &[], listed);
+            println!("{} new, {} listed.", results.len() - listed, results.len());
+        }
+    }
+}
+
+// A search's first page, with alternative names looked up for the first
+// results when the source doesn't include them.
+fn search(source: &dyn Source, query: &str) -> SourceResult<Vec<SearchResult>> {
+    let mut results = source.search(query)?;
+    for result in results.iter_mut().take(ALT_TITLE_LOOKUPS) {
+        if result.alt_titles.is_empty() {
+            result.alt_titles = source.alt_titles(&result.url).unwrap_or_default();
+        }
+    }
+    Ok(results)
+}
+
+// An answer to the search results' prompt.
+enum Pick {
+    Number(usize),
+    More,
+    Search(String),
+}
+
+fn parse_pick(answer: &str) -> Result<Pick, String> {
+    if let Ok(number) = prompt::parse_index(answer) {
+        return Ok(Pick::Number(number));
+    }
+    match answer.trim() {
+        "" => Err("please enter a number, m or a search".to_string()),
+        "m" | "M" | "more" => Ok(Pick::More),
+        words => Ok(Pick::Search(words.to_string())),
+    }
+}
+
+// find_manga() across every source at once (--source all), with a column
+// naming each result's source.
+fn find_manga_everywhere(
+    name: &str,
+    languages: &[String],
+    low_data: bool,
+    pattern: Option<&Regex>,
+) -> Result<(SourceKind, SearchResult), Box<dyn std::error::Error>> {
+    let source::MergedSearch { found, failed } =
+        source::search_all(name, languages, low_data, ALT_TITLE_LOOKUPS);
+    let reasons: Vec<String> = failed
+        .iter()
+        .map(|(kind, error)| format!("{}: {}", source(*kind, &[], false).name(), error))
+        .collect();
+    if failed.len() == SourceKind::value_variants().len() {
+        return Err(format!(
+            "Failed to fetch manga IDs from every source:\n  {}",
+            reasons.join("\n  ")
+        )
+        .into());
+    }
+    for reason in &reasons {
+        println!("Skipping {}", reason);
+    }
+
+    let (kinds, mut results): (Vec<SourceKind>, Vec<SearchResult>) = found.into_iter().unzip();
+    let labels: Vec<&str> = kinds
+        .iter()
+        .map(|kind| source(*kind, &[], false).name())
+        .collect();
+    let index = pick_result(&results, &labels, pattern)?;
+    Ok((kinds[index], results.swap_remove(index)))
+}
+
+// Index of the result matching `pattern`, or of the one the user picks.
+// `labels`, when given, are shown in a column before each result.
+fn pick_result(
+    results: &[SearchResult],
+    labels: &[&str],
+    pattern: Option<&Regex>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    if let Some(pattern) = pattern {
+        return Ok(match_result(results, pattern)?);
+    }
+
+    print_results(results, labels, 0);
+    let manga_number = prompt::ask("Enter number: ", None, prompt::parse_index)?;
+    if manga_number == 0 || manga_number > results.len() {
+        return Err(format!("No manga numbered {}.", manga_number).into());
+    }
+    Ok(manga_number - 1)
+}
+
+// Lists the results from index `from` on, numbered from 1, cut to fit a line
+// each.
+fn print_results(results: &[SearchResult], labels: &[&str], from: usize) {
+    for (prefix, description) in result_lines(results, labels, from, ui::terminal_width()) {
+        println!("{}{}", ui::dim(&prefix), description);
+    }
+}
+
+// The index and label, then the description of each result from `from` on,
+// cut to `columns` when known.
+fn result_lines(
+    results: &[SearchResult],
+    labels: &[&str],
+    from: usize,
+    columns: Option<usize>,
+) -> Vec<(String, String)> {
+    let index_width = results.len().to_string().len();
+    let label_width = labels
+        .iter()
+        .map(|label| ui::width(label))
+        .max()
+        .unwrap_or(0);
+    let mut lines = Vec::new();
+    for (index, result) in results.iter().enumerate().skip(from) {
+        let mut prefix = format!("[{:>width$}] ", index + 1, width = index_width);
+        if let Some(label) = labels.get(index) {
+            prefix.push_str(&ui::pad(label, label_width));
+            prefix.push_str("  ");
+        }
+        let description = describe_result(result);
+        let description = match columns {
+            Some(columns) => ui::truncate(&description, columns.saturating_sub(ui::width(&prefix))),
+            None => description,
+        };
+        // Styled once cut, so escape codes don't count towards the width.
+        let description = match description.strip_prefix(result.title.as_str()) {
+            Some(rest) => format!("{}{}", ui::bold(&result.title), rest),
+            None => ui::bold(&description),
+        };
+        lines.push((prefix, description));
+    }
+    lines
+}
+
+// Offers to keep a series without a folder of its own yet in the folder of
+// one already in the library that goes by the same title, e.g. the same
+// series from another source. The answer is remembered either way.
+fn offer_merge(store: &mut SeriesStore, manga_url: &str, title: &str, alt_titles: &[String]) {
+    if store.get(manga_url).folder.is_some() {
+        return;
+    }
+    let (own, _) = series_folder(title, manga_url);
+    let mut titles = vec![title.to_string()];
+    titles.extend(alt_titles.iter().cloned());
+    let Some(existing) = library::lookalike(&series_root(IMAGE_DIR), &own, &titles) else {
+        return;
+    };
+    if !io::stdin().is_terminal() {
+        println!(
+            "Note: {} looks like the existing series {}; `manga-cli library dedupe` can merge them.",
+            title,
+            existing.path.display()
+        );
+        return;
+    }
+    let merge = prompt::confirm_default_no(&format!(
+        "This looks like an existing series: {} - merge?",
+        existing.path.display()
+    ));
+    let folder = if merge {
+        println!("Keeping {} in {}", title, existing.path.display());
+        existing.name
+    } else {
+        own
+    };
+    store.get_mut(manga_url).folder = Some(folder);
+    if let Err(e) = store.save() {
+        log::warn!("Failed to save the series folder: {}", e);
+    }
+}
+
+// Offers to merge each group of series folders that look like the same
+// series into the one with the most chapters. Series downloaded into a
+// merged folder go into that one from then on.
+fn library_dedupe() -> Result<(), Box<dyn std::error::Error>> {
+    let root = series_root(IMAGE_DIR);
+    let groups = library::duplicates(&root);
+    if groups.is_empty() {
+        println!("No series folders look alike.");
+        return Ok(());
+    }
+    let mut store = SeriesStore::load();
+    for group in &groups {
+        let (target, others) = group.split_first().unwrap();
+        println!();
+        println!("These look like the same series:");
+        for folder in group {
+            println!("  {} ({} chapters)", folder.name, folder.chapters);
+        }
+        if !prompt::confirm_default_no(&format!("Merge them into {}?", target.name)) {
+            continue;
+        }
+        for other in others {
+            let renamed = library::merge(&other.path, &target.path)
+                .map_err(|e| Error::filesystem(&other.path.to_string_lossy(), e))?;
+            for (name, new_name) in renamed {
+                println!(
+                    "  {} already has {}, moved {}'s as {}",
+                    target.name, name, other.name, new_name
+                );
+            }
+            for (manga_url, meta) in store.iter_mut() {
+                let folder = match (&meta.folder, &meta.title) {
+                    (Some(folder), _) => folder.clone(),
+                    (None, Some(title)) => series_folder(title, manga_url).0,
+                    (None, None) => continue,
+                };
+                if folder == other.name {
+                    meta.folder = Some(target.name.clone());
+                }
+            }
+        }
+        println!("Merged into {}", target.path.display());
+    }
+    store.save()
+}
+
+// Writes index.json in each series folder of `root` from what is cached.
+fn rebuild_indexes(root: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if !root.is_dir() {
+        return Err(format!("{} isn't a folder.", root.display()).into());
+    }
+    let mut written = 0;
+    for folder in library::folders(root) {
+        match index::update(&folder.path, None) {
+            Ok(_) => written += 1,
+            Err(e) => ui::warn(&e.to_string()),
+        }
+    }
+    println!(
+        "Wrote index.json for {} series in {}",
+        written,
+        root.display()
+    );
+    Ok(())
+}
+
+// The aliases in effect for `config` and those of its aliases that are
+// ignored for naming a command.
+fn aliases(config: &Config) -> (Vec<alias::Alias>, Vec<String>) {
+    let command = CLI::command();
+    let mut commands = Vec::new();
+    let mut short_forms = Vec::new();
+    for subcommand in command.get_subcommands() {
+        commands.push(subcommand.get_name().to_string());
+        for name in subcommand.get_all_aliases() {
+            short_forms.push((name.to_string(), subcommand.get_name().to_string()));
+        }
+    }
+    alias::effective(&config.alias, &commands, &short_forms)
+}
+
+fn list_aliases(config: &Config) {
+    let (aliases, shadowing) = aliases(config);
+    let width = aliases
+        .iter()
+        .map(|alias| alias.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    for alias in &aliases {
+        let (expansion, origin) = match alias.origin {
+            alias::Origin::BuiltIn if alias.expansion.is_empty() => (
+                "(download, when a title follows)".to_string(),
+                "built-in".to_string(),
+            ),
+            alias::Origin::BuiltIn => (alias.expansion.clone(), "built-in".to_string()),
+            alias::Origin::Config => (
+                alias.expansion.clone(),
+                Config::path().display().to_string(),
+            ),
+        };
+        println!(
+            "{:width$}  {}  ({})",
+            alias.name,
+            expansion,
+            origin,
+            width = width
+        );
+    }
+    for name in shadowing {
+        ui::warn(&format!(
+            "the alias {} in {} is ignored: {} is a command.",
+            name,
+            Config::path().display(),
+            name
+        ));
+    }
+}
+
+// Makes identical files in the cache's chapter folders and the outputs in
+// `dirs`, or the cache with none given, share their storage through
+// copy-on-write clones. Where the filesystem has none, or with `dry_run`,
+// says what that would save instead.
+fn library_optimize(dirs: &[PathBuf], dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let pages = library::intact_pages(&series_root(IMAGE_DIR));
+    let dirs = if dirs.is_empty() {
+        vec![PathBuf::from(IMAGE_DIR)]
+    } else {
+        dirs.to_vec()
+    };
+    let mut outputs = Vec::new();
+    for dir in &dirs {
+        let entries =
+            fs::read_dir(dir).map_err(|e| Error::filesystem(&dir.to_string_lossy(), e))?;
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            if path.is_file() && matches!(extension, Some("cbz" | "pdf")) {
+                outputs.push(path);
+            }
+        }
+    }
+
+    let mut files = pages.clone();
+    files.extend(outputs.iter().cloned());
+    let groups = library::identical(&files).map_err(|e| Error::filesystem(IMAGE_DIR, e))?;
+    let (mut shared, mut freed, mut unshared, mut left) = (0, 0, 0, 0);
+    for group in &groups {
+        let (original, duplicates) = group.paths.split_first().unwrap();
+        for duplicate in duplicates {
+            if !dry_run {
+                match library::share(original, duplicate, &group.hash) {
+                    Ok(()) => {
+                        log::info!("{} now shares {}", duplicate.display(), original.display());
+                        shared += 1;
+                        freed += group.size;
+                        continue;
+                    }
+                    // No clones on this filesystem, or across two of them.
+                    Err(e) if e.kind() == io::ErrorKind::Unsupported => {}
+                    Err(e) => ui::warn(&format!("{}: {}", duplicate.display(), e)),
+                }
+            }
+            unshared += 1;
+            left += group.size;
+        }
+    }
+
+    let archives: Vec<PathBuf> = outputs
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|extension| extension == "cbz"))
+        .collect();
+    let (archived, archived_bytes) = if archives.is_empty() {
+        (0, 0)
+    } else {
+        let hashes = pages
+            .iter()
+            .filter_map(|page| library::hash_file(page).ok())
+            .collect();
+        library::archived_copies(&archives, &hashes)
+    };
+
+    if shared == 0 && unshared == 0 && archived == 0 {
+        println!("Nothing to optimize: no page or output repeats another.");
+        return Ok(());
+    }
+    if shared > 0 {
+        println!(
+            "Shared the storage of {} duplicate files, freeing up to {}.",
+            shared,
+            report::format_bytes(freed)
+        );
+    }
+    if unshared > 0 {
+        let how = if dry_run {
+            "run without --dry-run to share them where the filesystem has copy-on-write clones"
+        } else {
+            "a filesystem with copy-on-write clones, like btrfs, XFS or APFS, would share them"
+        };
+        println!(
+            "{} duplicate files take {}; {}.",
+            unshared,
+            report::format_bytes(left),
+            how
+        );
+    }
+    if archived > 0 {
+        println!(
+            "{} pages inside CBZs repeat pages in the cache ({}). Pages inside an archive can't share storage; removing chapters from the cache once packaged would save that.",
+            archived,
+            report::format_bytes(archived_bytes)
+        );
+    }
+    Ok(())
+}
+
+// Searches for the series and writes a project file for it into the current
+// folder, pinning its source and languages along with the per-series flags
+// given on the command line.
+fn init_project(
+    cli: &CLI,
+    matches: &ArgMatches,
+    config: &Config,
+    kind: SourceKind,
+    manga_name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if Path::new(project::PROJECT_FILE).exists() {
+        return Err(format!("{} already exists here.", project::PROJECT_FILE).into());
+    }
+    if let Ok(Some((path, _))) = project::find() {
+        ui::warn(&format!(
+            "{} is a project already; the new one takes precedence below here.",
+            path.parent().unwrap_or(&path).display()
+        ));
+    }
+    let languages = languages(cli, config);
+    // A manga URL picks its own source.
+    let kind = kind_for_url(manga_name).unwrap_or(kind);
+    let source = source(kind, &languages, false);
+    let manga_url = if batch::is_url(manga_name) {
+        manga_name.to_string()
+    } else {
+        find_manga(source.as_ref(), manga_name, cli.match_pattern.as_ref())?.url
+    };
+    let manga = chapter_list(source.as_ref(), &manga_url, &languages, config, false)
+        .map_err(|e| context(format!("Failed to fetch the series: {}", e), e))?;
+
+    let mut settings = overrides::given(matches);
+    if let Some(name) = kind.to_possible_value() {
+        settings.insert("source".to_string(), name.get_name().into());
+    }
+    settings.insert("lang".to_string(), languages.into());
+    let path = project::write(&manga_url, &manga.title, &settings)?;
+    println!(
+        "Wrote {} for {}. Runs in this folder and below download it unless given another series.",
+        path.display(),
+        ui::bold(&manga.title)
+    );
+    Ok(())
+}
+
+// Asks once, before a download, whether to sort files an older version left
+// in the cache; a no is remembered too.
+fn offer_cache_migration() {
+    let cache = Path::new(IMAGE_DIR);
+    if migrate::is_done(cache)
+        || !io::stdin().is_terminal()
+        || !io::stdout().is_terminal()
+        || migrate::detect(cache).is_none()
+    {
+        return;
+    }
+    if prompt::confirm(&format!(
+        "{} holds pages from an older version of manga-cli. Sort them into the series folders now?",
+        IMAGE_DIR
+    )) {
+        if let Err(e) = migrate_cache() {
+            ui::error(&format!("Failed to migrate the cache: {}", e));
+        }
+        println!();
+    } else {
+        println!("`manga-cli migrate-cache` sorts them later.");
+        if let Err(e) = migrate::mark_done(cache) {
+            log::warn!("Failed to remember the answer: {}", e);
+        }
+    }
+}
+
+// Moves the chapter older versions kept straight in the cache into its series
+// folder and names its outputs after it. The series and chapter come from
+// the outputs' ComicInfo.xml and the manifest, and the user confirms or
+// corrects them. Whatever can't be placed is parked in the unsorted folder,
+// deleted or left, as the user answers. Only moves and renames happen
+// without asking, so running it again is harmless.
+fn migrate_cache() -> Result<(), Box<dyn std::error::Error>> {
+    let cache = Path::new(IMAGE_DIR);
+    let Some(legacy) = migrate::detect(cache) else {
+        println!(
+            "Nothing to migrate: {} has no files from older versions.",
+            IMAGE_DIR
+        );
+        migrate::mark_done(cache)?;
+        return Ok(());
+    };
+    println!(
+        "Found in {}: {} page(s){}{}",
+        IMAGE_DIR,
+        legacy.pages.len(),
+        if legacy.manifest.is_some() {
+            ", a manifest"
+        } else {
+            ""
+        },
+        if legacy.outputs.is_empty() {
+            String::new()
+        } else {
+            format!(", {} output(s)", legacy.outputs.len())
+        }
+    );
+
+    let mut unplaced = Vec::new();
+    match (legacy.chapter_url(), &legacy.manifest) {
+        (Some(chapter_url), Some((_, manifest))) => {
+            let manga_url = manifest.manga_url.clone();
+            let (info_series, info_number) = legacy.comic_info();
+            let title = SeriesStore::load()
+                .get(&manga_url)
+                .title
+                .or(info_series)
+                .unwrap_or_else(|| title_from_url(&manga_url));
+            let number = migrate::guess_number(chapter_url).or(info_number);
+            let title = prompt::ask(
+                &format!("Series of {} [{}]: ", chapter_url, title),
+                Some(title),
+                |answer| Ok(answer.to_string()),
+            )?;
+            let number = match number {
+                Some(number) => prompt::ask(
+                    &format!("Chapter number [{}]: ", number),
+                    Some(number),
+                    prompt::parse_chapter_number,
+                )?,
+                None => prompt::ask("Chapter number: ", None, prompt::parse_chapter_number)?,
+            };
+
+            let chapter_name = format!("Chapter {}", number);
+            let folder = series_folder(&title, &manga_url).0;
+            let chapter_dir = series_dir(IMAGE_DIR, &folder).join(&chapter_name);
+            if chapter_dir.exists() {
+                println!(
+                    "{} {} is already in the cache; these pages are a second copy.",
+                    title, chapter_name
+                );
+                unplaced.extend(legacy.pages.iter().cloned());
+                unplaced.extend(legacy.manifest.iter().map(|(path, _)| path.clone()));
+            } else {
+                let migrated = Manifest {
+                    series_title: Some(title.clone()),
+                    chapter_name: Some(chapter_name.clone()),
+                    ..Manifest::load(IMAGE_DIR).ok_or("The manifest can't be read.")?
+                };
+                migrate::move_chapter(&legacy, &chapter_dir, &migrated)
+                    .map_err(|e| Error::filesystem(&chapter_dir.to_string_lossy(), e))?;
+                println!("Moved the pages to {}", chapter_dir.display());
+            }
+            let name = format!("{} c{}", filename::sanitize(&title), number);
+            for output in &legacy.outputs {
+                // A later single-chapter download may have replaced it.
+                if let (_, Some(held)) = migrate::comic_info(output) {
+                    if held != number {
+                        println!(
+                            "Left {}: it holds chapter {}, not {}.",
+                            output.display(),
+                            held,
+                            number
+                        );
+                        continue;
+                    }
+                }
+                let renamed = migrate::rename_output(output, &name)
+                    .map_err(|e| Error::filesystem(&output.to_string_lossy(), e))?;
+                println!("Renamed {} to {}", output.display(), renamed.display());
+            }
+        }
+        _ => {
+            println!("Without a manifest naming a single chapter, they can't be placed.");
+            unplaced = legacy.files();
+        }
+    }
+
+    if !unplaced.is_empty() {
+        for file in &unplaced {
+            println!("  {}", file.display());
+        }
+        let choice = prompt::ask(
+            &format!(
+                "[p]ark these in {}/{}, [d]elete them or [l]eave them [p]? ",
+                IMAGE_DIR,
+                migrate::UNSORTED_DIR
+            ),
+            Some('p'),
+            |answer| match answer.to_lowercase().as_str() {
+                "p" | "park" => Ok('p'),
+                "d" | "delete" => Ok('d'),
+                "l" | "leave" => Ok('l'),
+                _ => Err("please answer park, delete or leave".to_string()),
+            },
+        )
+        .unwrap_or('l');
+        match choice {
+            'p' => {
+                let unsorted =
+                    migrate::park(cache, &unplaced).map_err(|e| Error::filesystem(IMAGE_DIR, e))?;
+                println!("Parked them in {}", unsorted.display());
+            }
+            'd' => {
+                for file in &unplaced {
+                    fs::remove_file(file)
+                        .map_err(|e| Error::filesystem(&file.to_string_lossy(), e))?;
+                }
+                println!("Deleted them.");
+            }
+            _ => println!("Left them where they are."),
+        }
+    }
+    migrate::mark_done(cache)?;
+    Ok(())
+}
+
+// What `follow` changes besides marking the series followed.
+struct FollowEdits<'a> {
+    set: &'a [String],
+    unset: &'a [String],
+    schedule: Option<&'a str>,
+}
+
+// Marks a series as followed and edits its stored settings.
+fn follow(
+    source: &dyn Source,
+    name: &str,
+    edits: &FollowEdits,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Check the settings before asking the user to pick a series.
+    let assignments = edits
+        .set
+        .iter()
+        .map(|assignment| overrides::parse_assignment(assignment))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let result = find_manga(source, name, None)?;
+    let mut store = SeriesStore::load();
+    offer_merge(&mut store, &result.url, &result.title, &result.alt_titles);
+    let meta = store.get_mut(&result.url);
+    meta.followed = true;
+    meta.title = Some(result.title.clone());
+    for key in edits.unset {
+        if key == "schedule" {
+            meta.schedule = None;
+        }
+        meta.overrides.remove(&key.replace('_', "-"));
+    }
+    meta.overrides.extend(assignments);
+    if let Some(schedule) = edits.schedule {
+        meta.schedule = Some(schedule.to_string());
+    }
+
+    println!("Following {}", result.title);
+    if let Some(schedule) = &meta.schedule {
+        println!("  checked by watch: {}", schedule);
+    }
+    for (key, value) in &meta.overrides {
+        println!("  {} = {}", key, value);
+    }
+    store.save()
+}
+
+// The chapters `info` lists, in reading order. The chapter list is fetched
+// fresh and not stored.
+fn list_chapters(
+    source: &dyn Source,
+    name: &str,
+    range: Option<&ChapterRange>,
+    languages: &[String],
+) -> Result<Vec<Chapter>, Box<dyn std::error::Error>> {
+    let manga_url = if batch::is_url(name) {
+        name.to_string()
+    } else {
+        find_manga(source, name, None)?.url
+    };
+    let manga = source
+        .manga(&manga_url)
+        .map_err(|e| context(format!("Failed to fetch the chapter list: {}", e), e))?;
+    let chapters = match range {
+        Some(range) => chapters_in_range(&manga, range),
+        None => manga.chapters.iter().rev().cloned().collect(),
+    };
+    let chapters = pick_versions(chapters, None, languages);
+    if chapters.is_empty() {
+        return Err(match range {
+            Some(range) => format!("No chapters match {}.", range),
+            None => "The chapter list is empty.".to_string(),
+        }
+        .into());
+    }
+    Ok(chapters)
+}
+
+// The title followed by up to a few alternative names.
+fn describe_result(result: &SearchResult) -> String {
+    if result.alt_titles.is_empty() {
+        return result.title.clone();
+    }
+    let shown: Vec<&str> = result
+        .alt_titles
+        .iter()
+        .take(ALT_TITLES_SHOWN)
+        .map(|name| name.as_str())
+        .collect();
+    let more = if result.alt_titles.len() > ALT_TITLES_SHOWN {
+        ", ..."
+    } else {
+        ""
+    };
+    format!("{} (aka {}{})", result.title, shown.join(", "), more)
+}
+
+// Index of the only result whose title or an alternative name matches
+// `pattern`.
+fn match_result(results: &[SearchResult], pattern: &Regex) -> Result<usize, String> {
+    let matches: Vec<usize> = (0..results.len())
+        .filter(|&i| {
+            pattern.is_match(&results[i].title)
+                || results[i]
+                    .alt_titles
+                    .iter()
+                    .any(|name| pattern.is_match(name))
+        })
+        .collect();
+    match matches.as_slice() {
+        [index] => Ok(*index),
+        [] => Err(format!("No search result matches {}.", pattern)),
+        _ => {
+            let candidates: Vec<String> = matches
+                .iter()
+                .map(|&i| format!("  {} ({})", describe_result(&results[i]), results[i].url))
+                .collect();
+            Err(format!(
+                "{} search results match {}:\n{}",
+                matches.len(),
+                pattern,
+                candidates.join("\n")
+            ))
+        }
+    }
+}
+
+fn select_chapter(
+    source: &dyn Source,
+    manga_link: &str,
+    manga: &Manga,
+    number: &ChapterId,
+    preferred_group: Option<&str>,
+    languages: &[String],
+) -> Option<Chapter> {
+    let mut versions: Vec<&Chapter> = manga
+        .chapters
+        .iter()
+        .filter(|chapter| chapter.number.as_ref() == Some(number))
+        .collect();
+    versions.sort_by_key(|chapter| language_rank(chapter, languages));
+
+    match versions.len() {
+        0 => source.chapter_url(manga_link, number).map(|url| Chapter {
+            url,
+            name: format!("Chapter {}", number),
+            number: Some(number.clone()),
+            volume: None,
+            title: None,
+            group: None,
+            language: None,
+            uploaded: None,
+        }),
+        1 => Some(versions[0].clone()),
+        _ if preferred_group.is_some() => Some(pick_version(&versions, preferred_group).clone()),
+        _ => {
+            let width = versions
+                .iter()
+                .map(|c| ui::width(&c.name))
+                .max()
+                .unwrap_or(0);
+            let index_width = versions.len().to_string().len();
+            let show_language = versions
+                .iter()
+                .any(|chapter| chapter.language != versions[0].language);
+            for (index, chapter) in versions.iter().enumerate() {
+                let language = match (&chapter.language, show_language) {
+                    (Some(language), true) => format!("[{}] ", language),
+                    _ => String::new(),
+                };
+                println!(
+                    "{}{}{}  {}",
+                    ui::dim(&format!("[{:>index_width$}] ", index + 1)),
+                    language,
+                    ui::pad(&chapter.name, width),
+                    chapter.group.as_deref().unwrap_or("unknown group"),
+                );
+            }
+            let choice = match prompt::ask("Enter version number: ", None, prompt::parse_index) {
+                Ok(choice) => choice,
+                Err(e) => {
+                    println!("{}", e);
+                    return None;
+                }
+            };
+            versions
+                .get(choice.wrapping_sub(1))
+                .map(|chapter| (*chapter).clone())
+        }
+    }
+}
+
+// Position of a release's language in the preference list; unknown languages
+// sort last.
+fn language_rank(chapter: &Chapter, languages: &[String]) -> usize {
+    chapter
+        .language
+        .as_deref()
+        .and_then(|language| {
+            languages
+                .iter()
+                .position(|wanted| wanted.eq_ignore_ascii_case(language))
+        })
+        .unwrap_or(languages.len())
+}
+
+// Picks the preferred group's release, falling back to the first one.
+fn pick_version<'a>(versions: &[&'a Chapter], preferred_group: Option<&str>) -> &'a Chapter {
+    if let Some(group) = preferred_group {
+        let preferred = versions.iter().find(|chapter| {
+            chapter
+                .group
+                .as_deref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(group))
+        });
+        if let Some(chapter) = preferred {
+            return chapter;
+        }
+        ui::warn(&format!(
+            "{} has no release of {}, using {}.",
+            group,
+            versions[0].name,
+            versions[0].group.as_deref().unwrap_or("another group")
+        ));
+    }
+    versions[0]
+}
+
+// Keeps one release per chapter number, preserving order.
+fn pick_versions(
+    chapters: Vec<Chapter>,
+    preferred_group: Option<&str>,
+    languages: &[String],
+) -> Vec<Chapter> {
+    let mut picked: Vec<Chapter> = Vec::new();
+    for chapter in &chapters {
+        let Some(number) = &chapter.number else {
+            picked.push(chapter.clone());
+            continue;
+        };
+        if picked.iter().any(|c| c.number.as_ref() == Some(number)) {
+            continue;
+        }
+        let mut versions: Vec<&Chapter> = chapters
+            .iter()
+            .filter(|c| c.number.as_ref() == Some(number))
+            .collect();
+        versions.sort_by_key(|chapter| language_rank(chapter, languages));
+        picked.push(pick_version(&versions, preferred_group).clone());
+    }
+    picked
+}
+
+// Chapters of a volume in reading order; "none" selects chapters without one.
+fn chapters_in_volume(manga: &Manga, volume: &str) -> Vec<Chapter> {
+    let wanted = match volume.trim().to_lowercase().as_str() {
+        "none" => None,
+        volume => Some(normalize_number(volume)),
+    };
+    manga
+        .chapters
+        .iter()
+        .rev()
+        .filter(|chapter| chapter.volume == wanted)
+        .cloned()
+        .collect()
+}
+
+// Follows the reader's next-chapter links from the first chapter of an open
+// range, which also finds chapters a stale chapter list lacks and copes with
+// renumbered releases. Where a link is missing, the chapter list continues
+// the range.
+fn follow_next_links(
+    source: &dyn Source,
+    manga_url: &str,
+    manga: &Manga,
+    listed: Vec<Chapter>,
+    first: &ChapterId,
+) -> Vec<Chapter> {
+    let start = listed.first().cloned().or_else(|| {
+        Some(Chapter {
+            url: source.chapter_url(manga_url, first)?,
+            name: format!("Chapter {}", first),
+            number: Some(first.clone()),
+            volume: None,
+            title: None,
+            group: None,
+            language: None,
+            uploaded: None,
+        })
+    });
+    let Some(mut current) = start else {
+        return listed;
+    };
+    let mut seen = HashSet::from([chapter_path(&current.url)]);
+    let mut chapters = vec![current.clone()];
+    loop {
+        let next = match source.next_chapter(&current) {
+            Ok(Some(next)) => next,
+            Ok(None) => break,
+            Err(e) => {
+                log::debug!("No next-chapter link from {}: {}", current.name, e);
+                break;
+            }
+        };
+        if !seen.insert(chapter_path(&next.url)) || next.number == current.number {
+            ui::warn(&format!(
+                "{} links back to {} as its next chapter, stopping there.",
+                current.name, next.name
+            ));
+            break;
+        }
+        log::debug!("{} links to {} as the next chapter", current.name, next.url);
+        // The listed chapter carries more metadata than the link.
+        current = manga
+            .chapters
+            .iter()
+            .find(|chapter| chapter_path(&chapter.url) == chapter_path(&next.url))
+            .cloned()
+            .unwrap_or(next);
+        chapters.push(current.clone());
+    }
+
+    let last = chapters.last().and_then(|chapter| chapter.number.clone());
+    chapters.extend(
+        listed
+            .into_iter()
+            .filter(|chapter| !seen.contains(&chapter_path(&chapter.url)) && chapter.number > last),
+    );
+    chapters
+}
+
+// A chapter URL without its domain, so links on another mirror compare equal.
+fn chapter_path(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .map(|url| url.path().trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| url.to_string())
+}
+
+// Chapters whose number is in `range`, in reading order.
+fn chapters_in_range(manga: &Manga, range: &ChapterRange) -> Vec<Chapter> {
+    let mut numbers: Vec<&ChapterId> = manga
+        .chapters
+        .iter()
+        .filter_map(|chapter| chapter.number.as_ref())
+        .filter(|number| range.contains(number))
+        .collect();
+    numbers.sort_by(|a, b| b.cmp(a));
+    numbers.dedup();
+    if let ChapterRange::Last(count) = range {
+        numbers.truncate(*count);
+    }
+
+    let mut chapters: Vec<Chapter> = manga
+        .chapters
+        .iter()
+        .filter(|chapter| {
+            chapter
+                .number
+                .as_ref()
+                .is_some_and(|number| numbers.contains(&number))
+        })
+        .cloned()
+        .collect();
+    chapters.sort_by(|a, b| a.number.cmp(&b.number));
+    chapters
+}
+
+// Number of the newest chapter in the list.
+fn latest_number(manga: &Manga) -> Option<ChapterId> {
+    manga
+        .chapters
+        .iter()
+        .filter_map(|chapter| chapter.number.clone())
+        .max()
+}
+
+// A chapter's number for names; empty for chapters without one.
+fn number_text(number: &Option<ChapterId>) -> String {
+    number.as_ref().map(ToString::to_string).unwrap_or_default()
+}
+
+fn range_output(
+    manga: &Manga,
+    chapters: &[Chapter],
+    output_dir: &str,
+    report: &mut Report,
+) -> Output {
+    let first = &chapters[0].number;
+    let last = &chapters[chapters.len() - 1].number;
+    let title = filename::sanitize(&manga.title);
+    let (name, number, chapter_title) = if first == last {
+        let chapter_title = chapters[0].title.as_deref().and_then(clean_chapter_title);
+        let detail = chapter_title.as_deref().map(filename::sanitize);
+        let parts = NameParts {
+            title: &title,
+            number: &format!(" c{}", number_text(first)),
+            separator: " - ",
+            detail: detail.as_deref(),
+        };
+        (
+            output_name(&parts, output_dir, report),
+            first.clone(),
+            chapter_title,
+        )
+    } else {
+        let parts = NameParts {
+            title: &title,
+            number: &format!(" c{}-{}", number_text(first), number_text(last)),
+            separator: "",
+            detail: None,
+        };
+        (output_name(&parts, output_dir, report), None, None)
+    };
+    Output {
+        name,
+        info: ComicInfo {
+            series: manga.title.clone(),
+            title: chapter_title,
+            number,
+            volume: None,
+            page_count: 0,
+            bookmarks: Vec::new(),
+        },
+        chapter_starts: Vec::new(),
+    }
+}
+
+fn volume_output(manga: &Manga, volume: &str, output_dir: &str, report: &mut Report) -> Output {
+    let title = filename::sanitize(&manga.title);
+    let (suffix, number) = match volume.trim().parse::<u32>() {
+        Ok(number) => (format!(" v{:02}", number), Some(number.to_string())),
+        Err(_) if volume.eq_ignore_ascii_case("none") => (" no volume".to_string(), None),
+        Err(_) => (format!(" v{}", filename::sanitize(volume)), None),
+    };
+    let parts = NameParts {
+        title: &title,
+        number: &suffix,
+        separator: "",
+        detail: None,
+    };
+    let name = output_name(&parts, output_dir, report);
+    Output {
+        name,
+        info: ComicInfo {
+            series: manga.title.clone(),
+            title: None,
+            number: None,
+            volume: number,
+            page_count: 0,
+            bookmarks: Vec::new(),
+        },
+        chapter_starts: Vec::new(),
+    }
+}
+
+// An output's file name, shortened to fit into both the work directory and
+// `output_dir`.
+fn output_name(parts: &NameParts, output_dir: &str, report: &mut Report) -> String {
+    let work_dir = longest_work_dir(IMAGE_DIR);
+    let (name, shortened) = filename::fit(
+        &[Path::new(output_dir), &work_dir],
+        parts,
+        OUTPUT_NAME_RESERVE,
+    );
+    if shortened {
+        warn_shortened(&parts.full(), &name, report);
+    }
+    name
+}
+
+// The series' folder in the cache, leaving room for chapter folders in it.
+// A series merged into another one's folder keeps using that.
+fn series_folder(title: &str, manga_url: &str) -> (String, bool) {
+    if let Some(folder) = SeriesStore::load().get(manga_url).folder {
+        return (folder, false);
+    }
+    let title = filename::sanitize(title);
+    let parts = NameParts {
+        title: &title,
+        number: "",
+        separator: "",
+        detail: None,
+    };
+    filename::fit(&[&series_root(IMAGE_DIR)], &parts, SERIES_FOLDER_RESERVE)
+}
+
+// A chapter's folder in the series folder `series`, shortened from the
+// chapter title when it has one at the end of its name.
+fn chapter_folder(chapter: &Chapter, series: &str, work: &WorkDir) -> (String, bool) {
+    let name = filename::sanitize(&chapter.name);
+    let title = chapter.title.as_deref().map(filename::sanitize);
+    let split = title
+        .as_deref()
+        .filter(|title| !title.is_empty() && name.ends_with(title))
+        .map(|title| {
+            let prefix = &name[..name.len() - title.len()];
+            let head = prefix.trim_end_matches([' ', '_', '-', ':']);
+            (head, &prefix[head.len()..], Some(title))
+        });
+    let (head, separator, detail) = split.unwrap_or((&name, "", None));
+    let parts = NameParts {
+        title: head,
+        number: "",
+        separator,
+        detail,
+    };
+    filename::fit(
+        &[&series_dir(IMAGE_DIR, series), &work.staging_dir()],
+        &parts,
+        CHAPTER_FOLDER_RESERVE,
+    )
+}
+
+// Reports a name filename::fit() shortened.
+fn warn_shortened(full: &str, short: &str, report: &mut Report) {
+    let warning = format!(
+        "Shortened \"{}\" to \"{}\" to keep paths within {} bytes (max_path_length).",
+        full,
+        short,
+        filename::max_path()
+    );
+    ui::warn(&warning);
+    report.warnings.push(warning);
+}
+
+fn level_options(cli: &CLI) -> Option<LevelsOptions> {
+    let levels = match cli.levels {
+        Some((black, white)) => Levels::Fixed(black, white),
+        None if cli.autocontrast => Levels::Auto,
+        None if cli.gamma != 1.0 => Levels::Fixed(0, 255),
+        None => return None,
+    };
+    Some(LevelsOptions {
+        levels,
+        gamma: cli.gamma,
+        force_color: cli.adjust_color_pages,
+    })
+}
+
+fn parse_levels(value: &str) -> Result<(u8, u8), String> {
+    let (black, white) = value
+        .split_once(',')
+        .ok_or("expected BLACK,WHITE, e.g. 20,235")?;
+    let black: u8 = black
+        .trim()
+        .parse()
+        .map_err(|_| "black level must be 0-255")?;
+    let white: u8 = white
+        .trim()
+        .parse()
+        .map_err(|_| "white level must be 0-255")?;
+    if black >= white {
+        return Err("black level must be below white level".into());
+    }
+    Ok((black, white))
+}
+
+fn parse_mirror(value: &str) -> Result<String, String> {
+    match reqwest::Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {
+            Ok(value.trim_end_matches('/').to_string())
+        }
+        _ => Err("expected an http(s) URL such as https://m.manganelo.com".into()),
+    }
+}
+
+fn parse_entry_template(value: &str) -> Result<Template, String> {
+    let template = Template::parse(value, &["series", "chapter", "page"])?;
+    if !template.uses("page") {
+        return Err("the template needs {page} to tell pages apart".into());
+    }
+    if template
+        .literal_text()
+        .any(|text| text.contains('/') || text.contains('\\'))
+    {
+        return Err("entry names can't contain path separators".into());
+    }
+    Ok(template)
+}
+
+fn parse_stamp_opacity(value: &str) -> Result<f64, String> {
+    match value.trim_end_matches('%').parse::<f64>() {
+        Ok(opacity) if (1.0..=100.0).contains(&opacity) => Ok(opacity),
+        _ => Err("opacity must be a percentage from 1 to 100".into()),
+    }
+}
+
+fn parse_stamp_size(value: &str) -> Result<f64, String> {
+    match value.trim_end_matches('%').parse::<f64>() {
+        Ok(size) if size > 0.0 && size <= 20.0 => Ok(size),
+        _ => Err("size must be a percentage of the page height, above 0 and up to 20".into()),
+    }
+}
+
+fn parse_bundle_size(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err("expected a number of chapters, at least 1".into()),
+    }
+}
+
+// A size in bytes, or with a K, M or G suffix: "80M", "1.5G".
+fn parse_target_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let upper = value.to_uppercase();
+    let digits = upper.trim_end_matches(['B', 'I']);
+    let (number, unit) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1u64 << 10),
+        Some('M') => (&digits[..digits.len() - 1], 1 << 20),
+        Some('G') => (&digits[..digits.len() - 1], 1 << 30),
+        _ => (digits, 1),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number > 0.0 => Ok((number * unit as f64) as u64),
+        _ => Err(format!(
+            "\"{}\" is not a size; expected bytes or a number with K, M or G, e.g. 80M",
+            value
+        )),
+    }
+}
+
+fn parse_quarantine_after(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err("expected a number of failures, at least 1".into()),
+    }
+}
+
+// A schedule is stored as written, once it parses.
+fn parse_schedule_text(value: &str) -> Result<String, String> {
+    parse_schedule(value).map(|_| value.trim().to_string())
+}
+
+fn parse_gamma(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(gamma) if gamma > 0.0 && gamma.is_finite() => Ok(gamma),
+        _ => Err("gamma must be a positive number".into()),
+    }
+}
+
+fn download_chapters(
+    source: &dyn Source,
+    manga_link: &str,
+    chapters: &[Chapter],
+    mut output: Output,
+    options: &DownloadOptions,
+    report: &mut Report,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let work = WorkDir::create(IMAGE_DIR).map_err(|e| Error::filesystem(IMAGE_DIR, e))?;
+
+    let known_hashes = known_promo_hashes(source, options);
+    let ads = ad_blocklist(source, options);
+
+    // Failed chapters are retried after the main pass, waiting longer before
+    // each pass since failures are mostly rate limiting.
+    let first_report = report.chapters.len();
+    report
+        .chapters
+        .extend(chapters.iter().map(new_chapter_report));
+    let mut downloads: Vec<Option<ChapterPages>> = chapters.iter().map(|_| None).collect();
+    // The last error of each chapter, which decides the exit code.
+    let mut causes: Vec<Option<Box<dyn std::error::Error>>> =
+        chapters.iter().map(|_| None).collect();
+    let mut next_page = 1;
+    // Indices of `chapters` in the order they are downloaded.
+    let sequence: Vec<usize> = match options.order {
+        Order::Oldest => (0..chapters.len()).collect(),
+        Order::Newest => (0..chapters.len()).rev().collect(),
+    };
+    if chapters.len() > 1 {
+        report.order = Some(
+            match options.order {
+                Order::Oldest => "oldest first",
+                Order::Newest => "newest first",
+            }
+            .to_string(),
+        );
+    }
+    // --strict checks every chapter's page list before any page is
+    // downloaded. Lists that can't be fetched are left to the download passes
+    // and their retries.
+    if options.strict {
+        for &i in &sequence {
+            let chapter = &chapters[i];
+            let chapter_report = &mut report.chapters[first_report + i];
+            let fetched = fetch_page_list(
+                source,
+                chapter,
+                &mut downloads[i],
+                &mut next_page,
+                &work,
+                options.dedupe,
+                chapter_report,
+            );
+            if let Some(mismatch) = fetched
+                .ok()
+                .and_then(|_| chapter_report.page_count_mismatch())
+            {
+                return Err(format!("{} Stopping because of --strict.", mismatch).into());
+            }
+        }
+    }
+    // Pages are processed as they arrive, so CPU work overlaps the downloads.
+    let processor = (!options.process.is_empty())
+        .then(|| PageProcessor::start(&options.process, options.process_jobs));
+    let profile = options.profile.then(|| Mutex::new(Vec::new()));
+    let stage = PageStage {
+        work: &work,
+        jobs: options.jobs,
+        compat_format: options.compat_format,
+        processor: processor.as_ref(),
+        profile: profile.as_ref(),
+        strict: options.strict,
+        dedupe: options.dedupe,
+    };
+    let started = Instant::now();
+    for pass in 0..=options.retry_passes {
+        let pending: Vec<usize> = sequence
+            .iter()
+            .copied()
+            .filter(|&i| report.chapters[first_report + i].status == ChapterStatus::Failed)
+            .collect();
+        if pending.is_empty() {
+            break;
+        }
+        if pass > 0 {
+            let delay = RETRY_DELAY * pass as u32;
+            println!(
+                "Retrying {} failed chapter(s) in {}s...",
+                pending.len(),
+                delay.as_secs()
+            );
+            thread::sleep(delay);
+        }
+        for (n, &i) in pending.iter().enumerate() {
+            if pass > 0 {
+                thread::sleep(RETRY_CHAPTER_DELAY * pass as u32);
+            }
+            let page_bytes = options.space_check.then(|| match report.pages_downloaded {
+                0 => ASSUMED_PAGE_BYTES,
+                pages => report.bytes_downloaded / pages as u64,
+            });
+            if let Some(cap) = options.monthly_cap {
+                if let Err(e) = transfer::check_cap(cap) {
+                    if let Some(processor) = processor {
+                        let _ = processor.finish();
+                    }
+                    return Err(e.into());
+                }
+            }
+            let later = Later::new(&downloads, &pending[n + 1..]);
+            let chapter_started = Instant::now();
+            let transferred_before = http::transferred();
+            let (count, bytes, cause) = download_chapter(
+                source,
+                &chapters[i],
+                &mut downloads[i],
+                &mut next_page,
+                &stage,
+                page_bytes,
+                &later,
+                &mut report.chapters[first_report + i],
+            );
+            let chapter_report = &mut report.chapters[first_report + i];
+            chapter_report.seconds += chapter_started.elapsed().as_secs_f64();
+            chapter_report.bytes_transferred += http::transferred() - transferred_before;
+            log_transfer(chapter_report);
+            report.pages_downloaded += count;
+            report.bytes_downloaded += bytes;
+            match cause {
+                // Later chapters won't fit either.
+                Some(cause) if is_out_of_space(cause.as_ref()) => {
+                    if let Some(processor) = processor {
+                        let _ = processor.finish();
+                    }
+                    return Err(cause);
+                }
+                cause => causes[i] = cause,
+            }
+        }
+    }
+    // Chapters still failing are decided on by policy or by asking: pages
+    // that kept failing by --on-page-failure, whole chapters by
+    // --on-chapter-failure.
+    let mut left_out = vec![false; chapters.len()];
+    for &i in &sequence {
+        loop {
+            let chapter_report = &report.chapters[first_report + i];
+            if chapter_report.status != ChapterStatus::Failed {
+                break;
+            }
+            if chapter_report.error.is_none() {
+                let problem = format!(
+                    "{}: {} of {} pages failed",
+                    chapter_report.name,
+                    chapter_report.failed_pages.len(),
+                    chapter_report.pages
+                );
+                let (policy, asked) = policy::decide(
+                    &problem,
+                    options.on_page_failure,
+                    PagePolicy::Retry,
+                    &mut report.decisions,
+                );
+                match policy {
+                    PagePolicy::Skip => {
+                        if let Some(download) = &mut downloads[i] {
+                            let done = download.done.clone();
+                            let mut keep = done.iter();
+                            download.images.retain(|_| *keep.next().unwrap());
+                            let mut keep = done.iter();
+                            download.paths.retain(|_| *keep.next().unwrap());
+                            download.done.retain(|done| *done);
+                        }
+                        report.chapters[first_report + i].status = ChapterStatus::Partial;
+                        causes[i] = None;
+                        break;
+                    }
+                    PagePolicy::Retry if asked => {
+                        let (count, bytes, cause) = download_chapter(
+                            source,
+                            &chapters[i],
+                            &mut downloads[i],
+                            &mut next_page,
+                            &stage,
+                            None,
+                            &Later::default(),
+                            &mut report.chapters[first_report + i],
+                        );
+                        report.pages_downloaded += count;
+                        report.bytes_downloaded += bytes;
+                        causes[i] = cause;
+                        continue;
+                    }
+                    PagePolicy::Retry => {}
+                    PagePolicy::Abort => break,
+                }
+            }
+            let problem = format!("{} failed", report.chapters[first_report + i].name);
+            let (policy, _) = policy::decide(
+                &problem,
+                options.on_chapter_failure,
+                ChapterPolicy::Abort,
+                &mut report.decisions,
+            );
+            left_out[i] = policy == ChapterPolicy::Continue;
+            break;
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    log::debug!(
+        "Downloaded {} pages ({}) in {:.1}s ({:.1} pages/s)",
+        report.pages_downloaded,
+        report::format_bytes(report.bytes_downloaded),
+        elapsed,
+        report.pages_downloaded as f64 / elapsed.max(0.001)
+    );
+    report.transcoded_pages += report.chapters[first_report..]
+        .iter()
+        .map(|chapter| chapter.transcoded_pages)
+        .sum::<usize>();
+    let processed = match processor {
+        Some(processor) => processor
+            .finish()
+            .map_err(|e| e as Box<dyn std::error::Error>)?,
+        None => Vec::new(),
+    };
+    if let Some(profile) = profile {
+        let mut timings = profile.into_inner().unwrap();
+        for (sequence, elapsed) in processed {
+            if let Some(timing) = timings.iter_mut().find(|t| t.sequence == sequence) {
+                timing.process_ms = Some(millis(elapsed));
+                timing.update_total();
+            }
+        }
+        report.profile = Some(Profile::new(timings));
+    }
 
-#[derive(Parser)]
-#[clap(name = "manga-cli")]
-#[clap(about = "A command-line manga downloader.")]
-struct CLI {
-    #[clap(short, long, arg_enum)]
-    format: Option<Format>, // Add `format` as Option<Format>
+    if !options.strict {
+        let mismatches: Vec<String> = report.chapters[first_report..]
+            .iter()
+            .filter_map(ChapterReport::page_count_mismatch)
+            .collect();
+        report.warnings.extend(mismatches);
+    }
 
-    #[clap(short, long)]
-    clear: bool,
+    let failed: Vec<String> = report.chapters[first_report..]
+        .iter()
+        .zip(&left_out)
+        .filter(|(chapter, left_out)| chapter.status == ChapterStatus::Failed && !**left_out)
+        .map(|(chapter, _)| match &chapter.error {
+            Some(error) => format!("{}: {}", chapter.name, error),
+            None => format!(
+                "{}: {} of {} pages failed",
+                chapter.name,
+                chapter.failed_pages.len(),
+                chapter.pages
+            ),
+        })
+        .collect();
+    if !failed.is_empty() {
+        let message = format!(
+            "{} of {} chapters failed ({})",
+            failed.len(),
+            chapters.len(),
+            failed.join("; ")
+        );
+        return Err(match causes.into_iter().flatten().next() {
+            Some(cause) => context(message, cause),
+            None => message.into(),
+        });
+    }
+    if left_out.iter().all(|left_out| *left_out) {
+        return Err("Every chapter failed; there is nothing to build.".into());
+    }
 
-    #[clap(short, long)]
-    viewer: Option<String>,
+    // Chapters are put into one continuous page sequence so a volume can be
+    // packed into a single archive.
+    let mut pages: Vec<String> = Vec::new();
+    let mut chapter_page_lists = Vec::new();
+    for ((chapter, download), left_out) in chapters.iter().zip(downloads).zip(&left_out) {
+        if *left_out {
+            chapter_page_lists.push(Vec::new());
+            continue;
+        }
+        output
+            .chapter_starts
+            .push((pages.len(), chapter.number.clone()));
+        if chapters.len() > 1 {
+            output
+                .info
+                .bookmarks
+                .push((pages.len(), chapter.name.clone()));
+        }
+
+        let (mut chapter_pages, urls) = download
+            .map(|download| (download.paths, download.images))
+            .unwrap_or_default();
+        if let Some(ads) = &ads {
+            for (i, reason) in promo::trailing_ads(&chapter_pages, &urls, ads) {
+                report.flagged_pages.push(FlaggedPage {
+                    chapter: chapter.name.clone(),
+                    page: i + 1,
+                    reason,
+                    skipped: true,
+                    ad: true,
+                });
+                chapter_pages.remove(i);
+            }
+        }
+        if options.dedupe {
+            for i in dedupe::repeated_pages(&chapter_pages).into_iter().rev() {
+                let warning = format!(
+                    "{}: page {} is identical to page {}, dropped it (--no-dedupe keeps it).",
+                    chapter.name,
+                    i + 1,
+                    i
+                );
+                ui::warn(&warning);
+                report.warnings.push(warning);
+                chapter_pages.remove(i);
+            }
+        }
+        let flagged = suspicious_pages(&chapter_pages, &known_hashes);
+        for (i, reason) in flagged.iter().rev() {
+            report.flagged_pages.push(FlaggedPage {
+                chapter: chapter.name.clone(),
+                page: i + 1,
+                reason: reason.clone(),
+                skipped: options.promo.skip,
+                ad: false,
+            });
+            if options.promo.skip {
+                chapter_pages.remove(*i);
+            }
+        }
+        pages.extend(chapter_pages.iter().cloned());
+        chapter_page_lists.push(chapter_pages);
+    }
+
+    let mut encodings = Vec::new();
+    if options.low_data {
+        report.low_data = Some(if source.compressed_images() {
+            LowData {
+                recompressed: false,
+                saved_percent: None,
+                saved_bytes: 0,
+                kept_originals: 0,
+            }
+        } else {
+            encodings = recompress_pages(&pages, options.always_reencode)
+                .map_err(|e| e as Box<dyn std::error::Error>)?;
+            let before: u64 = encodings.iter().map(|page| page.original_bytes).sum();
+            let after: u64 = encodings
+                .iter()
+                .map(|page| {
+                    if page.kept_original {
+                        page.original_bytes
+                    } else {
+                        page.encoded_bytes
+                    }
+                })
+                .sum();
+            let saved = before as i64 - after as i64;
+            LowData {
+                recompressed: true,
+                saved_percent: (before > 0).then(|| 100.0 * saved as f64 / before as f64),
+                saved_bytes: saved,
+                kept_originals: encodings.iter().filter(|page| page.kept_original).count(),
+            }
+        });
+    }
+
+    let mut size_settings = Vec::new();
+    if let Some(budget) = options.target_size {
+        let quality = (options.low_data && !source.compressed_images()).then_some(LOW_DATA_QUALITY);
+        for (chapter, chapter_pages) in chapters.iter().zip(&chapter_page_lists) {
+            let fitted = fit_to_size(chapter_pages, budget, quality)
+                .map_err(|e| e as Box<dyn std::error::Error>)?;
+            let settings = match fitted.settings {
+                Some(settings) => format!(
+                    "quality {}{}",
+                    settings.quality,
+                    settings
+                        .max_height
+                        .map(|height| format!(", at most {}px high", height))
+                        .unwrap_or_default()
+                ),
+                None => "as downloaded".to_string(),
+            };
+            let fitted_line = format!(
+                "{}: {}, {} of {}",
+                chapter.name,
+                settings,
+                report::format_bytes(fitted.bytes),
+                report::format_bytes(budget)
+            );
+            if !fitted.fits {
+                let warning = format!(
+                    "{}: still over --target-size at the lowest quality ({} of {}).",
+                    chapter.name,
+                    report::format_bytes(fitted.bytes),
+                    report::format_bytes(budget)
+                );
+                ui::warn(&warning);
+                report.warnings.push(warning);
+            }
+            report.fitted.push(fitted_line);
+            size_settings.push(fitted.settings);
+        }
+    }
 
-    manga_name: String,
+    let now = OffsetDateTime::now_utc();
+    let release_date = latest_release(chapters, now);
+    let series = filename::sanitize(&output.info.series);
+    let series_title = output.info.series.clone();
+    package(&pages, output, release_date, options, &work, report)?;
+
+    let (folder, shortened) = series_folder(&series_title, manga_link);
+    if shortened {
+        warn_shortened(&series, &folder, report);
+    }
+
+    // Keep the finished chapters in the per-series cache layout.
+    let mut encodings = encodings.into_iter();
+    for (i, (chapter, chapter_pages)) in chapters.iter().zip(&chapter_page_lists).enumerate() {
+        // Renumbered from the whole sequence to the chapter's own pages.
+        let chapter_encodings: Vec<Encoding> = encodings
+            .by_ref()
+            .take(chapter_pages.len())
+            .enumerate()
+            .map(|(page, encoding)| Encoding {
+                page: page + 1,
+                ..encoding
+            })
+            .collect();
+        // Chapters left out or missing pages would look complete.
+        if report.chapters[first_report + i].status == ChapterStatus::Partial || left_out[i] {
+            continue;
+        }
+        let declared_pages = report.chapters[first_report + i].declared_pages;
+        let found_pages = report.chapters[first_report + i].pages;
+        let uploaded = chapter
+            .uploaded
+            .as_deref()
+            .and_then(|uploaded| parse_release_date(uploaded, now));
+        let manifest = Manifest {
+            manga_url: manga_link.to_string(),
+            chapter_urls: vec![chapter.url.clone()],
+            pages: chapter_pages.len(),
+            release_date: uploaded.unwrap_or(now).format(&Rfc3339)?,
+            release_date_estimated: uploaded.is_none(),
+            chapter_title: chapter.title.clone(),
+            series_title: Some(series_title.clone()),
+            chapter_name: Some(chapter.name.clone()),
+            number: chapter.number.clone(),
+            volume: chapter.volume.clone(),
+            group: chapter.group.clone(),
+            language: chapter.language.clone(),
+            low_data: options.low_data,
+            encodings: chapter_encodings,
+            size_settings: size_settings.get(i).copied().flatten(),
+            declared_pages,
+            page_count_matches: declared_pages.map(|declared| declared == found_pages),
+        };
+        let names = cache_names(
+            chapter_pages,
+            &series,
+            chapter.number.as_ref(),
+            options.entry_template.as_ref(),
+        )?;
+        let (chapter_folder, shortened) = chapter_folder(chapter, &folder, &work);
+        if shortened {
+            warn_shortened(&filename::sanitize(&chapter.name), &chapter_folder, report);
+        }
+        work.promote_chapter(
+            IMAGE_DIR,
+            &folder,
+            &chapter_folder,
+            chapter_pages,
+            &names,
+            &manifest,
+        )?;
+    }
+    Ok(())
 }
 
-#[derive(ArgEnum, Clone)]
-enum Format {
-    Pdf,
-    Cbz,
+// A chapter's report before its first attempt.
+fn new_chapter_report(chapter: &Chapter) -> ChapterReport {
+    ChapterReport {
+        name: chapter.name.clone(),
+        url: chapter.url.clone(),
+        status: ChapterStatus::Failed,
+        attempts: 0,
+        pages: 0,
+        error: None,
+        failed_pages: Vec::new(),
+        transcoded_pages: 0,
+        declared_pages: None,
+        seconds: 0.0,
+        bytes_transferred: 0,
+    }
 }
 
-const SEARCH_URL: &str = "https://m.manganelo.com/search/story/";
-const IMAGE_DIR: &str = ".cache/manga-cli";
+// Running totals for --verbose after each chapter.
+fn log_transfer(chapter: &ChapterReport) {
+    log::debug!(
+        "Transferred {} for {}, {} this month",
+        report::format_bytes(chapter.bytes_transferred),
+        chapter.name,
+        report::format_bytes(transfer::this_month())
+    );
+}
 
-fn main() {
-    let cli = CLI::parse();
+// Release date of the newest chapter, `now` when none is known.
+fn latest_release(chapters: &[Chapter], now: OffsetDateTime) -> OffsetDateTime {
+    chapters
+        .iter()
+        .filter_map(|chapter| chapter.uploaded.as_deref())
+        .filter_map(|uploaded| parse_release_date(uploaded, now))
+        .max()
+        .unwrap_or(now)
+}
 
-    if cli.clear {
-        clear_cache();
-        return;
+// Flags --stream-cbz can't honor, since they work on page files that
+// streaming never writes.
+fn check_stream_args(cli: &CLI) -> Result<(), String> {
+    if !cli.stream_cbz {
+        return Ok(());
+    }
+    let mut conflicts = Vec::new();
+    for format in cli.format.iter().filter(|format| **format != Format::Cbz) {
+        conflicts.push(format!(
+            "--format {}",
+            format.to_possible_value().unwrap().get_name()
+        ));
+    }
+    let flags = [
+        ("--trim-margins", cli.trim_margins),
+        ("--autocontrast", cli.autocontrast),
+        ("--levels", cli.levels.is_some()),
+        ("--gamma", cli.gamma != 1.0),
+        ("--adjust-color-pages", cli.adjust_color_pages),
+        ("--upscale-cmd", cli.upscale_cmd.is_some()),
+        ("--stamp-pages", cli.stamp_pages.is_some()),
+        ("--compat-format", cli.compat_format.is_some()),
+        ("--low-data", cli.low_data),
+        ("--target-size", cli.target_size.is_some()),
+        ("--skip-promo-pages", cli.skip_promo_pages),
+        ("--profile-run", cli.profile_run),
+        ("--order newest", cli.order == Order::Newest),
+    ];
+    conflicts.extend(
+        flags
+            .iter()
+            .filter(|(_, set)| *set)
+            .map(|(flag, _)| flag.to_string()),
+    );
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "--stream-cbz writes pages into the archive unprocessed and in reading order, so it can't be combined with {}.",
+        conflicts.join(", ")
+    ))
+}
+
+// Downloads the chapters straight into a CBZ (--stream-cbz) instead of
+// staging page files. Pages are fetched `jobs` at a time and written in order
+// as stored entries, so no more than that many are held in memory. Pages
+// still failing after the retries are left out of an archive published as
+// "<name>.partial.cbz", except with --strict, which deletes it. Nothing is
+// kept in the series cache.
+fn stream_cbz(
+    source: &dyn Source,
+    chapters: &[Chapter],
+    mut output: Output,
+    options: &DownloadOptions,
+    report: &mut Report,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let work = WorkDir::create(IMAGE_DIR).map_err(|e| Error::filesystem(IMAGE_DIR, e))?;
+    let first_report = report.chapters.len();
+    report
+        .chapters
+        .extend(chapters.iter().map(new_chapter_report));
+
+    // Every page list is fetched first: entry names are padded to the total
+    // page count, and --strict refuses a chapter before anything is written.
+    let mut page_lists = Vec::new();
+    for (i, chapter) in chapters.iter().enumerate() {
+        let chapter_report = &mut report.chapters[first_report + i];
+        chapter_report.attempts += 1;
+        let images = page_urls(source, chapter, options.dedupe).inspect_err(|e| {
+            chapter_report.error = Some(e.to_string());
+        })?;
+        chapter_report.pages = images.len();
+        chapter_report.declared_pages = source.declared_pages(chapter);
+        if let Some(mismatch) = chapter_report
+            .page_count_mismatch()
+            .filter(|_| options.strict)
+        {
+            return Err(format!("{} Stopping because of --strict.", mismatch).into());
+        }
+        page_lists.push(images);
+    }
+    let total: usize = page_lists.iter().map(Vec::len).sum();
+    if options.space_check {
+        space::check(IMAGE_DIR, total as u64 * ASSUMED_PAGE_BYTES)?;
+    }
+
+    let release_date = latest_release(chapters, OffsetDateTime::now_utc());
+    let cbz_path = format!("{}/{}.cbz", work.path, output.name);
+    let tmp_path = format!("{}{}", cbz_path, UNFINISHED_SUFFIX);
+    let written = stream_pages(
+        source,
+        chapters,
+        &page_lists,
+        &tmp_path,
+        &mut output,
+        release_date,
+        options,
+        report,
+        first_report,
+    );
+    let (pages, bytes) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+    };
+    report.pages_downloaded += pages;
+    report.bytes_downloaded += bytes;
+    if !options.strict {
+        let mismatches: Vec<String> = report.chapters[first_report..]
+            .iter()
+            .filter_map(ChapterReport::page_count_mismatch)
+            .collect();
+        report.warnings.extend(mismatches);
+    }
+
+    let missing: usize = report.chapters[first_report..]
+        .iter()
+        .map(|chapter| chapter.failed_pages.len())
+        .sum();
+    // Under its own name, --skip-existing and --on-conflict don't take an
+    // archive missing pages for the finished one.
+    let partial_name = format!("{}{}", output.name, PARTIAL_SUFFIX);
+    let cbz_path = if missing > 0 {
+        format!("{}/{}.cbz", work.path, partial_name)
+    } else {
+        cbz_path
+    };
+    fs::rename(&tmp_path, &cbz_path)?;
+    set_release_mtime(&cbz_path, release_date)?;
+    let published = publish_output(&Format::Cbz, &cbz_path, options, &work)?;
+    report.outputs.push(published.clone());
+    if missing == 0 {
+        println!("CBZ created successfully in {}", published);
+        run_post_command(&Format::Cbz, &published, &output, options, report)?;
+        // What an earlier run left of this output is replaced.
+        let earlier = Path::new(&options.output_dir).join(format!("{}.cbz", partial_name));
+        if let Err(e) = fs::remove_file(&earlier) {
+            if e.kind() != io::ErrorKind::NotFound {
+                report
+                    .warnings
+                    .push(format!("Failed to remove {}: {}", earlier.display(), e));
+            }
+        }
+        return Ok(());
     }
 
-    let manga_name = format_manga_name(&cli.manga_name);
-    let manga_ids = fetch_manga_ids(&manga_name).expect("Failed to fetch manga IDs");
+    println!("Partial CBZ saved in {}", published);
+    let problem = format!("{} of {} pages failed", missing, total);
+    // The archive is already written, so only skipping is left to choose.
+    let (policy, _) = policy::decide(
+        &problem,
+        options.on_page_failure,
+        PagePolicy::Abort,
+        &mut report.decisions,
+    );
+    if policy != PagePolicy::Skip {
+        return Err(format!("{} and are missing from {}", problem, published).into());
+    }
+    for chapter in &mut report.chapters[first_report..] {
+        if !chapter.failed_pages.is_empty() {
+            chapter.status = ChapterStatus::Partial;
+        }
+    }
+    Ok(())
+}
 
-    // Display available manga titles
-    for (index, title) in manga_ids.iter().enumerate() {
-        println!("[{}] {}", index + 1, title);
+// Writes the pages of `page_lists` into a new archive at `path` as they
+// download, with ComicInfo.xml last once the page count is known. Returns the
+// pages and bytes written.
+#[allow(clippy::too_many_arguments)]
+fn stream_pages(
+    source: &dyn Source,
+    chapters: &[Chapter],
+    page_lists: &[Vec<String>],
+    path: &str,
+    output: &mut Output,
+    release_date: OffsetDateTime,
+    options: &DownloadOptions,
+    // The chapters' reports start at `first_report`.
+    report: &mut Report,
+    first_report: usize,
+) -> Result<(usize, u64), Box<dyn std::error::Error>> {
+    let mut zip = ZipWriter::new(fs::File::create(path)?);
+    let mut entry_options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    if options.reproducible {
+        entry_options = entry_options
+            .last_modified_time(zip::DateTime::default())
+            .unix_permissions(0o644);
+    } else if let Ok(modified) = zip::DateTime::try_from(release_date) {
+        entry_options = entry_options.last_modified_time(modified);
     }
 
-    let manga_number: usize = prompt("Enter number: ") - 1;
-    let manga_link = &manga_ids[manga_number];
+    let series = filename::sanitize(&output.info.series);
+    let total: usize = page_lists.iter().map(Vec::len).sum();
+    let width = total.to_string().len().max(3);
+    let scheduler = Scheduler::new(options.jobs, MAX_REQUESTS_PER_HOST);
+    let mut names = HashSet::new();
+    let (mut pages, mut bytes) = (0, 0);
+    let ads = ad_blocklist(source, options);
+    for (k, (chapter, images)) in chapters.iter().zip(page_lists).enumerate() {
+        if let Some(cap) = options.monthly_cap {
+            transfer::check_cap(cap)?;
+        }
+        let started = Instant::now();
+        let transferred_before = http::transferred();
+        output.chapter_starts.push((pages, chapter.number.clone()));
+        if chapters.len() > 1 {
+            output.info.bookmarks.push((pages, chapter.name.clone()));
+        }
+        let headers = source.image_headers(chapter);
+        let later = page_lists[k + 1..].iter().map(Vec::len).sum();
+        let progress = Progress::new(&chapter.name, 0, images.len(), later);
+        let indices: Vec<usize> = (0..images.len()).collect();
+        // The last page written, to drop pages identical to it.
+        let mut previous: Option<(usize, Vec<u8>)> = None;
+        for window in indices.chunks(options.jobs.max(1)) {
+            let mut results: Vec<_> = window.iter().map(|_| None).collect();
+            for pass in 0..=options.retry_passes {
+                let pending: Vec<usize> = (0..window.len())
+                    .filter(|&j| !matches!(results[j], Some(Ok(_))))
+                    .collect();
+                if pending.is_empty() {
+                    break;
+                }
+                if pass > 0 {
+                    thread::sleep(RETRY_CHAPTER_DELAY * pass as u32);
+                }
+                let tasks = pending
+                    .iter()
+                    .map(|&j| (host_of(&images[window[j]]), j))
+                    .collect();
+                let fetched = scheduler.run(tasks, |j| fetch_page(&images[window[j]], &headers));
+                for (j, result) in pending.into_iter().zip(fetched) {
+                    results[j] = Some(result);
+                }
+            }
+
+            for (&i, result) in window.iter().zip(results) {
+                let data = match result.unwrap() {
+                    Ok(data) => data,
+                    Err(e) if options.strict => {
+                        progress.finish();
+                        return Err(format!(
+                            "{} page {}: {} Stopping because of --strict.",
+                            chapter.name,
+                            i + 1,
+                            e
+                        )
+                        .into());
+                    }
+                    Err(e) => {
+                        report.chapters[first_report + k]
+                            .failed_pages
+                            .push(FailedPage {
+                                page: i + 1,
+                                url: images[i].clone(),
+                                error: e.to_string(),
+                            });
+                        continue;
+                    }
+                };
+                if let Some((previous_page, _)) = previous
+                    .as_ref()
+                    .filter(|(_, previous)| options.dedupe && *previous == data)
+                {
+                    let warning = format!(
+                        "{}: page {} is identical to page {}, dropped it (--no-dedupe keeps it).",
+                        chapter.name,
+                        i + 1,
+                        previous_page + 1
+                    );
+                    ui::warn(&warning);
+                    report.warnings.push(warning);
+                    progress.advance(data.len() as u64);
+                    continue;
+                }
+                // Pages are judged as they arrive, so any ad among the last
+                // few pages is dropped, not only a trailing run of them.
+                let ad = ads
+                    .as_ref()
+                    .filter(|_| AdBlocklist::in_tail(i, images.len()))
+                    .and_then(|ads| ads.reason(&images[i], &data));
+                if let Some(reason) = ad {
+                    report.flagged_pages.push(FlaggedPage {
+                        chapter: chapter.name.clone(),
+                        page: i + 1,
+                        reason,
+                        skipped: true,
+                        ad: true,
+                    });
+                    progress.advance(data.len() as u64);
+                    continue;
+                }
+                let extension = compat::extension_of(&data);
+                let name = match &options.entry_template {
+                    Some(template) => {
+                        template_name(template, &series, chapter.number.as_ref(), i + 1, extension)
+                    }
+                    None => format!("{:0width$}.{}", pages + 1, extension, width = width),
+                };
+                if !is_safe_entry_name(&name) {
+                    return Err(format!("Refusing to write unsafe archive entry {:?}", name).into());
+                }
+                if !names.insert(name.clone()) {
+                    return Err(format!(
+                        "--entry-template gives more than one page the name {}",
+                        name
+                    )
+                    .into());
+                }
+                zip.start_file(&name, entry_options)?;
+                zip.write_all(&data)?;
+                pages += 1;
+                bytes += data.len() as u64;
+                progress.advance(data.len() as u64);
+                if options.dedupe {
+                    previous = Some((i, data));
+                }
+            }
+        }
+        progress.finish();
+        let chapter_report = &mut report.chapters[first_report + k];
+        chapter_report.seconds += started.elapsed().as_secs_f64();
+        chapter_report.bytes_transferred += http::transferred() - transferred_before;
+        log_transfer(chapter_report);
+        if chapter_report.failed_pages.is_empty() {
+            chapter_report.status = ChapterStatus::Downloaded;
+        }
+    }
 
-    let chapter_number: usize = prompt("Enter chapter number: ");
-    let chapter_link = format!("{}/chapter-{}", manga_link, chapter_number);
+    output.info.page_count = pages;
+    zip.start_file("ComicInfo.xml", entry_options)?;
+    zip.write_all(output.info.to_xml().as_bytes())?;
+    zip.finish()?;
+    drop(zip);
+    let archive = zip::ZipArchive::new(fs::File::open(path)?)
+        .map_err(|e| format!("The written archive doesn't read back: {}", e))?;
+    if archive.len() != pages + 1 {
+        return Err(format!(
+            "The written archive lists {} of {} entries",
+            archive.len(),
+            pages + 1
+        )
+        .into());
+    }
+    Ok((pages, bytes))
+}
 
-    // Use cli.format directly, passing it as Option<Format>
-    download_chapter(&chapter_link, cli.format).expect("Failed to download chapter");
+// A page's content for --stream-cbz, checked against the decoding limits.
+fn fetch_page(
+    url: &str,
+    headers: &[(String, String)],
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let response = http::get(url, headers, Kind::Image)?;
+    response.check_status(url)?;
+    decode::check(url, &response.body)?;
+    Ok(response.body)
 }
 
-fn fetch_manga_ids(manga_name: &str) -> Result<Vec<String>, reqwest::Error> {
-    let client = Client::new();
-    let response = client
-        .get(format!("{}{}", SEARCH_URL, manga_name))
-        .header(USER_AGENT, "Mozilla/5.0")
-        .send()?
-        .text()?;
+// Downloads whatever pages of `chapter` are still missing, updating its report.
+// Page paths are taken from `next_page` once the chapter's page list is known
+// and kept across retries. Returns the pages and bytes downloaded.
+#[allow(clippy::too_many_arguments)]
+fn download_chapter(
+    source: &dyn Source,
+    chapter: &Chapter,
+    download: &mut Option<ChapterPages>,
+    next_page: &mut usize,
+    stage: &PageStage,
+    // Expected size of a page, when free space should be checked.
+    page_bytes: Option<u64>,
+    later: &Later,
+    chapter_report: &mut ChapterReport,
+) -> (usize, u64, Option<Box<dyn std::error::Error>>) {
+    chapter_report.attempts += 1;
+    chapter_report.error = None;
+    chapter_report.failed_pages.clear();
+
+    if let Err(e) = fetch_page_list(
+        source,
+        chapter,
+        download,
+        next_page,
+        stage.work,
+        stage.dedupe,
+        chapter_report,
+    ) {
+        chapter_report.error = Some(e.to_string());
+        return (0, 0, Some(e));
+    }
+    let download = download.as_mut().unwrap();
+    if let Some(mismatch) = chapter_report
+        .page_count_mismatch()
+        .filter(|_| stage.strict)
+    {
+        chapter_report.error = Some(mismatch.clone());
+        return (0, 0, Some(mismatch.into()));
+    }
 
-    let document = Document::from(response.as_str());
-    let titles: Vec<String> = document
-        .find(Name("h3"))
-        .filter_map(|node: Node| node.find(Name("a")).next())
-        .filter_map(|node: Node| node.attr("href").map(|href| href.to_string()))
+    let headers = source.image_headers(chapter);
+    let missing: Vec<usize> = (0..download.images.len())
+        .filter(|&i| !download.done[i])
+        .collect();
+    if let Some(page_bytes) = page_bytes {
+        let needed = missing.len() as u64 * page_bytes * SPACE_MARGIN;
+        if let Err(e) = space::check(IMAGE_DIR, needed) {
+            chapter_report.error = Some(e.to_string());
+            return (0, 0, Some(e.into()));
+        }
+    }
+    let tasks = missing
+        .iter()
+        .map(|&i| (host_of(&download.images[i]), i))
         .collect();
+    let progress = Progress::new(
+        &chapter.name,
+        download.images.len() - missing.len(),
+        download.images.len(),
+        later.estimate(download.images.len()),
+    );
+    let results = Scheduler::new(stage.jobs, MAX_REQUESTS_PER_HOST).run(tasks, |i| {
+        let downloaded = download_image(&download.images[i], &download.paths[i], &headers)?;
+        if let Some(profile) = stage.profile {
+            let mut timing = PageTiming {
+                chapter: chapter.name.clone(),
+                page: i + 1,
+                sequence: download.first_page + i,
+                bytes: downloaded.bytes,
+                first_byte_ms: downloaded.network.as_ref().map(|t| millis(t.first_byte)),
+                transfer_ms: downloaded.network.as_ref().map(|t| millis(t.transfer)),
+                write_ms: millis(downloaded.write),
+                process_ms: None,
+                total_ms: 0.0,
+            };
+            timing.update_total();
+            profile.lock().unwrap().push(timing);
+        }
+        let transcoded = match stage.compat_format {
+            Some(target) => compat::transcode(&download.paths[i], target)?,
+            None => false,
+        };
+        if let Some(processor) = stage.processor {
+            processor.submit(download.first_page + i, &download.paths[i]);
+        }
+        progress.advance(downloaded.bytes);
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>((downloaded.bytes, transcoded))
+    });
 
-    Ok(titles)
+    progress.finish();
+
+    let (mut count, mut bytes, mut cause) = (0, 0, None);
+    for (i, result) in missing.into_iter().zip(results) {
+        match result {
+            Ok((size, transcoded)) => {
+                download.done[i] = true;
+                count += 1;
+                bytes += size;
+                chapter_report.transcoded_pages += transcoded as usize;
+            }
+            Err(e) => {
+                chapter_report.failed_pages.push(FailedPage {
+                    page: i + 1,
+                    url: download.images[i].clone(),
+                    error: e.to_string(),
+                });
+                cause = Some(e as Box<dyn std::error::Error>);
+            }
+        }
+    }
+    if chapter_report.failed_pages.is_empty() {
+        chapter_report.status = if chapter_report.attempts > 1 {
+            ChapterStatus::Recovered
+        } else {
+            ChapterStatus::Downloaded
+        };
+    }
+    (count, bytes, cause)
 }
 
-fn download_chapter(
-    chapter_link: &str,
-    format: Option<Format>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let images = fetch_image_links(chapter_link)?;
+// What the run downloads after a chapter, for the run's ETA.
+#[derive(Default)]
+struct Later {
+    // Pages not downloaded yet of chapters whose page lists are known.
+    pages: usize,
+    // Chapters whose page lists aren't.
+    unknown: usize,
+    // Page count of the known lists on average, if there are any.
+    average: Option<usize>,
+}
 
-    create_image_directory()?;
-    for (i, image_url) in images.iter().enumerate() {
-        let image_path = format!("{}/{}.jpg", IMAGE_DIR, i + 1);
-        download_image(image_url, &image_path)?;
+impl Later {
+    fn new(downloads: &[Option<ChapterPages>], later: &[usize]) -> Later {
+        let known: Vec<&ChapterPages> = downloads.iter().flatten().collect();
+        let mut after = Later {
+            average: (!known.is_empty())
+                .then(|| known.iter().map(|pages| pages.images.len()).sum::<usize>() / known.len()),
+            ..Later::default()
+        };
+        for &i in later {
+            match &downloads[i] {
+                Some(pages) => after.pages += pages.done.iter().filter(|done| !**done).count(),
+                None => after.unknown += 1,
+            }
+        }
+        after
     }
 
-    match format {
-        Some(Format::Pdf) => create_pdf()?,
-        Some(Format::Cbz) => create_cbz()?,
-        None => println!("No format specified, skipping conversion."),
+    // The pages, counting the unknown chapters as long as `guess` without
+    // an average to go by.
+    fn estimate(&self, guess: usize) -> usize {
+        self.pages + self.unknown * self.average.unwrap_or(guess)
+    }
+}
+
+fn is_out_of_space(error: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(error.downcast_ref::<Error>(), Some(Error::NoSpace { .. }))
+}
+
+// The chapter's image URLs, without repeats unless --no-dedupe.
+fn page_urls(
+    source: &dyn Source,
+    chapter: &Chapter,
+    dedupe: bool,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut images = source.pages(chapter)?;
+    if dedupe {
+        let dropped = dedupe::unique_urls(&mut images);
+        if dropped > 0 {
+            log::debug!("{}: dropped {} repeated image URLs", chapter.name, dropped);
+        }
     }
+    Ok(images)
+}
 
+// Fetches the chapter's image URLs, once, and places its pages at the end of
+// the page sequence.
+fn fetch_page_list(
+    source: &dyn Source,
+    chapter: &Chapter,
+    download: &mut Option<ChapterPages>,
+    next_page: &mut usize,
+    work: &WorkDir,
+    dedupe: bool,
+    chapter_report: &mut ChapterReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if download.is_some() {
+        return Ok(());
+    }
+    let images = page_urls(source, chapter, dedupe)?;
+    chapter_report.pages = images.len();
+    chapter_report.declared_pages = source.declared_pages(chapter);
+    let paths = (*next_page..*next_page + images.len())
+        .map(|i| work.page(i))
+        .collect();
+    *next_page += images.len();
+    *download = Some(ChapterPages {
+        done: vec![false; images.len()],
+        first_page: *next_page - images.len(),
+        images,
+        paths,
+    });
     Ok(())
 }
 
-fn fetch_image_links(chapter_link: &str) -> Result<Vec<String>, reqwest::Error> {
-    let client = Client::new();
-    let response = client
-        .get(chapter_link)
-        .header(USER_AGENT, "Mozilla/5.0")
-        .send()?
-        .text()?;
+// The source's promotional page digests and the config file's.
+fn known_promo_hashes(source: &dyn Source, options: &DownloadOptions) -> Vec<String> {
+    source
+        .promo_hashes()
+        .iter()
+        .map(|hash| hash.to_string())
+        .chain(options.promo.known_hashes.iter().cloned())
+        .collect()
+}
 
-    let document = Document::from(response.as_str());
-    let images: Vec<String> = document
-        .find(Name("img"))
-        .filter_map(|node: Node| node.attr("src").map(|src| src.to_string()))
+// What marks trailing app ads, unless --keep-ads. Known promotional pages
+// count as ads too.
+fn ad_blocklist(source: &dyn Source, options: &DownloadOptions) -> Option<AdBlocklist> {
+    if options.promo.keep_ads {
+        return None;
+    }
+    let url_patterns = source
+        .ad_url_patterns()
+        .iter()
+        .map(|pattern| Regex::new(pattern).expect("invalid ad URL pattern"))
+        .chain(options.promo.ad_url_patterns.iter().cloned())
         .collect();
+    Some(AdBlocklist {
+        hashes: known_promo_hashes(source, options),
+        url_patterns,
+    })
+}
 
-    Ok(images)
+// Builds the requested output from pages in the work directory and moves it
+// into IMAGE_DIR.
+fn package(
+    pages: &[String],
+    mut output: Output,
+    release_date: OffsetDateTime,
+    options: &DownloadOptions,
+    work: &WorkDir,
+    report: &mut Report,
+) -> Result<(), Box<dyn std::error::Error>> {
+    output.info.page_count = pages.len();
+
+    if let Some(upscale) = &options.upscale {
+        upscale_pages(pages, &work.path, upscale).map_err(|e| e as Box<dyn std::error::Error>)?;
+    }
+    // Last, so the numbers are drawn at the pages' final size.
+    let stamped;
+    let pages = match &options.stamp {
+        Some(stamp) => {
+            let labels = stamp::labels(pages.len(), &output.chapter_starts, stamp);
+            stamped = stamp_pages(pages, &labels, &work.path, stamp)
+                .map_err(|e| e as Box<dyn std::error::Error>)?;
+            &stamped
+        }
+        None => pages,
+    };
+
+    if options.formats.is_empty() {
+        println!("No format specified, skipping conversion.");
+        return Ok(());
+    }
+    if options.space_check {
+        let page_bytes: u64 = pages
+            .iter()
+            .filter_map(|page| fs::metadata(page).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        space::check(
+            IMAGE_DIR,
+            page_bytes * SPACE_MARGIN * options.formats.len() as u64,
+        )?;
+    }
+
+    // Every format is built from the same pages; one failing doesn't undo the
+    // others.
+    let mut failures: Vec<Box<dyn std::error::Error>> = Vec::new();
+    for format in &options.formats {
+        let label = format_label(format);
+        let published = create_output(format, pages, &output, release_date, options, work)
+            .and_then(|path| publish_output(format, &path, options, work));
+        match published {
+            Ok(published) => {
+                println!("{} created successfully in {}", label, published);
+                run_post_command(format, &published, &output, options, report)?;
+                report.outputs.push(published);
+            }
+            Err(e) => failures.push(context(format!("Failed to create {}: {}", label, e), e)),
+        }
+    }
+
+    match failures.len() {
+        0 => Ok(()),
+        1 => Err(failures.remove(0)),
+        _ => {
+            let message = failures
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("; ");
+            Err(context(message, failures.remove(0)))
+        }
+    }
+}
+
+// Runs --post-cmd for an output just published. A failing command stops the
+// run with --post-cmd-failures abort and is a warning otherwise.
+fn run_post_command(
+    format: &Format,
+    published: &str,
+    output: &Output,
+    options: &DownloadOptions,
+    report: &mut Report,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(command) = &options.post_command else {
+        return Ok(());
+    };
+    // Multi-chapter outputs give their first and last chapter.
+    let chapter = match (&output.info.number, output.chapter_starts.as_slice()) {
+        (Some(number), _) => number.to_string(),
+        (None, [(_, first), .., (_, last)]) => {
+            format!("{}-{}", number_text(first), number_text(last))
+        }
+        (None, _) => String::new(),
+    };
+    let format = format.to_possible_value().unwrap().get_name();
+    let file = exec::path_arg(published);
+    let result = command.run(&[
+        ("file", &file),
+        ("series", &output.info.series),
+        ("chapter", &chapter),
+        ("format", format),
+    ]);
+    match (result, options.post_command_failures) {
+        (Ok(()), _) => Ok(()),
+        (Err(e), PostCommandFailures::Abort) => Err(e.into()),
+        (Err(e), PostCommandFailures::Warn) => {
+            ui::warn(&e.to_string());
+            report
+                .warnings
+                .push(format!("Post command for {}: {}", published, e));
+            Ok(())
+        }
+    }
+}
+
+fn format_label(format: &Format) -> &'static str {
+    match format {
+        Format::Pdf => "PDF",
+        Format::Cbz => "CBZ",
+        Format::Html => "HTML reader",
+    }
+}
+
+// Builds one format in the work directory and returns the path written.
+fn create_output(
+    format: &Format,
+    pages: &[String],
+    output: &Output,
+    release_date: OffsetDateTime,
+    options: &DownloadOptions,
+    work: &WorkDir,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match format {
+        Format::Pdf => create_pdf(pages, &work.path, output, release_date, &options.pdf),
+        Format::Cbz => create_cbz(
+            pages,
+            &work.path,
+            output,
+            release_date,
+            options.entry_template.as_ref(),
+            options.reproducible,
+        ),
+        Format::Html => {
+            let html_path = create_html(
+                pages,
+                &work.path,
+                &output.name,
+                &output.info.series,
+                &options.html,
+            )?;
+            set_release_mtime(&html_path, release_date)?;
+            Ok(html_path)
+        }
+    }
+}
+
+// Moves a finished output into IMAGE_DIR and returns where it ended up.
+fn publish_output(
+    format: &Format,
+    path: &str,
+    options: &DownloadOptions,
+    work: &WorkDir,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // A folder-style HTML reader is moved as a whole.
+    let path = Path::new(path);
+    let published = match (format, path.parent()) {
+        (Format::Html, Some(folder)) if !options.html.single_file => {
+            let page = path.file_name().unwrap_or_default();
+            let verify = |folder: &Path| verify_output(format, &folder.join(page));
+            work.publish(folder, &options.output_dir, verify, options.backup)?
+                .join(page)
+        }
+        _ => work.publish(
+            path,
+            &options.output_dir,
+            |published: &Path| verify_output(format, published),
+            options.backup,
+        )?,
+    };
+    Ok(published.to_string_lossy().into_owned())
 }
 
-fn download_image(url: &str, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let response = reqwest::blocking::get(url)?.bytes()?;
-    fs::write(path, &response)?;
+// Reads a published output back far enough to trust it over the older one it
+// replaced: a CBZ's entry list, a PDF's header.
+fn verify_output(format: &Format, path: &Path) -> io::Result<()> {
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} isn't a readable {}", path.display(), what),
+        )
+    };
+    match format {
+        Format::Cbz => {
+            let archive = ZipArchive::new(fs::File::open(path)?).map_err(|_| invalid("CBZ"))?;
+            if archive.is_empty() {
+                return Err(invalid("CBZ"));
+            }
+        }
+        Format::Pdf => {
+            let mut header = [0; 5];
+            fs::File::open(path)?.read_exact(&mut header)?;
+            if &header != b"%PDF-" {
+                return Err(invalid("PDF"));
+            }
+        }
+        Format::Html => {
+            fs::metadata(path)?;
+        }
+    }
     Ok(())
 }
 
-fn create_image_directory() -> std::io::Result<()> {
-    fs::create_dir_all(IMAGE_DIR)?;
+// Packages images downloaded by another tool, e.g. from an --export-urls list.
+fn package_directory(
+    dir: &str,
+    options: &DownloadOptions,
+    report: &mut Report,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut images: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    ["jpg", "jpeg", "png", "webp", "gif"]
+                        .contains(&extension.to_lowercase().as_str())
+                })
+        })
+        .collect();
+    if images.is_empty() {
+        return Err(format!("No images found in {}", dir).into());
+    }
+    // Numeric names sort by value so "10.jpg" follows "9.jpg".
+    images.sort_by_key(|path| {
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("");
+        (stem.parse::<u64>().unwrap_or(u64::MAX), path.clone())
+    });
+
+    // Work on copies so processing never touches the user's files. Hard
+    // links do where nothing rewrites a page in place: processing and
+    // upscaling write each page anew, --compat-format doesn't.
+    let work = WorkDir::create(IMAGE_DIR).map_err(|e| Error::filesystem(IMAGE_DIR, e))?;
+    let linkable = options.compat_format.is_none();
+    let mut pages = Vec::new();
+    for (i, image) in images.iter().enumerate() {
+        let page = work.page(i + 1);
+        reflink::copy(image, Path::new(&page), linkable)?;
+        pages.push(page);
+    }
+
+    let title = Path::new(dir)
+        .canonicalize()?
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "output".to_string());
+    let sanitized = filename::sanitize(&title);
+    let parts = NameParts {
+        title: &sanitized,
+        number: "",
+        separator: "",
+        detail: None,
+    };
+    let output = Output {
+        name: output_name(&parts, &options.output_dir, report),
+        info: ComicInfo {
+            series: title,
+            // Chapters from the series cache remember their title.
+            title: Manifest::load(dir).and_then(|manifest| manifest.chapter_title),
+            number: None,
+            volume: None,
+            page_count: 0,
+            bookmarks: Vec::new(),
+        },
+        chapter_starts: Vec::new(),
+    };
+    report.manga = output.info.series.clone();
+    if let Some(target) = options.compat_format {
+        for page in &pages {
+            if compat::transcode(page, target).map_err(|e| e as Box<dyn std::error::Error>)? {
+                report.transcoded_pages += 1;
+            }
+        }
+    }
+    process_pages(&pages, &options.process, options.process_jobs)
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+    package(
+        &pages,
+        output,
+        OffsetDateTime::now_utc(),
+        options,
+        &work,
+        report,
+    )
+}
+
+// Writes the chapters' image URLs as an aria2c input file (`aria2c -i FILE`),
+// numbering pages the way --from-dir expects.
+fn export_urls(
+    source: &dyn Source,
+    chapters: &[Chapter],
+    file: &str,
+    dedupe: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entries = Vec::new();
+    for chapter in chapters {
+        let headers = source.image_headers(chapter);
+        for url in page_urls(source, chapter, dedupe)? {
+            entries.push((url, headers.clone()));
+        }
+    }
+
+    let width = entries.len().to_string().len().max(3);
+    let mut list = String::new();
+    for (i, (url, headers)) in entries.iter().enumerate() {
+        let extension = Path::new(url.split('?').next().unwrap_or(url))
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("jpg");
+        list.push_str(&format!("{}\n", url));
+        list.push_str(&format!(
+            "  out={:0width$}.{}\n",
+            i + 1,
+            extension,
+            width = width
+        ));
+        for (name, value) in headers {
+            list.push_str(&format!("  header={}: {}\n", name, value));
+        }
+    }
+
+    fs::write(file, list)?;
+    println!("Exported {} image URLs to {}", entries.len(), file);
     Ok(())
 }
 
-fn create_pdf() -> Result<(), Box<dyn std::error::Error>> {
-    println!("Converting images to PDF...");
+// A saved page's size and how long fetching and writing it took.
+struct Downloaded {
+    bytes: u64,
+    network: Option<http::Timing>,
+    write: Duration,
+}
 
-    let mut images: Vec<String> = Vec::new();
-    for i in 1..=1000 {
-        let img_path = format!("{}/{}.jpg", IMAGE_DIR, i);
-        if PathBuf::from(&img_path).exists() {
-            images.push(img_path);
-        } else {
-            break; // Stop when no more numbered images are found
+fn download_image(
+    url: &str,
+    path: &str,
+    headers: &[(String, String)],
+) -> Result<Downloaded, Box<dyn std::error::Error + Send + Sync>> {
+    let response = http::get(url, headers, Kind::Image)?;
+    response.check_status(url)?;
+    let started = Instant::now();
+    fs::write(path, &response.body).map_err(|source| Error::Filesystem {
+        path: path.to_string(),
+        source,
+        needed: Some(response.body.len() as u64),
+    })?;
+    if let Err(e) = decode::check(url, &response.body) {
+        // Said right away, as the chapter's failure only counts its pages.
+        let message = reject_page(url, path, e);
+        ui::warn(&message);
+        return Err(message.into());
+    }
+    Ok(Downloaded {
+        bytes: response.body.len() as u64,
+        network: response.timing,
+        write: started.elapsed(),
+    })
+}
+
+// Moves a page refused by the decoding limits out of the work directory,
+// which is removed after the run, and says where it went.
+fn reject_page(url: &str, path: &str, error: Error) -> String {
+    let dir = Path::new(IMAGE_DIR).join(REJECTED_DIR);
+    let name = url
+        .rsplit('/')
+        .next()
+        .map(|name| name.split('?').next().unwrap_or(name))
+        .filter(|name| !name.is_empty())
+        .unwrap_or("page");
+    let kept = dir.join(format!(
+        "{}-{}",
+        &format!("{:x}", Sha256::digest(url))[..8],
+        name
+    ));
+    match fs::create_dir_all(&dir).and_then(|_| fs::rename(path, &kept)) {
+        Ok(()) => format!("{} The file was kept as {}.", error, kept.display()),
+        Err(e) => {
+            log::debug!("Failed to keep rejected page {}: {}", path, e);
+            error.to_string()
         }
     }
+}
+
+fn create_pdf(
+    pages: &[String],
+    work_dir: &str,
+    output: &Output,
+    release_date: OffsetDateTime,
+    options: &PdfOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    println!("Converting images to PDF...");
+
+    let split;
+    let pages = match options.max_side {
+        Some(max_side) => {
+            split = split_oversize(pages, max_side).map_err(|e| e as Box<dyn std::error::Error>)?;
+            &split
+        }
+        None => pages,
+    };
+
+    // magick runs inside the work directory, so pass the pages by file name.
+    let images: Vec<&str> = pages
+        .iter()
+        .filter(|page| Path::new(page).exists())
+        .filter_map(|page| Path::new(page).file_name()?.to_str())
+        .collect();
 
     if images.is_empty() {
         return Err("No images found to convert to PDF.".into());
     }
 
-    let status = std::process::Command::new("magick")
-        .args(["convert", "-quality", "100"])
-        .args(&images)
-        .arg("output.pdf")
-        .current_dir(IMAGE_DIR)
-        .status()?;
-
-    if !status.success() {
-        return Err("Failed to create PDF".into());
+    let pdf_name = format!("{}.pdf", output.name);
+    // ImageMagick reads "%d" and the like in output names, so it writes to a
+    // fixed name and the title only goes into the rename.
+    let tmp_name = format!("output.pdf{}", UNFINISHED_SUFFIX);
+    let mut metadata = Vec::new();
+    if let Some(title) = &output.info.title {
+        metadata.extend(["-define".to_string(), format!("pdf:title={}", title)]);
+    }
+    let imagemagick = tools::imagemagick().ok_or_else(|| Error::MissingTool {
+        tool: "ImageMagick (magick or convert)".to_string(),
+        purpose: "PDF output".to_string(),
+        hint: "install it from https://imagemagick.org".to_string(),
+    })?;
+    imagemagick
+        .command()
+        .args(["-quality", "100"])
+        .args(images.iter().map(|image| exec::path_arg(image)))
+        .args(page_args(options)?)
+        .args(&metadata)
+        .arg(format!("pdf:{}", tmp_name))
+        .current_dir(work_dir)
+        .hint("is ImageMagick installed?")
+        .run()?;
+    let tmp_path = format!("{}/{}", work_dir, tmp_name);
+    if !is_complete_pdf(&tmp_path)? {
+        return Err(Error::Tool {
+            tool: imagemagick.program().to_string(),
+            message: "wrote an incomplete PDF".to_string(),
+        }
+        .into());
     }
+    let pdf_path = format!("{}/{}", work_dir, pdf_name);
+    fs::rename(&tmp_path, &pdf_path)?;
+    set_release_mtime(&pdf_path, release_date)?;
+    Ok(pdf_path)
+}
 
-    println!("PDF created successfully in {}/output.pdf", IMAGE_DIR);
-    Ok(())
+// A PDF starts with its header and ends with an end-of-file marker, which a
+// writer that was cut off never reaches.
+fn is_complete_pdf(path: &str) -> std::io::Result<bool> {
+    let data = fs::read(path)?;
+    let tail = &data[data.len().saturating_sub(1024)..];
+    Ok(data.starts_with(b"%PDF-") && tail.windows(5).any(|window| window == b"%%EOF"))
+}
+
+// Removes outputs a crashed run left half-written in `dir`.
+fn remove_unfinished_outputs(dir: &str) {
+    match workdir::remove_unfinished_outputs(dir) {
+        Ok(removed) => {
+            for path in removed {
+                println!("Removed unfinished output {}", path.display());
+            }
+        }
+        Err(e) => log::warn!("Failed to remove unfinished outputs in {}: {}", dir, e),
+    }
 }
 
-fn create_cbz() -> Result<(), Box<dyn std::error::Error>> {
-    let cbz_path = format!("{}/output.cbz", IMAGE_DIR);
-    let file = fs::File::create(&cbz_path)?;
+// With `reproducible` set, the same pages and metadata always give the same
+// bytes: entries are sorted by name and carry a fixed timestamp and mode.
+fn create_cbz(
+    pages: &[String],
+    work_dir: &str,
+    output: &Output,
+    release_date: OffsetDateTime,
+    entry_template: Option<&Template>,
+    reproducible: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let names = entry_names(pages, output, entry_template)?;
+    let cbz_path = format!("{}/{}.cbz", work_dir, output.name);
+    let tmp_path = format!("{}{}", cbz_path, UNFINISHED_SUFFIX);
+    let file = fs::File::create(&tmp_path)?;
     let mut zip = ZipWriter::new(file);
+    let mut options = FileOptions::default();
+    if reproducible {
+        options = options
+            .last_modified_time(zip::DateTime::default())
+            .unix_permissions(0o644);
+    } else if let Ok(modified) = zip::DateTime::try_from(release_date) {
+        // Zip timestamps can't predate 1980; keep the default in that case.
+        options = options.last_modified_time(modified);
+    }
 
-    for i in 1..=1000 {
-        let img_path = format!("{}/{}.jpg", IMAGE_DIR, i);
-        let path_buf = PathBuf::from(&img_path);
-        if path_buf.exists() {
-            zip.start_file(format!("{}.jpg", i), FileOptions::default())?;
-            let img_data = fs::read(&img_path)?;
-            zip.write_all(&img_data)?;
-        } else {
-            break;
+    let mut entries: Vec<(String, Option<&String>)> = vec![("ComicInfo.xml".to_string(), None)];
+    entries.extend(names.into_iter().zip(pages.iter().map(Some)));
+    if reproducible {
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    let mut written = HashSet::new();
+    for (name, page) in &entries {
+        if !is_safe_entry_name(name) {
+            return Err(format!("Refusing to write unsafe archive entry {:?}", name).into());
+        }
+        if !written.insert(name) {
+            return Err(format!("Duplicate archive entry {}", name).into());
+        }
+        zip.start_file(name, options)?;
+        match page {
+            Some(page) => zip.write_all(&fs::read(page)?)?,
+            None => zip.write_all(output.info.to_xml().as_bytes())?,
         }
     }
 
     zip.finish()?;
-    println!("CBZ created successfully.");
-    Ok(())
+    drop(zip);
+    // Only an archive whose central directory reads back complete gets the
+    // final name.
+    let archive = zip::ZipArchive::new(fs::File::open(&tmp_path)?)
+        .map_err(|e| format!("The written archive doesn't read back: {}", e))?;
+    if archive.len() != entries.len() {
+        return Err(format!(
+            "The written archive lists {} of {} entries",
+            archive.len(),
+            entries.len()
+        )
+        .into());
+    }
+    fs::rename(&tmp_path, &cbz_path)?;
+    set_release_mtime(&cbz_path, release_date)?;
+    Ok(cbz_path)
+}
+
+// Names of the pages inside an archive. Without a template they are zero-padded
+// page numbers, which keep readers that sort lexically in page order.
+fn entry_names(
+    pages: &[String],
+    output: &Output,
+    template: Option<&Template>,
+) -> Result<Vec<String>, String> {
+    let Some(template) = template else {
+        let width = pages.len().to_string().len().max(3);
+        return Ok(pages
+            .iter()
+            .enumerate()
+            .map(|(i, page)| {
+                format!(
+                    "{:0width$}.{}",
+                    i + 1,
+                    compat::extension(page),
+                    width = width
+                )
+            })
+            .collect());
+    };
+    let series = filename::sanitize(&output.info.series);
+    let names: Vec<String> = pages
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let (start, chapter) = output
+                .chapter_starts
+                .iter()
+                .rev()
+                .find(|(start, _)| *start <= i)
+                .map(|(start, chapter)| (*start, chapter.as_ref()))
+                .unwrap_or((0, output.info.number.as_ref()));
+            template_name(
+                template,
+                &series,
+                chapter,
+                i - start + 1,
+                compat::extension(path),
+            )
+        })
+        .collect();
+    check_unique_names(&names)?;
+    Ok(names)
+}
+
+// Page file names in the series cache: 1.jpg, 2.jpg, ... unless a template
+// is given.
+fn cache_names(
+    pages: &[String],
+    series: &str,
+    chapter: Option<&ChapterId>,
+    template: Option<&Template>,
+) -> Result<Vec<String>, String> {
+    let names: Vec<String> = pages
+        .iter()
+        .enumerate()
+        .map(|(i, path)| match template {
+            Some(template) => {
+                template_name(template, series, chapter, i + 1, compat::extension(path))
+            }
+            None => format!("{}.{}", i + 1, compat::extension(path)),
+        })
+        .collect();
+    if template.is_none() {
+        return Ok(names);
+    }
+    check_unique_names(&names)?;
+    Ok(names)
+}
+
+// `extension` is the page content's, compat::extension(); it replaces one
+// written into the template.
+fn template_name(
+    template: &Template,
+    series: &str,
+    chapter: Option<&ChapterId>,
+    page: usize,
+    extension: &str,
+) -> String {
+    let page = page.to_string();
+    let chapter = chapter.map(ToString::to_string).unwrap_or_default();
+    let name = template.render(&[("series", series), ("chapter", &chapter), ("page", &page)]);
+    let stem = [".jpg", ".jpeg", ".png", ".webp", ".gif", ".avif"]
+        .iter()
+        .find_map(|extension| {
+            name.to_lowercase()
+                .ends_with(extension)
+                .then(|| &name[..name.len() - extension.len()])
+        })
+        .unwrap_or(&name);
+    format!("{}.{}", stem, extension)
+}
+
+fn check_unique_names(names: &[String]) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    match names.iter().find(|name| !seen.insert(name.as_str())) {
+        Some(name) => Err(format!(
+            "--entry-template gives more than one page the name {}",
+            name
+        )),
+        None => Ok(()),
+    }
+}
+
+// Archive entries must stay inside the archive when extracted.
+fn is_safe_entry_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains('\\')
+        && Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+fn set_release_mtime(path: &str, release_date: OffsetDateTime) -> std::io::Result<()> {
+    let mtime = FileTime::from_unix_time(release_date.unix_timestamp(), 0);
+    filetime::set_file_mtime(path, mtime)
+}
+
+// --clear only goes on to download with --then-download, so a name given with
+// it isn't silently ignored.
+fn check_clear_args(cli: &CLI, matches: &ArgMatches) {
+    let downloads =
+        cli.manga_name.is_some() || cli.from_dir.is_some() || cli.again || cli.last_selection;
+    if downloads && !cli.then_download {
+        CLI::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--clear doesn't download anything; add --then-download to clear the cache \
+                 and then download",
+            )
+            .exit();
+    }
+    if cli.then_download && !downloads {
+        CLI::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--then-download needs a manga name, --from-dir, --again or --last-selection",
+            )
+            .exit();
+    }
+    if !cli.then_download {
+        for flag in ["format", "viewer"] {
+            if matches.value_source(flag) == Some(ValueSource::CommandLine) {
+                ui::warn(&format!("--{} has no effect with --clear.", flag));
+            }
+        }
+    }
 }
 
 fn clear_cache() {
@@ -189,17 +6028,257 @@ fn clear_cache() {
     }
 }
 
-fn prompt(message: &str) -> usize {
-    print!("{}", message);
-    io::stdout().flush().unwrap();
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).unwrap();
-    input.trim().parse().unwrap_or_else(|_| {
-        println!("Invalid input, please enter a number.");
-        prompt(message)
-    })
+fn clear_stale() {
+    match workdir::remove_stale(IMAGE_DIR) {
+        Ok(1) => println!("Removed 1 stale work directory."),
+        Ok(removed) => println!("Removed {} stale work directories.", removed),
+        Err(e) => ui::error(&format!("Failed to remove stale work directories: {}", e)),
+    }
 }
 
-fn format_manga_name(manga_name: &str) -> String {
-    manga_name.replace(" ", "_").replace("-", "_")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn output(name: &str, pages: usize) -> Output {
+        Output {
+            name: name.to_string(),
+            info: ComicInfo {
+                series: "Series".to_string(),
+                title: None,
+                number: None,
+                volume: None,
+                page_count: pages,
+                bookmarks: Vec::new(),
+            },
+            chapter_starts: vec![(0, None)],
+        }
+    }
+
+    // Page files 1.jpg, 2.jpg, ... of different content in `dir`.
+    fn pages(dir: &TestDir, count: usize) -> Vec<String> {
+        (1..=count)
+            .map(|page| {
+                let path = dir.join(format!("{}.jpg", page));
+                fs::write(&path, vec![page as u8; 512]).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cbz_cut_off_while_writing_gets_no_final_name() {
+        let dir = TestDir::new("cbz-cut-off");
+        let mut pages = pages(&dir, 2);
+        // The writer stops at the missing page.
+        pages.push(dir.join("missing.jpg").to_string_lossy().into_owned());
+        let release = OffsetDateTime::now_utc();
+        let created = create_cbz(
+            &pages,
+            dir.str(),
+            &output("Series c1", 3),
+            release,
+            None,
+            false,
+        );
+        assert!(created.is_err());
+        assert!(!dir.join("Series c1.cbz").exists());
+    }
+
+    fn parse_args(args: &[&str]) -> Result<CLI, clap::Error> {
+        CLI::try_parse_from(std::iter::once("manga-cli").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn chapter_ranges_on_the_command_line() {
+        let cli = parse_args(&["--chapters", "-10", "one piece"]).unwrap();
+        assert!(matches!(cli.chapters, Some(ChapterRange::Last(10))));
+        let cli = parse_args(&["--chapters", "100-", "one piece"]).unwrap();
+        assert!(matches!(cli.chapters, Some(ChapterRange::From(_))));
+
+        let conflict = parse_args(&["--chapter", "latest", "--chapters", "1-", "x"])
+            .err()
+            .unwrap();
+        assert_eq!(conflict.kind(), ErrorKind::ArgumentConflict);
+        for bad in ["-0", "20-10", "ten"] {
+            let error = parse_args(&["--chapters", bad, "x"]).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::ValueValidation, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn reproducible_cbz_builds_are_byte_identical() {
+        let dir = TestDir::new("cbz-reproducible");
+        let pages = pages(&dir, 3);
+        let release = OffsetDateTime::now_utc();
+        let build = |name: &str| {
+            let output = output(name, pages.len());
+            let path = create_cbz(&pages, dir.str(), &output, release, None, true).unwrap();
+            fs::read(path).unwrap()
+        };
+        let first = build("first");
+        // Pages touched in between, as a re-download would.
+        std::thread::sleep(Duration::from_millis(1100));
+        for page in &pages {
+            fs::write(page, fs::read(page).unwrap()).unwrap();
+        }
+        let second = build("second");
+        assert_eq!(first, second);
+
+        // Entries are in name order, with fixed times.
+        let mut archive = ZipArchive::new(fs::File::open(dir.join("second.cbz")).unwrap()).unwrap();
+        let names: Vec<String> = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect();
+        assert_eq!(names, ["001.jpg", "002.jpg", "003.jpg", "ComicInfo.xml"]);
+        let modified = archive.by_index(0).unwrap().last_modified();
+        assert_eq!(modified.year(), 1980);
+    }
+
+    #[test]
+    fn search_results_line_up_with_wide_titles() {
+        let result = |title: &str, alt_titles: &[&str]| SearchResult {
+            title: title.to_string(),
+            url: String::new(),
+            alt_titles: alt_titles.iter().map(|title| title.to_string()).collect(),
+        };
+        let mut results = vec![
+            result("One Piece", &["ワンピース"]),
+            result("ワンピース", &[]),
+            result(
+                "나 혼자만 레벨업",
+                &["Solo Leveling", "俺だけレベルアップな件"],
+            ),
+            result("Ｆｕｌｌｗｉｄｔｈ Ｔｉｔｌｅ", &[]),
+        ];
+        results.extend((5..=10).map(|n| result(&format!("Series {}", n), &[])));
+        let labels = ["manganelo", "漫画", "mangadex", "manganelo"];
+
+        for columns in [Some(24), Some(41), None] {
+            let lines = result_lines(&results, &labels, 0, columns);
+            assert_eq!(lines.len(), 10);
+            // Titles start in the same column, past a two-digit index.
+            let starts: Vec<usize> = lines[..4]
+                .iter()
+                .map(|(prefix, _)| ui::width(prefix))
+                .collect();
+            assert_eq!(starts, [16; 4]);
+            assert!(lines[9].0.starts_with("[10] "));
+            assert!(lines[0].0.starts_with("[ 1] "));
+            for (prefix, description) in &lines {
+                let line = format!("{}{}", prefix, description);
+                if let Some(columns) = columns {
+                    assert!(ui::width(&line) <= columns, "{:?}", line);
+                }
+            }
+        }
+        let lines = result_lines(&results, &labels, 0, Some(24));
+        assert_eq!(lines[1].1, "ワンピ…");
+        let lines = result_lines(&results, &labels, 0, None);
+        assert_eq!(
+            lines[2].1,
+            "나 혼자만 레벨업 (aka Solo Leveling, 俺だけレベルアップな件)"
+        );
+        // Listing more starts where the last listing stopped.
+        assert_eq!(result_lines(&results, &labels, 8, None).len(), 2);
+    }
+
+    #[test]
+    fn outputs_are_verified_before_replacing_older_ones() {
+        let dir = TestDir::new("verify-output");
+        let cbz = dir.join("good.cbz");
+        let mut zip = ZipWriter::new(fs::File::create(&cbz).unwrap());
+        zip.start_file("001.jpg", FileOptions::default()).unwrap();
+        zip.write_all(b"page").unwrap();
+        zip.finish().unwrap();
+        assert!(verify_output(&Format::Cbz, &cbz).is_ok());
+
+        let data = fs::read(&cbz).unwrap();
+        let cut = dir.join("cut.cbz");
+        fs::write(&cut, &data[..data.len() / 2]).unwrap();
+        let empty = dir.join("empty.cbz");
+        ZipWriter::new(fs::File::create(&empty).unwrap())
+            .finish()
+            .unwrap();
+        for path in [&cut, &empty] {
+            let error = verify_output(&Format::Cbz, path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let pdf = dir.join("good.pdf");
+        fs::write(&pdf, b"%PDF-1.4\n").unwrap();
+        assert!(verify_output(&Format::Pdf, &pdf).is_ok());
+        fs::write(&pdf, b"<html>").unwrap();
+        assert!(verify_output(&Format::Pdf, &pdf).is_err());
+        assert!(verify_output(&Format::Html, &dir.join("index.html")).is_err());
+    }
+
+    #[test]
+    fn no_short_form_stands_for_self_update() {
+        let (aliases, _) = aliases(&Config::default());
+        let names: Vec<&str> = aliases.iter().map(|alias| alias.name.as_str()).collect();
+        assert_eq!(names, ["d", "download"]);
+        let command = CLI::command();
+        let self_update = command.find_subcommand("self-update").unwrap();
+        assert_eq!(self_update.get_all_aliases().count(), 0);
+    }
+
+    #[test]
+    fn flag_values_named_like_a_command_are_not_the_command() {
+        let position = |words: &[&str]| {
+            let args: Vec<OsString> = std::iter::once("manga-cli")
+                .chain(words.iter().copied())
+                .map(OsString::from)
+                .collect();
+            command_position(&CLI::command(), &args)
+        };
+        assert_eq!(position(&["batch", "list.txt"]), Some(1));
+        assert_eq!(
+            position(&["--output-dir", "batch", "batch", "list.txt"]),
+            Some(3)
+        );
+        assert_eq!(
+            position(&["--output-dir=batch", "batch", "list.txt"]),
+            Some(2)
+        );
+        assert_eq!(
+            position(&["-f", "cbz", "--plain", "diff", "x", "2"]),
+            Some(4)
+        );
+        assert_eq!(position(&["-fcbz", "watch"]), Some(2));
+        // A value only given with '='.
+        assert_eq!(position(&["--stamp-pages", "fresh"]), Some(2));
+        assert_eq!(position(&["--output-dir", "gaps", "naruto"]), None);
+        assert_eq!(position(&["--", "batch"]), None);
+    }
+
+    #[test]
+    fn single_chapters_are_named_after_their_title() {
+        let dir = TestDir::new("single-chapter-name");
+        let name = |title: Option<&str>| {
+            let manga = Manga {
+                title: "Fixture Tales".to_string(),
+                chapters: Vec::new(),
+            };
+            let chapter = Chapter {
+                url: String::new(),
+                name: "Chapter 3".to_string(),
+                number: Some("3".parse().unwrap()),
+                volume: None,
+                title: title.map(str::to_string),
+                group: None,
+                language: None,
+                uploaded: None,
+            };
+            let mut report = Report::default();
+            range_output(&manga, &[chapter], dir.str(), &mut report).name
+        };
+        assert_eq!(name(None), "Fixture Tales c3");
+        assert_eq!(name(Some("Part 1/2")), "Fixture Tales c3 - Part 1_2");
+        let long = name(Some(&"Flames ".repeat(30)));
+        let title = long.strip_prefix("Fixture Tales c3 - ").unwrap();
+        assert_eq!(title.chars().count(), 80);
+        assert!(title.starts_with("Flames Flames") && title.ends_with('…'));
+    }
 }
@@ -0,0 +1,105 @@
+use crate::chapter_id::ChapterId;
+use crate::process::{Encoding, SizeSettings};
+use crate::workdir::UNFINISHED_SUFFIX;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+// Describes the chapters currently held in a cache directory.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub manga_url: String,
+    pub chapter_urls: Vec<String>,
+    pub pages: usize,
+    // RFC 3339 upload date of the chapter.
+    pub release_date: String,
+    // Set when the site gave no usable date and the download time was used.
+    pub release_date_estimated: bool,
+    // Kept so re-packaging names the chapter the same way.
+    #[serde(default)]
+    pub chapter_title: Option<String>,
+    // The full names, since the series and chapter folders may be shortened
+    // to keep paths within max_path_length.
+    #[serde(default)]
+    pub series_title: Option<String>,
+    #[serde(default)]
+    pub chapter_name: Option<String>,
+    // What the chapter list said of the chapter, for index.json.
+    #[serde(default)]
+    pub number: Option<ChapterId>,
+    #[serde(default)]
+    pub volume: Option<String>,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub language: Option<String>,
+    // Pages are compressed on purpose (--low-data), so they are smaller than
+    // the site's originals.
+    #[serde(default)]
+    pub low_data: bool,
+    // Whether each page was re-encoded for --low-data or kept as it was.
+    #[serde(default)]
+    pub encodings: Vec<Encoding>,
+    // How --target-size re-encoded the pages, when it had to.
+    #[serde(default)]
+    pub size_settings: Option<SizeSettings>,
+    // Page count the site's reader stated, and whether the images found when
+    // downloading matched it.
+    #[serde(default)]
+    pub declared_pages: Option<usize>,
+    #[serde(default)]
+    pub page_count_matches: Option<bool>,
+}
+
+impl Manifest {
+    pub fn load(dir: &str) -> Option<Manifest> {
+        let data = fs::read_to_string(Path::new(dir).join(MANIFEST_FILE)).ok()?;
+        serde_json::from_str(&data).ok()
+    }
+
+    // Written under a temporary name and renamed into place, so a crash
+    // leaves the old manifest or the new one, never half of one.
+    pub fn save(&self, dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let data = serde_json::to_string_pretty(self)?;
+        let path = Path::new(dir).join(MANIFEST_FILE);
+        let unfinished = Path::new(dir).join(format!("{}{}", MANIFEST_FILE, UNFINISHED_SUFFIX));
+        let mut file = fs::File::create(&unfinished)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&unfinished, &path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn manifest(pages: usize) -> Manifest {
+        let data = format!(
+            r#"{{"manga_url": "https://a.test/manga/x", "chapter_urls": ["https://a.test/manga/x/chapter-1"],
+                "pages": {}, "release_date": "2024-01-01T00:00:00Z", "release_date_estimated": false}}"#,
+            pages
+        );
+        serde_json::from_str(&data).unwrap()
+    }
+
+    #[test]
+    fn a_save_cut_short_leaves_the_previous_manifest() {
+        let dir = TestDir::new("manifest");
+        manifest(3).save(dir.str()).unwrap();
+        // Killed while writing the next one.
+        let unfinished = dir.join(format!("{}{}", MANIFEST_FILE, UNFINISHED_SUFFIX));
+        fs::write(&unfinished, "{\"manga_url\": \"https://a.te").unwrap();
+        assert_eq!(Manifest::load(dir.str()).unwrap().pages, 3);
+
+        // The next save goes over the leftover.
+        manifest(4).save(dir.str()).unwrap();
+        assert_eq!(Manifest::load(dir.str()).unwrap().pages, 4);
+        assert!(!unfinished.exists());
+    }
+}
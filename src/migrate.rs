@@ -0,0 +1,341 @@
+use crate::chapter_id::ChapterId;
+use crate::comicinfo;
+use crate::conflict;
+use crate::manifest::Manifest;
+use regex::Regex;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Under the cache, where files the migration couldn't place are parked.
+pub const UNSORTED_DIR: &str = "unsorted";
+// Written into the cache once the migration ran or was turned down, so it
+// isn't offered again; `migrate-cache` runs regardless.
+const MARKER_FILE: &str = ".layout-migrated";
+const MANIFEST_FILE: &str = "manifest.json";
+// The name single-chapter outputs had before they were named after the
+// chapter.
+const GENERIC_OUTPUT: &str = "output";
+
+// What versions from before per-run work directories left straight in the
+// cache: one chapter's pages "1.jpg" to "N.jpg", their manifest, and the
+// "output.cbz" or "output.pdf" built from them.
+pub struct Legacy {
+    // In page order.
+    pub pages: Vec<PathBuf>,
+    pub manifest: Option<(PathBuf, Manifest)>,
+    pub outputs: Vec<PathBuf>,
+}
+
+impl Legacy {
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = self.pages.clone();
+        files.extend(self.manifest.iter().map(|(path, _)| path.clone()));
+        files.extend(self.outputs.iter().cloned());
+        files
+    }
+
+    // The one chapter URL of the manifest, when the pages can be placed.
+    pub fn chapter_url(&self) -> Option<&str> {
+        match &self.manifest {
+            Some((_, manifest)) if manifest.chapter_urls.len() == 1 => {
+                Some(&manifest.chapter_urls[0])
+            }
+            _ => None,
+        }
+    }
+
+    // The series and chapter number the outputs' ComicInfo.xml records.
+    pub fn comic_info(&self) -> (Option<String>, Option<ChapterId>) {
+        self.outputs
+            .iter()
+            .map(|path| comic_info(path))
+            .find(|(series, number)| series.is_some() || number.is_some())
+            .unwrap_or((None, None))
+    }
+}
+
+// The files of the old layout in `cache_dir`, if there are any. Outputs
+// named "output" only count beside old pages or manifest, since versions
+// after per-run work directories still wrote them for single chapters.
+pub fn detect(cache_dir: &Path) -> Option<Legacy> {
+    let mut pages = Vec::new();
+    let mut manifest = None;
+    let mut outputs = Vec::new();
+    for entry in fs::read_dir(cache_dir).ok()?.flatten() {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let (stem, extension) = name.rsplit_once('.').unwrap_or((&name, ""));
+        if let (Ok(number), "jpg") = (stem.parse::<usize>(), extension) {
+            pages.push((number, path));
+        } else if name == MANIFEST_FILE {
+            manifest = Manifest::load(&cache_dir.to_string_lossy()).map(|loaded| (path, loaded));
+        } else if stem == GENERIC_OUTPUT && matches!(extension, "cbz" | "pdf") {
+            outputs.push(path);
+        }
+    }
+    if pages.is_empty() && manifest.is_none() {
+        return None;
+    }
+    pages.sort();
+    outputs.sort();
+    Some(Legacy {
+        pages: pages.into_iter().map(|(_, path)| path).collect(),
+        manifest,
+        outputs,
+    })
+}
+
+pub fn is_done(cache_dir: &Path) -> bool {
+    cache_dir.join(MARKER_FILE).exists()
+}
+
+pub fn mark_done(cache_dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(cache_dir)?;
+    fs::write(cache_dir.join(MARKER_FILE), "")
+}
+
+// Moves the old pages and `manifest` into the chapter folder `chapter_dir`,
+// which must not exist yet. The folder is gathered under a temporary name
+// and renamed into place, so it is never there half-filled.
+pub fn move_chapter(legacy: &Legacy, chapter_dir: &Path, manifest: &Manifest) -> io::Result<()> {
+    let mut staging = chapter_dir.as_os_str().to_os_string();
+    staging.push(crate::workdir::UNFINISHED_SUFFIX);
+    let staging = PathBuf::from(staging);
+    fs::create_dir_all(&staging)?;
+    for page in &legacy.pages {
+        fs::rename(page, staging.join(page.file_name().unwrap_or_default()))?;
+    }
+    manifest
+        .save(&staging.to_string_lossy())
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    fs::rename(&staging, chapter_dir)?;
+    if let Some((path, _)) = &legacy.manifest {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// Renames `path` to `name` plus its extension in the same folder, taking the
+// first free " (n)" when the name is taken. Returns the new path.
+pub fn rename_output(path: &Path, name: &str) -> io::Result<PathBuf> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let taken = |candidate: &str| dir.join(format!("{}{}", candidate, extension)).exists();
+    let free = if taken(name) {
+        conflict::free_name(name, taken)
+    } else {
+        name.to_string()
+    };
+    let target = dir.join(format!("{}{}", free, extension));
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+// Moves `files` into the cache's UNSORTED_DIR, renaming any whose name is
+// taken there. Returns the folder.
+pub fn park(cache_dir: &Path, files: &[PathBuf]) -> io::Result<PathBuf> {
+    let unsorted = cache_dir.join(UNSORTED_DIR);
+    fs::create_dir_all(&unsorted)?;
+    for file in files.iter().filter(|file| file.exists()) {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) => (stem.to_string(), format!(".{}", extension)),
+            None => (name.to_string(), String::new()),
+        };
+        let taken = |candidate: &str| {
+            unsorted
+                .join(format!("{}{}", candidate, extension))
+                .exists()
+        };
+        let free = if taken(&stem) {
+            conflict::free_name(&stem, taken)
+        } else {
+            stem
+        };
+        fs::rename(file, unsorted.join(format!("{}{}", free, extension)))?;
+    }
+    Ok(unsorted)
+}
+
+// A chapter number from the end of a chapter URL, like the 12.5 of
+// ".../chapter-12.5".
+pub fn guess_number(chapter_url: &str) -> Option<ChapterId> {
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    let number = NUMBER.get_or_init(|| {
+        Regex::new(r"(?i)(?:^|[^a-z])(?:chapter|ch|c)[-_]?(\d+(?:\.\d+)?)$").unwrap()
+    });
+    let last = chapter_url.trim_end_matches('/').rsplit('/').next()?;
+    number.captures(last)?[1].parse().ok()
+}
+
+// What an output's ComicInfo.xml says it holds; PDFs don't say.
+pub fn comic_info(path: &Path) -> (Option<String>, Option<ChapterId>) {
+    read_comic_info(path)
+        .map(|xml| comicinfo::series_and_number(&xml))
+        .unwrap_or((None, None))
+}
+
+fn read_comic_info(path: &Path) -> Option<String> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path).ok()?).ok()?;
+    let mut entry = archive.by_name("ComicInfo.xml").ok()?;
+    let mut xml = String::new();
+    entry.read_to_string(&mut xml).ok()?;
+    Some(xml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    const CHAPTER_URL: &str = "https://m.manganelo.com/manga/fixture-tales/chapter-3";
+
+    fn manifest() -> Manifest {
+        let data = format!(
+            r#"{{"manga_url": "https://m.manganelo.com/manga/fixture-tales", "chapter_urls": ["{}"], "pages": 2, "release_date": "2024-01-01T00:00:00Z", "release_date_estimated": false}}"#,
+            CHAPTER_URL
+        );
+        serde_json::from_str(&data).unwrap()
+    }
+
+    fn cbz(path: &Path, comic_info: &str) {
+        let mut zip = ZipWriter::new(fs::File::create(path).unwrap());
+        zip.start_file("001.jpg", FileOptions::default()).unwrap();
+        zip.write_all(b"page").unwrap();
+        zip.start_file("ComicInfo.xml", FileOptions::default())
+            .unwrap();
+        zip.write_all(comic_info.as_bytes()).unwrap();
+        zip.finish().unwrap();
+    }
+
+    // The old layout: pages 1, 2 and 10, a manifest and an output.
+    fn old_cache(name: &str) -> TestDir {
+        let cache = TestDir::new(name);
+        for page in [1, 2, 10] {
+            fs::write(cache.join(format!("{}.jpg", page)), [page as u8]).unwrap();
+        }
+        manifest().save(cache.str()).unwrap();
+        cbz(
+            &cache.join("output.cbz"),
+            "<ComicInfo>\n  <Series>Fixture Tales</Series>\n  <Number>3</Number>\n</ComicInfo>",
+        );
+        cache
+    }
+
+    #[test]
+    fn old_files_are_found_in_page_order() {
+        let cache = old_cache("migrate-detect");
+        fs::create_dir_all(cache.join("series/Other/Chapter 1")).unwrap();
+        fs::write(cache.join("notes.txt"), b"mine").unwrap();
+        let legacy = detect(&cache.path).unwrap();
+        assert_eq!(
+            legacy.pages,
+            [
+                cache.join("1.jpg"),
+                cache.join("2.jpg"),
+                cache.join("10.jpg")
+            ]
+        );
+        assert_eq!(legacy.chapter_url(), Some(CHAPTER_URL));
+        assert_eq!(legacy.outputs, [cache.join("output.cbz")]);
+        assert_eq!(
+            legacy.comic_info(),
+            (
+                Some("Fixture Tales".to_string()),
+                Some("3".parse().unwrap())
+            )
+        );
+        assert_eq!(legacy.files().len(), 5);
+    }
+
+    #[test]
+    fn current_caches_have_nothing_to_migrate() {
+        let cache = TestDir::new("migrate-current");
+        assert!(detect(&cache.path).is_none());
+        // Later versions still wrote "output" outputs for single chapters.
+        cbz(&cache.join("output.cbz"), "<ComicInfo></ComicInfo>");
+        fs::create_dir_all(cache.join("series/Fixture Tales/Chapter 3")).unwrap();
+        assert!(detect(&cache.path).is_none());
+
+        assert!(!is_done(&cache.path));
+        mark_done(&cache.path).unwrap();
+        assert!(is_done(&cache.path));
+    }
+
+    #[test]
+    fn pages_and_manifest_move_into_the_chapter_folder() {
+        let cache = old_cache("migrate-move");
+        let legacy = detect(&cache.path).unwrap();
+        let chapter_dir = cache.join("series/Fixture Tales/Chapter 3");
+        fs::create_dir_all(chapter_dir.parent().unwrap()).unwrap();
+        let migrated = Manifest {
+            chapter_name: Some("Chapter 3".to_string()),
+            ..manifest()
+        };
+        move_chapter(&legacy, &chapter_dir, &migrated).unwrap();
+
+        for page in [1, 2, 10] {
+            let name = format!("{}.jpg", page);
+            assert!(!cache.join(&name).exists());
+            assert_eq!(fs::read(chapter_dir.join(&name)).unwrap(), [page as u8]);
+        }
+        assert!(!cache.join(MANIFEST_FILE).exists());
+        let moved = Manifest::load(&chapter_dir.to_string_lossy()).unwrap();
+        assert_eq!(moved.chapter_name.as_deref(), Some("Chapter 3"));
+        assert!(!cache.join("series/Fixture Tales/Chapter 3.tmp").exists());
+        // Only the output is left, which doesn't count on its own.
+        assert!(detect(&cache.path).is_none());
+    }
+
+    #[test]
+    fn moves_never_overwrite() {
+        let cache = old_cache("migrate-rename");
+        fs::write(cache.join("Fixture Tales c3.cbz"), b"newer").unwrap();
+        let renamed = rename_output(&cache.join("output.cbz"), "Fixture Tales c3").unwrap();
+        assert_eq!(renamed, cache.join("Fixture Tales c3 (1).cbz"));
+        assert_eq!(
+            fs::read(cache.join("Fixture Tales c3.cbz")).unwrap(),
+            b"newer"
+        );
+
+        let unsorted = cache.join(UNSORTED_DIR);
+        fs::create_dir_all(&unsorted).unwrap();
+        fs::write(unsorted.join("1.jpg"), b"parked before").unwrap();
+        let files = [
+            cache.join("1.jpg"),
+            cache.join("2.jpg"),
+            cache.join("gone.jpg"),
+        ];
+        assert_eq!(park(&cache.path, &files).unwrap(), unsorted);
+        assert_eq!(fs::read(unsorted.join("1.jpg")).unwrap(), b"parked before");
+        assert_eq!(fs::read(unsorted.join("1 (1).jpg")).unwrap(), [1]);
+        assert_eq!(fs::read(unsorted.join("2.jpg")).unwrap(), [2]);
+        assert!(!cache.join("1.jpg").exists());
+    }
+
+    #[test]
+    fn chapter_numbers_from_chapter_urls() {
+        for (url, number) in [
+            (CHAPTER_URL, Some("3")),
+            ("https://site.test/manga/x/chapter_12.5/", Some("12.5")),
+            ("https://site.test/read/x/c7", Some("7")),
+            ("https://site.test/read/x/ch-40", Some("40")),
+            ("https://site.test/read/x/epic7", None),
+            ("https://site.test/manga/x", None),
+        ] {
+            let expected = number.map(|number| number.parse().unwrap());
+            assert_eq!(guess_number(url), expected, "{}", url);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::http;
+use crate::series::data_dir;
+use reqwest::blocking::Client;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const MIRRORS_FILE: &str = "mirrors.json";
+// Measured latencies are reused for this long before probing again.
+const PROBE_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Latencies of one source's mirrors in milliseconds; None for mirrors that
+// didn't answer.
+#[derive(Serialize, Deserialize)]
+struct Probe {
+    checked_at: u64,
+    latencies: BTreeMap<String, Option<u64>>,
+}
+
+static PINNED: OnceLock<HashMap<String, String>> = OnceLock::new();
+// The mirror that last answered, by source.
+static USED: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+// Makes each listed source use only the given mirror (--mirror, or the
+// [mirror] table of the config file).
+pub fn pin(pinned: HashMap<String, String>) {
+    let _ = PINNED.set(pinned);
+}
+
+// `mirrors` ordered fastest first, probing them unless a recent enough
+// measurement is stored. A pinned mirror is used on its own.
+pub fn ordered(source: &str, mirrors: &[&str]) -> Vec<String> {
+    if let Some(pinned) = PINNED.get().and_then(|pinned| pinned.get(source)) {
+        return vec![pinned.trim_end_matches('/').to_string()];
+    }
+    // Fixtures answer for any mirror, and probing would go to the network.
+    if mirrors.len() < 2 || http::offline() {
+        return mirrors.iter().map(|mirror| mirror.to_string()).collect();
+    }
+
+    let stored = load();
+    let fresh = stored.get(source).filter(|probe| {
+        now().saturating_sub(probe.checked_at) < PROBE_MAX_AGE.as_secs()
+            && mirrors
+                .iter()
+                .all(|mirror| probe.latencies.contains_key(*mirror))
+    });
+    let latencies = match fresh {
+        Some(probe) => probe.latencies.clone(),
+        None => measure(source, mirrors),
+    };
+
+    let mut ordered: Vec<String> = mirrors.iter().map(|mirror| mirror.to_string()).collect();
+    // Mirrors that didn't answer go last, in their registered order.
+    ordered.sort_by_key(|mirror| latencies.get(mirror).copied().flatten().unwrap_or(u64::MAX));
+    ordered
+}
+
+pub fn record_used(source: &str, mirror: &str) {
+    USED.lock()
+        .unwrap()
+        .insert(source.to_string(), mirror.to_string());
+}
+
+pub fn used(source: &str) -> Option<String> {
+    USED.lock().unwrap().get(source).cloned()
+}
+
+// Drops the stored measurement so the next run probes again, e.g. after the
+// preferred mirror stopped answering.
+pub fn forget(source: &str) {
+    let mut stored = load();
+    if stored.remove(source).is_some() {
+        save(&stored);
+    }
+}
+
+// Probes `mirrors` and stores the latencies for later runs.
+pub fn measure(source: &str, mirrors: &[&str]) -> BTreeMap<String, Option<u64>> {
+    let latencies: BTreeMap<String, Option<u64>> = probe(mirrors)
+        .into_iter()
+        .map(|(mirror, latency)| (mirror, latency.map(|latency| latency.as_millis() as u64)))
+        .collect();
+    let mut stored = load();
+    stored.insert(
+        source.to_string(),
+        Probe {
+            checked_at: now(),
+            latencies: latencies.clone(),
+        },
+    );
+    save(&stored);
+    latencies
+}
+
+// Sends a HEAD request to every mirror at once and times the answers.
+fn probe(mirrors: &[&str]) -> Vec<(String, Option<Duration>)> {
+    let Ok(client) = Client::builder()
+        .user_agent("Mozilla/5.0")
+        .timeout(PROBE_TIMEOUT)
+        .build()
+    else {
+        return mirrors
+            .iter()
+            .map(|mirror| (mirror.to_string(), None))
+            .collect();
+    };
+    thread::scope(|scope| {
+        let probes: Vec<_> = mirrors
+            .iter()
+            .map(|mirror| {
+                let client = &client;
+                scope.spawn(move || {
+                    let started = Instant::now();
+                    let answered = client
+                        .head(*mirror)
+                        .send()
+                        .is_ok_and(|response| !response.status().is_server_error());
+                    answered.then(|| started.elapsed())
+                })
+            })
+            .collect();
+        mirrors
+            .iter()
+            .zip(probes)
+            .map(|(mirror, probe)| (mirror.to_string(), probe.join().ok().flatten()))
+            .collect()
+    })
+}
+
+// `url` moved onto `mirror` when it points at one of the `known` mirrors;
+// other URLs, such as image hosts, are left alone.
+pub fn on_mirror(url: &str, mirror: &str, known: &[&str]) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    let origin = parsed.origin().ascii_serialization();
+    if !known
+        .iter()
+        .any(|known| known.trim_end_matches('/') == origin)
+    {
+        return None;
+    }
+    let mut moved = format!("{}{}", mirror.trim_end_matches('/'), parsed.path());
+    if let Some(query) = parsed.query() {
+        moved.push('?');
+        moved.push_str(query);
+    }
+    Some(moved)
+}
+
+fn load() -> BTreeMap<String, Probe> {
+    fs::read_to_string(data_dir().join(MIRRORS_FILE))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+fn save(stored: &BTreeMap<String, Probe>) {
+    let saved = fs::create_dir_all(data_dir()).and_then(|_| {
+        let data = serde_json::to_string_pretty(stored).unwrap_or_default();
+        fs::write(data_dir().join(MIRRORS_FILE), data)
+    });
+    if let Err(e) = saved {
+        log::debug!("Failed to save mirror latencies: {}", e);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
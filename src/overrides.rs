@@ -0,0 +1,216 @@
+use crate::CLI;
+use clap::{ArgMatches, CommandFactory, Parser, ValueSource};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+
+// Flags that may be stored per series. Anything deciding which series or
+// chapter is downloaded stays command-line only.
+const OVERRIDABLE: &[&str] = &[
+    "format",
+    "jobs",
+    "delay",
+    "process-jobs",
+    "trim-margins",
+    "trim-safety-margin",
+    "trim-max-crop",
+    "autocontrast",
+    "levels",
+    "gamma",
+    "adjust-color-pages",
+    "upscale-cmd",
+    "upscale-jobs",
+    "upscale-fallback",
+    "group",
+    "lang",
+    "single-file",
+    "rtl",
+    "skip-promo-pages",
+    "no-dedupe",
+    "no-exif-rotate",
+    "no-backup",
+    "on-conflict",
+    "on-page-failure",
+    "on-chapter-failure",
+    "on-parse-failure",
+    "keep-ads",
+    "low-data",
+    "always-reencode",
+    "target-size",
+    "retry-passes",
+    "reproducible",
+    "entry-template",
+    "compat-format",
+    "pdf-page-size",
+    "pdf-margin",
+    "pdf-max-side",
+    "no-split-oversize",
+    "output-dir",
+    "order",
+    "stream-cbz",
+    "post-cmd",
+    "post-cmd-failures",
+    "index",
+];
+
+// Flags the config file's [defaults] table may set besides the per-series
+// ones.
+const DEFAULT_ONLY: &[&str] = &["source", "viewer", "prefetch"];
+
+// Checks the keys of the config file's defaults; their values are checked
+// when they are applied.
+pub fn check_defaults(defaults: &BTreeMap<String, String>) -> Result<(), String> {
+    for key in defaults.keys() {
+        if !OVERRIDABLE.contains(&key.as_str()) && !DEFAULT_ONLY.contains(&key.as_str()) {
+            return Err(format!(
+                "\"{}\" can't be set in [defaults]; use one of: {}, {}",
+                key,
+                OVERRIDABLE.join(", "),
+                DEFAULT_ONLY.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Checks the keys of a project file's settings: the per-series flags and the
+// source.
+pub fn check_project(settings: &BTreeMap<String, String>) -> Result<(), String> {
+    for key in settings.keys() {
+        if !OVERRIDABLE.contains(&key.as_str()) && key != "source" {
+            return Err(format!(
+                "\"{}\" can't be set in a project; use one of: {}, source",
+                key,
+                OVERRIDABLE.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+// The per-series flags given on the command line, the way a project file
+// records them.
+pub fn given(matches: &ArgMatches) -> BTreeMap<String, toml::Value> {
+    let command = CLI::command();
+    let mut given = BTreeMap::new();
+    for arg in command.get_arguments() {
+        let key = arg.get_id();
+        if !OVERRIDABLE.contains(&key)
+            || matches.value_source(key) != Some(ValueSource::CommandLine)
+        {
+            continue;
+        }
+        let values: Vec<toml::Value> = matches
+            .get_raw(key)
+            .into_iter()
+            .flatten()
+            .map(|value| toml::Value::from(value.to_string_lossy().into_owned()))
+            .collect();
+        let value = if !arg.is_takes_value_set() {
+            toml::Value::from(true)
+        } else if arg.is_multiple_occurrences_set() {
+            toml::Value::from(values)
+        } else {
+            match values.into_iter().next() {
+                Some(value) => value,
+                None => continue,
+            }
+        };
+        given.insert(key.to_string(), value);
+    }
+    given
+}
+
+// Splits a `--set KEY=VALUE` argument and checks it the way the flag itself
+// would be checked on the command line.
+pub fn parse_assignment(assignment: &str) -> Result<(String, String), String> {
+    let (key, value) = assignment
+        .split_once('=')
+        .ok_or(format!("expected KEY=VALUE, got \"{}\"", assignment))?;
+    let key = key.trim().trim_start_matches("--").replace('_', "-");
+    let value = value.trim().to_string();
+    if !OVERRIDABLE.contains(&key.as_str()) {
+        return Err(format!(
+            "\"{}\" can't be set per series; use one of: {}",
+            key,
+            OVERRIDABLE.join(", ")
+        ));
+    }
+    let mut args = vec![OsString::from("manga-cli")];
+    args.extend(flag_args(&key, &value)?);
+    args.push(OsString::from("placeholder"));
+    CLI::try_parse_from(args).map_err(|e| e.to_string())?;
+    Ok((key, value))
+}
+
+// Re-parses the command line `args` with the overrides (stored per series, or
+// config defaults) in front of it. Flags given explicitly on the command line
+// keep their values.
+pub fn apply(
+    args: &[OsString],
+    matches: &ArgMatches,
+    overrides: &BTreeMap<String, String>,
+) -> Result<CLI, String> {
+    let mut args = args.to_vec();
+    let mut extra = Vec::new();
+    for (key, value) in overrides {
+        if matches.value_source(key) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        extra.extend(flag_args(key, value)?);
+    }
+    args.splice(1..1, extra);
+    CLI::try_parse_from(args).map_err(|e| format!("Invalid stored settings: {}", e))
+}
+
+fn flag_args(key: &str, value: &str) -> Result<Vec<OsString>, String> {
+    let command = CLI::command();
+    let arg = command
+        .get_arguments()
+        .find(|arg| arg.get_id() == key)
+        .ok_or(format!("unknown setting \"{}\"", key))?;
+    let flag = OsString::from(format!("--{}", key));
+    if !arg.is_takes_value_set() {
+        return match value.parse::<bool>() {
+            Ok(true) => Ok(vec![flag]),
+            Ok(false) => Ok(Vec::new()),
+            Err(_) => Err(format!("{} takes true or false", key)),
+        };
+    }
+    if arg.is_multiple_occurrences_set() {
+        return Ok(value
+            .split(',')
+            .flat_map(|value| [flag.clone(), OsString::from(value.trim())])
+            .collect());
+    }
+    Ok(vec![flag, OsString::from(value)])
+}
+
+// The flags given on the command line `matches` came from, less those in
+// `dropped`, for running manga-cli again on other chapters. Positionals and
+// subcommands are left out.
+pub fn command_line(matches: &ArgMatches, dropped: &[&str]) -> Vec<OsString> {
+    let command = CLI::command();
+    let mut args = Vec::new();
+    for arg in command.get_arguments() {
+        let key = arg.get_id();
+        if arg.is_positional()
+            || dropped.contains(&key)
+            || !matches.try_contains_id(key).unwrap_or(false)
+            || matches.value_source(key) != Some(ValueSource::CommandLine)
+        {
+            continue;
+        }
+        let flag = OsString::from(format!("--{}", key));
+        if !arg.is_takes_value_set() {
+            args.extend((0..matches.occurrences_of(key)).map(|_| flag.clone()));
+            continue;
+        }
+        for value in matches.get_raw(key).into_iter().flatten() {
+            let mut assignment = flag.clone();
+            assignment.push("=");
+            assignment.push(value);
+            args.push(assignment);
+        }
+    }
+    args
+}
@@ -0,0 +1,239 @@
+use crate::decode;
+use std::path::Path;
+
+type SplitResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Resolution used to turn physical page sizes into pixels for magick.
+const PDF_DPI: f64 = 150.0;
+const MM_PER_INCH: f64 = 25.4;
+// Pieces of a split page repeat this many pixels of the previous piece, so
+// nothing is lost at a seam.
+const SPLIT_OVERLAP: u32 = 64;
+const MIN_MAX_SIDE: u32 = 4 * SPLIT_OVERLAP;
+
+#[derive(Clone, Copy)]
+pub enum PageSize {
+    // Every page keeps its image's own size.
+    Original,
+    // Width and height in millimetres.
+    Fixed(f64, f64),
+}
+
+pub struct PdfOptions {
+    pub page_size: PageSize,
+    // Blank space kept around the image on fixed-size pages, in millimetres.
+    pub margin: f64,
+    // Pages longer than this many pixels on a side are split into several;
+    // None with --no-split-oversize.
+    pub max_side: Option<u32>,
+}
+
+pub fn parse_page_size(value: &str) -> Result<PageSize, String> {
+    match value.trim().to_lowercase().as_str() {
+        "original" => Ok(PageSize::Original),
+        "a4" => Ok(PageSize::Fixed(210.0, 297.0)),
+        "a5" => Ok(PageSize::Fixed(148.0, 210.0)),
+        "b5" => Ok(PageSize::Fixed(176.0, 250.0)),
+        size => {
+            let (width, height) = size
+                .split_once('x')
+                .ok_or("expected original, a4, a5, b5 or WxH in millimetres")?;
+            let parse = |value: &str| match value.trim().parse::<f64>() {
+                Ok(mm) if mm > 0.0 && mm.is_finite() => Ok(mm),
+                _ => Err(format!("\"{}\" is not a page size in millimetres", value)),
+            };
+            Ok(PageSize::Fixed(parse(width)?, parse(height)?))
+        }
+    }
+}
+
+pub fn parse_margin(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(mm) if mm >= 0.0 && mm.is_finite() => Ok(mm),
+        _ => Err("margin must be a non-negative number of millimetres".into()),
+    }
+}
+
+pub fn parse_max_side(value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(pixels) if pixels >= MIN_MAX_SIDE => Ok(pixels),
+        _ => Err(format!(
+            "expected a number of pixels, at least {}",
+            MIN_MAX_SIDE
+        )),
+    }
+}
+
+// Splits pages longer than `max_side` on a side, such as stitched webtoon
+// strips, into overlapping pieces saved next to them, since some readers and
+// printers can't show larger images. Returns the pages to put into the PDF in
+// reading order. Pages whose size can't be read are left as they are.
+pub fn split_oversize(pages: &[String], max_side: u32) -> SplitResult<Vec<String>> {
+    let mut split = Vec::new();
+    for page in pages {
+        let dimensions = decode::reader(page).and_then(|reader| Ok(reader.into_dimensions()?));
+        let (width, height) = match dimensions {
+            Ok((width, height)) if width.max(height) > max_side => (width, height),
+            Ok(_) => {
+                split.push(page.clone());
+                continue;
+            }
+            Err(e) => {
+                log::debug!("Not checking the size of {}: {}", page, e);
+                split.push(page.clone());
+                continue;
+            }
+        };
+        let image = decode::reader(page)?.decode()?;
+        let mut pieces = Vec::new();
+        for (y, piece_height) in spans(height, max_side) {
+            for (x, piece_width) in spans(width, max_side) {
+                let piece = format!("{}.part{}.png", page, pieces.len() + 1);
+                image
+                    .crop_imm(x, y, piece_width, piece_height)
+                    .save(&piece)?;
+                pieces.push(piece);
+            }
+        }
+        println!(
+            "Split {} ({}x{}) into {} PDF pages of at most {}px",
+            Path::new(page)
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default(),
+            width,
+            height,
+            pieces.len(),
+            max_side
+        );
+        split.extend(pieces);
+    }
+    Ok(split)
+}
+
+// Start and length of the pieces a side of `length` pixels is cut into.
+fn spans(length: u32, max_side: u32) -> Vec<(u32, u32)> {
+    if length <= max_side {
+        return vec![(0, length)];
+    }
+    let step = max_side - SPLIT_OVERLAP;
+    let mut spans = Vec::new();
+    let mut start = 0;
+    loop {
+        let end = (start + max_side).min(length);
+        spans.push((start, end - start));
+        if end == length {
+            return spans;
+        }
+        start += step;
+    }
+}
+
+// magick operators that scale every image into the page box, keeping its
+// aspect ratio, and center it on a white page of the requested size.
+pub fn page_args(options: &PdfOptions) -> Result<Vec<String>, String> {
+    let PageSize::Fixed(width, height) = options.page_size else {
+        return Ok(Vec::new());
+    };
+    let pixels = |mm: f64| (mm / MM_PER_INCH * PDF_DPI).round() as u32;
+    let (page_w, page_h) = (pixels(width), pixels(height));
+    let margin = pixels(options.margin);
+    if margin * 2 >= page_w.min(page_h) {
+        return Err("PDF margin leaves no room for the page image".into());
+    }
+    Ok(vec![
+        "-resize".to_string(),
+        format!("{}x{}", page_w - 2 * margin, page_h - 2 * margin),
+        "-background".to_string(),
+        "white".to_string(),
+        "-gravity".to_string(),
+        "center".to_string(),
+        "-extent".to_string(),
+        format!("{}x{}", page_w, page_h),
+        "-units".to_string(),
+        "PixelsPerInch".to_string(),
+        "-density".to_string(),
+        PDF_DPI.to_string(),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args_for(page_size: &str, margin: f64) -> Result<Vec<String>, String> {
+        page_args(&PdfOptions {
+            page_size: parse_page_size(page_size)?,
+            margin,
+            max_side: None,
+        })
+    }
+
+    // The image box and the page, in pixels at PDF_DPI.
+    fn boxes(args: &[String]) -> (&str, &str) {
+        let after = |flag: &str| {
+            let at = args.iter().position(|arg| arg == flag).unwrap();
+            args[at + 1].as_str()
+        };
+        (after("-resize"), after("-extent"))
+    }
+
+    #[test]
+    fn named_and_custom_sizes_give_the_page_in_pixels() {
+        // 210x297 mm at 150 dpi.
+        assert_eq!(
+            boxes(&args_for("A4", 0.0).unwrap()),
+            ("1240x1754", "1240x1754")
+        );
+        assert_eq!(
+            boxes(&args_for("a5", 0.0).unwrap()),
+            ("874x1240", "874x1240")
+        );
+        assert_eq!(
+            boxes(&args_for("b5", 0.0).unwrap()),
+            ("1039x1476", "1039x1476")
+        );
+        assert_eq!(
+            boxes(&args_for(" 100x150.5 ", 0.0).unwrap()),
+            ("591x889", "591x889")
+        );
+        // Every page is the same size whatever its image is.
+        let args = args_for("a4", 0.0).unwrap();
+        assert!(args.windows(2).any(|pair| pair == ["-gravity", "center"]));
+        assert!(args.windows(2).any(|pair| pair == ["-density", "150"]));
+    }
+
+    #[test]
+    fn margins_shrink_the_image_box_not_the_page() {
+        // 10 mm is 59 px.
+        assert_eq!(
+            boxes(&args_for("a4", 10.0).unwrap()),
+            ("1122x1636", "1240x1754")
+        );
+        assert!(args_for("a4", 105.0).is_err());
+        assert!(args_for("100x150", 49.9).is_ok());
+    }
+
+    #[test]
+    fn original_pages_are_left_alone() {
+        assert_eq!(args_for("original", 20.0).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn bad_sizes_are_rejected() {
+        for size in [
+            "letter", "0x100", "100x", "x100", "-5x10", "infx10", "10x10x10",
+        ] {
+            assert!(parse_page_size(size).is_err(), "{}", size);
+        }
+        for margin in ["-1", "nan", "wide"] {
+            assert!(parse_margin(margin).is_err(), "{}", margin);
+        }
+    }
+
+    #[test]
+    fn split_pieces_cover_the_page_with_overlap() {
+        assert_eq!(spans(1000, 1000), [(0, 1000)]);
+        assert_eq!(spans(2000, 1000), [(0, 1000), (936, 1000), (1872, 128)]);
+    }
+}
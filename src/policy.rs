@@ -0,0 +1,95 @@
+use crate::prompt;
+use clap::ArgEnum;
+use std::io::{self, IsTerminal};
+
+// What to do about pages still failing after the retry passes
+// (--on-page-failure).
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+pub enum PagePolicy {
+    // Builds the chapter without them.
+    Skip,
+    // Counts the chapter as failed; asked interactively, tries once more.
+    Retry,
+    Abort,
+}
+
+// What to do about a chapter that failed for good (--on-chapter-failure).
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+pub enum ChapterPolicy {
+    // Builds the output from the other chapters.
+    Continue,
+    Abort,
+}
+
+// What to do when a chapter list can't be read (--on-parse-failure).
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+pub enum ParsePolicy {
+    // Goes on without the list, guessing chapter URLs.
+    Fallback,
+    Abort,
+}
+
+// Decides what to do about `problem`: `chosen` when a policy was given,
+// otherwise the user's answer, or `default` when there's no one to ask.
+// Returns the choice and whether it was asked for. Every decision is added
+// to `decisions` for the run report.
+pub fn decide<T: ArgEnum + Copy>(
+    problem: &str,
+    chosen: Option<T>,
+    default: T,
+    decisions: &mut Vec<String>,
+) -> (T, bool) {
+    let (choice, how, asked) = match chosen {
+        Some(choice) => (choice, "by policy", false),
+        None if io::stdin().is_terminal() && io::stdout().is_terminal() => {
+            match prompt::ask(&question(problem, default), Some(default), parse_answer) {
+                Ok(choice) => (choice, "answered", true),
+                Err(_) => (default, "by default", false),
+            }
+        }
+        None => (default, "by default", false),
+    };
+    let decision = format!("{}: {} ({})", problem, name(choice), how);
+    log::info!("{}", decision);
+    decisions.push(decision);
+    (choice, asked)
+}
+
+// Like "Chapter 3: 2 of 20 pages failed. [s]kip, [r]etry, [a]bort [r]? ".
+fn question<T: ArgEnum + Copy>(problem: &str, default: T) -> String {
+    let choices: Vec<String> = T::value_variants()
+        .iter()
+        .map(|choice| {
+            let name = name(*choice);
+            format!("[{}]{}", &name[..1], &name[1..])
+        })
+        .collect();
+    format!(
+        "{}. {} [{}]? ",
+        problem,
+        choices.join(", "),
+        &name(default)[..1]
+    )
+}
+
+fn parse_answer<T: ArgEnum + Copy>(answer: &str) -> Result<T, String> {
+    let answer = answer.trim().to_lowercase();
+    T::value_variants()
+        .iter()
+        .copied()
+        .find(|choice| {
+            let name = name(*choice);
+            answer == name || answer == name[..1]
+        })
+        .ok_or_else(|| {
+            let names: Vec<&str> = T::value_variants()
+                .iter()
+                .map(|choice| name(*choice))
+                .collect();
+            format!("please answer {}", names.join(", "))
+        })
+}
+
+fn name<T: ArgEnum>(choice: T) -> &'static str {
+    choice.to_possible_value().unwrap().get_name()
+}
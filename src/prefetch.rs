@@ -0,0 +1,65 @@
+use crate::chapter_id::ChapterId;
+use crate::exec::Tool;
+use crate::ui;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Child;
+
+// Under the cache, where the download's output goes instead of the terminal
+// the viewer may be using.
+const LOG_FILE: &str = "prefetch.log";
+
+// The download of the chapter after the one being read. It runs as a second
+// manga-cli once this one is done downloading, so the two never fetch at the
+// same time, and it goes through the same rate limits and monthly cap.
+pub struct Prefetch {
+    child: Child,
+    number: ChapterId,
+    log: PathBuf,
+}
+
+// Starts downloading chapter `number` of `manga_url` with the flags `args`.
+pub fn start(
+    cache_dir: &Path,
+    args: Vec<OsString>,
+    manga_url: &str,
+    number: ChapterId,
+) -> io::Result<Prefetch> {
+    fs::create_dir_all(cache_dir)?;
+    let log = cache_dir.join(LOG_FILE);
+    let out = File::create(&log)?;
+    let child = Tool::new(env::current_exe()?)
+        .args(args)
+        .arg("--prefetching")
+        .arg("--skip-existing")
+        .arg(format!("--chapters={}-{}", number, number))
+        .arg(manga_url)
+        .spawn_logged(out)
+        .map_err(io::Error::other)?;
+    Ok(Prefetch { child, number, log })
+}
+
+impl Prefetch {
+    // Called once the viewer exits: a download still going is stopped, with
+    // the pages it fetched left in the cache for next time.
+    pub fn finish(mut self) {
+        match self.child.try_wait() {
+            Ok(Some(status)) if status.success() => {
+                println!("Prefetched chapter {}.", self.number)
+            }
+            Ok(Some(_)) => ui::warn(&format!(
+                "prefetching chapter {} failed; see {}.",
+                self.number,
+                self.log.display()
+            )),
+            Ok(None) | Err(_) => {
+                let _ = self.child.kill();
+                let _ = self.child.wait();
+                println!("Stopped prefetching chapter {}.", self.number);
+            }
+        }
+    }
+}
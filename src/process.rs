@@ -0,0 +1,692 @@
+use crate::decode;
+use crate::exif;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, Pixel};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+type ProcessResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+// Processing time of each page, by page number.
+type PageTimes = Vec<(usize, Duration)>;
+
+// Rows or columns whose luma standard deviation stays below this are treated
+// as part of a uniform border.
+const BORDER_MAX_STDDEV: f64 = 8.0;
+
+// Share of darkest/brightest pixels clipped when auto-contrast picks levels.
+const AUTOCONTRAST_CLIP: f64 = 0.005;
+
+// A sampled pixel counts as colored above this HSV saturation, and a page counts
+// as a color page once more than COLOR_PAGE_RATIO of its samples are colored.
+const COLOR_SATURATION: f64 = 0.25;
+const COLOR_PAGE_RATIO: f64 = 0.05;
+
+// JPEG quality used when recompressing pages for --low-data.
+pub const LOW_DATA_QUALITY: u8 = 60;
+
+// Quality and longest allowed page height --target-size steps down through
+// while a chapter would come out too large. The last step is the floor.
+const SIZE_STEPS: &[SizeSettings] = &[
+    SizeSettings {
+        quality: 85,
+        max_height: None,
+    },
+    SizeSettings {
+        quality: 75,
+        max_height: None,
+    },
+    SizeSettings {
+        quality: 60,
+        max_height: None,
+    },
+    SizeSettings {
+        quality: 60,
+        max_height: Some(2400),
+    },
+    SizeSettings {
+        quality: 50,
+        max_height: Some(2000),
+    },
+    SizeSettings {
+        quality: 40,
+        max_height: Some(1600),
+    },
+];
+
+// Pages re-encoded between projections of the chapter's size.
+const SIZE_CHECK_PAGES: usize = 8;
+
+// Unmodified downloads are kept here so processing can be redone from scratch.
+pub const ORIGINALS_DIR: &str = "original";
+
+// Pages that may wait for a processing worker, per worker.
+const QUEUED_PAGES_PER_JOB: usize = 2;
+
+#[derive(Clone)]
+pub struct ProcessOptions {
+    pub trim: Option<TrimOptions>,
+    pub levels: Option<LevelsOptions>,
+    // Turn pages tagged with an EXIF orientation upright.
+    pub exif_rotate: bool,
+}
+
+#[derive(Clone)]
+pub struct TrimOptions {
+    // Share of each detected border (in percent of the page dimension) that is
+    // kept so content touching the border isn't clipped.
+    pub safety_margin: f64,
+    // Upper bound on how much may be cropped from any one side, in percent.
+    pub max_crop: f64,
+}
+
+#[derive(Clone)]
+pub enum Levels {
+    Auto,
+    Fixed(u8, u8),
+}
+
+#[derive(Clone)]
+pub struct LevelsOptions {
+    pub levels: Levels,
+    pub gamma: f64,
+    // Adjust pages detected as color too; auto-levels tends to ruin spreads.
+    pub force_color: bool,
+}
+
+impl ProcessOptions {
+    pub fn is_empty(&self) -> bool {
+        self.trim.is_none() && self.levels.is_none() && !self.exif_rotate
+    }
+}
+
+// Applies the requested transforms to pages on `jobs` worker threads while the
+// caller keeps downloading. Each download is first moved into ORIGINALS_DIR
+// and the processed copy written in its place. The queue is bounded, so a CPU
+// that can't keep up slows the downloads down instead of piling up work.
+pub struct PageProcessor {
+    sender: Option<SyncSender<(usize, String)>>,
+    workers: Vec<JoinHandle<ProcessResult<PageTimes>>>,
+    started: Instant,
+}
+
+impl PageProcessor {
+    pub fn start(options: &ProcessOptions, jobs: usize) -> PageProcessor {
+        let jobs = jobs.max(1);
+        let (sender, receiver) = mpsc::sync_channel(jobs * QUEUED_PAGES_PER_JOB);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..jobs)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let options = options.clone();
+                thread::spawn(move || process_queue(&receiver, &options))
+            })
+            .collect();
+        PageProcessor {
+            sender: Some(sender),
+            workers,
+            started: Instant::now(),
+        }
+    }
+
+    // Queues a downloaded page, waiting while the queue is full. Pages are
+    // identified by their number, so the order they finish in doesn't matter.
+    pub fn submit(&self, page: usize, path: &str) {
+        if let Some(sender) = &self.sender {
+            // Fails only once every worker has stopped on an error, which
+            // finish() reports.
+            let _ = sender.send((page, path.to_string()));
+        }
+    }
+
+    // Waits for the queued pages and returns how long each one took, or the
+    // first error.
+    pub fn finish(mut self) -> ProcessResult<PageTimes> {
+        self.sender = None;
+        let mut processed = Vec::new();
+        let mut error = None;
+        for worker in self.workers.drain(..) {
+            match worker.join() {
+                Ok(Ok(times)) => processed.extend(times),
+                Ok(Err(e)) => error = error.or(Some(e)),
+                Err(_) => error = error.or(Some("page processing panicked".into())),
+            }
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        log::debug!(
+            "Processed {} pages in {:.1}s ({:.1} pages/s)",
+            processed.len(),
+            elapsed,
+            processed.len() as f64 / elapsed.max(0.001)
+        );
+        error.map_or(Ok(processed), Err)
+    }
+}
+
+fn process_queue(
+    receiver: &Mutex<Receiver<(usize, String)>>,
+    options: &ProcessOptions,
+) -> ProcessResult<PageTimes> {
+    let mut processed = Vec::new();
+    loop {
+        let next = receiver.lock().unwrap().recv();
+        let Ok((page, path)) = next else {
+            return Ok(processed);
+        };
+        let started = Instant::now();
+        process_page(page, &path, options)?;
+        processed.push((page, started.elapsed()));
+    }
+}
+
+// Processes pages that are already on disk.
+pub fn process_pages(pages: &[String], options: &ProcessOptions, jobs: usize) -> ProcessResult<()> {
+    if options.is_empty() {
+        return Ok(());
+    }
+
+    let processor = PageProcessor::start(options, jobs);
+    for (i, path) in pages.iter().enumerate() {
+        processor.submit(i + 1, path);
+    }
+    processor.finish().map(|_| ())
+}
+
+fn process_page(page: usize, path: &str, options: &ProcessOptions) -> ProcessResult<()> {
+    let original = original_path(path);
+    let orientation = if options.exif_rotate {
+        let download = if Path::new(path).exists() {
+            Path::new(path)
+        } else {
+            original.as_path()
+        };
+        fs::read(download)
+            .ok()
+            .and_then(|data| exif::orientation(&data))
+    } else {
+        None
+    };
+    // Untagged pages are left alone when turning them is all there is to do.
+    if orientation.is_none() && options.trim.is_none() && options.levels.is_none() {
+        return Ok(());
+    }
+
+    if Path::new(path).exists() {
+        fs::create_dir_all(original.parent().unwrap())?;
+        fs::rename(path, &original)?;
+    }
+
+    let reader = decode::reader(&original)?;
+    let format = reader.format().ok_or("Unrecognized image format")?;
+    let mut img = reader.decode()?;
+    let mut changed = false;
+
+    // First, so trimming and levels see the page the right way up. Saving
+    // below drops the tag along with the rest of the metadata.
+    if let Some(orientation) = orientation {
+        img = exif::apply(img, orientation);
+        changed = true;
+        println!(
+            "Rotated page {} upright (EXIF orientation {})",
+            page, orientation
+        );
+    }
+
+    if let Some(trim) = &options.trim {
+        let (before_w, before_h) = (img.width(), img.height());
+        if let Some(trimmed) = trim_margins(&img, trim) {
+            img = trimmed;
+            changed = true;
+            println!(
+                "Trimmed page {}: {}x{} -> {}x{}",
+                page,
+                before_w,
+                before_h,
+                img.width(),
+                img.height()
+            );
+        }
+    }
+
+    if let Some(levels) = &options.levels {
+        if !levels.force_color && is_color_page(&img) {
+            println!("Skipping levels on color page {}", page);
+        } else {
+            img = adjust_levels(&img, levels);
+            changed = true;
+        }
+    }
+
+    if changed {
+        img.save_with_format(path, format)?;
+    } else {
+        fs::copy(&original, path)?;
+    }
+    Ok(())
+}
+
+// Sizes of a page before and after --low-data re-encoded it. The original is
+// kept when the JPEG came out no smaller.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Encoding {
+    pub page: usize,
+    pub original_bytes: u64,
+    pub encoded_bytes: u64,
+    pub kept_original: bool,
+}
+
+// Re-encodes pages as lower-quality JPEGs for sources that only serve full
+// quality images. Pages are compared with the file they replace, so changes
+// made by trimming or levels are in both. With `always`, the JPEG replaces
+// the page even when it is larger.
+pub fn recompress_pages(pages: &[String], always: bool) -> ProcessResult<Vec<Encoding>> {
+    pages
+        .par_iter()
+        .enumerate()
+        .map(|(i, path)| recompress_page(i + 1, path, always))
+        .collect()
+}
+
+fn recompress_page(page: usize, path: &str, always: bool) -> ProcessResult<Encoding> {
+    let before = fs::metadata(path)?.len();
+    let img = decode::reader(path)?.decode()?;
+    let img = if img.color().has_alpha() {
+        DynamicImage::ImageRgb8(img.to_rgb8())
+    } else {
+        img
+    };
+    let mut data = Vec::new();
+    JpegEncoder::new_with_quality(&mut data, LOW_DATA_QUALITY).encode_image(&img)?;
+    let kept_original = !always && data.len() as u64 >= before;
+    if !kept_original {
+        fs::write(path, &data)?;
+    }
+    Ok(Encoding {
+        page,
+        original_bytes: before,
+        encoded_bytes: data.len() as u64,
+        kept_original,
+    })
+}
+
+// How --target-size re-encoded a chapter's pages, kept in the manifest so the
+// chapter can be packaged the same way again.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct SizeSettings {
+    pub quality: u8,
+    // Taller pages were scaled down to this height.
+    pub max_height: Option<u32>,
+}
+
+// What fitting a chapter into --target-size came to.
+pub struct Fitted {
+    // None when the pages fit as downloaded.
+    pub settings: Option<SizeSettings>,
+    pub bytes: u64,
+    // False when even the floor left the chapter too large.
+    pub fits: bool,
+}
+
+// Re-encodes a chapter's pages as JPEGs until they add up to no more than
+// `budget` bytes, stepping quality and page height down from `quality`, the
+// quality the pages already have if known. Pages that fit are left alone. The size is projected from the
+// pages done every SIZE_CHECK_PAGES pages, so a step down happens early and
+// only the pages encoded before it are done again.
+pub fn fit_to_size(pages: &[String], budget: u64, quality: Option<u8>) -> ProcessResult<Fitted> {
+    let originals = pages
+        .iter()
+        .map(fs::read)
+        .collect::<Result<Vec<Vec<u8>>, _>>()?;
+    let downloaded: u64 = originals.iter().map(|data| data.len() as u64).sum();
+    if downloaded <= budget {
+        return Ok(Fitted {
+            settings: None,
+            bytes: downloaded,
+            fits: true,
+        });
+    }
+
+    let mut step = SIZE_STEPS
+        .iter()
+        .position(|settings| quality.is_none_or(|quality| settings.quality <= quality))
+        .unwrap_or(SIZE_STEPS.len() - 1);
+    // The step each page was last encoded with, and its size.
+    let mut encoded: Vec<Option<(usize, u64)>> = vec![None; pages.len()];
+    let mut done = 0;
+    loop {
+        let end = (done + SIZE_CHECK_PAGES).min(pages.len());
+        encode_pages(pages, &originals, done..end, step, &mut encoded)?;
+        done = end;
+        let so_far: u64 = encoded[..done]
+            .iter()
+            .flatten()
+            .map(|(_, bytes)| bytes)
+            .sum();
+        let projected = so_far * pages.len() as u64 / done.max(1) as u64;
+        let floor = step + 1 == SIZE_STEPS.len();
+        if projected > budget && !floor {
+            step += 1;
+            log::debug!(
+                "Projected {} bytes after {} pages, over {}; stepping down to quality {}",
+                projected,
+                done,
+                budget,
+                SIZE_STEPS[step].quality
+            );
+        }
+        if done < pages.len() {
+            continue;
+        }
+
+        // Pages encoded before the last step down are done again with it.
+        let stale: Vec<usize> = (0..pages.len())
+            .filter(|&i| encoded[i].is_some_and(|(used, _)| used != step))
+            .collect();
+        for i in stale {
+            encode_pages(pages, &originals, i..i + 1, step, &mut encoded)?;
+        }
+        let bytes: u64 = encoded.iter().flatten().map(|(_, bytes)| bytes).sum();
+        if bytes > budget && step + 1 < SIZE_STEPS.len() {
+            // The projection was off; start over one step lower.
+            step += 1;
+            done = 0;
+            continue;
+        }
+        return Ok(Fitted {
+            settings: Some(SIZE_STEPS[step]),
+            bytes,
+            fits: bytes <= budget,
+        });
+    }
+}
+
+fn encode_pages(
+    pages: &[String],
+    originals: &[Vec<u8>],
+    range: std::ops::Range<usize>,
+    step: usize,
+    encoded: &mut [Option<(usize, u64)>],
+) -> ProcessResult<()> {
+    let settings = SIZE_STEPS[step];
+    let sizes = range
+        .clone()
+        .into_par_iter()
+        .map(|i| encode_page(&pages[i], &originals[i], settings))
+        .collect::<ProcessResult<Vec<u64>>>()?;
+    for (i, bytes) in range.zip(sizes) {
+        encoded[i] = Some((step, bytes));
+    }
+    Ok(())
+}
+
+// Writes `original` to `path` re-encoded with `settings`; returns its size.
+fn encode_page(path: &str, original: &[u8], settings: SizeSettings) -> ProcessResult<u64> {
+    let mut img = decode::from_memory(path, original)?;
+    if let Some(max_height) = settings.max_height {
+        if img.height() > max_height {
+            img = img.resize(img.width(), max_height, FilterType::Lanczos3);
+        }
+    }
+    let img = if img.color().has_alpha() {
+        DynamicImage::ImageRgb8(img.to_rgb8())
+    } else {
+        img
+    };
+    let mut data = Vec::new();
+    JpegEncoder::new_with_quality(&mut data, settings.quality).encode_image(&img)?;
+    fs::write(path, &data)?;
+    Ok(data.len() as u64)
+}
+
+pub fn original_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    dir.join(ORIGINALS_DIR).join(path.file_name().unwrap())
+}
+
+fn trim_margins(img: &DynamicImage, options: &TrimOptions) -> Option<DynamicImage> {
+    let luma = img.to_luma8();
+    let (width, height) = luma.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let row_uniform = |y: u32| is_uniform((0..width).map(|x| luma.get_pixel(x, y)[0]));
+    let col_uniform = |x: u32| is_uniform((0..height).map(|y| luma.get_pixel(x, y)[0]));
+
+    let top = (0..height).take_while(|&y| row_uniform(y)).count() as u32;
+    if top == height {
+        // Blank page; nothing sensible to crop to.
+        return None;
+    }
+    let bottom = (0..height).rev().take_while(|&y| row_uniform(y)).count() as u32;
+    let left = (0..width).take_while(|&x| col_uniform(x)).count() as u32;
+    let right = (0..width).rev().take_while(|&x| col_uniform(x)).count() as u32;
+
+    let top = limit_crop(top, height, options);
+    let bottom = limit_crop(bottom, height, options);
+    let left = limit_crop(left, width, options);
+    let right = limit_crop(right, width, options);
+
+    if top + bottom + left + right == 0 {
+        return None;
+    }
+
+    Some(img.crop_imm(left, top, width - left - right, height - top - bottom))
+}
+
+fn limit_crop(border: u32, dimension: u32, options: &TrimOptions) -> u32 {
+    let safety = (dimension as f64 * options.safety_margin / 100.0).round() as u32;
+    let max = (dimension as f64 * options.max_crop / 100.0).floor() as u32;
+    border.saturating_sub(safety).min(max)
+}
+
+fn is_uniform(values: impl Iterator<Item = u8>) -> bool {
+    let (mut count, mut sum, mut sum_sq) = (0f64, 0f64, 0f64);
+    for value in values {
+        let value = value as f64;
+        count += 1.0;
+        sum += value;
+        sum_sq += value * value;
+    }
+    let mean = sum / count;
+    let variance = (sum_sq / count - mean * mean).max(0.0);
+    variance.sqrt() < BORDER_MAX_STDDEV
+}
+
+fn is_color_page(img: &DynamicImage) -> bool {
+    let rgb = img.to_rgb8();
+    let step = ((rgb.width() as u64 * rgb.height() as u64) / 10_000).max(1) as usize;
+
+    let (mut sampled, mut colored) = (0usize, 0usize);
+    for pixel in rgb.pixels().step_by(step) {
+        let [r, g, b] = pixel.0;
+        let max = r.max(g).max(b) as f64;
+        let min = r.min(g).min(b) as f64;
+        sampled += 1;
+        if max > 0.0 && (max - min) / max > COLOR_SATURATION {
+            colored += 1;
+        }
+    }
+    sampled > 0 && colored as f64 / sampled as f64 > COLOR_PAGE_RATIO
+}
+
+fn adjust_levels(img: &DynamicImage, options: &LevelsOptions) -> DynamicImage {
+    let (black, white) = match options.levels {
+        Levels::Fixed(black, white) => (black, white),
+        Levels::Auto => auto_levels(img),
+    };
+
+    let range = (white.saturating_sub(black)).max(1) as f64;
+    let mut table = [0u8; 256];
+    for (value, entry) in table.iter_mut().enumerate() {
+        let normalized = ((value as f64 - black as f64) / range).clamp(0.0, 1.0);
+        *entry = (normalized.powf(1.0 / options.gamma) * 255.0).round() as u8;
+    }
+
+    match img {
+        DynamicImage::ImageLuma8(gray) => {
+            let mut gray = gray.clone();
+            gray.pixels_mut().for_each(|p| p[0] = table[p[0] as usize]);
+            DynamicImage::ImageLuma8(gray)
+        }
+        // Transparency is kept as it was.
+        DynamicImage::ImageLumaA8(gray) => {
+            let mut gray = gray.clone();
+            gray.pixels_mut()
+                .for_each(|p| p.apply_without_alpha(|channel| table[channel as usize]));
+            DynamicImage::ImageLumaA8(gray)
+        }
+        _ if img.color().has_alpha() => {
+            let mut rgba = img.to_rgba8();
+            rgba.pixels_mut()
+                .for_each(|p| p.apply_without_alpha(|channel| table[channel as usize]));
+            DynamicImage::ImageRgba8(rgba)
+        }
+        _ => {
+            let mut rgb = img.to_rgb8();
+            rgb.pixels_mut()
+                .for_each(|p| p.apply(|channel| table[channel as usize]));
+            DynamicImage::ImageRgb8(rgb)
+        }
+    }
+}
+
+// Picks black and white points from the luma histogram, clipping a small share
+// of outliers at each end.
+fn auto_levels(img: &DynamicImage) -> (u8, u8) {
+    let luma = img.to_luma8();
+    let mut histogram = [0u64; 256];
+    for pixel in luma.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+
+    let total: u64 = histogram.iter().sum();
+    let clip = (total as f64 * AUTOCONTRAST_CLIP) as u64;
+    let cutoff = |values: &mut dyn Iterator<Item = usize>| {
+        let mut seen = 0;
+        for value in values {
+            seen += histogram[value];
+            if seen > clip {
+                return value as u8;
+            }
+        }
+        0
+    };
+    let black = cutoff(&mut (0..256));
+    let white = cutoff(&mut (0..256).rev());
+
+    if white <= black {
+        (0, 255)
+    } else {
+        (black, white)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exif::tests::tagged_jpeg;
+    use crate::testdir::TestDir;
+    use image::{GenericImageView, GrayAlphaImage, LumaA, Rgb, RgbImage, Rgba, RgbaImage};
+
+    fn options(exif_rotate: bool) -> ProcessOptions {
+        ProcessOptions {
+            trim: None,
+            levels: None,
+            exif_rotate,
+        }
+    }
+
+    // 64x32 in red, green, blue and white quarters, clockwise from the top
+    // left, big enough for JPEG to keep the colours.
+    fn quarters() -> RgbImage {
+        RgbImage::from_fn(64, 32, |x, y| match (x < 32, y < 16) {
+            (true, true) => Rgb([255, 0, 0]),
+            (false, true) => Rgb([0, 255, 0]),
+            (false, false) => Rgb([0, 0, 255]),
+            (true, false) => Rgb([255, 255, 255]),
+        })
+    }
+
+    fn near(pixel: &Rgb<u8>, expected: [u8; 3]) -> bool {
+        pixel
+            .0
+            .iter()
+            .zip(expected)
+            .all(|(a, b)| a.abs_diff(b) < 48)
+    }
+
+    #[test]
+    fn levels_leave_transparency_alone() {
+        let options = LevelsOptions {
+            levels: Levels::Fixed(50, 200),
+            gamma: 1.0,
+            force_color: true,
+        };
+        let rgba = RgbaImage::from_fn(2, 1, |x, _| Rgba([50, 125, 200, 64 * x as u8 + 10]));
+        let adjusted = adjust_levels(&DynamicImage::ImageRgba8(rgba), &options);
+        let DynamicImage::ImageRgba8(adjusted) = adjusted else {
+            panic!("not RGBA");
+        };
+        assert_eq!(adjusted.get_pixel(0, 0).0, [0, 128, 255, 10]);
+        assert_eq!(adjusted.get_pixel(1, 0).0, [0, 128, 255, 74]);
+
+        let gray = GrayAlphaImage::from_pixel(1, 1, LumaA([200, 30]));
+        let adjusted = adjust_levels(&DynamicImage::ImageLumaA8(gray), &options);
+        let DynamicImage::ImageLumaA8(adjusted) = adjusted else {
+            panic!("not gray with alpha");
+        };
+        assert_eq!(adjusted.get_pixel(0, 0).0, [255, 30]);
+
+        let rgb = RgbImage::from_pixel(1, 1, Rgb([50, 125, 200]));
+        let adjusted = adjust_levels(&DynamicImage::ImageRgb8(rgb), &options);
+        assert_eq!(adjusted.to_rgb8().get_pixel(0, 0).0, [0, 128, 255]);
+        assert!(!adjusted.color().has_alpha());
+    }
+
+    #[test]
+    fn tagged_pages_are_turned_and_lose_the_tag() {
+        let dir = TestDir::new("exif-rotate");
+        let path = dir.join("1.jpg");
+        // Stored on its side: shown turned 90 degrees clockwise.
+        fs::write(&path, tagged_jpeg(&quarters(), 6, false)).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        process_page(1, &path, &options(true)).unwrap();
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(exif::orientation(&data), None);
+        let upright = image::load_from_memory(&data).unwrap().to_rgb8();
+        assert_eq!(upright.dimensions(), (32, 64));
+        // The stored top left, red, is now at the top right.
+        assert!(near(upright.get_pixel(24, 8), [255, 0, 0]));
+        assert!(near(upright.get_pixel(24, 56), [0, 255, 0]));
+        assert!(near(upright.get_pixel(8, 56), [0, 0, 255]));
+        assert!(near(upright.get_pixel(8, 8), [255, 255, 255]));
+        // The download is kept as it was.
+        let original = fs::read(original_path(&path)).unwrap();
+        assert_eq!(exif::orientation(&original), Some(6));
+    }
+
+    #[test]
+    fn untagged_pages_and_no_exif_rotate_leave_pages_alone() {
+        let dir = TestDir::new("exif-untouched");
+        for (name, orientation, exif_rotate) in [("1.jpg", 1, true), ("2.jpg", 6, false)] {
+            let path = dir.join(name);
+            let data = tagged_jpeg(&quarters(), orientation, true);
+            fs::write(&path, &data).unwrap();
+            let path = path.to_string_lossy().into_owned();
+            process_page(1, &path, &options(exif_rotate)).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), data);
+            assert!(!original_path(&path).exists());
+        }
+        let upright = image::open(dir.join("2.jpg")).unwrap();
+        assert_eq!(upright.dimensions(), (64, 32));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::report::format_bytes;
+use serde::Serialize;
+use std::time::Duration;
+
+// Pages listed as the slowest in the --profile-run summary.
+const SLOWEST_SHOWN: usize = 5;
+
+// Where the time of one page went, in milliseconds. Network times are missing
+// for pages answered from the HTTP cache, and processing for runs without it.
+// The HTTP client doesn't expose DNS and connect times, so they are part of
+// first_byte.
+#[derive(Serialize, Clone)]
+pub struct PageTiming {
+    pub chapter: String,
+    pub page: usize,
+    // Position in the run's page sequence, which processing reports by.
+    #[serde(skip)]
+    pub sequence: usize,
+    pub bytes: u64,
+    pub first_byte_ms: Option<f64>,
+    pub transfer_ms: Option<f64>,
+    pub write_ms: f64,
+    pub process_ms: Option<f64>,
+    pub total_ms: f64,
+}
+
+#[derive(Serialize)]
+pub struct Profile {
+    pub stages: Vec<StageSummary>,
+    pub slowest: Vec<PageTiming>,
+    pub pages: Vec<PageTiming>,
+}
+
+#[derive(Serialize)]
+pub struct StageSummary {
+    pub stage: &'static str,
+    pub pages: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl PageTiming {
+    pub fn update_total(&mut self) {
+        self.total_ms = self.first_byte_ms.unwrap_or(0.0)
+            + self.transfer_ms.unwrap_or(0.0)
+            + self.write_ms
+            + self.process_ms.unwrap_or(0.0);
+    }
+}
+
+impl Profile {
+    pub fn new(mut pages: Vec<PageTiming>) -> Profile {
+        pages.sort_by_key(|timing| timing.sequence);
+        let stages = [
+            (
+                "first byte",
+                pages.iter().map(|t| t.first_byte_ms).collect(),
+            ),
+            ("transfer", pages.iter().map(|t| t.transfer_ms).collect()),
+            ("write", pages.iter().map(|t| Some(t.write_ms)).collect()),
+            ("process", pages.iter().map(|t| t.process_ms).collect()),
+            ("total", pages.iter().map(|t| Some(t.total_ms)).collect()),
+        ]
+        .into_iter()
+        .filter_map(|(stage, times): (&'static str, Vec<Option<f64>>)| {
+            let mut times: Vec<f64> = times.into_iter().flatten().collect();
+            if times.is_empty() {
+                return None;
+            }
+            times.sort_by(f64::total_cmp);
+            Some(StageSummary {
+                stage,
+                pages: times.len(),
+                p50_ms: percentile(&times, 0.50),
+                p95_ms: percentile(&times, 0.95),
+            })
+        })
+        .collect();
+
+        let mut slowest = pages.clone();
+        slowest.sort_by(|a, b| b.total_ms.total_cmp(&a.total_ms));
+        slowest.truncate(SLOWEST_SHOWN);
+        Profile {
+            stages,
+            slowest,
+            pages,
+        }
+    }
+
+    pub fn print(&self) {
+        println!("  Profile:   stage         pages      p50      p95");
+        for stage in &self.stages {
+            println!(
+                "             {:<12} {:>6} {:>6.0}ms {:>6.0}ms",
+                stage.stage, stage.pages, stage.p50_ms, stage.p95_ms
+            );
+        }
+        if !self.slowest.is_empty() {
+            println!("  Slowest:");
+        }
+        for timing in &self.slowest {
+            println!(
+                "    {} page {}: {:.0}ms ({})",
+                timing.chapter,
+                timing.page,
+                timing.total_ms,
+                format_bytes(timing.bytes)
+            );
+        }
+    }
+}
+
+// Nearest-rank percentile of sorted, non-empty `times`.
+pub fn percentile(times: &[f64], fraction: f64) -> f64 {
+    let rank = (fraction * times.len() as f64).ceil() as usize;
+    times[rank.clamp(1, times.len()) - 1]
+}
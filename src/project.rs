@@ -0,0 +1,88 @@
+use crate::config::setting_value;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const PROJECT_FILE: &str = "manga-cli.toml";
+
+// A manga-cli.toml pinning the series and settings of a folder, so everyone
+// running manga-cli in it gets the same files. Its settings take precedence
+// over the config file and per-series settings; flags given on the command
+// line still win.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Project {
+    // The series downloaded when no manga is named.
+    pub url: Option<String>,
+    pub title: Option<String>,
+    // Flags by name, like the config file's [defaults].
+    pub settings: BTreeMap<String, toml::Value>,
+}
+
+// The project file of the current folder or the nearest folder above it.
+pub fn find() -> Result<Option<(PathBuf, Project)>, String> {
+    let current = env::current_dir().map_err(|e| e.to_string())?;
+    let Some(path) = current
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+    else {
+        return Ok(None);
+    };
+    let data = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let project =
+        toml::from_str(&data).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+    Ok(Some((path, project)))
+}
+
+impl Project {
+    // The settings as flag values. A relative output-dir is taken from the
+    // project's folder, wherever in it manga-cli runs.
+    pub fn settings(&self, path: &Path) -> BTreeMap<String, String> {
+        let root = path.parent().unwrap_or_else(|| Path::new(""));
+        self.settings
+            .iter()
+            .map(|(key, value)| {
+                let key = key.replace('_', "-");
+                let value = setting_value(value);
+                match key.as_str() {
+                    "output-dir" if Path::new(&value).is_relative() => {
+                        let dir = root.join(&value).display().to_string();
+                        (key, dir)
+                    }
+                    _ => (key, value),
+                }
+            })
+            .collect()
+    }
+}
+
+// Writes a new project file for the series at `url` into the current folder.
+pub fn write(
+    url: &str,
+    title: &str,
+    settings: &BTreeMap<String, toml::Value>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = PathBuf::from(PROJECT_FILE);
+    let mut project = String::from(concat!(
+        "# Written by `manga-cli project init`. Runs in this folder and below use\n",
+        "# these settings over the config file's; flags given on the command line\n",
+        "# still take precedence. A relative output-dir is from this folder.\n",
+    ));
+    project.push_str(&format!("url = {}\n", toml::Value::from(url)));
+    project.push_str(&format!("title = {}\n", toml::Value::from(title)));
+    project.push_str("\n[settings]\n");
+    for (key, value) in settings {
+        project.push_str(&format!("{} = {}\n", key, value));
+    }
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    file.write_all(project.as_bytes())?;
+    Ok(path)
+}
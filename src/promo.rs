@@ -0,0 +1,125 @@
+use crate::process::original_path;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Cursor;
+
+// Pages under this size are usually banners or "read on our site" notices.
+const TINY_PAGE_BYTES: u64 = 10 * 1024;
+// Manga pages and spreads are never this much wider than tall.
+const MAX_ASPECT_RATIO: f64 = 2.5;
+// How many pages at each end of a chapter are looked at.
+const EDGE_PAGES: usize = 2;
+
+pub struct PromoOptions {
+    // Drop flagged pages instead of only reporting them.
+    pub skip: bool,
+    // SHA-256 hex digests of known promotional pages, from the config file.
+    pub known_hashes: Vec<String>,
+    // Keep trailing app ads instead of dropping them (--keep-ads).
+    pub keep_ads: bool,
+    // Image URLs of ad pages, from the config file.
+    pub ad_url_patterns: Vec<Regex>,
+}
+
+// What marks a page as the site's app ad: its content or its image URL.
+pub struct AdBlocklist {
+    pub hashes: Vec<String>,
+    pub url_patterns: Vec<Regex>,
+}
+
+impl AdBlocklist {
+    // Why the page is an ad, if it is one.
+    pub fn reason(&self, url: &str, data: &[u8]) -> Option<String> {
+        if let Some(pattern) = self
+            .url_patterns
+            .iter()
+            .find(|pattern| pattern.is_match(url))
+        {
+            return Some(format!("app ad, URL matches {}", pattern));
+        }
+        let hash = format!("{:x}", Sha256::digest(data));
+        self.hashes
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(&hash))
+            .then(|| "app ad, matches a known ad page".to_string())
+    }
+
+    // Whether the page at `index` of a chapter with `count` pages is near
+    // enough to the end to be an ad.
+    pub fn in_tail(index: usize, count: usize) -> bool {
+        index + ad_tail(count) >= count
+    }
+}
+
+// How many pages at the end of a chapter may be ads; a chapter is never
+// emptied.
+fn ad_tail(count: usize) -> usize {
+    EDGE_PAGES.min(count.saturating_sub(1))
+}
+
+// The chapter's last pages that are app ads, read from the downloaded
+// `pages` whose image URLs are `urls`. Returns (index, reason) pairs, last
+// page first.
+pub fn trailing_ads(
+    pages: &[String],
+    urls: &[String],
+    blocklist: &AdBlocklist,
+) -> Vec<(usize, String)> {
+    let mut ads = Vec::new();
+    for i in (pages.len() - ad_tail(pages.len())..pages.len()).rev() {
+        let original = original_path(&pages[i]);
+        let Ok(data) = fs::read(&original).or_else(|_| fs::read(&pages[i])) else {
+            break;
+        };
+        match blocklist.reason(&urls[i], &data) {
+            Some(reason) => ads.push((i, reason)),
+            None => break,
+        }
+    }
+    ads
+}
+
+// Flags leading and trailing pages of a chapter that look like injected
+// promotions. Returns (index into `pages`, reason) pairs.
+pub fn suspicious_pages(pages: &[String], known_hashes: &[String]) -> Vec<(usize, String)> {
+    let edge = EDGE_PAGES.min(pages.len() / 2);
+    let mut flagged = Vec::new();
+    let leading = 0..edge;
+    let trailing = (pages.len() - edge..pages.len()).rev();
+    for end in [leading.collect::<Vec<_>>(), trailing.collect()] {
+        for i in end {
+            match promo_reason(&pages[i], known_hashes) {
+                Some(reason) => flagged.push((i, reason)),
+                None => break,
+            }
+        }
+    }
+    flagged.sort();
+    flagged
+}
+
+fn promo_reason(page: &str, known_hashes: &[String]) -> Option<String> {
+    // Judge processed pages by the download they were made from.
+    let original = original_path(page);
+    let data = fs::read(&original).or_else(|_| fs::read(page)).ok()?;
+    let hash = format!("{:x}", Sha256::digest(&data));
+    if known_hashes
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(&hash))
+    {
+        return Some("matches a known promotional page".to_string());
+    }
+    if (data.len() as u64) < TINY_PAGE_BYTES {
+        return Some(format!("only {} bytes", data.len()));
+    }
+    let (width, height) = image::io::Reader::new(Cursor::new(&data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()?;
+    if height > 0 && width as f64 / height as f64 > MAX_ASPECT_RATIO {
+        return Some(format!("unusual {}x{} size", width, height));
+    }
+    None
+}
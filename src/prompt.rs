@@ -0,0 +1,157 @@
+use crate::chapter_id::ChapterId;
+use std::io::{self, BufRead, Write};
+
+// Invalid answers in a row before giving up, so piped garbage can't keep the
+// prompt going forever.
+const MAX_ATTEMPTS: usize = 5;
+
+// Asks until `parse` accepts the answer; an empty answer picks `default` when
+// there is one. Fails when stdin ends or after MAX_ATTEMPTS invalid answers.
+pub fn ask<T>(
+    message: &str,
+    default: Option<T>,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<T, String> {
+    ask_from(&mut io::stdin().lock(), message, default, parse)
+}
+
+// ask() reading the answers from `input`.
+fn ask_from<T>(
+    input: &mut impl BufRead,
+    message: &str,
+    default: Option<T>,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut default = default;
+    for _ in 0..MAX_ATTEMPTS {
+        print!("{}", message);
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) => {
+                println!();
+                return Err("No answer given: input ended.".to_string());
+            }
+            Ok(_) => {}
+            Err(e) => return Err(format!("Failed to read the answer: {}", e)),
+        }
+        let answer = line.trim();
+        if answer.is_empty() {
+            if let Some(default) = default.take() {
+                return Ok(default);
+            }
+        }
+        match parse(answer) {
+            Ok(value) => return Ok(value),
+            Err(e) => println!("Invalid input, {}.", e),
+        }
+    }
+    Err(format!("No valid answer after {} attempts.", MAX_ATTEMPTS))
+}
+
+// Asks a yes/no question, defaulting to yes. Input that ended counts as no.
+pub fn confirm(message: &str) -> bool {
+    ask(&format!("{} [Y/n] ", message), Some(true), parse_yes_no).unwrap_or(false)
+}
+
+// Asks a yes/no question, defaulting to no. Input that ended counts as no.
+pub fn confirm_default_no(message: &str) -> bool {
+    ask(&format!("{} [y/N] ", message), Some(false), parse_yes_no).unwrap_or(false)
+}
+
+// List positions: "3", "03" and "3." all pick the third entry.
+pub fn parse_index(answer: &str) -> Result<usize, String> {
+    answer
+        .trim()
+        .trim_end_matches('.')
+        .parse()
+        .map_err(|_| "please enter a number".to_string())
+}
+
+// Chapter numbers may be decimal, written with a comma too: "12,5" is 12.5.
+pub fn parse_chapter_number(answer: &str) -> Result<ChapterId, String> {
+    let number = answer.trim().replace(',', ".");
+    number
+        .trim_end_matches('.')
+        .parse()
+        .map_err(|_| "please enter a chapter number such as 12 or 12.5".to_string())
+}
+
+fn parse_yes_no(answer: &str) -> Result<bool, String> {
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Err("please answer y or n".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn messy_list_positions() {
+        for (answer, index) in [
+            ("3", Some(3)),
+            (" 03 ", Some(3)),
+            ("1.", Some(1)),
+            ("\t7\r", Some(7)),
+            ("1,5", None),
+            ("1.5", None),
+            ("-1", None),
+            ("three", None),
+            ("", None),
+        ] {
+            assert_eq!(parse_index(answer).ok(), index, "{:?}", answer);
+        }
+    }
+
+    #[test]
+    fn messy_chapter_numbers() {
+        for (answer, number) in [
+            ("12", Some("12")),
+            (" 012 ", Some("12")),
+            ("1.", Some("1")),
+            ("1,5", Some("1.5")),
+            ("12.50", Some("12.5")),
+            ("0", Some("0")),
+            ("1,5,2", None),
+            ("twelve", None),
+            ("", None),
+        ] {
+            let parsed = parse_chapter_number(answer).ok().map(|id| id.to_string());
+            assert_eq!(parsed.as_deref(), number, "{:?}", answer);
+        }
+    }
+
+    #[test]
+    fn answers_after_invalid_ones_and_defaults() {
+        let mut input = Cursor::new("x\n\n 2 \n");
+        assert_eq!(ask_from(&mut input, "? ", None, parse_index), Ok(2));
+        let mut input = Cursor::new("\n");
+        assert_eq!(ask_from(&mut input, "? ", Some(9), parse_index), Ok(9));
+        let mut input = Cursor::new("maybe\nN\n");
+        assert_eq!(
+            ask_from(&mut input, "? ", Some(true), parse_yes_no),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn input_ending_gives_up() {
+        let mut input = Cursor::new("");
+        assert!(ask_from(&mut input, "? ", Some(1), parse_index).is_err());
+        let mut input = Cursor::new("nope\n");
+        assert!(ask_from(&mut input, "? ", None, parse_index).is_err());
+    }
+
+    #[test]
+    fn garbage_pipe_stops_after_the_attempt_cap() {
+        let garbage = "garbage\n".repeat(10_000);
+        let mut input = Cursor::new(garbage.as_bytes());
+        assert!(ask_from(&mut input, "? ", None, parse_chapter_number).is_err());
+        // Only MAX_ATTEMPTS lines were read.
+        assert_eq!(input.position() as usize, MAX_ATTEMPTS * "garbage\n".len());
+    }
+}
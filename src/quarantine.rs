@@ -0,0 +1,165 @@
+use crate::chapter_id::ChapterId;
+use crate::history;
+use crate::series::data_dir;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+const FAILURES_FILE: &str = "failures.json";
+
+// Chapters `watch` failed to download, so later checks try them again until
+// they work or have failed permanently too often and are quarantined.
+#[derive(Serialize, Deserialize, Default)]
+pub struct FailureStore {
+    // By manga URL, then chapter number.
+    series: BTreeMap<String, BTreeMap<String, ChapterFailures>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChapterFailures {
+    pub title: String,
+    // Permanent failures in a row; transient ones (rate limiting, timeouts)
+    // don't count towards quarantine.
+    pub permanent: u32,
+    pub last_error: String,
+    // RFC 3339.
+    pub last_failed: String,
+    // Skipped by `watch` until `manga-cli retry` clears it.
+    pub quarantined: bool,
+}
+
+impl FailureStore {
+    pub fn load() -> FailureStore {
+        fs::read_to_string(failures_path())
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(data_dir())?;
+        let path = failures_path();
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    // Failed chapters of a series still to be tried again, and the
+    // quarantined ones.
+    pub fn chapters(&self, manga_url: &str) -> (Vec<ChapterId>, Vec<ChapterId>) {
+        let mut pending = Vec::new();
+        let mut quarantined = Vec::new();
+        for (number, failures) in self.series.get(manga_url).into_iter().flatten() {
+            let Ok(number) = number.parse() else {
+                continue;
+            };
+            if failures.quarantined {
+                quarantined.push(number);
+            } else {
+                pending.push(number);
+            }
+        }
+        (pending, quarantined)
+    }
+
+    // Counts a failed download and returns whether it put the chapter into
+    // quarantine, which takes `limit` permanent failures in a row.
+    pub fn failed(
+        &mut self,
+        manga_url: &str,
+        title: &str,
+        number: &ChapterId,
+        error: &str,
+        permanent: bool,
+        limit: u32,
+    ) -> bool {
+        let failures = self
+            .series
+            .entry(manga_url.to_string())
+            .or_default()
+            .entry(number.to_string())
+            .or_insert_with(|| ChapterFailures {
+                title: title.to_string(),
+                permanent: 0,
+                last_error: String::new(),
+                last_failed: String::new(),
+                quarantined: false,
+            });
+        failures.permanent = if permanent { failures.permanent + 1 } else { 0 };
+        failures.last_error = error.to_string();
+        failures.last_failed = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+        let quarantine = !failures.quarantined && failures.permanent >= limit;
+        failures.quarantined |= quarantine;
+        quarantine
+    }
+
+    pub fn succeeded(&mut self, manga_url: &str, number: &ChapterId) {
+        if let Some(chapters) = self.series.get_mut(manga_url) {
+            chapters.remove(&number.to_string());
+            if chapters.is_empty() {
+                self.series.remove(manga_url);
+            }
+        }
+    }
+
+    // Adds the failed chapters of `other`, an imported store. When both
+    // know a chapter, the record of the later failure is kept. Returns how
+    // many records were new or newer.
+    pub fn merge(&mut self, other: FailureStore) -> usize {
+        let mut merged = 0;
+        for (url, chapters) in other.series {
+            let ours = self.series.entry(url).or_default();
+            for (number, theirs) in chapters {
+                if ours
+                    .get(&number)
+                    .is_none_or(|ours| history::is_later(&theirs.last_failed, &ours.last_failed))
+                {
+                    ours.insert(number, theirs);
+                    merged += 1;
+                }
+            }
+        }
+        merged
+    }
+
+    // Every failed chapter, by manga URL and chapter number.
+    pub fn all(&self) -> impl Iterator<Item = (&str, &str, &ChapterFailures)> {
+        self.series.iter().flat_map(|(manga_url, chapters)| {
+            chapters
+                .iter()
+                .map(move |(number, failures)| (manga_url.as_str(), number.as_str(), failures))
+        })
+    }
+
+    // Takes a chapter out of quarantine, so the next check tries it again
+    // with a clean count. `series` is the manga URL or (part of) the title.
+    // Returns the series' title.
+    pub fn release(&mut self, series: &str, number: &ChapterId) -> Result<String, String> {
+        let wanted = series.to_lowercase();
+        let key = number.to_string();
+        let mut matches = self.series.iter_mut().filter_map(|(manga_url, chapters)| {
+            let failures = chapters.get_mut(&key)?;
+            (manga_url == series || failures.title.to_lowercase().contains(&wanted))
+                .then_some(failures)
+        });
+        let (Some(failures), None) = (matches.next(), matches.next()) else {
+            return Err(format!(
+                "No single failed chapter {} of \"{}\"; see `manga-cli status`.",
+                number, series
+            ));
+        };
+        failures.permanent = 0;
+        failures.quarantined = false;
+        Ok(failures.title.clone())
+    }
+}
+
+fn failures_path() -> PathBuf {
+    data_dir().join(FAILURES_FILE)
+}
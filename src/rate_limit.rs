@@ -0,0 +1,260 @@
+use crate::config;
+use crate::series::data_dir;
+use crate::ui;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const RATE_LIMITS_FILE: &str = "rate_limits.json";
+// Hosts limiting fewer of the requests than this are left as they are.
+const TARGET_LIMITED: f64 = 0.01;
+// Suggestions ask for this share of the rate a host was seen to accept, so
+// bursts and a busier host still stay under TARGET_LIMITED.
+const HEADROOM: f64 = 0.8;
+// Suggested delays are rounded up to this many milliseconds.
+const DELAY_STEP: u64 = 50;
+// Suggested when the requests came too close together to measure a rate.
+const FALLBACK_DELAY: u64 = 500;
+
+// What a run asks of the hosts it requests from.
+struct Settings {
+    source: String,
+    delay: Duration,
+    // Requests in flight against one host at once.
+    concurrency: usize,
+}
+
+struct Counts {
+    requests: u64,
+    limited: u64,
+    first: Instant,
+    last: Instant,
+}
+
+static SETTINGS: Mutex<Option<Settings>> = Mutex::new(None);
+static COUNTS: Mutex<BTreeMap<String, Counts>> = Mutex::new(BTreeMap::new());
+// When each host may next be requested from, with a delay set.
+static NEXT: Mutex<BTreeMap<String, Instant>> = Mutex::new(BTreeMap::new());
+
+// Requests one run sent a host and how many of them were turned away.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Observed {
+    pub host: String,
+    pub source: String,
+    pub requests: u64,
+    // Answered 429 Too Many Requests or 403 Forbidden, which some sites send
+    // instead.
+    pub limited: u64,
+    // From the first request to the last.
+    pub seconds: f64,
+    pub delay_ms: u64,
+    pub concurrency: usize,
+    // Seconds since the Unix epoch.
+    pub observed_at: u64,
+}
+
+pub struct Suggestion {
+    pub delay_ms: u64,
+    // None when requests already went one at a time.
+    pub jobs: Option<usize>,
+}
+
+// Counts the rest of the run's requests under `source` and waits `delay`
+// between those to the same host, of which `concurrency` may be in flight at
+// once.
+pub fn configure(source: &str, delay: Duration, concurrency: usize) {
+    *SETTINGS.lock().unwrap() = Some(Settings {
+        source: source.to_string(),
+        delay,
+        concurrency,
+    });
+}
+
+// Blocks until `host` may be sent another request under the run's delay.
+pub fn wait(host: &str) {
+    let delay = match &*SETTINGS.lock().unwrap() {
+        Some(settings) if !settings.delay.is_zero() => settings.delay,
+        _ => return,
+    };
+    let now = Instant::now();
+    let start = {
+        let mut next = NEXT.lock().unwrap();
+        let start = next
+            .get(host)
+            .copied()
+            .filter(|at| *at > now)
+            .unwrap_or(now);
+        next.insert(host.to_string(), start + delay);
+        start
+    };
+    thread::sleep(start - now);
+}
+
+// Counts a response `host` sent over the network.
+pub fn observe(host: &str, status: u16) {
+    let now = Instant::now();
+    let mut counts = COUNTS.lock().unwrap();
+    let counts = counts.entry(host.to_string()).or_insert(Counts {
+        requests: 0,
+        limited: 0,
+        first: now,
+        last: now,
+    });
+    counts.requests += 1;
+    counts.last = now;
+    if status == 429 || status == 403 {
+        counts.limited += 1;
+    }
+}
+
+// What was counted since the last call, by host.
+pub fn take() -> Vec<Observed> {
+    let (source, delay, concurrency) = match &*SETTINGS.lock().unwrap() {
+        Some(settings) => (
+            settings.source.clone(),
+            settings.delay,
+            settings.concurrency,
+        ),
+        None => (String::new(), Duration::ZERO, 1),
+    };
+    let observed_at = now();
+    std::mem::take(&mut *COUNTS.lock().unwrap())
+        .into_iter()
+        .map(|(host, counts)| Observed {
+            host,
+            source: source.clone(),
+            requests: counts.requests,
+            limited: counts.limited,
+            seconds: (counts.last - counts.first).as_secs_f64(),
+            delay_ms: delay.as_millis() as u64,
+            concurrency,
+            observed_at,
+        })
+        .collect()
+}
+
+impl Observed {
+    pub fn limited_share(&self) -> f64 {
+        self.limited as f64 / self.requests.max(1) as f64
+    }
+
+    // None when few enough requests were limited. Otherwise the host is taken
+    // to accept what it answered without limiting: the delay suggested spaces
+    // requests to HEADROOM of that rate, and the jobs suggested cut how many
+    // go at once by the share limited and HEADROOM. Either should do.
+    pub fn suggestion(&self) -> Option<Suggestion> {
+        if self.limited == 0 || self.limited_share() < TARGET_LIMITED {
+            return None;
+        }
+        let accepted = self.requests - self.limited;
+        let spacing = if accepted > 1 && self.seconds > 0.0 {
+            self.seconds * 1000.0 / accepted as f64 / HEADROOM
+        } else {
+            FALLBACK_DELAY.max(self.delay_ms * 2) as f64
+        };
+        // Always more than what was already waited.
+        let delay_ms = (spacing.ceil() as u64).max(self.delay_ms + 1);
+        let delay_ms = delay_ms.div_ceil(DELAY_STEP) * DELAY_STEP;
+        let jobs = (self.concurrency > 1).then(|| {
+            let jobs = self.concurrency as f64 * (1.0 - self.limited_share()) * HEADROOM;
+            (jobs.floor() as usize).clamp(1, self.concurrency - 1)
+        });
+        Some(Suggestion { delay_ms, jobs })
+    }
+
+    // "<host> received N rate-limit responses ...", None when there's nothing
+    // to suggest.
+    pub fn hint(&self) -> Option<String> {
+        let suggestion = self.suggestion()?;
+        let jobs = suggestion
+            .jobs
+            .map(|jobs| format!(" or --jobs {}", jobs))
+            .unwrap_or_default();
+        Some(format!(
+            "{} received {} rate-limit response(s) of {} ({:.1}%); consider --delay {}{} for {}",
+            self.host,
+            self.limited,
+            self.requests,
+            self.limited_share() * 100.0,
+            suggestion.delay_ms,
+            jobs,
+            self.source
+        ))
+    }
+}
+
+// The longest delay suggested for each source in `observed`.
+fn suggested_delays(observed: &[Observed]) -> BTreeMap<String, u64> {
+    let mut delays: BTreeMap<String, u64> = BTreeMap::new();
+    for observed in observed
+        .iter()
+        .filter(|observed| !observed.source.is_empty())
+    {
+        if let Some(suggestion) = observed.suggestion() {
+            let delay = delays.entry(observed.source.clone()).or_default();
+            *delay = (*delay).max(suggestion.delay_ms);
+        }
+    }
+    delays
+}
+
+// Saves the delay suggested for each source in `observed` under [delay] in
+// the config file, where later runs pick it up. Returns what was done, to be
+// printed.
+pub fn apply(observed: &[Observed]) -> Vec<String> {
+    let delays = suggested_delays(observed);
+    if delays.is_empty() {
+        return vec!["No rate limiting to tune for; config.toml is left as it was.".to_string()];
+    }
+    let mut done = Vec::new();
+    for (source, delay) in delays {
+        match config::set_value("delay", &source, toml::Value::Integer(delay as i64)) {
+            Ok(path) => done.push(format!(
+                "Set {} = {} under [delay] in {}.",
+                source,
+                delay,
+                path.display()
+            )),
+            Err(e) => ui::error(&format!(
+                "Failed to save the delay suggested for {}: {}",
+                source, e
+            )),
+        }
+    }
+    done
+}
+
+// The latest run's counts for every host seen, for `doctor`.
+pub fn load() -> BTreeMap<String, Observed> {
+    fs::read_to_string(data_dir().join(RATE_LIMITS_FILE))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+// Keeps `observed` in place of what earlier runs saw of the same hosts.
+pub fn save(observed: &[Observed]) -> Result<(), Box<dyn std::error::Error>> {
+    if observed.is_empty() {
+        return Ok(());
+    }
+    let mut stored = load();
+    for observed in observed {
+        stored.insert(observed.host.clone(), observed.clone());
+    }
+    fs::create_dir_all(data_dir())?;
+    fs::write(
+        data_dir().join(RATE_LIMITS_FILE),
+        serde_json::to_string_pretty(&stored)?,
+    )?;
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,75 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Copy-on-write clones: the clone shares the original's storage until one of
+// the two is written to, so it costs no space. btrfs, XFS and APFS have them.
+
+// Clones `from` into `to`, which must not exist yet. Fails with
+// ErrorKind::Unsupported where the platform or filesystem can't, leaving no
+// `to` behind.
+#[cfg(target_os = "linux")]
+pub fn clone(from: &Path, to: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let source = fs::File::open(from)?;
+    let target = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)?;
+    // SAFETY: both descriptors are open for as long as the call runs.
+    let cloned = unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE as _, source.as_raw_fd()) };
+    if cloned == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    drop(target);
+    let _ = fs::remove_file(to);
+    Err(match e.raw_os_error() {
+        // Not supported here, or the two are on different filesystems.
+        Some(libc::EOPNOTSUPP) | Some(libc::EINVAL) | Some(libc::EXDEV) | Some(libc::ENOTTY) => {
+            io::Error::new(io::ErrorKind::Unsupported, e)
+        }
+        _ => e,
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub fn clone(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let (source, target) = (c_path(from)?, c_path(to)?);
+    // SAFETY: both paths are NUL-terminated.
+    if unsafe { libc::clonefile(source.as_ptr(), target.as_ptr(), 0) } == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    Err(match e.raw_os_error() {
+        Some(libc::ENOTSUP) | Some(libc::EXDEV) => io::Error::new(io::ErrorKind::Unsupported, e),
+        _ => e,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn clone(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "no copy-on-write clones on this platform",
+    ))
+}
+
+// Puts a copy of `from` at `to` as cheaply as the filesystem allows: a clone,
+// else with `linkable` a hard link, else a plain copy. A hard link is the
+// same file under a second name, so only pass `linkable` when nothing will
+// write into `to` in place.
+pub fn copy(from: &Path, to: &Path, linkable: bool) -> io::Result<()> {
+    if clone(from, to).is_ok() || (linkable && fs::hard_link(from, to).is_ok()) {
+        return Ok(());
+    }
+    fs::copy(from, to).map(|_| ())
+}
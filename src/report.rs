@@ -0,0 +1,292 @@
+use crate::chapter_id::ChapterId;
+use crate::http;
+use crate::profile::Profile;
+use crate::rate_limit::{self, Observed};
+use crate::ui;
+use serde::Serialize;
+use std::fs;
+use std::time::Duration;
+
+// Outcome of a run, printed as a summary and optionally saved as JSON for
+// scripts that wrap manga-cli.
+#[derive(Serialize, Default)]
+pub struct Report {
+    pub manga: String,
+    pub source: String,
+    // The source's mirror that last answered, for sources with mirrors.
+    pub mirror: Option<String>,
+    pub success: bool,
+    // Why the run stopped, when it failed.
+    pub error: Option<String>,
+    pub chapters: Vec<ChapterReport>,
+    pub pages_downloaded: usize,
+    pub bytes_downloaded: u64,
+    // Everything received from the network, page lists and retries included.
+    pub bytes_transferred: u64,
+    // This month's total once the run was added to it.
+    pub month_transferred: Option<u64>,
+    #[serde(skip)]
+    transferred_before: u64,
+    pub outputs: Vec<String>,
+    // The series and chapters the first output holds, marked read once the
+    // viewer it's handed to exits.
+    #[serde(skip)]
+    pub read: Option<(String, Vec<ChapterId>)>,
+    // The listed chapter after those, for --prefetch.
+    #[serde(skip)]
+    pub next_chapter: Option<ChapterId>,
+    // Leading/trailing pages that looked like promotions.
+    pub flagged_pages: Vec<FlaggedPage>,
+    pub low_data: Option<LowData>,
+    // Per chapter, what --target-size re-encoded the pages with.
+    pub fitted: Vec<String>,
+    // Pages converted by --compat-format.
+    pub transcoded_pages: usize,
+    // Order chapters were downloaded in (--order), for multi-chapter runs.
+    pub order: Option<String>,
+    // Formats built in place of requested ones (--fallback-format).
+    pub format_substitutions: Vec<String>,
+    // Every output already existed (--skip-existing).
+    pub skipped: bool,
+    // Per-page timings, with --profile-run.
+    pub profile: Option<Profile>,
+    // Requests by host and how many were rate limited.
+    pub rate_limits: Vec<Observed>,
+    pub warnings: Vec<String>,
+    // How failures the retries didn't fix were dealt with, and why
+    // (--on-page-failure and friends, or the user's answer).
+    pub decisions: Vec<String>,
+    pub elapsed_seconds: f64,
+}
+
+#[derive(Serialize)]
+pub struct ChapterReport {
+    pub name: String,
+    pub url: String,
+    pub status: ChapterStatus,
+    pub attempts: usize,
+    pub pages: usize,
+    // Set when the chapter's page list couldn't be fetched.
+    pub error: Option<String>,
+    pub failed_pages: Vec<FailedPage>,
+    pub transcoded_pages: usize,
+    // What the chapter's reader said its page count was.
+    pub declared_pages: Option<usize>,
+    // Time spent downloading the chapter, over all attempts.
+    pub seconds: f64,
+    pub bytes_transferred: u64,
+}
+
+impl ChapterReport {
+    // Describes a difference between the images found and the declared
+    // page count.
+    pub fn page_count_mismatch(&self) -> Option<String> {
+        let declared = self.declared_pages?;
+        (declared != self.pages).then(|| {
+            format!(
+                "{}: the reader lists {} pages but {} images were found; some may be missing or ads.",
+                self.name, declared, self.pages
+            )
+        })
+    }
+}
+
+#[derive(Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChapterStatus {
+    Downloaded,
+    // Failed at first, then succeeded in a retry pass.
+    Recovered,
+    // Built without the pages that kept failing (--on-page-failure skip).
+    Partial,
+    Failed,
+}
+
+#[derive(Serialize)]
+pub struct FailedPage {
+    pub page: usize,
+    pub url: String,
+    pub error: String,
+}
+
+#[derive(Serialize)]
+pub struct FlaggedPage {
+    pub chapter: String,
+    pub page: usize,
+    pub reason: String,
+    pub skipped: bool,
+    // An app ad from the blocklist rather than a page that only looks like
+    // a promotion.
+    pub ad: bool,
+}
+
+#[derive(Serialize)]
+pub struct LowData {
+    // False when the source served its own compressed images.
+    pub recompressed: bool,
+    pub saved_percent: Option<f64>,
+    // Negative when --always-reencode made pages larger.
+    pub saved_bytes: i64,
+    // Pages whose re-encoded copy wasn't smaller.
+    pub kept_originals: usize,
+}
+
+impl Report {
+    pub fn new(manga: &str) -> Report {
+        Report {
+            manga: manga.to_string(),
+            transferred_before: http::transferred(),
+            ..Report::default()
+        }
+    }
+
+    pub fn finish(&mut self, elapsed: Duration, error: Option<String>) {
+        self.elapsed_seconds = elapsed.as_secs_f64();
+        self.bytes_transferred = http::transferred() - self.transferred_before;
+        self.rate_limits = rate_limit::take();
+        self.success = error.is_none();
+        self.error = error;
+    }
+
+    pub fn print_summary(&self) {
+        let count = |status: ChapterStatus| {
+            self.chapters
+                .iter()
+                .filter(|chapter| chapter.status == status)
+                .count()
+        };
+        println!();
+        println!("{}", ui::bold("Summary"));
+        println!("  Manga:     {}", ui::bold(&self.manga));
+        println!(
+            "  Chapters:  {} attempted, {} recovered on retry, {} permanently failed",
+            self.chapters.len(),
+            count(ChapterStatus::Recovered),
+            count(ChapterStatus::Failed)
+        );
+        if let Some(order) = &self.order {
+            println!("  Order:     {}", order);
+        }
+        println!(
+            "  Pages:     {} ({})",
+            self.pages_downloaded,
+            format_bytes(self.bytes_downloaded)
+        );
+        match self.month_transferred {
+            Some(month) => println!(
+                "  Transfer:  {} ({} this month)",
+                format_bytes(self.bytes_transferred),
+                format_bytes(month)
+            ),
+            None => println!("  Transfer:  {}", format_bytes(self.bytes_transferred)),
+        }
+        for output in &self.outputs {
+            println!("  Output:    {}", output);
+        }
+        for substitution in &self.format_substitutions {
+            println!("  Format:    {}", substitution);
+        }
+        for decision in &self.decisions {
+            println!("  Decision:  {}", decision);
+        }
+        match &self.low_data {
+            Some(LowData {
+                saved_percent: Some(saved),
+                saved_bytes,
+                kept_originals,
+                ..
+            }) => {
+                let size = format_bytes(saved_bytes.unsigned_abs());
+                println!(
+                    "  Low data:  {} ~{:.0}% ({}) vs full quality, {} page(s) kept as downloaded",
+                    if *saved_bytes < 0 { "grew" } else { "saved" },
+                    saved.abs(),
+                    size,
+                    kept_originals
+                );
+            }
+            Some(_) => println!("  Low data:  compressed images from the source"),
+            None => {}
+        }
+        for fitted in &self.fitted {
+            println!("  Size:      {}", fitted);
+        }
+        if self.transcoded_pages > 0 {
+            println!(
+                "  Converted: {} page(s) for --compat-format",
+                self.transcoded_pages
+            );
+        }
+        let skipped = self
+            .flagged_pages
+            .iter()
+            .filter(|page| page.skipped && !page.ad)
+            .count();
+        if skipped > 0 {
+            println!("  Skipped:   {} promotional page(s)", skipped);
+        }
+        for page in self.flagged_pages.iter().filter(|page| page.ad) {
+            let action = if page.skipped { "Removed" } else { "Kept" };
+            println!(
+                "  {:<10} {} page {} ({})",
+                format!("{}:", action),
+                page.chapter,
+                page.page,
+                page.reason
+            );
+        }
+        for page in self
+            .flagged_pages
+            .iter()
+            .filter(|page| !page.skipped && !page.ad)
+        {
+            println!(
+                "  Check:     {} page {} ({}), use --skip-promo-pages to drop it",
+                page.chapter, page.page, page.reason
+            );
+        }
+        if let Some(profile) = &self.profile {
+            profile.print();
+        }
+        for hint in self.rate_limits.iter().filter_map(Observed::hint) {
+            println!("  Rate limit: {}", ui::caution(&hint));
+        }
+        match self.warnings.len() {
+            0 => println!("  Warnings:  0"),
+            count => println!("  Warnings:  {}", ui::caution(&count.to_string())),
+        }
+        for warning in &self.warnings {
+            println!("    {}", ui::caution(warning));
+        }
+        println!("  Elapsed:   {:.1}s", self.elapsed_seconds);
+        println!(
+            "  Status:    {}",
+            match (self.success, self.skipped) {
+                (true, true) => ui::dim("skipped"),
+                (true, false) => ui::success("ok"),
+                (false, _) => ui::failure("failed"),
+            }
+        );
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let data = serde_json::to_string_pretty(self)?;
+        fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
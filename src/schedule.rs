@@ -0,0 +1,356 @@
+use crate::dates::parse_window;
+use time::{Date, Duration, OffsetDateTime, UtcOffset};
+
+// Cron expressions are parsed here rather than with a cron crate: those work
+// on chrono's types and time zones, while the rest of the tree uses `time`
+// and takes local time from the system through localtime_r().
+
+// No schedule that can fire at all needs longer to (29 February on a
+// weekday does within 28 years, but nobody follows a series like that).
+const SEARCH_LIMIT_DAYS: i64 = 5 * 366;
+
+// When `watch` checks for new chapters: every so often, or at the times a
+// cron expression gives in local time.
+#[derive(Clone)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(Cron),
+}
+
+// A five-field cron expression ("minute hour day month weekday") as bit sets
+// of the values each field allows.
+#[derive(Clone)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // With both day fields restricted, either one matching is enough.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// "6h" or "1d" for a plain interval, or a cron expression such as
+// "0 7,19 * * *" or "@daily".
+pub fn parse_schedule(value: &str) -> Result<Schedule, String> {
+    let value = value.trim();
+    let expression = match value {
+        "@hourly" => "0 * * * *",
+        "@daily" | "@midnight" => "0 0 * * *",
+        "@weekly" => "0 0 * * 0",
+        "@monthly" => "0 0 1 * *",
+        "@yearly" | "@annually" => "0 0 1 1 *",
+        value if !value.contains(' ') => {
+            let interval = parse_window(value)?;
+            if interval < Duration::minutes(1) {
+                return Err("the interval must be at least a minute".to_string());
+            }
+            return Ok(Schedule::Every(interval));
+        }
+        value => value,
+    };
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    let [minutes, hours, days, months, weekdays] = fields[..] else {
+        return Err(format!(
+            "\"{}\" needs five fields: minute hour day month weekday, as in \"0 7 * * *\"",
+            value
+        ));
+    };
+    Ok(Schedule::Cron(Cron {
+        minutes: parse_field(minutes, "minute", 0, 59, &[])?,
+        hours: parse_field(hours, "hour", 0, 23, &[])?,
+        days: parse_field(days, "day", 1, 31, &[])?,
+        months: parse_field(months, "month", 1, 12, MONTHS)?,
+        // Sunday is both 0 and 7.
+        weekdays: {
+            let weekdays = parse_field(weekdays, "weekday", 0, 7, WEEKDAYS)?;
+            (weekdays | weekdays >> 7) & 0x7F
+        },
+        any_day: days == "*",
+        any_weekday: weekdays == "*",
+    }))
+}
+
+// One field: "*", "5", "1-5", "*/15", "10-50/20", or a list of them. `names`
+// stand for the values from `min` on.
+fn parse_field(field: &str, what: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |text: &str| -> Result<u32, String> {
+        let lower = text.to_lowercase();
+        let number = match names.iter().position(|name| *name == lower) {
+            Some(i) => i as u32 + min,
+            None => text
+                .parse()
+                .map_err(|_| format!("\"{}\" isn't a valid {}", text, what))?,
+        };
+        if number < min || number > max {
+            return Err(format!(
+                "{} {} is out of range {}-{}",
+                what, number, min, max
+            ));
+        }
+        Ok(number)
+    };
+
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("\"{}\" isn't a valid {} step", step, what)),
+            },
+            None => (part, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            // "5/10" runs from 5 to the end.
+            None if step > 1 => (value(range)?, max),
+            None => {
+                let value = value(range)?;
+                (value, value)
+            }
+        };
+        if first > last {
+            return Err(format!("{} range {} runs backwards", what, range));
+        }
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Schedule {
+    // The first time after `after` the schedule fires. Cron times are local:
+    // a time clocks skip when they go forward doesn't fire that day, and one
+    // they repeat when they go back fires only the first time.
+    pub fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        match self {
+            Schedule::Every(interval) => Some(after + *interval),
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+impl Cron {
+    fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        self.next_after_in(after, &local_offset)
+    }
+
+    // next_after() with the UTC offset in effect at each time from
+    // `offset_at`.
+    fn next_after_in(
+        &self,
+        after: OffsetDateTime,
+        offset_at: &impl Fn(OffsetDateTime) -> UtcOffset,
+    ) -> Option<OffsetDateTime> {
+        let mut time = after.replace_second(0).ok()?.replace_nanosecond(0).ok()? + Duration::MINUTE;
+        let limit = after + Duration::days(SEARCH_LIMIT_DAYS);
+        while time <= limit {
+            let local = time.to_offset(offset_at(time));
+            if !has(self.months, local.month() as u32) || !self.day_matches(local.date()) {
+                time = next_midnight(local, offset_at)?;
+            } else if !has(self.hours, local.hour() as u32) {
+                time += Duration::minutes(60 - local.minute() as i64);
+            } else if !has(self.minutes, local.minute() as u32) || repeated(time, offset_at) {
+                time += Duration::MINUTE;
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, date: Date) -> bool {
+        let day = has(self.days, date.day() as u32);
+        let weekday = has(
+            self.weekdays,
+            date.weekday().number_days_from_sunday() as u32,
+        );
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+// The start of the local day after `local`'s. Days aren't always 24 hours
+// long, so its midnight is converted back with the offset in effect then.
+fn next_midnight(
+    local: OffsetDateTime,
+    offset_at: &impl Fn(OffsetDateTime) -> UtcOffset,
+) -> Option<OffsetDateTime> {
+    let date = local.date().next_day()?;
+    let guess = date.midnight().assume_offset(local.offset());
+    let midnight = date.midnight().assume_offset(offset_at(guess));
+    // Where the clocks skip midnight, the day starts when they resume.
+    if midnight.to_offset(offset_at(midnight)).date() == date {
+        Some(midnight)
+    } else {
+        Some(guess)
+    }
+}
+
+// Whether the local time at `time` was already shown earlier that night,
+// before the clocks went back.
+fn repeated(time: OffsetDateTime, offset_at: &impl Fn(OffsetDateTime) -> UtcOffset) -> bool {
+    let offset = offset_at(time);
+    let before = offset_at(time - Duration::hours(3));
+    let shift = before.whole_seconds() - offset.whole_seconds();
+    shift > 0 && offset_at(time - Duration::seconds(shift as i64)) == before
+}
+
+// The local UTC offset in effect at `time`, from the system's time zone.
+#[cfg(unix)]
+pub fn local_offset(time: OffsetDateTime) -> UtcOffset {
+    let seconds = time.unix_timestamp() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&seconds, &mut tm) }.is_null() {
+        return UtcOffset::UTC;
+    }
+    UtcOffset::from_whole_seconds(tm.tm_gmtoff as i32).unwrap_or(UtcOffset::UTC)
+}
+
+// Without the system's time zone, cron times are UTC.
+#[cfg(not(unix))]
+pub fn local_offset(_: OffsetDateTime) -> UtcOffset {
+    UtcOffset::UTC
+}
+
+// Like "Sun 2024-05-05 07:00".
+pub fn format_local(time: OffsetDateTime) -> String {
+    let local = time.to_offset(local_offset(time));
+    format!(
+        "{} {}-{:02}-{:02} {:02}:{:02}",
+        &local.weekday().to_string()[..3],
+        local.year(),
+        local.month() as u8,
+        local.day(),
+        local.hour(),
+        local.minute()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+    use time::Month;
+
+    // Europe/Berlin: UTC+2 from 01:00 UTC on the last Sunday of March to
+    // 01:00 UTC on the last Sunday of October, UTC+1 otherwise.
+    fn berlin(time: OffsetDateTime) -> UtcOffset {
+        let last_sunday = |month: Month| {
+            let mut date = Date::from_calendar_date(time.year(), month, 31).unwrap();
+            while date.weekday() != time::Weekday::Sunday {
+                date = date.previous_day().unwrap();
+            }
+            date.with_hms(1, 0, 0).unwrap().assume_utc()
+        };
+        let summer = last_sunday(Month::March) <= time && time < last_sunday(Month::October);
+        UtcOffset::from_hms(if summer { 2 } else { 1 }, 0, 0).unwrap()
+    }
+
+    fn next(expression: &str, after: OffsetDateTime) -> OffsetDateTime {
+        let Schedule::Cron(cron) = parse_schedule(expression).unwrap() else {
+            panic!("{} isn't a cron expression", expression);
+        };
+        let next = cron.next_after_in(after, &berlin).unwrap();
+        next.to_offset(berlin(next))
+    }
+
+    #[test]
+    fn weekly_fire_after_spring_forward_stays_on_its_day() {
+        // The Sunday between has 23 hours.
+        assert_eq!(
+            next("0 0 * * 1", datetime!(2024-03-30 12:00 +1)),
+            datetime!(2024-04-01 00:00 +2)
+        );
+        assert_eq!(
+            next("0 0 * * mon", datetime!(2024-10-26 12:00 +2)),
+            datetime!(2024-10-28 00:00 +1)
+        );
+    }
+
+    #[test]
+    fn daily_midnight_across_both_changes() {
+        assert_eq!(
+            next("@daily", datetime!(2024-03-30 12:00 +1)),
+            datetime!(2024-03-31 00:00 +1)
+        );
+        assert_eq!(
+            next("@daily", datetime!(2024-03-31 00:00 +1)),
+            datetime!(2024-04-01 00:00 +2)
+        );
+        assert_eq!(
+            next("@daily", datetime!(2024-10-27 00:00 +2)),
+            datetime!(2024-10-28 00:00 +1)
+        );
+    }
+
+    #[test]
+    fn skipped_time_doesnt_fire_that_day() {
+        // 02:30 doesn't exist on 31 March.
+        assert_eq!(
+            next("30 2 * * *", datetime!(2024-03-30 03:00 +1)),
+            datetime!(2024-04-01 02:30 +2)
+        );
+        assert_eq!(
+            next("0 * * * *", datetime!(2024-03-31 01:30 +1)),
+            datetime!(2024-03-31 03:00 +2)
+        );
+    }
+
+    #[test]
+    fn repeated_time_fires_once() {
+        let first = next("30 2 * * *", datetime!(2024-10-27 00:00 +2));
+        assert_eq!(first, datetime!(2024-10-27 02:30 +2));
+        assert_eq!(next("30 2 * * *", first), datetime!(2024-10-28 02:30 +1));
+        // Hourly fires at 02:00 once too, then 03:00.
+        assert_eq!(
+            next("0 * * * *", datetime!(2024-10-27 02:00 +2)),
+            datetime!(2024-10-27 03:00 +1)
+        );
+    }
+
+    #[test]
+    fn either_day_field_matches_when_both_are_set() {
+        // The 1st, or any Friday.
+        assert_eq!(
+            next("0 9 1 * fri", datetime!(2024-03-30 12:00 +1)),
+            datetime!(2024-04-01 09:00 +2)
+        );
+        assert_eq!(
+            next("0 9 1 * fri", datetime!(2024-04-01 12:00 +2)),
+            datetime!(2024-04-05 09:00 +2)
+        );
+    }
+
+    #[test]
+    fn intervals_and_bad_expressions() {
+        let Schedule::Every(interval) = parse_schedule("6h").unwrap() else {
+            panic!("6h isn't an interval");
+        };
+        assert_eq!(interval, Duration::hours(6));
+        for bad in [
+            "30s",
+            "0 7 * *",
+            "60 * * * *",
+            "0 7-5 * * *",
+            "*/0 * * * *",
+            "0 7 * foo *",
+        ] {
+            assert!(parse_schedule(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+// Runs download tasks on a fixed pool of workers while capping how many
+// requests may be in flight against any single host at once.
+pub struct Scheduler {
+    jobs: usize,
+    per_host: usize,
+}
+
+struct State<T> {
+    pending: Vec<Option<(String, T)>>,
+    remaining: usize,
+    in_flight: HashMap<String, usize>,
+}
+
+impl Scheduler {
+    pub fn new(jobs: usize, per_host: usize) -> Self {
+        Scheduler {
+            jobs: jobs.max(1),
+            per_host: per_host.max(1),
+        }
+    }
+
+    // Results are returned in the order of `tasks`, not completion order.
+    pub fn run<T, R, F>(&self, tasks: Vec<(String, T)>, work: F) -> Vec<R>
+    where
+        T: Send,
+        R: Send,
+        F: Fn(T) -> R + Sync,
+    {
+        let count = tasks.len();
+        let state = Mutex::new(State {
+            pending: tasks.into_iter().map(Some).collect(),
+            remaining: count,
+            in_flight: HashMap::new(),
+        });
+        let wakeup = Condvar::new();
+        let results: Mutex<Vec<Option<R>>> = Mutex::new((0..count).map(|_| None).collect());
+
+        thread::scope(|scope| {
+            for _ in 0..self.jobs.min(count) {
+                scope.spawn(|| loop {
+                    let (index, host, task) = {
+                        let mut state = state.lock().unwrap();
+                        loop {
+                            if state.remaining == 0 {
+                                return;
+                            }
+                            if let Some(index) = self.next_task(&state) {
+                                let (host, task) = state.pending[index].take().unwrap();
+                                state.remaining -= 1;
+                                *state.in_flight.entry(host.clone()).or_insert(0) += 1;
+                                break (index, host, task);
+                            }
+                            state = wakeup.wait(state).unwrap();
+                        }
+                    };
+
+                    let result = work(task);
+                    results.lock().unwrap()[index] = Some(result);
+
+                    let mut state = state.lock().unwrap();
+                    if let Some(active) = state.in_flight.get_mut(&host) {
+                        *active -= 1;
+                    }
+                    wakeup.notify_all();
+                });
+            }
+        });
+
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|result| result.expect("scheduler finished with an unrun task"))
+            .collect()
+    }
+
+    // Picks the earliest pending task on the least busy host that still has a
+    // free slot, so pages keep roughly in order while hosts share the workers.
+    fn next_task<T>(&self, state: &State<T>) -> Option<usize> {
+        let mut best: Option<(usize, usize)> = None;
+        for (index, entry) in state.pending.iter().enumerate() {
+            let Some((host, _)) = entry else { continue };
+            let active = state.in_flight.get(host).copied().unwrap_or(0);
+            if active >= self.per_host {
+                continue;
+            }
+            if best.is_none_or(|(_, best_active)| active < best_active) {
+                best = Some((index, active));
+            }
+        }
+        best.map(|(index, _)| index)
+    }
+}
+
+pub fn host_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Requests in flight at once, now and at most.
+    #[derive(Default)]
+    struct Load {
+        active: usize,
+        most: usize,
+    }
+
+    // An HTTP server on `ip` answering every request after a pause, keeping
+    // track of how many it handles at once. Returns its URL.
+    fn mock_server(ip: &str, load: Arc<Mutex<Load>>, total: Arc<Mutex<Load>>) -> String {
+        let listener = TcpListener::bind((ip, 0)).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (load, total) = (load.clone(), total.clone());
+                thread::spawn(move || {
+                    let mut line = String::new();
+                    let mut reader = BufReader::new(&stream);
+                    while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+                        line.clear();
+                    }
+                    for load in [&load, &total] {
+                        let mut load = load.lock().unwrap();
+                        load.active += 1;
+                        load.most = load.most.max(load.active);
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                    for load in [&load, &total] {
+                        load.lock().unwrap().active -= 1;
+                    }
+                    let _ = (&stream).write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                    );
+                });
+            }
+        });
+        url
+    }
+
+    // Two hosts need two loopback addresses, which Linux has without setup.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn never_more_than_the_limit_in_flight_per_host() {
+        let total = Arc::new(Mutex::new(Load::default()));
+        let loads = [
+            Arc::new(Mutex::new(Load::default())),
+            Arc::new(Mutex::new(Load::default())),
+        ];
+        let urls = [
+            mock_server("127.0.0.1", loads[0].clone(), total.clone()),
+            mock_server("127.0.0.2", loads[1].clone(), total.clone()),
+        ];
+        let tasks: Vec<(String, (usize, String))> = (0..16)
+            .map(|i| {
+                let url = format!("{}/{}.jpg", urls[i % 2], i);
+                (host_of(&url), (i, url))
+            })
+            .collect();
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let results = Scheduler::new(8, 2).run(tasks, |(i, url)| {
+            let body = client.get(&url).send().unwrap().text().unwrap();
+            (i, body)
+        });
+
+        let expected: Vec<(usize, String)> = (0..16).map(|i| (i, "ok".to_string())).collect();
+        assert_eq!(results, expected);
+        for load in &loads {
+            assert_eq!(load.lock().unwrap().most, 2);
+        }
+        // The hosts were worked on at the same time.
+        assert_eq!(total.lock().unwrap().most, 4);
+    }
+
+    #[test]
+    fn one_job_runs_tasks_in_order() {
+        let order = Mutex::new(Vec::new());
+        let tasks = (0..6).map(|i| (format!("host{}", i % 3), i)).collect();
+        Scheduler::new(1, 2).run(tasks, |i| order.lock().unwrap().push(i));
+        assert_eq!(order.into_inner().unwrap(), vec![0, 1, 2, 3, 4, 5]);
+    }
+}
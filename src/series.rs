@@ -0,0 +1,312 @@
+use crate::chapter_id::ChapterId;
+use crate::history;
+use crate::journal::{self, Store};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+const LAST_SELECTION_FILE: &str = "last.json";
+
+// Settings remembered per series between runs, keyed by manga URL. Kept as
+// a journal::Store that saves the settings changed, so `watch` and a
+// download saving at once keep both their changes unless they changed the
+// same setting.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SeriesStore {
+    series: HashMap<String, SeriesMeta>,
+    // The series as loaded or last saved, to tell what changed.
+    #[serde(skip)]
+    saved: HashMap<String, SeriesMeta>,
+}
+
+// The settings of a series one save changed, by field name.
+#[derive(Serialize, Deserialize)]
+pub struct SeriesChange {
+    url: String,
+    fields: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct SeriesMeta {
+    // Scanlation group used for the last download of this series.
+    pub group: Option<String>,
+    #[serde(default)]
+    pub followed: bool,
+    #[serde(default)]
+    pub title: Option<String>,
+    // Flag values applied whenever this series is downloaded, keyed by the
+    // long flag name, e.g. "format" = "cbz".
+    #[serde(default)]
+    pub overrides: BTreeMap<String, String>,
+    // When `watch` checks the series, overriding its --schedule.
+    #[serde(default)]
+    pub schedule: Option<String>,
+    // The series folder in the cache, when it isn't named after the title:
+    // the folder of the same series from another source it was merged into.
+    #[serde(default)]
+    pub folder: Option<String>,
+    // RFC 3339 time a setting of the series last changed, so an import can
+    // tell which of two values is newer. Set when saving.
+    #[serde(default)]
+    pub modified: Option<String>,
+}
+
+impl Store for SeriesStore {
+    const NAME: &'static str = "series";
+    type Change = SeriesChange;
+
+    fn apply(&mut self, change: SeriesChange) {
+        let meta = self.series.entry(change.url).or_default();
+        let mut value = serde_json::to_value(&*meta).unwrap_or_default();
+        if let Some(fields) = value.as_object_mut() {
+            for (name, changed) in change.fields {
+                match (fields.get_mut(&name), changed) {
+                    (Some(Value::Object(ours)), Value::Object(keys)) => {
+                        for (key, changed) in keys {
+                            if changed.is_null() {
+                                ours.remove(&key);
+                            } else {
+                                ours.insert(key, changed);
+                            }
+                        }
+                    }
+                    (_, changed) => {
+                        fields.insert(name, changed);
+                    }
+                }
+            }
+        }
+        match serde_json::from_value(value) {
+            Ok(changed) => *meta = changed,
+            Err(e) => log::warn!("Skipping a change to the series settings: {}", e),
+        }
+    }
+}
+
+impl SeriesStore {
+    pub fn load() -> SeriesStore {
+        let mut store: SeriesStore = journal::load();
+        store.saved = store.series.clone();
+        store
+    }
+
+    // Saves the series changed since loading, marking them modified now
+    // unless their time was set, as an import does.
+    pub fn save(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc().format(&Rfc3339).ok();
+        for (url, meta) in self.series.iter_mut() {
+            let saved = self.saved.get(url);
+            if meta.modified == saved.and_then(|saved| saved.modified.clone())
+                && !changed_fields(meta, saved).is_empty()
+            {
+                meta.modified = now.clone();
+            }
+        }
+        let mut changes: Vec<SeriesChange> = self
+            .series
+            .iter()
+            .map(|(url, meta)| SeriesChange {
+                url: url.clone(),
+                fields: changed_fields(meta, self.saved.get(url)),
+            })
+            .filter(|change| !change.fields.is_empty())
+            .collect();
+        changes.sort_by(|a, b| a.url.cmp(&b.url));
+        journal::save::<SeriesStore>(changes)?;
+        self.saved = self.series.clone();
+        Ok(())
+    }
+
+    pub fn get(&self, manga_url: &str) -> SeriesMeta {
+        self.series.get(manga_url).cloned().unwrap_or_default()
+    }
+
+    pub fn get_mut(&mut self, manga_url: &str) -> &mut SeriesMeta {
+        self.series.entry(manga_url.to_string()).or_default()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut SeriesMeta)> {
+        self.series.iter_mut()
+    }
+
+    // Adds the series of `other`, an imported store. Settings only one side
+    // has are kept; where both set one differently the value of the side
+    // that changed the series last wins, this store's when that can't be
+    // told, and the difference is returned.
+    pub fn merge(&mut self, other: SeriesStore) -> (usize, Vec<String>) {
+        let mut added = 0;
+        let mut conflicts = Vec::new();
+        let mut others: Vec<(String, SeriesMeta)> = other.series.into_iter().collect();
+        others.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (url, theirs) in others {
+            let Some(ours) = self.series.get_mut(&url) else {
+                self.series.insert(url, theirs);
+                added += 1;
+                continue;
+            };
+            let theirs_newer = history::is_later(
+                theirs.modified.as_deref().unwrap_or_default(),
+                ours.modified.as_deref().unwrap_or_default(),
+            );
+            let kept = if theirs_newer {
+                "the export's"
+            } else {
+                "this one's"
+            };
+            let name = ours.title.clone().or(theirs.title.clone()).unwrap_or(url);
+            let mut differs = |setting: &str, ours: &str, theirs: &str| {
+                conflicts.push(format!(
+                    "{}: {} is {} here, {} in the export; kept {}",
+                    name, setting, ours, theirs, kept
+                ))
+            };
+            if theirs_newer {
+                ours.followed = theirs.followed;
+                ours.modified = theirs.modified;
+            }
+            for (setting, ours, theirs) in [
+                ("title", &mut ours.title, theirs.title),
+                ("group", &mut ours.group, theirs.group),
+                ("schedule", &mut ours.schedule, theirs.schedule),
+                ("folder", &mut ours.folder, theirs.folder),
+            ] {
+                match (ours.as_ref(), theirs) {
+                    (None, theirs) => *ours = theirs,
+                    (Some(value), Some(theirs)) if *value != theirs => {
+                        differs(setting, value, &theirs);
+                        if theirs_newer {
+                            *ours = Some(theirs);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            for (flag, value) in theirs.overrides {
+                match ours.overrides.get(&flag) {
+                    None => {
+                        ours.overrides.insert(flag, value);
+                    }
+                    Some(ours_value) if *ours_value != value => {
+                        differs(&format!("--{}", flag), ours_value, &value);
+                        if theirs_newer {
+                            ours.overrides.insert(flag, value);
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        (added, conflicts)
+    }
+
+    // Followed series by manga URL, sorted by title.
+    pub fn followed(&self) -> Vec<(String, SeriesMeta)> {
+        let mut followed: Vec<(String, SeriesMeta)> = self
+            .series
+            .iter()
+            .filter(|(_, meta)| meta.followed)
+            .map(|(url, meta)| (url.clone(), meta.clone()))
+            .collect();
+        followed.sort_by(|(a_url, a), (b_url, b)| {
+            (a.title.as_ref(), a_url).cmp(&(b.title.as_ref(), b_url))
+        });
+        followed
+    }
+}
+
+// The fields of `meta` that differ from `saved`, or from the defaults for a
+// series new to this store. Maps, like the overrides, only hold the keys that
+// changed, with null for those removed, so two runs setting different flags
+// keep both.
+fn changed_fields(meta: &SeriesMeta, saved: Option<&SeriesMeta>) -> Map<String, Value> {
+    let saved = serde_json::to_value(saved.cloned().unwrap_or_default()).unwrap_or_default();
+    let Value::Object(fields) = serde_json::to_value(meta).unwrap_or_default() else {
+        return Map::new();
+    };
+    let mut changed = Map::new();
+    for (name, value) in fields {
+        match (value, saved.get(&name)) {
+            (Value::Object(now), Some(Value::Object(before))) => {
+                let mut keys: Map<String, Value> = now
+                    .iter()
+                    .filter(|(key, value)| before.get(*key) != Some(value))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                for key in before.keys().filter(|key| !now.contains_key(*key)) {
+                    keys.insert(key.clone(), Value::Null);
+                }
+                if !keys.is_empty() {
+                    changed.insert(name, Value::Object(keys));
+                }
+            }
+            (value, before) if before != Some(&value) => {
+                changed.insert(name, value);
+            }
+            _ => {}
+        }
+    }
+    changed
+}
+
+// What the previous run downloaded, offered again by --again.
+#[derive(Serialize, Deserialize)]
+pub struct LastSelection {
+    // Source::name() of the source the manga was found on.
+    pub source: String,
+    pub query: String,
+    pub manga_url: String,
+    pub title: String,
+    // Last chapter downloaded, in reading order.
+    pub chapter: Option<ChapterId>,
+}
+
+impl LastSelection {
+    pub fn load() -> Option<LastSelection> {
+        let data = fs::read_to_string(data_dir().join(LAST_SELECTION_FILE)).ok()?;
+        serde_json::from_str(&data).ok()
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = data_dir();
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join(LAST_SELECTION_FILE),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn clear() {
+        let _ = fs::remove_file(data_dir().join(LAST_SELECTION_FILE));
+    }
+
+    // The chapter after the last one downloaded.
+    pub fn next_chapter(&self) -> Option<ChapterId> {
+        Some(self.chapter.as_ref()?.next_whole())
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    // Where data_dir() points on this thread, set by TestDir::use_as_data_dir.
+    pub static TEST_DATA_DIR: std::cell::RefCell<Option<PathBuf>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+// $XDG_DATA_HOME/manga-cli, falling back to ~/.local/share/manga-cli.
+pub fn data_dir() -> PathBuf {
+    #[cfg(test)]
+    if let Some(dir) = TEST_DATA_DIR.with(|dir| dir.borrow().clone()) {
+        return dir;
+    }
+    let base = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("manga-cli")
+}
@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const SERIES_FILE: &str = "series.json";
+
+// The "metadata" object of series.json, in the Mylar layout Komga reads series
+// metadata from. Komga ignores the fields after `status`. Keys listed in
+// `locked` were edited by hand and are left alone when the file is updated.
+#[derive(Serialize, Deserialize)]
+pub struct SeriesMetadata {
+    // Always "comicSeries".
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub publisher: String,
+    pub name: String,
+    pub comicid: String,
+    // Of the oldest chapter known.
+    pub year: i32,
+    pub description_text: Option<String>,
+    pub booktype: String,
+    pub total_issues: usize,
+    pub publication_run: String,
+    // "Continuing" or "Ended".
+    pub status: String,
+    #[serde(default)]
+    pub alt_titles: Vec<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub source_url: String,
+    #[serde(default)]
+    pub locked: Vec<String>,
+}
+
+// Komga only knows the two states.
+pub fn status(site_status: Option<&str>) -> String {
+    let status = site_status.unwrap_or_default().to_lowercase();
+    if ["complete", "ended", "finished"]
+        .iter()
+        .any(|word| status.contains(word))
+    {
+        "Ended".to_string()
+    } else {
+        "Continuing".to_string()
+    }
+}
+
+// The "metadata" object of series.json in `dir`, if there is one.
+pub fn metadata(dir: &Path) -> Option<Map<String, Value>> {
+    fs::read_to_string(dir.join(SERIES_FILE))
+        .ok()
+        .and_then(|data| existing_metadata(&data))
+}
+
+// The series' name and alternative titles from series.json in `dir`.
+pub fn titles(dir: &Path) -> Vec<String> {
+    let Some(metadata) = metadata(dir) else {
+        return Vec::new();
+    };
+    let mut titles: Vec<String> = metadata
+        .get("name")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .into_iter()
+        .collect();
+    if let Some(Value::Array(alt_titles)) = metadata.get("alt_titles") {
+        titles.extend(
+            alt_titles
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string),
+        );
+    }
+    titles
+}
+
+// Writes `metadata` to series.json in `dir`. Locked keys and keys this crate
+// doesn't write keep the values of the existing file; a file that doesn't
+// parse is left untouched rather than losing someone's edits.
+pub fn update(dir: &Path, metadata: &SeriesMetadata) -> Result<(), Box<dyn std::error::Error>> {
+    let path = dir.join(SERIES_FILE);
+    let mut merged = match fs::read_to_string(&path) {
+        Ok(data) => existing_metadata(&data)
+            .ok_or_else(|| format!("{} isn't series metadata, not updating it", path.display()))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Map::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let locked: Vec<String> = merged
+        .get("locked")
+        .and_then(|locked| serde_json::from_value(locked.clone()).ok())
+        .unwrap_or_default();
+    if let Value::Object(fresh) = serde_json::to_value(metadata)? {
+        for (key, value) in fresh {
+            if !merged.contains_key(&key) || (key != "locked" && !locked.contains(&key)) {
+                merged.insert(key, value);
+            }
+        }
+    }
+
+    fs::create_dir_all(dir)?;
+    let data = serde_json::to_string_pretty(&json!({ "metadata": merged }))?;
+    fs::write(&path, data)?;
+    Ok(())
+}
+
+fn existing_metadata(data: &str) -> Option<Map<String, Value>> {
+    match serde_json::from_str::<Value>(data)
+        .ok()?
+        .get_mut("metadata")?
+        .take()
+    {
+        Value::Object(metadata) => Some(metadata),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    // A file as --series-json writes it, then edited by hand: a publisher
+    // and a description of its own, both locked.
+    const FIXTURE: &str = include_str!("../tests/fixtures/komga/series.json");
+
+    fn fixture() -> SeriesMetadata {
+        let metadata = existing_metadata(FIXTURE).unwrap();
+        serde_json::from_value(Value::Object(metadata)).unwrap()
+    }
+
+    #[test]
+    fn fixture_round_trips() {
+        let metadata = fixture();
+        assert_eq!(metadata.kind, "comicSeries");
+        assert_eq!(metadata.alt_titles[0], "フィクスチャー物語");
+        assert_eq!(metadata.locked, ["publisher", "description_text"]);
+
+        let dir = TestDir::new("series-json");
+        update(&dir.path, &metadata).unwrap();
+        let written = fs::read_to_string(dir.join(SERIES_FILE)).unwrap();
+        let written: Value = serde_json::from_str(&written).unwrap();
+        let expected: Value = serde_json::from_str(FIXTURE).unwrap();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn fields_komga_needs_are_enough() {
+        // As Komga or another tool writes it, without this crate's fields.
+        let data = r#"{"metadata": {"type": "comicSeries", "publisher": "", "name": "X",
+            "comicid": "x", "year": 2020, "description_text": null, "booktype": "Print",
+            "total_issues": 1, "publication_run": "", "status": "Ended"}}"#;
+        let metadata: SeriesMetadata =
+            serde_json::from_value(Value::Object(existing_metadata(data).unwrap())).unwrap();
+        assert!(metadata.alt_titles.is_empty() && metadata.locked.is_empty());
+        assert_eq!(metadata.description_text, None);
+    }
+
+    #[test]
+    fn updates_keep_locked_and_unknown_keys() {
+        let dir = TestDir::new("series-json-update");
+        let mut edited: Value = serde_json::from_str(FIXTURE).unwrap();
+        edited["metadata"]["comic_image"] = json!("cover.jpg");
+        fs::write(dir.join(SERIES_FILE), edited.to_string()).unwrap();
+
+        let fresh = SeriesMetadata {
+            publisher: String::new(),
+            description_text: Some("The site's blurb.".to_string()),
+            total_issues: 4,
+            status: status(Some("Completed")),
+            locked: Vec::new(),
+            ..fixture()
+        };
+        update(&dir.path, &fresh).unwrap();
+        let stored = metadata(&dir.path).unwrap();
+        assert_eq!(stored["publisher"], "Fixture Press");
+        assert_eq!(
+            stored["description_text"],
+            "A series that exists for tests."
+        );
+        assert_eq!(stored["total_issues"], 4);
+        assert_eq!(stored["status"], "Ended");
+        assert_eq!(stored["comic_image"], "cover.jpg");
+        assert_eq!(stored["locked"], json!(["publisher", "description_text"]));
+    }
+
+    #[test]
+    fn unreadable_files_are_left_alone() {
+        let dir = TestDir::new("series-json-broken");
+        let path = dir.join(SERIES_FILE);
+        fs::write(&path, "{\"metadata\": [").unwrap();
+        assert!(update(&dir.path, &fixture()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"metadata\": [");
+        assert!(titles(&dir.path).is_empty());
+    }
+
+    #[test]
+    fn titles_are_the_name_then_alternatives() {
+        let dir = TestDir::new("series-json-titles");
+        fs::write(dir.join(SERIES_FILE), FIXTURE).unwrap();
+        assert_eq!(
+            titles(&dir.path),
+            ["Fixture Tales", "フィクスチャー物語", "Tales of Fixtures"]
+        );
+    }
+}
@@ -0,0 +1,626 @@
+use crate::workdir::{TMP_DIR, UNFINISHED_SUFFIX};
+use base64::Engine;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+// How often the accept loop looks for Ctrl-C while no one connects.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+// Connections handled at once. Further ones wait in the listen backlog until
+// a handler finishes, looked at this often.
+const MAX_CONNECTIONS: usize = 16;
+const BUSY_INTERVAL: Duration = Duration::from_millis(20);
+// Requests whose headers don't arrive within this are dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_HEADER_LINES: usize = 100;
+// Longest request or header line read; longer ones are refused with 431.
+const MAX_LINE_BYTES: u64 = 8 * 1024;
+const FILES_PREFIX: &str = "/files/";
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+pub struct ServeOptions {
+    pub library_dir: PathBuf,
+    pub address: SocketAddr,
+    // Required with any user name when set (config `serve_password`).
+    pub password: Option<String>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Body,
+}
+
+enum Body {
+    Text(String),
+    // `length` bytes from the file's current position.
+    File { file: fs::File, length: u64 },
+}
+
+// Serves the CBZ and PDF files under `library_dir` read-only until Ctrl-C,
+// letting requests in progress finish.
+pub fn run(options: &ServeOptions) -> io::Result<()> {
+    if !options.library_dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a directory", options.library_dir.display()),
+        ));
+    }
+    let listener = TcpListener::bind(options.address)?;
+    listener.set_nonblocking(true)?;
+    catch_interrupt();
+    println!(
+        "Serving {} on http://{}/ (Ctrl-C stops)",
+        options.library_dir.display(),
+        listener.local_addr()?
+    );
+
+    let active = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        while !STOP.load(Ordering::Relaxed) {
+            if active.load(Ordering::Acquire) >= MAX_CONNECTIONS {
+                thread::sleep(BUSY_INTERVAL);
+                continue;
+            }
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    active.fetch_add(1, Ordering::AcqRel);
+                    let active = &active;
+                    scope.spawn(move || {
+                        if let Err(e) = handle(stream, peer, options) {
+                            log::debug!("{}: {}", peer, e);
+                        }
+                        active.fetch_sub(1, Ordering::AcqRel);
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(e) => log::warn!("Failed to accept a connection: {}", e),
+            }
+        }
+        println!("Stopping; waiting for downloads in progress.");
+    });
+    Ok(())
+}
+
+#[cfg(unix)]
+fn catch_interrupt() {
+    extern "C" fn stop(_: libc::c_int) {
+        STOP.store(true, Ordering::Relaxed);
+    }
+    // SAFETY: the handler only stores to an atomic, which is signal-safe.
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            stop as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn catch_interrupt() {}
+
+fn handle(stream: TcpStream, peer: SocketAddr, options: &ServeOptions) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader) {
+        Err(e) if e.get_ref().is_some_and(|inner| inner.is::<LineTooLong>()) => {
+            send(stream, text(431, "Request header fields too large"), false)
+        }
+        Err(e) => return Err(e),
+        Ok(Some(request)) => {
+            let response = respond(&request, options);
+            log::debug!(
+                "{} {} {} {}",
+                peer,
+                request.method,
+                request.path,
+                response.status
+            );
+            send(stream, response, request.method == "HEAD")
+        }
+        Ok(None) => send(stream, text(400, "Bad request"), false),
+    };
+    match response {
+        // Readers cancelling a download isn't worth reporting.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+// None for requests that aren't HTTP. A line longer than MAX_LINE_BYTES
+// fails with LineTooLong.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    read_line(reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut headers = Vec::new();
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        if read_line(reader, &mut line)? == 0 || line.trim().is_empty() {
+            return Ok(Some(Request {
+                method,
+                path,
+                headers,
+            }));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    Ok(None)
+}
+
+// read_line() stopping at MAX_LINE_BYTES, so a client can't grow a line
+// without end.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    let read = reader.by_ref().take(MAX_LINE_BYTES).read_line(line)?;
+    if read as u64 == MAX_LINE_BYTES && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, LineTooLong));
+    }
+    Ok(read)
+}
+
+#[derive(Debug)]
+struct LineTooLong;
+
+impl std::fmt::Display for LineTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "request line longer than {} bytes", MAX_LINE_BYTES)
+    }
+}
+
+impl std::error::Error for LineTooLong {}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn respond(request: &Request, options: &ServeOptions) -> Response {
+    if !matches!(request.method.as_str(), "GET" | "HEAD") {
+        let mut response = text(405, "Only GET and HEAD are served");
+        response
+            .headers
+            .push(("Allow".to_string(), "GET, HEAD".to_string()));
+        return response;
+    }
+    if let Some(password) = &options.password {
+        if !authorized(request, password) {
+            let mut response = text(401, "Password required");
+            response.headers.push((
+                "WWW-Authenticate".to_string(),
+                "Basic realm=\"manga-cli\"".to_string(),
+            ));
+            return response;
+        }
+    }
+    let path = request.path.split('?').next().unwrap_or_default();
+    if path == "/" {
+        return match index(&options.library_dir) {
+            Ok(html) => Response {
+                status: 200,
+                headers: vec![(
+                    "Content-Type".to_string(),
+                    "text/html; charset=utf-8".to_string(),
+                )],
+                body: Body::Text(html),
+            },
+            Err(e) => text(500, &format!("Failed to list the library: {}", e)),
+        };
+    }
+    let Some(relative) = path
+        .strip_prefix(FILES_PREFIX)
+        .and_then(percent_decode)
+        .and_then(|relative| library_file(&options.library_dir, &relative))
+    else {
+        return text(404, "Not found");
+    };
+    match serve_file(&relative, request.header("range")) {
+        Ok(response) => response,
+        Err(e) => text(500, &format!("Failed to read the file: {}", e)),
+    }
+}
+
+// Any user name is accepted; only the password is checked.
+fn authorized(request: &Request, password: &str) -> bool {
+    let Some(encoded) = request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Basic "))
+    else {
+        return false;
+    };
+    let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(encoded.trim()) else {
+        return false;
+    };
+    String::from_utf8_lossy(&decoded)
+        .split_once(':')
+        .is_some_and(|(_, given)| same_secret(given.as_bytes(), password.as_bytes()))
+}
+
+// Compares every byte whatever the first difference, so the time taken
+// doesn't tell how much of a guess was right.
+fn same_secret(given: &[u8], expected: &[u8]) -> bool {
+    let mut difference = given.len() ^ expected.len();
+    for (i, byte) in expected.iter().enumerate() {
+        difference |= (given.get(i).copied().unwrap_or(0) ^ byte) as usize;
+    }
+    difference == 0
+}
+
+// The file a request path names, when it's a served file inside the library.
+// Symlinks are followed only as far as they stay inside it.
+fn library_file(library_dir: &Path, relative: &str) -> Option<PathBuf> {
+    let relative = Path::new(relative);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        || !is_served(relative)
+    {
+        return None;
+    }
+    let path = library_dir.join(relative).canonicalize().ok()?;
+    let library_dir = library_dir.canonicalize().ok()?;
+    (path.starts_with(&library_dir) && path.is_file()).then_some(path)
+}
+
+fn is_served(path: &Path) -> bool {
+    mime_type(path).is_some() && !path.to_string_lossy().ends_with(UNFINISHED_SUFFIX)
+}
+
+fn mime_type(path: &Path) -> Option<&'static str> {
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+        "cbz" => Some("application/vnd.comicbook+zip"),
+        "pdf" => Some("application/pdf"),
+        _ => None,
+    }
+}
+
+// The whole file, or the byte range asked for with a Range header. Only
+// single ranges are supported; other Range values get the whole file.
+fn serve_file(path: &Path, range: Option<&str>) -> io::Result<Response> {
+    let mut file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut headers = vec![
+        (
+            "Content-Type".to_string(),
+            mime_type(path)
+                .unwrap_or("application/octet-stream")
+                .to_string(),
+        ),
+        ("Accept-Ranges".to_string(), "bytes".to_string()),
+    ];
+    let (status, start, length) = match range.map(|range| parse_range(range, size)) {
+        Some(Some(Some((start, end)))) => {
+            headers.push((
+                "Content-Range".to_string(),
+                format!("bytes {}-{}/{}", start, end, size),
+            ));
+            (206, start, end - start + 1)
+        }
+        Some(Some(None)) => {
+            let mut response = text(416, "Range not satisfiable");
+            response
+                .headers
+                .push(("Content-Range".to_string(), format!("bytes */{}", size)));
+            return Ok(response);
+        }
+        _ => (200, 0, size),
+    };
+    file.seek(SeekFrom::Start(start))?;
+    Ok(Response {
+        status,
+        headers,
+        body: Body::File { file, length },
+    })
+}
+
+// First and last byte of a "bytes=A-B", "bytes=A-" or "bytes=-N" range.
+// None for a header this doesn't understand, Some(None) for a range outside
+// the file.
+fn parse_range(range: &str, size: u64) -> Option<Option<(u64, u64)>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let bounds = match (first.is_empty(), last.is_empty()) {
+        (true, false) => {
+            let suffix: u64 = last.parse().ok()?;
+            (suffix > 0 && size > 0).then(|| (size.saturating_sub(suffix), size - 1))
+        }
+        (false, _) => {
+            let start: u64 = first.parse().ok()?;
+            let end = if last.is_empty() {
+                size.saturating_sub(1)
+            } else {
+                last.parse::<u64>().ok()?.min(size.saturating_sub(1))
+            };
+            (start < size && start <= end).then_some((start, end))
+        }
+        (true, true) => return None,
+    };
+    Some(bounds)
+}
+
+fn send(mut stream: TcpStream, response: Response, head_only: bool) -> io::Result<()> {
+    let length = match &response.body {
+        Body::Text(text) => text.len() as u64,
+        Body::File { length, .. } => *length,
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason(response.status)
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        length
+    ));
+    stream.write_all(head.as_bytes())?;
+    if !head_only {
+        match response.body {
+            Body::Text(text) => stream.write_all(text.as_bytes())?,
+            Body::File { file, length } => {
+                io::copy(&mut file.take(length), &mut stream)?;
+            }
+        }
+    }
+    stream.flush()
+}
+
+fn text(status: u16, message: &str) -> Response {
+    Response {
+        status,
+        headers: vec![(
+            "Content-Type".to_string(),
+            "text/plain; charset=utf-8".to_string(),
+        )],
+        body: Body::Text(format!("{}\n", message)),
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        416 => "Range Not Satisfiable",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+// The library's files as links, grouped by series.
+fn index(library_dir: &Path) -> io::Result<String> {
+    let mut files = Vec::new();
+    collect_files(library_dir, Path::new(""), &mut files)?;
+    let mut series: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        series.entry(series_of(&file)).or_default().push(file);
+    }
+
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>manga-cli library</title></head><body>\n<h1>Library</h1>\n",
+    );
+    if series.is_empty() {
+        html.push_str("<p>No CBZ or PDF files yet.</p>\n");
+    }
+    for (name, mut files) in series {
+        files.sort();
+        html.push_str(&format!("<h2>{}</h2>\n<ul>\n", escape(&name)));
+        for file in files {
+            let relative = file.to_string_lossy();
+            html.push_str(&format!(
+                "<li><a href=\"{}{}\">{}</a></li>\n",
+                FILES_PREFIX,
+                percent_encode(&relative),
+                escape(&file.file_name().unwrap_or_default().to_string_lossy())
+            ));
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("</body></html>\n");
+    Ok(html)
+}
+
+// Served files under `dir`, relative to the library. Work directories and
+// unfinished outputs are left out.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let relative = dir.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if relative != Path::new(TMP_DIR) {
+                collect_files(root, &relative, files)?;
+            }
+        } else if file_type.is_file() && is_served(&relative) {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+// Files in a folder belong to the folder's series. Others are named like
+// "Series c12", "Series c12-15", "Series c12 - Title" or "Series v03".
+fn series_of(relative: &Path) -> String {
+    if let Some(folder) = relative.parent().and_then(Path::file_name) {
+        return folder.to_string_lossy().into_owned();
+    }
+    static OUTPUT_NAME: OnceLock<Regex> = OnceLock::new();
+    let output_name = OUTPUT_NAME.get_or_init(|| {
+        Regex::new(r"^(.+?) (?:c[\d.]+(?:-[\d.]+)?(?: - .*)?|v\S+|no volume)$").unwrap()
+    });
+    let stem = relative
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    match output_name.captures(&stem) {
+        Some(captures) => captures[1].to_string(),
+        None => stem,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Keeps '/' so links read as paths.
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// None for malformed escapes or names that aren't UTF-8.
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn only_files_inside_the_library_are_served() {
+        let dir = TestDir::new("serve-library");
+        let library = dir.join("library");
+        fs::create_dir_all(library.join("Series")).unwrap();
+        fs::write(library.join("Series/Series c1.cbz"), b"cbz").unwrap();
+        fs::write(library.join("notes.txt"), b"text").unwrap();
+        fs::write(library.join("Series c2.cbz.tmp"), b"cbz").unwrap();
+        fs::write(dir.join("secret.cbz"), b"outside").unwrap();
+
+        let served = library_file(&library, "Series/Series c1.cbz").unwrap();
+        assert_eq!(fs::read(served).unwrap(), b"cbz");
+        for relative in [
+            "../secret.cbz",
+            "Series/../../secret.cbz",
+            "/etc/passwd.pdf",
+            "notes.txt",
+            "Series c2.cbz.tmp",
+            "Series",
+            "missing.cbz",
+        ] {
+            assert!(library_file(&library, relative).is_none(), "{}", relative);
+        }
+    }
+
+    #[test]
+    fn overlong_lines_are_refused() {
+        let long = "a".repeat(MAX_LINE_BYTES as usize * 4);
+        for request in [
+            format!("GET /{} HTTP/1.1\r\n\r\n", long),
+            format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", long),
+        ] {
+            let error = read_request(&mut request.as_bytes()).err().unwrap();
+            assert!(error.get_ref().unwrap().is::<LineTooLong>());
+        }
+        let request = format!(
+            "GET /files/a.cbz HTTP/1.1\r\nRange: bytes=0-9\r\nX-Pad: {}\r\n\r\n",
+            "a".repeat(MAX_LINE_BYTES as usize - 20)
+        );
+        let request = read_request(&mut request.as_bytes()).unwrap().unwrap();
+        assert_eq!(request.path, "/files/a.cbz");
+        assert_eq!(request.header("range"), Some("bytes=0-9"));
+    }
+
+    #[test]
+    fn passwords_are_compared_whole() {
+        assert!(same_secret(b"hunter2", b"hunter2"));
+        for given in [&b"hunter"[..], b"hunter22", b"Hunter2", b"", b"hunter2\0"] {
+            assert!(!same_secret(given, b"hunter2"), "{:?}", given);
+        }
+        let request = |credentials: &str| Request {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            headers: vec![(
+                "authorization".to_string(),
+                format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(credentials)
+                ),
+            )],
+        };
+        assert!(authorized(&request("anyone:hunter2"), "hunter2"));
+        assert!(!authorized(&request("anyone:hunter3"), "hunter2"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_leading_out_of_the_library_are_refused() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TestDir::new("serve-symlinks");
+        let library = dir.join("library");
+        fs::create_dir_all(&library).unwrap();
+        fs::write(library.join("Series c1.cbz"), b"cbz").unwrap();
+        fs::create_dir_all(dir.join("elsewhere")).unwrap();
+        fs::write(dir.join("elsewhere/secret.cbz"), b"outside").unwrap();
+        symlink(dir.join("elsewhere/secret.cbz"), library.join("linked.cbz")).unwrap();
+        symlink(dir.join("elsewhere"), library.join("Linked")).unwrap();
+        symlink(library.join("Series c1.cbz"), library.join("Again c1.cbz")).unwrap();
+
+        assert!(library_file(&library, "linked.cbz").is_none());
+        assert!(library_file(&library, "Linked/secret.cbz").is_none());
+        let inside = library_file(&library, "Again c1.cbz").unwrap();
+        assert_eq!(fs::read(inside).unwrap(), b"cbz");
+    }
+}
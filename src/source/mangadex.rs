@@ -0,0 +1,336 @@
+use super::{
+    clean_chapter_title, normalize_number, Chapter, Details, HealthCheck, Manga, SearchResult,
+    Source, SourceResult,
+};
+use crate::error::Error;
+use crate::http::{self, Kind};
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const NAME: &str = "mangadex";
+// The API asks clients to name themselves rather than pose as a browser.
+const API_HEADERS: &[(&str, &str)] = &[("User-Agent", "manga-cli")];
+const API_URL: &str = "https://api.mangadex.org";
+const SITE_URL: &str = "https://mangadex.org";
+const FEED_PAGE_SIZE: usize = 500;
+const SEARCH_PAGE_SIZE: usize = 20;
+
+pub struct MangaDex {
+    pub languages: Vec<String>,
+    // Fetch the recompressed "data-saver" images instead of the originals.
+    pub data_saver: bool,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    data: T,
+    #[serde(default)]
+    total: usize,
+}
+
+#[derive(Deserialize)]
+struct MangaData {
+    id: String,
+    attributes: MangaAttributes,
+    #[serde(default)]
+    relationships: Vec<Relationship>,
+}
+
+#[derive(Deserialize)]
+struct MangaAttributes {
+    title: HashMap<String, String>,
+    #[serde(rename = "altTitles", default)]
+    alt_titles: Vec<HashMap<String, String>>,
+    // Localized like the title, but an empty list when there is none.
+    #[serde(default)]
+    description: serde_json::Value,
+    status: Option<String>,
+    #[serde(default)]
+    tags: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    attributes: TagAttributes,
+}
+
+#[derive(Deserialize)]
+struct TagAttributes {
+    name: HashMap<String, String>,
+    group: String,
+}
+
+#[derive(Deserialize)]
+struct ChapterData {
+    id: String,
+    attributes: ChapterAttributes,
+    #[serde(default)]
+    relationships: Vec<Relationship>,
+}
+
+#[derive(Deserialize)]
+struct ChapterAttributes {
+    volume: Option<String>,
+    chapter: Option<String>,
+    title: Option<String>,
+    #[serde(rename = "publishAt")]
+    publish_at: Option<String>,
+    #[serde(rename = "translatedLanguage")]
+    translated_language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Relationship {
+    #[serde(rename = "type")]
+    kind: String,
+    attributes: Option<RelationshipAttributes>,
+}
+
+#[derive(Deserialize)]
+struct RelationshipAttributes {
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AtHome {
+    #[serde(rename = "baseUrl")]
+    base_url: String,
+    chapter: AtHomeChapter,
+}
+
+#[derive(Deserialize)]
+struct AtHomeChapter {
+    hash: String,
+    data: Vec<String>,
+    #[serde(rename = "dataSaver")]
+    data_saver: Vec<String>,
+}
+
+impl Source for MangaDex {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn health_check(&self) -> HealthCheck {
+        HealthCheck {
+            site_url: "https://api.mangadex.org/ping",
+            query: "berserk",
+            expected_title: "Berserk",
+        }
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        self.search_page(query, 1)
+    }
+
+    fn search_page(&self, query: &str, page: usize) -> SourceResult<Vec<SearchResult>> {
+        let limit = SEARCH_PAGE_SIZE.to_string();
+        let offset = ((page.max(1) - 1) * SEARCH_PAGE_SIZE).to_string();
+        let mut params = vec![("limit", limit.as_str()), ("title", query)];
+        if page > 1 {
+            params.push(("offset", offset.as_str()));
+        }
+        let url = Url::parse_with_params(&format!("{}/manga", API_URL), &params)?;
+        let response: Response<Vec<MangaData>> = get_json(url.as_str())?;
+        let results = response
+            .data
+            .into_iter()
+            .map(|manga| {
+                let title = pick_title(&manga.attributes.title);
+                let alt_titles = other_titles(&title, manga.attributes.alt_titles);
+                SearchResult {
+                    title,
+                    url: format!("{}/title/{}", SITE_URL, manga.id),
+                    alt_titles,
+                }
+            })
+            .collect();
+        Ok(results)
+    }
+
+    fn manga(&self, manga_url: &str) -> SourceResult<Manga> {
+        let id = last_segment(manga_url);
+        let manga: Response<MangaData> = get_json(&format!("{}/manga/{}", API_URL, id))?;
+
+        let mut chapters = Vec::new();
+        let mut offset = 0;
+        loop {
+            let limit = FEED_PAGE_SIZE.to_string();
+            let offset_param = offset.to_string();
+            let mut params = vec![
+                ("order[chapter]", "desc"),
+                ("includes[]", "scanlation_group"),
+                ("limit", limit.as_str()),
+                ("offset", offset_param.as_str()),
+            ];
+            params.extend(
+                self.languages
+                    .iter()
+                    .map(|language| ("translatedLanguage[]", language.as_str())),
+            );
+            let url = Url::parse_with_params(&format!("{}/manga/{}/feed", API_URL, id), &params)?;
+            let feed: Response<Vec<ChapterData>> = get_json(url.as_str())?;
+            let fetched = feed.data.len();
+            chapters.extend(feed.data.into_iter().map(to_chapter));
+            offset += fetched;
+            if fetched == 0 || offset >= feed.total {
+                break;
+            }
+        }
+
+        Ok(Manga {
+            title: pick_title(&manga.data.attributes.title),
+            chapters,
+        })
+    }
+
+    fn pages(&self, chapter: &Chapter) -> SourceResult<Vec<String>> {
+        let id = last_segment(&chapter.url);
+        let at_home: AtHome = get_json(&format!("{}/at-home/server/{}", API_URL, id))?;
+        let (quality, files) = if self.data_saver {
+            ("data-saver", &at_home.chapter.data_saver)
+        } else {
+            ("data", &at_home.chapter.data)
+        };
+        let pages = files
+            .iter()
+            .map(|file| {
+                format!(
+                    "{}/{}/{}/{}",
+                    at_home.base_url, quality, at_home.chapter.hash, file
+                )
+            })
+            .collect();
+        Ok(pages)
+    }
+
+    fn details(&self, manga_url: &str) -> SourceResult<Details> {
+        let id = last_segment(manga_url);
+        let url = Url::parse_with_params(
+            &format!("{}/manga/{}", API_URL, id),
+            &[("includes[]", "author"), ("includes[]", "artist")],
+        )?;
+        let manga: Response<MangaData> = get_json(url.as_str())?;
+        let attributes = manga.data.attributes;
+        let title = pick_title(&attributes.title);
+
+        let mut authors: Vec<String> = Vec::new();
+        for relationship in manga.data.relationships {
+            if relationship.kind != "author" && relationship.kind != "artist" {
+                continue;
+            }
+            if let Some(name) = relationship.attributes.and_then(|a| a.name) {
+                if !authors.contains(&name) {
+                    authors.push(name);
+                }
+            }
+        }
+        let descriptions: HashMap<String, String> =
+            serde_json::from_value(attributes.description).unwrap_or_default();
+        Ok(Details {
+            alt_titles: other_titles(&title, attributes.alt_titles),
+            status: attributes.status,
+            authors,
+            genres: attributes
+                .tags
+                .iter()
+                .filter(|tag| tag.attributes.group == "genre")
+                .map(|tag| pick_title(&tag.attributes.name))
+                .collect(),
+            description: Some(pick_title(&descriptions)).filter(|text| !text.is_empty()),
+        })
+    }
+
+    fn default_headers(&self, kind: Kind) -> &'static [(&'static str, &'static str)] {
+        match kind {
+            Kind::Page => API_HEADERS,
+            Kind::Image => &[("User-Agent", "Mozilla/5.0")],
+        }
+    }
+
+    fn multilingual(&self) -> bool {
+        true
+    }
+
+    fn scanlation_groups(&self) -> bool {
+        true
+    }
+
+    fn compressed_images(&self) -> bool {
+        self.data_saver
+    }
+}
+
+fn to_chapter(data: ChapterData) -> Chapter {
+    let attributes = data.attributes;
+    let group = data
+        .relationships
+        .into_iter()
+        .find(|relationship| relationship.kind == "scanlation_group")
+        .and_then(|relationship| relationship.attributes?.name);
+
+    let mut name = match &attributes.chapter {
+        Some(number) => format!("Chapter {}", number),
+        None => "Oneshot".to_string(),
+    };
+    if let Some(volume) = &attributes.volume {
+        name = format!("Vol.{} {}", volume, name);
+    }
+    let title = attributes.title.as_deref().and_then(clean_chapter_title);
+    if let Some(title) = &title {
+        name = format!("{}: {}", name, title);
+    }
+
+    Chapter {
+        url: format!("{}/chapter/{}", SITE_URL, data.id),
+        name,
+        number: attributes
+            .chapter
+            .as_deref()
+            .and_then(|number| number.parse().ok()),
+        volume: attributes.volume.as_deref().map(normalize_number),
+        title,
+        group,
+        language: attributes.translated_language,
+        uploaded: attributes.publish_at,
+    }
+}
+
+fn get_json<T: DeserializeOwned>(url: &str) -> SourceResult<T> {
+    let headers = http::headers(NAME, Kind::Page, API_HEADERS, &[]);
+    let response = http::get(url, &headers, Kind::Page)?;
+    response.check_status(url)?;
+    serde_json::from_slice(&response.body).map_err(|e| {
+        log::debug!("Unexpected response from {}: {}", url, e);
+        Error::Parse {
+            url: url.to_string(),
+            what: "the expected API response",
+        }
+        .into()
+    })
+}
+
+fn pick_title(titles: &HashMap<String, String>) -> String {
+    titles
+        .get("en")
+        .or_else(|| titles.values().next())
+        .cloned()
+        .unwrap_or_default()
+}
+
+// The names in `alt_titles` other than `title`, without repeats.
+fn other_titles(title: &str, alt_titles: Vec<HashMap<String, String>>) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for name in alt_titles.into_iter().flat_map(|t| t.into_values()) {
+        if name != title && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+fn last_segment(url: &str) -> &str {
+    url.trim_end_matches('/').rsplit('/').next().unwrap_or(url)
+}
@@ -0,0 +1,657 @@
+use super::{
+    clean_chapter_title, normalize_number, render_chapter_url, title_from_url, Chapter, Details,
+    HealthCheck, Manga, SearchResult, Source, SourceResult,
+};
+use crate::chapter_id::ChapterId;
+use crate::error::Error;
+use crate::http::{self, Kind};
+use crate::mirrors;
+use regex::Regex;
+use reqwest::Url;
+use select::document::Document;
+use select::node::Node;
+use select::predicate::{Attr, Class, Name, Predicate};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+const NAME: &str = "manganelo";
+// What a browser sends, which the site's bot detection looks for.
+const PAGE_HEADERS: &[(&str, &str)] = &[
+    ("User-Agent", "Mozilla/5.0"),
+    (
+        "Accept",
+        "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+    ),
+    ("Accept-Language", "en-US,en;q=0.5"),
+];
+const IMAGE_HEADERS: &[(&str, &str)] = &[
+    ("User-Agent", "Mozilla/5.0"),
+    ("Accept", "image/avif,image/webp,image/*,*/*;q=0.8"),
+    ("Accept-Language", "en-US,en;q=0.5"),
+];
+const SEARCH_URL: &str = "https://m.manganelo.com/search/story/";
+const MIRRORS: &[&str] = &["https://m.manganelo.com", "https://manganelo.com"];
+// Where chapters not in the chapter list are looked for; `chapter_url_template`
+// in the config file replaces it.
+const CHAPTER_URL_TEMPLATE: &str = "{manga_url}/chapter-{chapter}";
+// The endpoint the series page loads its chapter list from. It outlives
+// layout redesigns, so it's tried before scraping the page.
+const CHAPTER_LIST_PATH: &str = "/api/manga/{slug}/chapters?limit=-1";
+
+#[derive(Default)]
+pub struct Manganelo {
+    // MIRRORS ordered by measured speed, worked out on the first request.
+    mirrors: OnceLock<Vec<String>>,
+    // Page images and next-chapter link of reader pages already fetched, by
+    // chapter URL, so following next links doesn't fetch chapters twice.
+    readers: Mutex<HashMap<String, Reader>>,
+}
+
+// What the chapter list endpoint answers with when it answers with JSON;
+// older mirrors send the list's HTML instead.
+#[derive(Deserialize)]
+struct ChapterList {
+    data: ChapterListData,
+}
+
+#[derive(Deserialize)]
+struct ChapterListData {
+    chapters: Vec<ListedChapter>,
+}
+
+#[derive(Deserialize)]
+struct ListedChapter {
+    chapter_name: String,
+    chapter_slug: String,
+    #[serde(default)]
+    updated_at: Option<String>,
+}
+
+#[derive(Clone)]
+struct Reader {
+    images: Vec<String>,
+    next: Option<String>,
+    declared_pages: Option<usize>,
+}
+
+impl Source for Manganelo {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn health_check(&self) -> HealthCheck {
+        HealthCheck {
+            site_url: "https://m.manganelo.com/",
+            query: "one piece",
+            expected_title: "One Piece",
+        }
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        self.search_page(query, 1)
+    }
+
+    fn search_page(&self, query: &str, page: usize) -> SourceResult<Vec<SearchResult>> {
+        let mut url = format!("{}{}", SEARCH_URL, format_manga_name(query));
+        if page > 1 {
+            url.push_str(&format!("?page={}", page));
+        }
+        let document = self.fetch_document(&url)?;
+        let results: Vec<SearchResult> = document
+            .find(Name("h3"))
+            .filter_map(|node: Node| node.find(Name("a")).next())
+            .filter_map(|node: Node| {
+                let url = node.attr("href")?.to_string();
+                let title = node.text().trim().to_string();
+                Some(SearchResult {
+                    title: if title.is_empty() {
+                        title_from_url(&url)
+                    } else {
+                        title
+                    },
+                    url,
+                    alt_titles: Vec::new(),
+                })
+            })
+            .collect();
+
+        Ok(results)
+    }
+
+    fn manga(&self, manga_url: &str) -> SourceResult<Manga> {
+        let document = self.fetch_document(manga_url)?;
+        let title = document
+            .find(Class("story-info-right").descendant(Name("h1")))
+            .next()
+            .map(|node| node.text().trim().to_string())
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| title_from_url(manga_url));
+        let chapters = match self.listed_chapters(manga_url) {
+            Ok(chapters) if !chapters.is_empty() => {
+                log::debug!("Chapter list of {} from the site's API", manga_url);
+                chapters
+            }
+            listed => {
+                match listed {
+                    Ok(_) => log::debug!("The chapter list API returned no chapters"),
+                    Err(e) => log::debug!("The chapter list API failed: {}", e),
+                }
+                log::debug!("Chapter list of {} from the series page", manga_url);
+                document
+                    .find(Class("row-content-chapter").descendant(Name("li")))
+                    .filter_map(list_item_chapter)
+                    .collect()
+            }
+        };
+
+        Ok(Manga { title, chapters })
+    }
+
+    fn pages(&self, chapter: &Chapter) -> SourceResult<Vec<String>> {
+        Ok(self.reader(chapter)?.images)
+    }
+
+    fn declared_pages(&self, chapter: &Chapter) -> Option<usize> {
+        self.reader(chapter).ok()?.declared_pages
+    }
+
+    fn next_chapter(&self, chapter: &Chapter) -> SourceResult<Option<Chapter>> {
+        let Some(url) = self.reader(chapter)?.next else {
+            return Ok(None);
+        };
+        let number = chapter_number(&url);
+        Ok(Some(Chapter {
+            name: match &number {
+                Some(number) => format!("Chapter {}", number),
+                None => title_from_url(&url),
+            },
+            number,
+            volume: None,
+            title: None,
+            group: None,
+            language: None,
+            uploaded: None,
+            url,
+        }))
+    }
+
+    // Listed in the info table as "Alternative : Name 1 ; Name 2".
+    fn alt_titles(&self, manga_url: &str) -> SourceResult<Vec<String>> {
+        let document = self.fetch_document(manga_url)?;
+        let value = info_value(&document, "Alternative").unwrap_or_default();
+        let separator = if value.contains(';') { ';' } else { ',' };
+        Ok(split_list(&value, separator))
+    }
+
+    // The info table also has "Author(s) : A - B", "Status : Ongoing" and
+    // "Genres : Action - Drama"; the description follows the table.
+    fn details(&self, manga_url: &str) -> SourceResult<Details> {
+        let document = self.fetch_document(manga_url)?;
+        let alternative = info_value(&document, "Alternative").unwrap_or_default();
+        let separator = if alternative.contains(';') { ';' } else { ',' };
+        let description = document
+            .find(Attr("id", "panel-story-info-description"))
+            .next()
+            .map(|node| {
+                let text = node.text();
+                let text = text.trim();
+                text.strip_prefix("Description :")
+                    .unwrap_or(text)
+                    .trim()
+                    .to_string()
+            })
+            .filter(|description| !description.is_empty());
+        Ok(Details {
+            alt_titles: split_list(&alternative, separator),
+            status: info_value(&document, "Status").map(|status| status.trim().to_string()),
+            authors: split_list(&info_value(&document, "Author").unwrap_or_default(), '-'),
+            genres: split_list(&info_value(&document, "Genres").unwrap_or_default(), '-'),
+            description,
+        })
+    }
+
+    fn mirrors(&self) -> &'static [&'static str] {
+        MIRRORS
+    }
+
+    fn chapter_url(&self, manga_url: &str, number: &ChapterId) -> Option<String> {
+        render_chapter_url(CHAPTER_URL_TEMPLATE, manga_url, number)
+    }
+
+    fn default_headers(&self, kind: Kind) -> &'static [(&'static str, &'static str)] {
+        match kind {
+            Kind::Page => PAGE_HEADERS,
+            Kind::Image => IMAGE_HEADERS,
+        }
+    }
+
+    // The image CDN refuses hotlinked requests without the reader's Referer.
+    fn image_headers(&self, chapter: &Chapter) -> Vec<(String, String)> {
+        let mut referer = Vec::new();
+        if let Ok(url) = Url::parse(&chapter.url) {
+            referer.push((
+                "Referer".to_string(),
+                format!("{}/", url.origin().ascii_serialization()),
+            ));
+        }
+        http::headers(NAME, Kind::Image, IMAGE_HEADERS, &referer)
+    }
+}
+
+impl Manganelo {
+    // Tries pages on the site's own domains fastest mirror first. The fastest
+    // one failing means the measurements are stale, so the next run probes
+    // again.
+    fn fetch_document(&self, url: &str) -> Result<Document, Error> {
+        let ordered = self
+            .mirrors
+            .get_or_init(|| mirrors::ordered(self.name(), MIRRORS));
+        let candidates: Vec<(&str, String)> = ordered
+            .iter()
+            .filter_map(|mirror| Some((mirror.as_str(), mirrors::on_mirror(url, mirror, MIRRORS)?)))
+            .collect();
+        let Some(((first_mirror, first), fallbacks)) = candidates.split_first() else {
+            return fetch_document(url);
+        };
+        let mut mirror = *first_mirror;
+        let mut result = fetch_document(first);
+        if result.is_err() && !fallbacks.is_empty() {
+            mirrors::forget(self.name());
+        }
+        for (fallback_mirror, fallback) in fallbacks {
+            let Err(e) = &result else { break };
+            log::debug!("Trying mirror {} after: {}", fallback, e);
+            mirror = fallback_mirror;
+            result = fetch_document(fallback);
+        }
+        if result.is_ok() {
+            mirrors::record_used(self.name(), mirror);
+        }
+        result
+    }
+
+    // The series' chapters from the endpoint behind its chapter list, which
+    // answers with JSON or with the list's HTML depending on the mirror. Only
+    // the mirror that served the series page is asked: the series page is
+    // still there to fall back on, and a mirror without the endpoint isn't a
+    // reason to probe the mirrors again.
+    fn listed_chapters(&self, manga_url: &str) -> Result<Vec<Chapter>, Error> {
+        let base = Url::parse(manga_url).map_err(|_| Error::Parse {
+            url: manga_url.to_string(),
+            what: "a series URL",
+        })?;
+        let path = CHAPTER_LIST_PATH.replace("{slug}", &title_from_url(manga_url));
+        let mut url = format!("{}{}", base.origin().ascii_serialization(), path);
+        if let Some(moved) =
+            mirrors::used(self.name()).and_then(|mirror| mirrors::on_mirror(&url, &mirror, MIRRORS))
+        {
+            url = moved;
+        }
+        let headers = http::headers(
+            NAME,
+            Kind::Page,
+            PAGE_HEADERS,
+            &[
+                ("X-Requested-With".to_string(), "XMLHttpRequest".to_string()),
+                (
+                    "Accept".to_string(),
+                    "application/json, text/html".to_string(),
+                ),
+                ("Referer".to_string(), manga_url.to_string()),
+            ],
+        );
+        let response = http::get(&url, &headers, Kind::Page)?;
+        response.check_status(&url)?;
+        parse_chapter_list(&response.text(), manga_url).ok_or(Error::Parse {
+            url,
+            what: "the chapter list",
+        })
+    }
+
+    // The chapter's reader page, fetched once per run.
+    fn reader(&self, chapter: &Chapter) -> SourceResult<Reader> {
+        if let Some(reader) = self.readers.lock().unwrap().get(&chapter.url) {
+            return Ok(reader.clone());
+        }
+        let document = self.fetch_document(&chapter.url)?;
+        let mut images = page_images(&document);
+        let parts = reader_parts(&document, &chapter.url);
+        let declared_pages = declared_pages(&document, !parts.is_empty());
+        for part in parts {
+            let part_images = page_images(&self.fetch_document(&part)?);
+            append_without_overlap(&mut images, part_images);
+        }
+        if images.is_empty() {
+            return Err(Error::Parse {
+                url: chapter.url.clone(),
+                what: "any page images",
+            }
+            .into());
+        }
+
+        let reader = Reader {
+            images,
+            next: next_link(&document, &chapter.url),
+            declared_pages,
+        };
+        self.readers
+            .lock()
+            .unwrap()
+            .insert(chapter.url.clone(), reader.clone());
+        Ok(reader)
+    }
+}
+
+fn fetch_document(url: &str) -> Result<Document, Error> {
+    let headers = http::headers(NAME, Kind::Page, PAGE_HEADERS, &[]);
+    let response = http::get(url, &headers, Kind::Page)?;
+
+    Ok(Document::from(response.text().as_str()))
+}
+
+// Chapters of a chapter list endpoint's answer, None when it's neither the
+// expected JSON nor HTML with chapter links.
+fn parse_chapter_list(body: &str, manga_url: &str) -> Option<Vec<Chapter>> {
+    if body.trim_start().starts_with('{') {
+        let list: ChapterList = serde_json::from_str(body)
+            .map_err(|e| log::debug!("Unexpected chapter list JSON: {}", e))
+            .ok()?;
+        let base = manga_url.trim_end_matches('/');
+        return Some(
+            list.data
+                .chapters
+                .into_iter()
+                .map(|listed| {
+                    let url = format!("{}/{}", base, listed.chapter_slug);
+                    let name = listed.chapter_name.trim().to_string();
+                    Chapter {
+                        number: chapter_number(&url),
+                        volume: volume_number(&name),
+                        title: chapter_title(&name),
+                        group: None,
+                        language: None,
+                        url,
+                        name,
+                        uploaded: listed.updated_at,
+                    }
+                })
+                .collect(),
+        );
+    }
+    let fragment = Document::from(body);
+    let chapters: Vec<Chapter> = fragment
+        .find(Name("li"))
+        .filter_map(list_item_chapter)
+        .collect();
+    (!chapters.is_empty()).then_some(chapters)
+}
+
+// A chapter from a `<li>` of the chapter list, on the series page or in the
+// endpoint's HTML.
+fn list_item_chapter(node: Node) -> Option<Chapter> {
+    let link = node.find(Name("a")).next()?;
+    let uploaded = node.find(Class("chapter-time")).next().map(|time| {
+        time.attr("title")
+            .map(|title| title.to_string())
+            .unwrap_or_else(|| time.text())
+    });
+    let url = link.attr("href")?.to_string();
+    let name = link.text().trim().to_string();
+    Some(Chapter {
+        number: chapter_number(&url),
+        volume: volume_number(&name),
+        title: chapter_title(&name),
+        group: None,
+        language: None,
+        url,
+        name,
+        uploaded,
+    })
+}
+
+// Text of the info table row whose label contains `label`.
+fn info_value(document: &Document, label: &str) -> Option<String> {
+    let row = document.find(Name("tr")).find(|row: &Node| {
+        row.find(Class("table-label"))
+            .next()
+            .is_some_and(|cell| cell.text().contains(label))
+    })?;
+    row.find(Class("table-value"))
+        .next()
+        .map(|value| value.text())
+}
+
+fn split_list(value: &str, separator: char) -> Vec<String> {
+    value
+        .split(separator)
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn page_images(document: &Document) -> Vec<String> {
+    document
+        .find(Name("img"))
+        .filter_map(|node: Node| node.attr("src").map(|src| src.to_string()))
+        .collect()
+}
+
+// Some mirrors split long chapters across "chapter-12?page=2", "?page=3", ...
+// Returns the URLs of the remaining parts in page order.
+fn reader_parts(document: &Document, chapter_url: &str) -> Vec<String> {
+    let Ok(base) = Url::parse(chapter_url) else {
+        return Vec::new();
+    };
+    let mut parts: Vec<(u32, String)> = document
+        .find(Name("a"))
+        .filter_map(|node: Node| {
+            let url = base.join(node.attr("href")?).ok()?;
+            if url.path().trim_end_matches('/') != base.path().trim_end_matches('/') {
+                return None;
+            }
+            let page = url
+                .query_pairs()
+                .find(|(key, _)| key == "page")?
+                .1
+                .parse::<u32>()
+                .ok()?;
+            (page > 1).then_some((page, url.to_string()))
+        })
+        .collect();
+    parts.sort();
+    parts.dedup_by_key(|(page, _)| *page);
+    parts.into_iter().map(|(_, url)| url).collect()
+}
+
+// Page count from a "Page 1 of 43" label, or the page-select dropdown. Split
+// readers use the dropdown to pick parts, so it only counts when the reader
+// isn't `split`.
+fn declared_pages(document: &Document, split: bool) -> Option<usize> {
+    static PAGE_OF: OnceLock<Regex> = OnceLock::new();
+    let page_of =
+        PAGE_OF.get_or_init(|| Regex::new(r"(?i)\bpage\s*\d+\s*(?:of|/)\s*(\d+)").unwrap());
+    let text = document.find(Name("body")).next()?.text();
+    if let Some(total) = page_of
+        .captures(&text)
+        .and_then(|captures| captures[1].parse().ok())
+    {
+        return Some(total);
+    }
+    if split {
+        return None;
+    }
+    let dropdown = document.find(Name("select")).find(|select: &Node| {
+        ["id", "class", "name"].iter().any(|attr| {
+            select
+                .attr(attr)
+                .is_some_and(|value| value.to_lowercase().contains("page"))
+        })
+    })?;
+    let options = dropdown.find(Name("option")).count();
+    (options > 0).then_some(options)
+}
+
+// The reader's "NEXT CHAPTER" button, absent on the newest chapter.
+fn next_link(document: &Document, chapter_url: &str) -> Option<String> {
+    let base = Url::parse(chapter_url).ok()?;
+    let link = document
+        .find(Class("navi-change-chapter-btn-next"))
+        .next()
+        .or_else(|| document.find(Name("a").and(Attr("rel", "next"))).next())?;
+    let url = base.join(link.attr("href")?).ok()?;
+    chapter_number(url.as_str())?;
+    Some(url.to_string())
+}
+
+// Parts may repeat the last images of the previous part, so skip the longest
+// prefix of `next` that the end of `images` already contains.
+fn append_without_overlap(images: &mut Vec<String>, next: Vec<String>) {
+    let overlap = (1..=images.len().min(next.len()))
+        .rev()
+        .find(|&n| images[images.len() - n..] == next[..n])
+        .unwrap_or(0);
+    images.extend(next.into_iter().skip(overlap));
+}
+
+// Chapter URLs end in "chapter-12" or "chapter-12.5".
+fn chapter_number(url: &str) -> Option<ChapterId> {
+    let (_, number) = url.trim_end_matches('/').rsplit_once("chapter-")?;
+    number.parse().ok()
+}
+
+// Link texts read "Chapter 1043: The Capital in Flames", or just "Chapter 1043".
+fn chapter_title(name: &str) -> Option<String> {
+    clean_chapter_title(name.split_once(':')?.1)
+}
+
+// Volume number from names like "Vol.3 Chapter 20".
+fn volume_number(name: &str) -> Option<String> {
+    // "vol" only as a word of its own, not inside "Revolution".
+    static VOLUME: OnceLock<Regex> = OnceLock::new();
+    let volume = VOLUME.get_or_init(|| Regex::new(r"(?i)\bvol(?:ume)?\.?\s*(\d+)").unwrap());
+    let captures = volume.captures(name)?;
+    Some(normalize_number(&captures[1]))
+}
+
+fn format_manga_name(manga_name: &str) -> String {
+    manga_name.replace(" ", "_").replace("-", "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANGA_URL: &str = "https://m.manganelo.com/manga/fixture-tales";
+    // The chapter list endpoint's two kinds of answer.
+    const LIST_JSON: &str = include_str!("../../tests/fixtures/manganelo/chapters.json");
+    const LIST_HTML: &str = include_str!("../../tests/fixtures/manganelo/chapters.html");
+
+    #[test]
+    fn preloaded_copies_of_pages_are_dropped() {
+        let reader = include_str!("../../tests/fixtures/manganelo/reader-preload.html");
+        let mut images = page_images(&Document::from(reader));
+        assert_eq!(images.len(), 6);
+        assert_eq!(crate::dedupe::unique_urls(&mut images), 3);
+        let base = "https://v12.mkklcdnv6tempv5.com/img/tab_12/fixture/chapter_3";
+        assert_eq!(
+            images,
+            [
+                format!("{}/1.jpg", base),
+                format!("{}/2.jpg?v=2&_=1709373600", base),
+                format!("{}/3.jpg?w=800", base),
+            ]
+        );
+    }
+
+    fn summary(chapters: &[Chapter]) -> Vec<(String, Option<String>, Option<String>)> {
+        chapters
+            .iter()
+            .map(|chapter| {
+                (
+                    chapter.number.as_ref().unwrap().to_string(),
+                    chapter.volume.clone(),
+                    chapter.title.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chapter_list_json() {
+        let chapters = parse_chapter_list(LIST_JSON, &format!("{}/", MANGA_URL)).unwrap();
+        assert_eq!(
+            summary(&chapters),
+            [
+                (
+                    "12.5".to_string(),
+                    Some("2".to_string()),
+                    Some("Side Story".to_string())
+                ),
+                (
+                    "12".to_string(),
+                    Some("2".to_string()),
+                    Some("The Capital in Flames".to_string())
+                ),
+                ("11".to_string(), None, None),
+            ]
+        );
+        assert_eq!(chapters[0].url, format!("{}/chapter-12.5", MANGA_URL));
+        assert_eq!(chapters[2].name, "Chapter 11");
+        assert_eq!(
+            chapters[1].uploaded.as_deref(),
+            Some("2024-02-24T10:00:00.000000Z")
+        );
+        assert_eq!(chapters[2].uploaded, None);
+    }
+
+    #[test]
+    fn chapter_list_html_fragment() {
+        let chapters = parse_chapter_list(LIST_HTML, MANGA_URL).unwrap();
+        assert_eq!(
+            summary(&chapters),
+            [
+                (
+                    "12".to_string(),
+                    Some("2".to_string()),
+                    Some("The Capital in Flames".to_string())
+                ),
+                ("11".to_string(), None, None),
+            ]
+        );
+        assert_eq!(chapters[1].url, format!("{}/chapter-11", MANGA_URL));
+        // The exact time when there is one, else what the list shows.
+        assert_eq!(chapters[0].uploaded.as_deref(), Some("Feb 24,2024 10:00"));
+        assert_eq!(chapters[1].uploaded.as_deref(), Some("3 day ago"));
+    }
+
+    #[test]
+    fn other_answers_fall_back_to_the_series_page() {
+        for body in [
+            "",
+            "<html><body>Not found</body></html>",
+            "{\"success\": false, \"message\": \"rate limited\"}",
+            "{\"data\": {\"chapters\": [{\"chapter_name\": 1}]}}",
+        ] {
+            assert!(parse_chapter_list(body, MANGA_URL).is_none(), "{}", body);
+        }
+        // An empty list parses, and manga() falls back on it too.
+        let empty = parse_chapter_list("{\"data\": {\"chapters\": []}}", MANGA_URL);
+        assert!(empty.unwrap().is_empty());
+    }
+
+    #[test]
+    fn volume_number_is_a_word_of_its_own() {
+        for (name, volume) in [
+            ("Vol.3 Chapter 20", Some("3")),
+            ("vol 03 chapter 20", Some("3")),
+            ("Volume 12 Chapter 100: End", Some("12")),
+            ("VOL.7", Some("7")),
+            ("Chapter 5: Revolution 2", None),
+            ("Chapter 6: Volunteer 2", None),
+            ("Chapter 7", None),
+        ] {
+            assert_eq!(volume_number(name).as_deref(), volume, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,534 @@
+mod mangadex;
+mod manganelo;
+
+use crate::chapter_id::ChapterId;
+use crate::http::{self, Kind};
+use crate::template::Template;
+use clap::ArgEnum;
+use mangadex::MangaDex;
+use manganelo::Manganelo;
+use serde::{Deserialize, Serialize};
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub type SourceResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+static CHAPTER_URL_TEMPLATE: OnceLock<Template> = OnceLock::new();
+
+// How long --source all waits for the slowest source before showing what the
+// others found.
+const SEARCH_ALL_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    // Other names and romanizations of the series, when the site lists them.
+    pub alt_titles: Vec<String>,
+}
+
+// What the site says about a series besides its title and chapters.
+#[derive(Default)]
+pub struct Details {
+    pub alt_titles: Vec<String>,
+    // As the site words it, e.g. "Ongoing" or "completed".
+    pub status: Option<String>,
+    pub authors: Vec<String>,
+    pub genres: Vec<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Manga {
+    pub title: String,
+    // Newest first, the way both sites list them.
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Chapter {
+    pub url: String,
+    pub name: String,
+    pub number: Option<ChapterId>,
+    pub volume: Option<String>,
+    // "The Capital in Flames" of "Chapter 1043: The Capital in Flames".
+    pub title: Option<String>,
+    pub group: Option<String>,
+    pub language: Option<String>,
+    pub uploaded: Option<String>,
+}
+
+// Canned expectations `doctor` uses to tell whether the source still works.
+// Update them together with the source's selectors.
+pub struct HealthCheck {
+    pub site_url: &'static str,
+    pub query: &'static str,
+    pub expected_title: &'static str,
+}
+
+pub trait Source {
+    fn name(&self) -> &'static str;
+
+    fn health_check(&self) -> HealthCheck;
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>>;
+
+    // Page `page` of a search's results, counting from 1; empty past the
+    // last. Sources that can't page return nothing after the first.
+    fn search_page(&self, query: &str, page: usize) -> SourceResult<Vec<SearchResult>> {
+        if page <= 1 {
+            self.search(query)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn manga(&self, manga_url: &str) -> SourceResult<Manga>;
+
+    fn pages(&self, chapter: &Chapter) -> SourceResult<Vec<String>>;
+
+    // Alternative names for sources whose search results don't include them.
+    fn alt_titles(&self, _manga_url: &str) -> SourceResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    // Series metadata for the series.json sidecar. Sources without more to
+    // say report the alternative names only.
+    fn details(&self, manga_url: &str) -> SourceResult<Details> {
+        Ok(Details {
+            alt_titles: self.alt_titles(manga_url)?,
+            ..Details::default()
+        })
+    }
+
+    // Guesses a chapter's URL for when it can't be found in the chapter list.
+    fn chapter_url(&self, _manga_url: &str, _number: &ChapterId) -> Option<String> {
+        None
+    }
+
+    // Number of pages the chapter's reader says it has ("page 1 of 43"), to
+    // check pages() against. Called after pages().
+    fn declared_pages(&self, _chapter: &Chapter) -> Option<usize> {
+        None
+    }
+
+    // The chapter the site's reader links to as the next one, for following
+    // a series past its chapter list. None when there's no such link.
+    fn next_chapter(&self, _chapter: &Chapter) -> SourceResult<Option<Chapter>> {
+        Ok(None)
+    }
+
+    // Headers the site's requests of each kind start from, before the
+    // config file's and the request's own.
+    fn default_headers(&self, _kind: Kind) -> &'static [(&'static str, &'static str)] {
+        &[("User-Agent", "Mozilla/5.0")]
+    }
+
+    // Headers image hosts expect when fetching a chapter's pages.
+    fn image_headers(&self, _chapter: &Chapter) -> Vec<(String, String)> {
+        http::headers(
+            self.name(),
+            Kind::Image,
+            self.default_headers(Kind::Image),
+            &[],
+        )
+    }
+
+    // SHA-256 hex digests of promotional pages the site is known to inject.
+    fn promo_hashes(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    // Regexes matching image URLs of the app ads the site appends to
+    // chapters.
+    fn ad_url_patterns(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    // Whether chapters come in several languages that --lang can choose from.
+    fn multilingual(&self) -> bool {
+        false
+    }
+
+    // Whether chapters name the group that released them, for --group.
+    fn scanlation_groups(&self) -> bool {
+        false
+    }
+
+    // Base URLs of the site's mirror domains, tried fastest first.
+    fn mirrors(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    // Whether pages() returns the site's own low-quality variant.
+    fn compressed_images(&self) -> bool {
+        false
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+pub enum SourceKind {
+    Manganelo,
+    Mangadex,
+}
+
+// What --source names: one source, or "all" to search every registered one.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SourceChoice {
+    One(SourceKind),
+    All,
+}
+
+impl SourceChoice {
+    // None for "all".
+    pub fn kind(self) -> Option<SourceKind> {
+        match self {
+            SourceChoice::One(kind) => Some(kind),
+            SourceChoice::All => None,
+        }
+    }
+}
+
+pub fn parse_source_choice(value: &str) -> Result<SourceChoice, String> {
+    if value.eq_ignore_ascii_case("all") {
+        return Ok(SourceChoice::All);
+    }
+    SourceKind::from_str(value, true)
+        .map(SourceChoice::One)
+        .map_err(|_| {
+            let names: Vec<String> = SourceKind::value_variants()
+                .iter()
+                .filter_map(|kind| kind.to_possible_value())
+                .map(|value| value.get_name().to_string())
+                .collect();
+            format!(
+                "\"{}\" isn't a source; use {} or all",
+                value,
+                names.join(", ")
+            )
+        })
+}
+
+// Whether a source can honour a flag.
+type Capability = fn(&dyn Source) -> bool;
+
+// Flags only some sources can honour.
+const CAPABILITIES: &[(&str, Capability)] = &[
+    ("group", |source| source.scanlation_groups()),
+    ("lang", |source| source.multilingual()),
+    ("mirror", |source| !source.mirrors().is_empty()),
+];
+
+// The flags `given` that `source` can't honour, each with the sources that
+// can.
+pub fn unsupported_flags(source: &dyn Source, given: impl Fn(&str) -> bool) -> Vec<String> {
+    CAPABILITIES
+        .iter()
+        .filter(|(flag, supported)| given(flag) && !supported(source))
+        .map(|(flag, supported)| {
+            let others: Vec<&str> = SourceKind::value_variants()
+                .iter()
+                .map(|kind| self::source(*kind, &[], false))
+                .filter(|other| supported(other.as_ref()))
+                .map(|other| other.name())
+                .collect();
+            if others.is_empty() {
+                format!("--{}", flag)
+            } else {
+                format!("--{} (only {} can)", flag, others.join(", "))
+            }
+        })
+        .collect()
+}
+
+// The source whose Source::name() is `name`.
+pub fn kind_named(name: &str) -> Option<SourceKind> {
+    SourceKind::value_variants()
+        .iter()
+        .copied()
+        .find(|kind| source(*kind, &[], false).name() == name)
+}
+
+pub struct MergedSearch {
+    pub found: Vec<(SourceKind, SearchResult)>,
+    // Sources that found nothing usable, and why.
+    pub failed: Vec<(SourceKind, String)>,
+}
+
+// Searches every source at once, looking up alternative names for the first
+// `alt_title_lookups` results of each. Results are grouped by source in
+// registration order; a result whose title matches one already found
+// elsewhere is dropped. Sources that fail or don't answer within
+// SEARCH_ALL_TIMEOUT are listed as failed instead.
+pub fn search_all(
+    query: &str,
+    languages: &[String],
+    low_data: bool,
+    alt_title_lookups: usize,
+) -> MergedSearch {
+    let (sender, receiver) = mpsc::channel();
+    for kind in SourceKind::value_variants().iter().copied() {
+        let sender = sender.clone();
+        let query = query.to_string();
+        let languages = languages.to_vec();
+        // Searches still running at the deadline are left to finish on
+        // their own; nothing reads their results.
+        thread::spawn(move || {
+            let source = source(kind, &languages, low_data);
+            let results = source.search(&query).map(|mut results| {
+                for result in results.iter_mut().take(alt_title_lookups) {
+                    if result.alt_titles.is_empty() {
+                        result.alt_titles = source.alt_titles(&result.url).unwrap_or_default();
+                    }
+                }
+                results
+            });
+            let _ = sender.send((kind, results.map_err(|e| e.to_string())));
+        });
+    }
+    drop(sender);
+
+    let deadline = Instant::now() + SEARCH_ALL_TIMEOUT;
+    let mut answers = Vec::new();
+    while answers.len() < SourceKind::value_variants().len() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(left) {
+            Ok(answer) => answers.push(answer),
+            Err(_) => break,
+        }
+    }
+
+    let mut found = Vec::new();
+    let mut failed = Vec::new();
+    let mut seen = Vec::new();
+    for kind in SourceKind::value_variants().iter().copied() {
+        match answers.iter_mut().find(|(answered, _)| *answered == kind) {
+            Some((_, Ok(results))) => {
+                for result in results.drain(..) {
+                    let title = normalize_title(&result.title);
+                    if !seen.contains(&title) {
+                        seen.push(title);
+                        found.push((kind, result));
+                    }
+                }
+            }
+            Some((_, Err(e))) => failed.push((kind, e.clone())),
+            None => failed.push((
+                kind,
+                format!("no answer within {}s", SEARCH_ALL_TIMEOUT.as_secs()),
+            )),
+        }
+    }
+    MergedSearch { found, failed }
+}
+
+// Lowercase letters and digits only, so "One-Punch Man" and "One Punch-Man"
+// count as the same series.
+fn normalize_title(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// `languages` lists the accepted chapter languages, most preferred first.
+// `low_data` asks for compressed images where the site offers them.
+pub fn source(kind: SourceKind, languages: &[String], low_data: bool) -> Box<dyn Source> {
+    match kind {
+        SourceKind::Manganelo => Box::new(Manganelo::default()),
+        SourceKind::Mangadex => Box::new(MangaDex {
+            languages: languages.to_vec(),
+            data_saver: low_data,
+        }),
+    }
+}
+
+// Chapter URL templates such as "{manga_url}/chapter-{chapter}". Both
+// placeholders are required, since a URL without either can't be right.
+pub fn parse_chapter_url_template(value: &str) -> Result<Template, String> {
+    let template = Template::parse(value, &["manga_url", "chapter"])?;
+    for field in ["manga_url", "chapter"] {
+        if !template.uses(field) {
+            return Err(format!("\"{}\" is missing {{{}}}", value, field));
+        }
+    }
+    Ok(template)
+}
+
+// Replaces the sources' own chapter URL templates for the rest of the run
+// (config file), for mirrors that lay their URLs out differently.
+pub fn set_chapter_url_template(template: Template) {
+    let _ = CHAPTER_URL_TEMPLATE.set(template);
+}
+
+// Guesses a chapter URL from `default`, the source's template, unless the
+// config file gave another.
+fn render_chapter_url(default: &str, manga_url: &str, number: &ChapterId) -> Option<String> {
+    // "12 extra" reads "12-extra" in URLs.
+    let number = number.to_string().replace(' ', "-");
+    let values = [
+        ("manga_url", manga_url.trim_end_matches('/')),
+        ("chapter", number.as_str()),
+    ];
+    match CHAPTER_URL_TEMPLATE.get() {
+        Some(template) => Some(template.render(&values)),
+        None => Some(parse_chapter_url_template(default).ok()?.render(&values)),
+    }
+}
+
+// The source a manga URL belongs to, by its host.
+pub fn kind_for_url(url: &str) -> Option<SourceKind> {
+    let host = reqwest::Url::parse(url).ok()?.host_str()?.to_lowercase();
+    if host.ends_with("mangadex.org") {
+        Some(SourceKind::Mangadex)
+    } else if host.ends_with("manganelo.com") {
+        Some(SourceKind::Manganelo)
+    } else {
+        None
+    }
+}
+
+pub fn title_from_url(manga_url: &str) -> String {
+    manga_url
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(manga_url)
+        .to_string()
+}
+
+// Longest chapter title kept, in characters.
+const MAX_CHAPTER_TITLE: usize = 80;
+
+// Collapses whitespace and shortens overly long titles. Empty titles are
+// treated as missing.
+pub fn clean_chapter_title(title: &str) -> Option<String> {
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.is_empty() {
+        return None;
+    }
+    if title.chars().count() <= MAX_CHAPTER_TITLE {
+        return Some(title);
+    }
+    let cut: String = title.chars().take(MAX_CHAPTER_TITLE - 1).collect();
+    Some(format!("{}…", cut.trim_end()))
+}
+
+// Strips leading zeros so volumes "03" and "3" compare equal.
+pub fn normalize_number(number: &str) -> String {
+    let number = number.trim();
+    let trimmed = number.trim_start_matches('0');
+    if trimmed.is_empty() || trimmed.starts_with('.') {
+        format!("0{}", trimmed)
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    // The source's own template; no test sets one from the config.
+    fn guess(template: &str, manga_url: &str, number: &str) -> String {
+        render_chapter_url(template, manga_url, &number.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn mirror_layouts() {
+        let manga = "https://mirror.test/manga/fixture-tales";
+        for (template, number, expected) in [
+            (
+                "{manga_url}/chapter-{chapter}",
+                "12",
+                "/manga/fixture-tales/chapter-12",
+            ),
+            (
+                "{manga_url}/chapter_{chapter}",
+                "12.5",
+                "/manga/fixture-tales/chapter_12.5",
+            ),
+            ("{manga_url}/c{chapter}", "7", "/manga/fixture-tales/c7"),
+            (
+                "{manga_url}/ch/{chapter:03}.html",
+                "7",
+                "/manga/fixture-tales/ch/007.html",
+            ),
+            (
+                "{manga_url}?chapter={chapter}",
+                "3",
+                "/manga/fixture-tales?chapter=3",
+            ),
+        ] {
+            assert_eq!(
+                guess(template, manga, number),
+                format!("https://mirror.test{}", expected),
+                "{}",
+                template
+            );
+        }
+        // Extras and a trailing slash on the series URL.
+        assert_eq!(
+            guess(
+                "{manga_url}/c{chapter}",
+                "https://mirror.test/m/x/",
+                "12 extra"
+            ),
+            "https://mirror.test/m/x/c12-extra"
+        );
+    }
+
+    #[test]
+    fn templates_need_both_placeholders() {
+        assert!(parse_chapter_url_template("{manga_url}/c{chapter}").is_ok());
+        for (template, problem) in [
+            ("{manga_url}/chapter", "missing {chapter}"),
+            ("https://mirror.test/c{chapter}", "missing {manga_url}"),
+            ("{manga_url}/c{number}", "unknown placeholder {number}"),
+            ("{manga_url}/c{chapter", "unclosed"),
+            ("{manga_url}/c{chapter:x}", "invalid width"),
+        ] {
+            let error = parse_chapter_url_template(template).err().unwrap();
+            assert!(error.contains(problem), "{}: {}", template, error);
+        }
+    }
+
+    #[test]
+    fn bad_templates_fail_config_loading() {
+        let config: Config =
+            toml::from_str("chapter_url_template = \"{manga_url}/c{chapter}\"").unwrap();
+        assert!(config.chapter_url_template.is_some());
+        let error = toml::from_str::<Config>("chapter_url_template = \"{manga_url}/c\"")
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().contains("invalid chapter_url_template"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn unsupported_flags_name_the_sources_that_support_them() {
+        let everything = |_: &str| true;
+        let manganelo = source(SourceKind::Manganelo, &[], false);
+        assert_eq!(
+            unsupported_flags(manganelo.as_ref(), everything),
+            ["--group (only mangadex can)", "--lang (only mangadex can)"]
+        );
+        let mangadex = source(SourceKind::Mangadex, &[], false);
+        assert_eq!(
+            unsupported_flags(mangadex.as_ref(), everything),
+            ["--mirror (only manganelo can)"]
+        );
+        // Flags that weren't given aren't checked.
+        let lang_only = |flag: &str| flag == "lang";
+        assert!(unsupported_flags(mangadex.as_ref(), lang_only).is_empty());
+        assert_eq!(
+            unsupported_flags(manganelo.as_ref(), lang_only),
+            ["--lang (only mangadex can)"]
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use crate::error::Error;
+use std::path::Path;
+
+// Fails with Error::NoSpace when the filesystem holding `path` has less than
+// `needed` bytes free. Filesystems whose free space can't be read pass.
+pub fn check(path: &str, needed: u64) -> Result<(), Error> {
+    let Some(available) = available(Path::new(path)) else {
+        return Ok(());
+    };
+    log::debug!("{} bytes free for {}, {} needed", available, path, needed);
+    if available >= needed {
+        return Ok(());
+    }
+    Err(Error::NoSpace {
+        path: path.to_string(),
+        needed,
+        available,
+    })
+}
+
+// Free space for unprivileged users on the filesystem of `path`, or of its
+// closest existing parent.
+#[cfg(unix)]
+fn available(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path.ancestors().find(|dir| dir.exists()).unwrap_or(path);
+    let existing = if existing.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        existing
+    };
+    let c_path = CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stats is a valid statvfs to fill.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available(_path: &Path) -> Option<u64> {
+    None
+}
@@ -0,0 +1,203 @@
+use crate::chapter_id::ChapterId;
+use crate::decode;
+use clap::ArgEnum;
+use image::{ColorType, DynamicImage, GenericImageView, Pixel, Rgba, RgbaImage};
+use rayon::prelude::*;
+use std::fs;
+use std::path::Path;
+
+type StampResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Stamped copies are written here in the work directory, so the pages kept
+// in the series cache stay clean.
+const STAMPED_DIR: &str = "stamped";
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+// A 5x7 bitmap font with what page labels need, one row per byte with the
+// leftmost pixel in bit 4. Other characters are left blank.
+const FONT: &[(char, [u8; 7])] = &[
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+    ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+    ('c', [0x00, 0x00, 0x0E, 0x10, 0x10, 0x11, 0x0E]),
+    ('p', [0x00, 0x00, 0x1E, 0x11, 0x1E, 0x10, 0x10]),
+];
+
+// Where on the page the number goes (--stamp-pages).
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Clone)]
+pub struct StampOptions {
+    pub corner: Corner,
+    // "c12 p3" rather than the page's number in the output.
+    pub chapter: bool,
+    // 0-1.
+    pub opacity: f64,
+    // Height of the digits in percent of the page height.
+    pub size: f64,
+}
+
+// What is stamped on each of `count` pages. `chapter_starts` gives the first
+// page of each chapter and its number.
+pub fn labels(
+    count: usize,
+    chapter_starts: &[(usize, Option<ChapterId>)],
+    options: &StampOptions,
+) -> Vec<String> {
+    (0..count)
+        .map(|i| {
+            let start = chapter_starts.iter().rev().find(|(first, _)| *first <= i);
+            match start {
+                Some((first, Some(number))) if options.chapter => {
+                    format!("c{} p{}", number, i - first + 1)
+                }
+                Some((first, None)) if options.chapter => format!("p{}", i - first + 1),
+                _ => (i + 1).to_string(),
+            }
+        })
+        .collect()
+}
+
+// Writes copies of `pages` with their `labels` stamped on into the work
+// directory `dir` and returns their paths.
+pub fn stamp_pages(
+    pages: &[String],
+    labels: &[String],
+    dir: &str,
+    options: &StampOptions,
+) -> StampResult<Vec<String>> {
+    let stamped_dir = Path::new(dir).join(STAMPED_DIR);
+    fs::create_dir_all(&stamped_dir)?;
+    pages
+        .par_iter()
+        .zip(labels)
+        .map(|(page, label)| {
+            let reader = decode::reader(page)?;
+            let format = reader.format().ok_or("Unrecognized image format")?;
+            let img = reader.decode()?;
+            let color = img.color();
+            let mut canvas = img.to_rgba8();
+            stamp(&mut canvas, label, options);
+            let canvas = DynamicImage::ImageRgba8(canvas);
+            // Back to the page's own kind of pixels; JPEG has no alpha.
+            let stamped = match color {
+                ColorType::L8 => DynamicImage::ImageLuma8(canvas.to_luma8()),
+                color if color.has_alpha() => canvas,
+                _ => DynamicImage::ImageRgb8(canvas.to_rgb8()),
+            };
+            let path = stamped_dir.join(Path::new(page).file_name().unwrap());
+            stamped.save_with_format(&path, format)?;
+            Ok(path.to_string_lossy().into_owned())
+        })
+        .collect()
+}
+
+// Draws `label` into the corner, one text height away from the edges. The
+// text is dark on light pages and light on dark ones, with an outline in the
+// other shade so it stays readable on anything.
+fn stamp(img: &mut RgbaImage, label: &str, options: &StampOptions) {
+    let scale =
+        ((img.height() as f64 * options.size / 100.0 / GLYPH_HEIGHT as f64).round() as u32).max(1);
+    let glyphs: Vec<[u8; 7]> = label
+        .chars()
+        .map(|c| {
+            FONT.iter()
+                .find(|(glyph, _)| *glyph == c)
+                .map_or([0; 7], |(_, rows)| *rows)
+        })
+        .collect();
+    // In font pixels, with a one pixel outline all around.
+    let columns = glyphs.len() as u32 * (GLYPH_WIDTH + 1) + 1;
+    let rows = GLYPH_HEIGHT + 2;
+    let inked = |column: i64, row: i64| {
+        let (x, y) = (column - 1, row - 1);
+        if x < 0 || y < 0 || y >= GLYPH_HEIGHT as i64 {
+            return false;
+        }
+        let (glyph, x) = (x as u32 / (GLYPH_WIDTH + 1), x as u32 % (GLYPH_WIDTH + 1));
+        x < GLYPH_WIDTH
+            && glyphs
+                .get(glyph as usize)
+                .is_some_and(|rows| rows[y as usize] & (0x10 >> x) != 0)
+    };
+
+    let (width, height) = (columns * scale, rows * scale);
+    let margin = GLYPH_HEIGHT * scale;
+    let right = img.width().saturating_sub(width + margin);
+    let bottom = img.height().saturating_sub(height + margin);
+    let (left, top) = match options.corner {
+        Corner::TopLeft => (margin.min(right), margin.min(bottom)),
+        Corner::TopRight => (right, margin.min(bottom)),
+        Corner::BottomLeft => (margin.min(right), bottom),
+        Corner::BottomRight => (right, bottom),
+    };
+
+    let (text, outline) = if mean_luma(img, left, top, width, height) > 127.0 {
+        (Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255]))
+    } else {
+        (Rgba([255, 255, 255, 255]), Rgba([0, 0, 0, 255]))
+    };
+    for row in 0..rows as i64 {
+        for column in 0..columns as i64 {
+            let color = if inked(column, row) {
+                text
+            } else if (-1..=1).any(|dy| (-1..=1).any(|dx| inked(column + dx, row + dy))) {
+                outline
+            } else {
+                continue;
+            };
+            for y in 0..scale {
+                for x in 0..scale {
+                    let (x, y) = (
+                        left + column as u32 * scale + x,
+                        top + row as u32 * scale + y,
+                    );
+                    if x < img.width() && y < img.height() {
+                        blend(img.get_pixel_mut(x, y), color, options.opacity);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn mean_luma(img: &RgbaImage, left: u32, top: u32, width: u32, height: u32) -> f64 {
+    let view = img.view(
+        left,
+        top,
+        width.min(img.width() - left),
+        height.min(img.height() - top),
+    );
+    let (sum, count) = view
+        .pixels()
+        .fold((0u64, 0u64), |(sum, count), (_, _, pixel)| {
+            (sum + pixel.to_luma()[0] as u64, count + 1)
+        });
+    sum as f64 / count.max(1) as f64
+}
+
+fn blend(pixel: &mut Rgba<u8>, color: Rgba<u8>, opacity: f64) {
+    for channel in 0..3 {
+        let mixed = pixel[channel] as f64 * (1.0 - opacity) + color[channel] as f64 * opacity;
+        pixel[channel] = mixed.round() as u8;
+    }
+    // Transparent pages get an opaque number.
+    pixel[3] = pixel[3].max((opacity * 255.0).round() as u8);
+}
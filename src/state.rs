@@ -0,0 +1,314 @@
+use crate::history::HistoryStore;
+use crate::quarantine::FailureStore;
+use crate::series::SeriesStore;
+use crate::tar;
+use crate::ui;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+// Every file of the export sits in this directory of the archive.
+const ROOT: &str = "manga-cli-state";
+const MARKER_FILE: &str = "state.json";
+// Bumped whenever a store changes in a way older versions can't read.
+const STATE_VERSION: u32 = 1;
+
+// What the export is, so an import can tell whether it understands it.
+#[derive(Serialize, Deserialize)]
+struct Marker {
+    version: u32,
+    #[serde(default)]
+    manga_cli: String,
+    // RFC 3339.
+    #[serde(default)]
+    exported: String,
+}
+
+// Writes the followed series and their settings, the reading history and the
+// failed chapters into a .tar.gz, to be imported on another machine. What
+// only makes sense on this one (transfer counts, mirror timings, usage, the
+// last selection) stays behind.
+pub fn export(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let now = OffsetDateTime::now_utc();
+    let marker = Marker {
+        version: STATE_VERSION,
+        manga_cli: env!("CARGO_PKG_VERSION").to_string(),
+        exported: now.format(&Rfc3339).unwrap_or_default(),
+    };
+    let series = SeriesStore::load();
+    let files = [
+        (MARKER_FILE, serde_json::to_string_pretty(&marker)?),
+        ("series.json", serde_json::to_string_pretty(&series)?),
+        (
+            "history.json",
+            serde_json::to_string_pretty(&HistoryStore::load())?,
+        ),
+        (
+            "failures.json",
+            serde_json::to_string_pretty(&FailureStore::load())?,
+        ),
+    ];
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    let mut archive = GzEncoder::new(File::create(&tmp_path)?, Compression::default());
+    let mtime = now.unix_timestamp().max(0) as u64;
+    for (name, data) in &files {
+        tar::entry(
+            &mut archive,
+            &format!("{}/{}", ROOT, name),
+            data.as_bytes(),
+            mtime,
+        )?;
+    }
+    tar::finish(&mut archive)?;
+    archive.finish()?;
+    fs::rename(&tmp_path, path)?;
+    println!(
+        "Exported {} followed series to {}",
+        series.followed().len(),
+        path.display()
+    );
+    Ok(())
+}
+
+// Merges an export into the state here. Series and settings only the export
+// has are added, reading history and failures keep the later record, and
+// settings both have but differ on take the value of the side that changed
+// the series last and are listed. Nothing is written unless the whole export
+// could be read.
+pub fn import(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = Vec::new();
+    GzDecoder::new(File::open(path)?)
+        .read_to_end(&mut data)
+        .map_err(|e| format!("{} is not a manga-cli state export: {}", path.display(), e))?;
+    let files = tar::files(&data)?;
+    // Only known names are read, but an export naming files outside its
+    // directory was not written by manga-cli.
+    if let Some((name, _)) = files.iter().find(|(name, _)| {
+        !Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    }) {
+        return Err(format!(
+            "{} is not a manga-cli state export: it has an entry {} outside {}",
+            path.display(),
+            name,
+            ROOT
+        )
+        .into());
+    }
+    let file = |name: &str| {
+        let wanted = format!("{}/{}", ROOT, name);
+        files
+            .iter()
+            .find(|(path, _)| *path == wanted)
+            .map(|(_, data)| data.as_slice())
+    };
+
+    let marker: Marker = match file(MARKER_FILE) {
+        Some(data) => parse(MARKER_FILE, data)?,
+        None => {
+            return Err(format!(
+                "{} is not a manga-cli state export: it has no {}/{}",
+                path.display(),
+                ROOT,
+                MARKER_FILE
+            )
+            .into())
+        }
+    };
+    if marker.version > STATE_VERSION {
+        return Err(format!(
+            "{} was exported by manga-cli {} in state format {}, but this is manga-cli {}, \
+             which reads up to format {}; update manga-cli before importing it.",
+            path.display(),
+            if marker.manga_cli.is_empty() {
+                "(unknown version)"
+            } else {
+                &marker.manga_cli
+            },
+            marker.version,
+            env!("CARGO_PKG_VERSION"),
+            STATE_VERSION
+        )
+        .into());
+    }
+    let series: Option<SeriesStore> = file("series.json")
+        .map(|data| parse("series.json", data))
+        .transpose()?;
+    let history: Option<HistoryStore> = file("history.json")
+        .map(|data| parse("history.json", data))
+        .transpose()?;
+    let failures: Option<FailureStore> = file("failures.json")
+        .map(|data| parse("failures.json", data))
+        .transpose()?;
+
+    println!(
+        "Importing {}{}",
+        path.display(),
+        if marker.exported.is_empty() {
+            String::new()
+        } else {
+            format!(", exported {}", marker.exported)
+        }
+    );
+    let mut conflicts = Vec::new();
+    if let Some(theirs) = series {
+        let mut ours = SeriesStore::load();
+        let (added, differ) = ours.merge(theirs);
+        ours.save()?;
+        conflicts = differ;
+        println!("  {} series added", added);
+    }
+    if let Some(theirs) = history {
+        let mut ours = HistoryStore::load();
+        let merged = ours.merge(theirs);
+        ours.save()?;
+        println!("  {} read chapters added or updated", merged);
+    }
+    if let Some(theirs) = failures {
+        let mut ours = FailureStore::load();
+        let merged = ours.merge(theirs);
+        ours.save()?;
+        println!("  {} failed chapters added or updated", merged);
+    }
+    if !conflicts.is_empty() {
+        ui::warn("these settings differ in the export; the newer value was kept:");
+        for conflict in &conflicts {
+            println!("  {}", conflict);
+        }
+    }
+    Ok(())
+}
+
+fn parse<T: DeserializeOwned>(name: &str, data: &[u8]) -> Result<T, String> {
+    serde_json::from_slice(data).map_err(|e| format!("Can't read {} in the export: {}", name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::series::SeriesMeta;
+    use crate::testdir::TestDir;
+
+    // A .tar.gz in `dir` holding `files` under their names.
+    fn archive(dir: &TestDir, files: &[(&str, String)]) -> std::path::PathBuf {
+        let path = dir.join("state.tar.gz");
+        let mut out = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        for (name, data) in files {
+            tar::entry(&mut out, name, data.as_bytes(), 0).unwrap();
+        }
+        tar::finish(&mut out).unwrap();
+        out.finish().unwrap();
+        path
+    }
+
+    fn marker(version: u32) -> (&'static str, String) {
+        (
+            "manga-cli-state/state.json",
+            format!("{{\"version\": {}, \"manga_cli\": \"9.0.0\"}}", version),
+        )
+    }
+
+    fn series(group: &str, modified: &str) -> SeriesMeta {
+        SeriesMeta {
+            group: Some(group.to_string()),
+            followed: true,
+            modified: Some(modified.to_string()),
+            ..SeriesMeta::default()
+        }
+    }
+
+    #[test]
+    fn exports_of_a_newer_format_are_refused_whole() {
+        let dir = TestDir::new("state-newer");
+        dir.use_as_data_dir();
+        let mut store = SeriesStore::default();
+        *store.get_mut("https://site/a") = series("Ours", "2026-01-01T00:00:00Z");
+        let path = archive(
+            &dir,
+            &[
+                marker(STATE_VERSION + 1),
+                (
+                    "manga-cli-state/series.json",
+                    serde_json::to_string(&store).unwrap(),
+                ),
+            ],
+        );
+        let e = import(&path).unwrap_err().to_string();
+        assert!(
+            e.contains("9.0.0") && e.contains("update manga-cli"),
+            "{}",
+            e
+        );
+        assert!(SeriesStore::load().followed().is_empty());
+    }
+
+    #[test]
+    fn entries_outside_the_export_are_refused() {
+        let dir = TestDir::new("state-dotdot");
+        dir.use_as_data_dir();
+        let mut store = SeriesStore::default();
+        *store.get_mut("https://site/a") = series("Theirs", "2026-01-01T00:00:00Z");
+        let path = archive(
+            &dir,
+            &[
+                marker(STATE_VERSION),
+                (
+                    "manga-cli-state/../series.json",
+                    serde_json::to_string(&store).unwrap(),
+                ),
+            ],
+        );
+        let e = import(&path).unwrap_err().to_string();
+        assert!(e.contains("manga-cli-state/../series.json"), "{}", e);
+        assert!(SeriesStore::load().followed().is_empty());
+        assert!(!dir.path.join("series.json").exists());
+    }
+
+    #[test]
+    fn the_newer_setting_wins() {
+        let there = TestDir::new("state-there");
+        there.use_as_data_dir();
+        let mut store = SeriesStore::load();
+        *store.get_mut("https://site/a") = series("Newer there", "2026-03-01T00:00:00Z");
+        *store.get_mut("https://site/b") = series("Older there", "2026-01-01T00:00:00Z");
+        store.get_mut("https://site/c").group = Some("Only there".to_string());
+        store.save().unwrap();
+        let path = there.join("state.tar.gz");
+        export(&path).unwrap();
+
+        let here = TestDir::new("state-here");
+        here.use_as_data_dir();
+        let mut store = SeriesStore::load();
+        *store.get_mut("https://site/a") = series("Older here", "2026-02-01T00:00:00Z");
+        *store.get_mut("https://site/b") = series("Newer here", "2026-02-01T00:00:00Z");
+        store.save().unwrap();
+        import(&path).unwrap();
+
+        let store = SeriesStore::load();
+        let a = store.get("https://site/a");
+        assert_eq!(a.group.as_deref(), Some("Newer there"));
+        assert_eq!(a.modified.as_deref(), Some("2026-03-01T00:00:00Z"));
+        assert_eq!(
+            store.get("https://site/b").group.as_deref(),
+            Some("Newer here")
+        );
+        assert_eq!(
+            store.get("https://site/c").group.as_deref(),
+            Some("Only there")
+        );
+        // Saving stamped the series changed without a time of their own.
+        assert!(store.get("https://site/c").modified.is_some());
+    }
+}
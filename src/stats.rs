@@ -0,0 +1,213 @@
+use crate::profile::percentile;
+use crate::report::{format_bytes, Report};
+use crate::series::data_dir;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE_FILE: &str = "usage.jsonl";
+// The file is rotated to usage.jsonl.1, replacing the previous one, once it
+// grows past this, so both together stay under twice the limit.
+const MAX_USAGE_BYTES: u64 = 1024 * 1024;
+const DAY_SECONDS: u64 = 24 * 60 * 60;
+
+// One run, as recorded when `usage_stats = true` is set in the config file.
+// Titles and URLs are left out; only the source, its mirror and numbers are
+// kept.
+#[derive(Serialize, Deserialize)]
+pub struct UsageRecord {
+    // Seconds since the Unix epoch.
+    pub finished_at: u64,
+    pub source: String,
+    pub mirror: Option<String>,
+    pub success: bool,
+    // Kind of error that stopped the run: network, http, parse, ...
+    pub error_class: Option<String>,
+    // Time spent downloading each chapter, retries included.
+    pub chapter_seconds: Vec<f64>,
+    pub pages: usize,
+    pub failed_pages: usize,
+    pub retries: usize,
+    pub bytes: u64,
+    pub elapsed_seconds: f64,
+}
+
+impl UsageRecord {
+    pub fn new(report: &Report, error_class: Option<&str>) -> UsageRecord {
+        UsageRecord {
+            finished_at: now(),
+            source: report.source.clone(),
+            mirror: report.mirror.clone(),
+            success: report.success,
+            error_class: error_class.map(|class| class.to_string()),
+            chapter_seconds: report
+                .chapters
+                .iter()
+                .map(|chapter| chapter.seconds)
+                .collect(),
+            pages: report.pages_downloaded,
+            failed_pages: report
+                .chapters
+                .iter()
+                .map(|chapter| chapter.failed_pages.len())
+                .sum(),
+            retries: report
+                .chapters
+                .iter()
+                .map(|chapter| chapter.attempts.saturating_sub(1))
+                .sum(),
+            bytes: report.bytes_downloaded,
+            elapsed_seconds: report.elapsed_seconds,
+        }
+    }
+
+    // Appends the record to the usage file, rotating it first when full.
+    pub fn append(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = data_dir();
+        fs::create_dir_all(&dir)?;
+        let path = usage_path();
+        if fs::metadata(&path).is_ok_and(|metadata| metadata.len() >= MAX_USAGE_BYTES) {
+            fs::rename(&path, rotated_path())?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        writeln!(file, "{}", serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+fn usage_path() -> PathBuf {
+    data_dir().join(USAGE_FILE)
+}
+
+fn rotated_path() -> PathBuf {
+    data_dir().join(format!("{}.1", USAGE_FILE))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// Records of the last `days` days, oldest first. Lines that don't parse are
+// skipped.
+fn load(days: u64) -> Vec<UsageRecord> {
+    let since = now().saturating_sub(days * DAY_SECONDS);
+    [rotated_path(), usage_path()]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|data| {
+            data.lines()
+                .filter_map(|line| serde_json::from_str::<UsageRecord>(line).ok())
+                .collect::<Vec<_>>()
+        })
+        .filter(|record| record.finished_at >= since)
+        .collect()
+}
+
+// Reliability of each source and mirror over the last `days` days, split by
+// week with `weekly`.
+pub fn print(days: u64, weekly: bool) {
+    let records = load(days);
+    if records.is_empty() {
+        println!(
+            "No usage recorded in the last {} days. Set usage_stats = true in config.toml to record runs in {}.",
+            days,
+            usage_path().display()
+        );
+        return;
+    }
+
+    let mut groups: BTreeMap<(String, String), Vec<&UsageRecord>> = BTreeMap::new();
+    for record in &records {
+        let mirror = record.mirror.clone().unwrap_or_else(|| "-".to_string());
+        groups
+            .entry((record.source.clone(), mirror))
+            .or_default()
+            .push(record);
+    }
+    println!("Usage of the last {} days", days);
+    println!(
+        "  {:<12} {:<28} {:>5} {:>7} {:>9} {:>9} {:>8} {:>7} {:>10}",
+        "source", "mirror", "runs", "failed", "p50 chap", "p95 chap", "pages", "failed", "data"
+    );
+    for ((source, mirror), group) in &groups {
+        print_row(source, mirror, group);
+    }
+
+    let mut errors: BTreeMap<&str, usize> = BTreeMap::new();
+    for class in records
+        .iter()
+        .filter_map(|record| record.error_class.as_deref())
+    {
+        *errors.entry(class).or_default() += 1;
+    }
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors
+            .iter()
+            .map(|(class, count)| format!("{} {}", class, count))
+            .collect();
+        println!("  Errors: {}", errors.join(", "));
+    }
+
+    if weekly {
+        let mut weeks: BTreeMap<(String, u64), Vec<&UsageRecord>> = BTreeMap::new();
+        for record in &records {
+            weeks
+                .entry((
+                    record.source.clone(),
+                    record.finished_at / (7 * DAY_SECONDS),
+                ))
+                .or_default()
+                .push(record);
+        }
+        println!("By week");
+        for ((source, week), group) in &weeks {
+            let start = time::OffsetDateTime::from_unix_timestamp((week * 7 * DAY_SECONDS) as i64)
+                .map(|start| start.date().to_string())
+                .unwrap_or_default();
+            print_row(source, &format!("week of {}", start), group);
+        }
+    }
+}
+
+fn print_row(source: &str, label: &str, group: &[&UsageRecord]) {
+    let failed_runs = group.iter().filter(|record| !record.success).count();
+    let mut chapter_seconds: Vec<f64> = group
+        .iter()
+        .flat_map(|record| record.chapter_seconds.iter().copied())
+        .collect();
+    chapter_seconds.sort_by(f64::total_cmp);
+    let (p50, p95) = if chapter_seconds.is_empty() {
+        ("-".to_string(), "-".to_string())
+    } else {
+        (
+            format!("{:.0}s", percentile(&chapter_seconds, 0.50)),
+            format!("{:.0}s", percentile(&chapter_seconds, 0.95)),
+        )
+    };
+    let pages: usize = group.iter().map(|record| record.pages).sum();
+    let failed_pages: usize = group.iter().map(|record| record.failed_pages).sum();
+    let attempted = pages + failed_pages;
+    let failed_share = if attempted == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.1}%", 100.0 * failed_pages as f64 / attempted as f64)
+    };
+    println!(
+        "  {:<12} {:<28} {:>5} {:>7} {:>9} {:>9} {:>8} {:>7} {:>10}",
+        source,
+        label,
+        group.len(),
+        failed_runs,
+        p50,
+        p95,
+        pages,
+        failed_share,
+        format_bytes(group.iter().map(|record| record.bytes).sum())
+    );
+}
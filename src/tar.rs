@@ -0,0 +1,92 @@
+use std::io::{self, Write};
+
+// One file in the ustar format: a 512-byte header, then the data padded to a
+// whole block.
+pub fn entry(out: &mut impl Write, name: &str, data: &[u8], mtime: u64) -> io::Result<()> {
+    if name.len() > 100 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is too long a name for the archive", name),
+        ));
+    }
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut header[100..108], 0o644);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], data.len() as u64);
+    octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // The checksum is computed with its own field filled with spaces.
+    header[148..156].fill(b' ');
+    let checksum: u64 = header.iter().map(|&byte| byte as u64).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+
+    out.write_all(&header)?;
+    out.write_all(data)?;
+    let padding = (512 - data.len() % 512) % 512;
+    out.write_all(&vec![0; padding])
+}
+
+// The archive ends with two empty blocks.
+pub fn finish(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(&[0; 1024])
+}
+
+// The regular files of an archive, by name. Directories, links and the like
+// are left out.
+pub fn files(archive: &[u8]) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut files = Vec::new();
+    let mut offset = 0;
+    while offset + 512 <= archive.len() {
+        let header = &archive[offset..offset + 512];
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+        let size = parse_octal(&header[124..136]).ok_or_else(|| invalid("Bad size in archive"))?;
+        let start = offset + 512;
+        let end = start
+            .checked_add(size as usize)
+            .filter(|&end| end <= archive.len())
+            .ok_or_else(|| invalid("Archive is cut short"))?;
+        let mut name = text(&header[..100]);
+        // ustar keeps the start of long names in a prefix field.
+        if &header[257..262] == b"ustar" {
+            let prefix = text(&header[345..500]);
+            if !prefix.is_empty() {
+                name = format!("{}/{}", prefix, name);
+            }
+        }
+        if matches!(header[156], b'0' | 0) {
+            files.push((name, archive[start..end].to_vec()));
+        }
+        offset = start + (size as usize).div_ceil(512) * 512;
+    }
+    Ok(files)
+}
+
+// Fills a numeric header field with zero-padded octal digits and a NUL.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(digits.as_bytes());
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let digits = text(field);
+    let digits = digits.trim();
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+fn text(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
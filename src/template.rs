@@ -0,0 +1,111 @@
+// Name templates with placeholders such as "{series}_{chapter:04}_{page:03}".
+// A width after ':' zero-pads numbers; other values are used as they are.
+
+#[derive(Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone)]
+enum Part {
+    Text(String),
+    Field { name: String, width: usize },
+}
+
+impl Template {
+    // Accepts only the placeholders in `fields`, so typos fail at startup
+    // instead of producing odd names later.
+    pub fn parse(template: &str, fields: &[&str]) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or(format!("unclosed '{{' in \"{}\"", template))?
+                + start;
+            let field = &rest[start + 1..end];
+            let (name, width) = match field.split_once(':') {
+                Some((name, width)) => (
+                    name,
+                    width
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid width in {{{}}}", field))?,
+                ),
+                None => (field, 0),
+            };
+            if !fields.contains(&name) {
+                return Err(format!(
+                    "unknown placeholder {{{}}}; use one of {}",
+                    name,
+                    fields
+                        .iter()
+                        .map(|field| format!("{{{}}}", field))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+            parts.push(Part::Field {
+                name: name.to_string(),
+                width,
+            });
+            rest = &rest[end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("unmatched '}}' in \"{}\"", template));
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+
+    pub fn uses(&self, field: &str) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Field { name, .. } if name == field))
+    }
+
+    pub fn literal_text(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Text(text) => Some(text.as_str()),
+            Part::Field { .. } => None,
+        })
+    }
+
+    // Missing values render as empty strings.
+    pub fn render(&self, values: &[(&str, &str)]) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Field { name, width } => {
+                    let value = values
+                        .iter()
+                        .find(|(field, _)| field == name)
+                        .map(|(_, value)| *value)
+                        .unwrap_or_default();
+                    rendered.push_str(&pad(value, *width));
+                }
+            }
+        }
+        rendered
+    }
+}
+
+// Pads the whole-number part, so chapter "12.5" with width 4 becomes "0012.5".
+fn pad(value: &str, width: usize) -> String {
+    let (whole, fraction) = match value.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (value, None),
+    };
+    if whole.is_empty() || !whole.chars().all(|c| c.is_ascii_digit()) {
+        return value.to_string();
+    }
+    match fraction {
+        Some(fraction) => format!("{:0>width$}.{}", whole, fraction, width = width),
+        None => format!("{:0>width$}", whole, width = width),
+    }
+}
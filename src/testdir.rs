@@ -0,0 +1,39 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A fresh folder for one test, removed afterwards unless the test failed.
+pub struct TestDir {
+    pub path: PathBuf,
+}
+
+impl TestDir {
+    pub fn new(test: &str) -> TestDir {
+        let path = env::temp_dir().join(format!("manga-cli-unit-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.path.join(name)
+    }
+
+    // Points data_dir() here for the rest of the calling thread.
+    pub fn use_as_data_dir(&self) {
+        let path = self.path.clone();
+        crate::series::TEST_DATA_DIR.with(|dir| *dir.borrow_mut() = Some(path));
+    }
+
+    pub fn str(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::exec::Tool;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// The ImageMagick command on PATH: `magick` since version 7, `convert` on
+// older installs.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ImageMagick {
+    Magick,
+    Convert,
+}
+
+static IMAGEMAGICK: OnceLock<Option<ImageMagick>> = OnceLock::new();
+
+impl ImageMagick {
+    pub fn program(self) -> &'static str {
+        match self {
+            ImageMagick::Magick => "magick",
+            ImageMagick::Convert => "convert",
+        }
+    }
+
+    // A command ready for convert's arguments.
+    pub fn command(self) -> Tool {
+        let tool = Tool::new(self.program());
+        if self == ImageMagick::Magick {
+            tool.arg("convert")
+        } else {
+            tool
+        }
+    }
+}
+
+// Looks ImageMagick up once per run.
+pub fn imagemagick() -> Option<ImageMagick> {
+    *IMAGEMAGICK.get_or_init(|| {
+        // Windows ships an unrelated convert.exe.
+        let candidates: &[ImageMagick] = if cfg!(windows) {
+            &[ImageMagick::Magick]
+        } else {
+            &[ImageMagick::Magick, ImageMagick::Convert]
+        };
+        let found = candidates
+            .iter()
+            .find_map(|tool| find_program(tool.program()).map(|path| (*tool, path)));
+        match &found {
+            Some((tool, path)) => log::debug!(
+                "Found ImageMagick as {} at {}",
+                tool.program(),
+                path.display()
+            ),
+            None => log::debug!("Neither magick nor convert is on PATH"),
+        }
+        found.map(|(tool, _)| tool)
+    })
+}
+
+// Where `program` is in the PATH directories.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(program).with_extension(env::consts::EXE_EXTENSION))
+        .find(|candidate| is_executable(candidate))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,113 @@
+use crate::http;
+use crate::report::format_bytes;
+use crate::series::data_dir;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use time::OffsetDateTime;
+
+const TRANSFER_FILE: &str = "transfer.json";
+
+// What this process received and already added to the monthly total.
+static RECORDED: AtomicU64 = AtomicU64::new(0);
+
+// Bytes received from the network in one calendar month (UTC), for metered
+// connections. A new month starts again from zero.
+#[derive(Serialize, Deserialize)]
+pub struct MonthlyTransfer {
+    // Like "2024-05".
+    pub month: String,
+    pub bytes: u64,
+}
+
+impl MonthlyTransfer {
+    // This month's total, zero when none was recorded or the file is from an
+    // earlier month.
+    pub fn load() -> MonthlyTransfer {
+        let month = current_month();
+        fs::read_to_string(transfer_path())
+            .ok()
+            .and_then(|data| serde_json::from_str::<MonthlyTransfer>(&data).ok())
+            .filter(|transfer| transfer.month == month)
+            .unwrap_or(MonthlyTransfer { month, bytes: 0 })
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(data_dir())?;
+        let path = transfer_path();
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+fn transfer_path() -> PathBuf {
+    data_dir().join(TRANSFER_FILE)
+}
+
+fn current_month() -> String {
+    let today = OffsetDateTime::now_utc().date();
+    format!("{}-{:02}", today.year(), today.month() as u8)
+}
+
+// Adds what was received since the last call to this month's total and
+// returns the new total.
+pub fn record() -> Result<u64, Box<dyn std::error::Error>> {
+    let received = http::transferred();
+    let unrecorded = received - RECORDED.swap(received, Ordering::Relaxed);
+    let mut transfer = MonthlyTransfer::load();
+    if unrecorded > 0 {
+        transfer.bytes += unrecorded;
+        transfer.save()?;
+    }
+    Ok(transfer.bytes)
+}
+
+// This month's total so far, including what this run hasn't recorded yet.
+pub fn this_month() -> u64 {
+    MonthlyTransfer::load().bytes + http::transferred() - RECORDED.load(Ordering::Relaxed)
+}
+
+// Fails once this month's total reached `cap`, so no new chapter is started.
+pub fn check_cap(cap: u64) -> Result<(), String> {
+    let used = this_month();
+    if used < cap {
+        return Ok(());
+    }
+    Err(format!(
+        "This month's transfer ({}) reached monthly_cap ({}) from config.toml, so no more chapters are started. Pass --ignore-monthly-cap to download anyway, or raise the cap.",
+        format_bytes(used),
+        format_bytes(cap)
+    ))
+}
+
+// Prints this month's total, or zeroes it with `reset`.
+pub fn print(cap: Option<u64>, reset: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if reset {
+        MonthlyTransfer {
+            month: current_month(),
+            bytes: 0,
+        }
+        .save()?;
+        println!("Reset this month's transfer to 0 B.");
+        return Ok(());
+    }
+    let transfer = MonthlyTransfer::load();
+    match cap {
+        Some(cap) => println!(
+            "Transferred in {}: {} of the {} monthly cap ({:.0}%)",
+            transfer.month,
+            format_bytes(transfer.bytes),
+            format_bytes(cap),
+            100.0 * transfer.bytes as f64 / cap.max(1) as f64
+        ),
+        None => println!(
+            "Transferred in {}: {}",
+            transfer.month,
+            format_bytes(transfer.bytes)
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,359 @@
+use crate::report::format_bytes;
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+// Plain mode prints a status line at most this often while a chapter
+// downloads.
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+// Pages downloaded within this window give the throughput ETAs go by. Below
+// RATE_MIN_PAGES pages or a RATE_MIN_SPAN, the estimate would be noise.
+const RATE_WINDOW: Duration = Duration::from_secs(30);
+const RATE_MIN_PAGES: usize = 4;
+const RATE_MIN_SPAN: Duration = Duration::from_secs(2);
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+static NO_COLOR: AtomicBool = AtomicBool::new(false);
+// Whether a progress line is being redrawn on stderr, without a newline yet.
+static LINE_OPEN: AtomicBool = AtomicBool::new(false);
+// When each page of the window finished, and its bytes. Kept across
+// chapters, so a new chapter's ETA doesn't start from nothing.
+static PAGES: Mutex<VecDeque<(Instant, u64)>> = Mutex::new(VecDeque::new());
+
+// SGR codes of the styles output uses.
+const BOLD: &str = "1";
+const DIM: &str = "2";
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+
+// Plain output (--plain) has no colors or cursor movement, for screen readers
+// and logs. TERM=dumb and NO_COLOR turn it on as well.
+pub fn set_plain(plain: bool) {
+    let dumb = env::var("TERM").is_ok_and(|term| term == "dumb");
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    PLAIN.store(plain || dumb || no_color, Ordering::Relaxed);
+}
+
+pub fn plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+// --no-color keeps the progress redraw but drops colors.
+pub fn set_no_color(no_color: bool) {
+    NO_COLOR.store(no_color, Ordering::Relaxed);
+}
+
+// Styles are only used on a terminal, so logs and pipes get plain text.
+fn styled(text: &str, code: &str, terminal: bool) -> String {
+    if plain() || NO_COLOR.load(Ordering::Relaxed) || !terminal {
+        return text.to_string();
+    }
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+// Series titles.
+pub fn bold(text: &str) -> String {
+    styled(text, BOLD, io::stdout().is_terminal())
+}
+
+// Indices and other text to read past.
+pub fn dim(text: &str) -> String {
+    styled(text, DIM, io::stdout().is_terminal())
+}
+
+pub fn success(text: &str) -> String {
+    styled(text, GREEN, io::stdout().is_terminal())
+}
+
+pub fn failure(text: &str) -> String {
+    styled(text, RED, io::stdout().is_terminal())
+}
+
+pub fn caution(text: &str) -> String {
+    styled(text, YELLOW, io::stdout().is_terminal())
+}
+
+pub fn warn(message: &str) {
+    println!("{} {}", caution("Warning:"), message);
+}
+
+// Errors go to stderr, in red when that's a terminal.
+pub fn error(message: &str) {
+    eprintln!("{}", styled(message, RED, io::stderr().is_terminal()));
+}
+
+// Ends a progress line cut short by a panic before the message is printed, so
+// it starts on a line of its own.
+pub fn install_panic_hook() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if LINE_OPEN.swap(false, Ordering::Relaxed) {
+            eprintln!();
+        }
+        default(info);
+    }));
+}
+
+// Columns `text` takes up on a terminal; CJK and fullwidth characters take
+// two.
+pub fn width(text: &str) -> usize {
+    text.width()
+}
+
+// `text` cut to at most `max` columns, ending in "…" when something was cut.
+pub fn truncate(text: &str, max: usize) -> String {
+    if text.width() <= max {
+        return text.to_string();
+    }
+    let mut cut = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let c_width = c.width().unwrap_or(0);
+        if used + c_width + 1 > max {
+            break;
+        }
+        cut.push(c);
+        used += c_width;
+    }
+    if max > 0 {
+        cut.push('…');
+    }
+    cut
+}
+
+// `text` followed by enough spaces to fill `columns`; format!'s padding
+// counts characters, which misaligns wide ones.
+pub fn pad(text: &str, columns: usize) -> String {
+    format!(
+        "{}{}",
+        text,
+        " ".repeat(columns.saturating_sub(text.width()))
+    )
+}
+
+// Columns of the terminal, from $COLUMNS or the terminal on stdout. None when
+// output doesn't go to a terminal of known size.
+pub fn terminal_width() -> Option<usize> {
+    if let Some(columns) = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+    {
+        return Some(columns);
+    }
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    terminal_columns()
+}
+
+#[cfg(unix)]
+fn terminal_columns() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: size is a valid winsize for TIOCGWINSZ to fill.
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 {
+        return None;
+    }
+    (size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+#[cfg(not(unix))]
+fn terminal_columns() -> Option<usize> {
+    None
+}
+
+// Page count of a chapter being downloaded, with the throughput and the time
+// left for it and for the run. Redrawn in place on a terminal, otherwise
+// printed as whole lines every STATUS_INTERVAL.
+pub struct Progress {
+    label: String,
+    total: usize,
+    // Pages of the run's chapters after this one, known or estimated.
+    later: usize,
+    done: AtomicUsize,
+    redraw: bool,
+    last_status: Mutex<Instant>,
+    // Columns the line took when last drawn.
+    drawn: Mutex<usize>,
+}
+
+impl Progress {
+    pub fn new(label: &str, done: usize, total: usize, later: usize) -> Progress {
+        let progress = Progress {
+            label: label.to_string(),
+            total,
+            later,
+            done: AtomicUsize::new(done),
+            redraw: !plain() && io::stderr().is_terminal(),
+            last_status: Mutex::new(Instant::now()),
+            drawn: Mutex::new(0),
+        };
+        if progress.redraw {
+            progress.draw(done);
+        }
+        progress
+    }
+
+    // Counts a page done that took `bytes` to download.
+    pub fn advance(&self, bytes: u64) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        {
+            let now = Instant::now();
+            let mut pages = PAGES.lock().unwrap();
+            pages.push_back((now, bytes));
+            while pages
+                .front()
+                .is_some_and(|(at, _)| now.duration_since(*at) > RATE_WINDOW)
+            {
+                pages.pop_front();
+            }
+        }
+        if self.redraw {
+            self.draw(done);
+            return;
+        }
+        let mut last_status = self.last_status.lock().unwrap();
+        if last_status.elapsed() >= STATUS_INTERVAL {
+            *last_status = Instant::now();
+            println!("{}{}", self.label, self.status(done));
+        }
+    }
+
+    // Like ": 12/40 pages, 1.2 MiB/s, 0:35 left, 14:10 for the run".
+    fn status(&self, done: usize) -> String {
+        let mut status = format!(": {}/{} pages", done, self.total);
+        if done >= self.total && self.later == 0 {
+            return status;
+        }
+        match rate() {
+            Some((pages_per_second, bytes_per_second)) => {
+                let left = self.total.saturating_sub(done) as f64 / pages_per_second;
+                status.push_str(&format!(
+                    ", {}/s, {} left",
+                    format_bytes(bytes_per_second as u64),
+                    format_eta(left)
+                ));
+                if self.later > 0 {
+                    let all = left + self.later as f64 / pages_per_second;
+                    status.push_str(&format!(", {} for the run", format_eta(all)));
+                }
+            }
+            None => status.push_str(", estimating…"),
+        }
+        status
+    }
+
+    pub fn finish(&self) {
+        let done = self.done.load(Ordering::Relaxed);
+        if self.redraw {
+            eprintln!();
+            LINE_OPEN.store(false, Ordering::Relaxed);
+        } else {
+            println!("{}: {}/{} pages", self.label, done, self.total);
+        }
+    }
+
+    fn draw(&self, done: usize) {
+        // Carriage return and erase-line redraw the same line, which only
+        // works while it doesn't wrap. The width is read on every draw, so a
+        // resized terminal gets a line that fits; after shrinking, the old
+        // line may have wrapped, and the new one starts below it rather than
+        // over the top of it.
+        let count = self.status(done);
+        let columns = terminal_width();
+        let label = match columns {
+            Some(columns) => truncate(&self.label, columns.saturating_sub(width(&count) + 1)),
+            None => self.label.clone(),
+        };
+        let mut drawn = self.drawn.lock().unwrap();
+        let start = match columns {
+            Some(columns) if *drawn > columns => "\n",
+            _ => "\r\x1b[K",
+        };
+        eprint!("{}{}{}", start, label, count);
+        let _ = io::stderr().flush();
+        *drawn = width(&label) + width(&count);
+        LINE_OPEN.store(true, Ordering::Relaxed);
+    }
+}
+
+// Pages and bytes a second over the window, once it holds enough to go by.
+fn rate() -> Option<(f64, f64)> {
+    let pages = PAGES.lock().unwrap();
+    let (first, _) = pages.front()?;
+    let span = first.elapsed();
+    if pages.len() < RATE_MIN_PAGES || span < RATE_MIN_SPAN {
+        return None;
+    }
+    let seconds = span.as_secs_f64();
+    let bytes: u64 = pages.iter().map(|(_, bytes)| bytes).sum();
+    Some((pages.len() as f64 / seconds, bytes as f64 / seconds))
+}
+
+// Like "0:35" or "1:04:10".
+fn format_eta(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Titles as sites list them: ASCII, CJK, fullwidth Latin, Hangul with
+    // spaces and an accent written as a combining mark.
+    const TITLES: &[(&str, usize)] = &[
+        ("One Piece", 9),
+        ("ワンピース", 10),
+        ("Ｆｕｌｌｗｉｄｔｈ", 18),
+        ("나 혼자만 레벨업", 16),
+        ("Cafe\u{301} au lait", 12),
+    ];
+
+    #[test]
+    fn width_counts_columns() {
+        for (title, columns) in TITLES {
+            assert_eq!(width(title), *columns, "{}", title);
+        }
+    }
+
+    #[test]
+    fn padding_lines_up_mixed_widths() {
+        for (title, _) in TITLES {
+            let padded = pad(title, 20);
+            assert_eq!(width(&padded), 20, "{:?}", padded);
+            assert!(padded.starts_with(title));
+        }
+        // Too long already: nothing is added or cut.
+        assert_eq!(pad("ワンピース", 4), "ワンピース");
+    }
+
+    #[test]
+    fn truncation_never_splits_a_wide_character() {
+        // Every cut fits, even where a wide character would straddle it.
+        for (title, columns) in TITLES {
+            for max in 1..=*columns {
+                let cut = truncate(title, max);
+                assert!(width(&cut) <= max, "{} to {}: {:?}", title, max, cut);
+                if max < *columns {
+                    assert!(cut.ends_with('…'), "{:?}", cut);
+                }
+            }
+            assert_eq!(truncate(title, *columns), *title);
+        }
+        assert_eq!(truncate("ワンピース", 6), "ワン…");
+        assert_eq!(truncate("ワンピース", 5), "ワン…");
+        assert_eq!(truncate("ワンピース", 0), "");
+        // The combining accent stays on its letter.
+        assert_eq!(truncate("Cafe\u{301} au lait", 6), "Cafe\u{301} …");
+    }
+}
@@ -0,0 +1,317 @@
+use crate::error::Error;
+use crate::series::data_dir;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/Altair-39/manga-cli/releases/latest";
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+const CHECK_FILE: &str = "update-check.json";
+// The startup check looks for a new release at most this often, and gives up
+// quickly so an unreachable GitHub doesn't hold up the download.
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LastCheck {
+    checked_at: u64,
+}
+
+// Replaces the running executable with the latest release's build for this
+// platform, after checking it against the release's SHA256SUMS.
+pub fn self_update() -> Result<(), Box<dyn std::error::Error>> {
+    let client = client(None)?;
+    let release = latest_release(&client)?;
+    let current = env!("CARGO_PKG_VERSION");
+    if !is_newer(&release.tag_name, current) {
+        println!("manga-cli {} is up to date.", current);
+        return Ok(());
+    }
+
+    let asset =
+        platform_asset(&release.assets, env::consts::ARCH, env::consts::OS).ok_or(format!(
+            "Release {} has no build for {}-{}.",
+            release.tag_name,
+            env::consts::ARCH,
+            env::consts::OS
+        ))?;
+    let checksums = release
+        .assets
+        .iter()
+        .find(|asset| asset.name == CHECKSUMS_ASSET)
+        .ok_or(format!(
+            "Release {} has no {}, refusing to install it.",
+            release.tag_name, CHECKSUMS_ASSET
+        ))?;
+
+    println!("Downloading {} ({})...", asset.name, release.tag_name);
+    let binary = fetch(&client, &asset.browser_download_url)?;
+    let checksums =
+        String::from_utf8_lossy(&fetch(&client, &checksums.browser_download_url)?).into_owned();
+    let expected = checksum_for(&checksums, &asset.name).ok_or(format!(
+        "{} has no entry for {}.",
+        CHECKSUMS_ASSET, asset.name
+    ))?;
+    let actual = format!("{:x}", Sha256::digest(&binary));
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(format!(
+            "Checksum mismatch for {}: expected {}, got {}.",
+            asset.name, expected, actual
+        )
+        .into());
+    }
+
+    replace_executable(&binary)?;
+    println!(
+        "Updated manga-cli {} -> {}.",
+        current,
+        release.tag_name.trim_start_matches('v')
+    );
+    Ok(())
+}
+
+// Warns when a newer release exists. Runs at most once per CHECK_INTERVAL and
+// stays quiet when GitHub can't be reached.
+pub fn check_for_update() {
+    let path = data_dir().join(CHECK_FILE);
+    let last: Option<LastCheck> = fs::read_to_string(&path)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok());
+    if last.is_some_and(|last| now().saturating_sub(last.checked_at) < CHECK_INTERVAL.as_secs()) {
+        return;
+    }
+    // Recorded before checking so a failing check isn't retried every run.
+    let checked = serde_json::to_string(&LastCheck { checked_at: now() }).unwrap_or_default();
+    if let Err(e) = fs::create_dir_all(data_dir()).and_then(|_| fs::write(&path, checked)) {
+        log::debug!("Failed to record update check: {}", e);
+    }
+
+    let current = env!("CARGO_PKG_VERSION");
+    match client(Some(CHECK_TIMEOUT)).and_then(|client| latest_release(&client)) {
+        Ok(release) if is_newer(&release.tag_name, current) => println!(
+            "manga-cli {} is available (this is {}); run `manga-cli self-update`.",
+            release.tag_name.trim_start_matches('v'),
+            current
+        ),
+        Ok(_) => {}
+        Err(e) => log::debug!("Update check failed: {}", e),
+    }
+}
+
+fn client(timeout: Option<Duration>) -> Result<Client, Error> {
+    let mut builder = Client::builder().user_agent("manga-cli");
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    builder.build().map_err(|e| Error::network(RELEASES_URL, e))
+}
+
+fn latest_release(client: &Client) -> Result<Release, Error> {
+    let body = fetch(client, RELEASES_URL)?;
+    serde_json::from_slice(&body).map_err(|e| {
+        log::debug!("Unexpected release information: {}", e);
+        Error::Parse {
+            url: RELEASES_URL.to_string(),
+            what: "the latest release",
+        }
+    })
+}
+
+// Release downloads bypass the HTTP cache; they are large and fetched once.
+fn fetch(client: &Client, url: &str) -> Result<Vec<u8>, Error> {
+    let response = client.get(url).send().map_err(|e| Error::network(url, e))?;
+    let status = response.status().as_u16();
+    if status >= 400 {
+        return Err(Error::Http {
+            url: url.to_string(),
+            status,
+        });
+    }
+    Ok(response
+        .bytes()
+        .map_err(|e| Error::network(url, e))?
+        .to_vec())
+}
+
+// "v1.2.3" and "1.2.3" compare as [1, 2, 3]; pre-release suffixes are ignored.
+fn version_numbers(version: &str) -> Vec<u64> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| {
+            let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().unwrap_or(0)
+        })
+        .collect()
+}
+
+fn is_newer(release: &str, current: &str) -> bool {
+    version_numbers(release) > version_numbers(current)
+}
+
+// Release builds are the bare binaries, named after the Rust target, e.g.
+// manga-cli-x86_64-unknown-linux-gnu or manga-cli-aarch64-apple-darwin, with
+// ".exe" on Windows. Archives of them and other files listed alongside have
+// an extension and are passed over, as they'd break the executable they
+// replaced.
+fn platform_asset<'a>(assets: &'a [Asset], arch: &str, os: &str) -> Option<&'a Asset> {
+    assets
+        .iter()
+        .find(|asset| is_platform_asset(&asset.name, arch, os))
+}
+
+fn is_platform_asset(name: &str, arch: &str, os: &str) -> bool {
+    let name = name.to_lowercase();
+    let Some(target) = name.strip_prefix("manga-cli-") else {
+        return false;
+    };
+    let target = match os {
+        "windows" => match target.strip_suffix(".exe") {
+            Some(target) => target,
+            None => return false,
+        },
+        _ => target,
+    };
+    let os_names: &[&str] = match os {
+        "macos" => &["darwin", "macos"],
+        os => &[os][..],
+    };
+    let parts: Vec<&str> = target.split('-').collect();
+    !target.contains('.')
+        && parts.first() == Some(&arch)
+        && parts[1..].iter().any(|part| os_names.contains(part))
+}
+
+// SHA256SUMS lines read "<hex digest>  <file name>", with binary-mode entries
+// marking the name with a '*'.
+fn checksum_for<'a>(checksums: &'a str, name: &str) -> Option<&'a str> {
+    checksums.lines().find_map(|line| {
+        let (digest, file) = line.trim().split_once(char::is_whitespace)?;
+        (file.trim().trim_start_matches('*') == name).then_some(digest)
+    })
+}
+
+// Writes the new build next to the running executable and renames it into
+// place, so an interrupted update never leaves a half-written binary.
+fn replace_executable(binary: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let current = env::current_exe()?;
+    let staged = current.with_extension("new");
+    fs::write(&staged, binary).map_err(|e| Error::filesystem(&staged.to_string_lossy(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+
+    // Windows won't overwrite a running executable but lets it be renamed, so
+    // the old one is moved aside first and removed by the next update.
+    if cfg!(windows) {
+        let old = current.with_extension("old.exe");
+        let _ = fs::remove_file(&old);
+        fs::rename(&current, &old)?;
+        if let Err(e) = fs::rename(&staged, &current) {
+            let _ = fs::rename(&old, &current);
+            return Err(Error::filesystem(&current.to_string_lossy(), e).into());
+        }
+        return Ok(());
+    }
+    fs::rename(&staged, &current).map_err(|e| Error::filesystem(&current.to_string_lossy(), e))?;
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(names: &[&str]) -> Vec<Asset> {
+        names
+            .iter()
+            .map(|name| Asset {
+                name: name.to_string(),
+                browser_download_url: format!("https://example.com/{}", name),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_the_bare_binary_is_installed() {
+        let assets = release(&[
+            "manga-cli-x86_64-unknown-linux-gnu.tar.gz",
+            "manga-cli-x86_64-linux.zip",
+            "manga-cli-x86_64-unknown-linux-gnu.sha256",
+            "manga-cli-aarch64-unknown-linux-gnu",
+            "manga-cli-x86_64-unknown-linux-gnu",
+            "SHA256SUMS",
+        ]);
+        let asset = platform_asset(&assets, "x86_64", "linux").unwrap();
+        assert_eq!(asset.name, "manga-cli-x86_64-unknown-linux-gnu");
+        let asset = platform_asset(&assets, "aarch64", "linux").unwrap();
+        assert_eq!(asset.name, "manga-cli-aarch64-unknown-linux-gnu");
+        // Only an archive for the platform: nothing to install.
+        assert!(platform_asset(&assets[..3], "x86_64", "linux").is_none());
+    }
+
+    #[test]
+    fn platform_names() {
+        for (name, arch, os, matches) in [
+            ("manga-cli-aarch64-apple-darwin", "aarch64", "macos", true),
+            (
+                "manga-cli-x86_64-pc-windows-msvc.exe",
+                "x86_64",
+                "windows",
+                true,
+            ),
+            (
+                "manga-cli-x86_64-pc-windows-msvc",
+                "x86_64",
+                "windows",
+                false,
+            ),
+            (
+                "manga-cli-x86_64-pc-windows-msvc.zip",
+                "x86_64",
+                "windows",
+                false,
+            ),
+            (
+                "manga-cli-x86_64-unknown-linux-musl",
+                "x86_64",
+                "linux",
+                true,
+            ),
+            (
+                "manga-cli-x86_64-unknown-linux-gnu",
+                "x86_64",
+                "macos",
+                false,
+            ),
+            ("manga-cli-i686-unknown-linux-gnu", "x86_64", "linux", false),
+            ("other-x86_64-unknown-linux-gnu", "x86_64", "linux", false),
+        ] {
+            assert_eq!(is_platform_asset(name, arch, os), matches, "{}", name);
+        }
+    }
+}
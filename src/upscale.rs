@@ -0,0 +1,152 @@
+use crate::error::Error;
+use crate::exec::{self, Tool};
+use rayon::prelude::*;
+use std::fs;
+use std::path::Path;
+
+type UpscaleResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+const WORK_DIR: &str = "upscale";
+
+pub struct UpscaleOptions {
+    // Command line template using {input}/{output} for one page per run, or
+    // {indir}/{outdir} for a single run over the whole chapter.
+    pub template: String,
+    pub jobs: usize,
+    // Keep the un-upscaled page instead of failing when the command fails.
+    pub fallback: bool,
+}
+
+impl UpscaleOptions {
+    fn per_directory(&self) -> bool {
+        self.template.contains("{indir}") || self.template.contains("{outdir}")
+    }
+}
+
+pub fn upscale_pages(pages: &[String], dir: &str, options: &UpscaleOptions) -> UpscaleResult<()> {
+    let args = shlex::split(&options.template).ok_or("Invalid --upscale-cmd template")?;
+    if args.is_empty() {
+        return Err("Empty --upscale-cmd template".into());
+    }
+
+    let work_dir = Path::new(dir).join(WORK_DIR);
+    let _ = fs::remove_dir_all(&work_dir);
+    let result = if options.per_directory() {
+        upscale_directory(pages, &args, &work_dir, options)
+    } else {
+        upscale_each(pages, &args, &work_dir, options)
+    };
+    let _ = fs::remove_dir_all(&work_dir);
+    result
+}
+
+fn upscale_each(
+    pages: &[String],
+    args: &[String],
+    work_dir: &Path,
+    options: &UpscaleOptions,
+) -> UpscaleResult<()> {
+    fs::create_dir_all(work_dir)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.max(1))
+        .build()?;
+
+    pool.install(|| {
+        pages.par_iter().enumerate().try_for_each(|(i, page)| {
+            let output = work_dir.join(Path::new(page).file_name().unwrap());
+            let outcome = run(
+                args,
+                &[
+                    ("{input}", &exec::path_arg(page)),
+                    ("{output}", &path_str(&output)),
+                ],
+            )
+            .and_then(|_| replace_page(&output, page));
+            handle_failure(i + 1, outcome, options)
+        })
+    })
+}
+
+fn upscale_directory(
+    pages: &[String],
+    args: &[String],
+    work_dir: &Path,
+    options: &UpscaleOptions,
+) -> UpscaleResult<()> {
+    let in_dir = work_dir.join("in");
+    let out_dir = work_dir.join("out");
+    fs::create_dir_all(&in_dir)?;
+    fs::create_dir_all(&out_dir)?;
+    for page in pages {
+        fs::copy(page, in_dir.join(Path::new(page).file_name().unwrap()))?;
+    }
+
+    let outcome = run(
+        args,
+        &[
+            ("{indir}", &path_str(&in_dir)),
+            ("{outdir}", &path_str(&out_dir)),
+        ],
+    );
+    if outcome.is_err() {
+        return handle_failure(0, outcome, options);
+    }
+
+    // Upscalers may change the extension, so outputs are matched by file stem.
+    for (i, page) in pages.iter().enumerate() {
+        let stem = Path::new(page).file_stem().unwrap();
+        let output = fs::read_dir(&out_dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .find(|path| path.file_stem() == Some(stem));
+        let outcome = match output {
+            Some(output) => replace_page(&output, page),
+            None => Err("no output file produced".into()),
+        };
+        handle_failure(i + 1, outcome, options)?;
+    }
+    Ok(())
+}
+
+fn run(args: &[String], substitutions: &[(&str, &str)]) -> UpscaleResult<()> {
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| exec::fill(arg, substitutions))
+        .collect();
+    Tool::new(&args[0]).args(&args[1..]).run()?;
+    Ok(())
+}
+
+fn replace_page(output: &Path, page: &str) -> UpscaleResult<()> {
+    if !output.exists() {
+        return Err("no output file produced".into());
+    }
+    fs::rename(output, page)?;
+    Ok(())
+}
+
+// Page 0 stands for a failure of the whole directory run.
+fn handle_failure(
+    page: usize,
+    outcome: UpscaleResult<()>,
+    options: &UpscaleOptions,
+) -> UpscaleResult<()> {
+    let Err(e) = outcome else { return Ok(()) };
+    let target = match page {
+        0 => "chapter".to_string(),
+        page => format!("page {}", page),
+    };
+    if options.fallback {
+        println!("Upscaling {} failed, keeping original: {}", target, e);
+        Ok(())
+    } else {
+        Err(Error::Tool {
+            tool: format!("Upscaling {}", target),
+            message: e.to_string(),
+        }
+        .into())
+    }
+}
+
+fn path_str(path: &Path) -> String {
+    exec::path_arg(&path.to_string_lossy())
+}
@@ -0,0 +1,115 @@
+use crate::config::Config;
+use crate::prompt;
+use crate::series::data_dir;
+use crate::source::SourceKind;
+use crate::tools;
+use clap::ArgEnum;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+const FORMATS: &[&str] = &["pdf", "cbz", "html"];
+
+// The setup is only offered to someone at a terminal who has no config file
+// yet, never to scripts or pipes.
+pub fn wanted() -> bool {
+    !Config::path().exists() && io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+// Asks for the usual defaults and writes them to a new config file. Every
+// question has a default taken by pressing Enter.
+pub fn run(cache_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Welcome to manga-cli! A few questions set your defaults; press Enter to take the");
+    println!("one in brackets. Pass --no-wizard to skip this.");
+    println!();
+    println!("Formats: pdf (needs ImageMagick), cbz (comic archive most readers open) or html");
+    println!("(a reader for the browser).");
+    let format_default = if tools::imagemagick().is_some() {
+        "pdf"
+    } else {
+        "cbz"
+    };
+    let format = prompt::ask(
+        &format!("Output format [{}]: ", format_default),
+        Some(format_default.to_string()),
+        parse_format,
+    )?;
+    let output_dir = prompt::ask(
+        &format!("Folder for finished files [{}]: ", cache_dir),
+        Some(cache_dir.to_string()),
+        |answer| Ok(answer.to_string()),
+    )?;
+    let source = prompt::ask(
+        "Source to search, manganelo or mangadex [manganelo]: ",
+        Some(SourceKind::Manganelo),
+        |answer| SourceKind::from_str(answer, true).map_err(|_| "not a source".to_string()),
+    )?;
+    let viewer = prompt::ask(
+        "Program to open finished files with, or none [none]: ",
+        None,
+        |answer| Ok(answer.to_string()),
+    )?;
+    let jobs = prompt::ask("Pages to download at once [4]: ", Some(4), parse_jobs)?;
+
+    let mut config = String::from(concat!(
+        "# Written by the first-run setup. Flags given on the command line take\n",
+        "# precedence over these defaults.\n",
+        "[defaults]\n",
+    ));
+    let mut set = |key: &str, value: toml::Value| {
+        config.push_str(&format!("{} = {}\n", key, value));
+    };
+    set("format", toml::Value::String(format));
+    set("output-dir", toml::Value::String(output_dir.clone()));
+    if let Some(source) = source.to_possible_value() {
+        set("source", toml::Value::String(source.get_name().to_string()));
+    }
+    if !viewer.is_empty() && !viewer.eq_ignore_ascii_case("none") {
+        set("viewer", toml::Value::String(viewer));
+    }
+    set("jobs", toml::Value::Integer(jobs as i64));
+
+    let path = Config::path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, config)?;
+
+    let absolute = |dir: &str| {
+        env::current_dir()
+            .map(|current| current.join(dir))
+            .unwrap_or_else(|_| Path::new(dir).to_path_buf())
+            .display()
+            .to_string()
+    };
+    println!();
+    println!("Saved your defaults to {}.", path.display());
+    println!("Finished files go to {},", absolute(&output_dir));
+    println!(
+        "downloads in progress and the page cache to {},",
+        absolute(cache_dir)
+    );
+    println!(
+        "and history and per-series settings to {}.",
+        data_dir().display()
+    );
+    println!();
+    Ok(())
+}
+
+fn parse_jobs(answer: &str) -> Result<usize, String> {
+    match prompt::parse_index(answer)? {
+        0 => Err("at least one is needed".to_string()),
+        jobs => Ok(jobs),
+    }
+}
+
+fn parse_format(answer: &str) -> Result<String, String> {
+    let format = answer.to_lowercase();
+    if FORMATS.contains(&format.as_str()) {
+        Ok(format)
+    } else {
+        Err(format!("choose one of {}", FORMATS.join(", ")))
+    }
+}
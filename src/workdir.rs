@@ -0,0 +1,456 @@
+use crate::manifest::Manifest;
+use filetime::FileTime;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const TMP_DIR: &str = "tmp";
+const SERIES_DIR: &str = "series";
+// Files and folders still being written carry this suffix.
+pub const UNFINISHED_SUFFIX: &str = ".tmp";
+// An output replaced by a newer one of the same name is kept with this
+// suffix, e.g. "name.cbz.bak".
+pub const BACKUP_SUFFIX: &str = ".bak";
+
+// Outputs a run publishes as files, by extension.
+const OUTPUT_EXTENSIONS: [&str; 3] = ["cbz", "pdf", "html"];
+// The page of a folder-style HTML reader.
+const READER_PAGE: &str = "index.html";
+// Put in a folder while it is copied to its final place, so one a crash cut
+// short is known to be ours.
+const COPYING_MARKER: &str = ".manga-cli-copying";
+
+// Work directories untouched for this long belong to crashed runs.
+const STALE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+// A directory under <cache>/tmp that only the current run writes to, so
+// concurrent runs can't interleave pages. Removed when dropped.
+pub struct WorkDir {
+    pub path: String,
+}
+
+// Where the finished chapters of `series` are kept.
+pub fn series_dir(cache_dir: &str, series: &str) -> PathBuf {
+    series_root(cache_dir).join(series)
+}
+
+pub fn series_root(cache_dir: &str) -> PathBuf {
+    Path::new(cache_dir).join(SERIES_DIR)
+}
+
+// The longest path a work directory can get, for fitting names into it: its
+// name is a process id and a nanosecond timestamp in hex.
+pub fn longest_work_dir(cache_dir: &str) -> PathBuf {
+    Path::new(cache_dir)
+        .join(TMP_DIR)
+        .join(format!("{}-{:x}", u32::MAX, u64::MAX))
+}
+
+impl WorkDir {
+    pub fn create(cache_dir: &str) -> io::Result<WorkDir> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or(0);
+        let id = format!("{}-{:x}", std::process::id(), nanos);
+        let path = Path::new(cache_dir).join(TMP_DIR).join(id);
+        fs::create_dir_all(&path)?;
+        Ok(WorkDir {
+            path: path.to_string_lossy().into_owned(),
+        })
+    }
+
+    pub fn page(&self, number: usize) -> String {
+        format!("{}/{}.jpg", self.path, number)
+    }
+
+    // Where promote_chapter() gathers a chapter's folder.
+    pub fn staging_dir(&self) -> PathBuf {
+        Path::new(&self.path).join("promote")
+    }
+
+    // Moves a chapter's pages, renamed to `names`, and manifest into
+    // <cache>/series/<series>/<chapter>, replacing whatever was there. The
+    // pages are gathered in the work directory first and the finished
+    // directory renamed into place in one step.
+    pub fn promote_chapter(
+        &self,
+        cache_dir: &str,
+        series: &str,
+        chapter: &str,
+        pages: &[String],
+        names: &[String],
+        manifest: &Manifest,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let staging = self.staging_dir().join(chapter);
+        fs::create_dir_all(&staging)?;
+        for (page, name) in pages.iter().zip(names) {
+            fs::rename(page, staging.join(name))?;
+        }
+        manifest.save(&staging.to_string_lossy())?;
+
+        let target = series_dir(cache_dir, series).join(chapter);
+        replace(&staging, &target)?;
+        Ok(target)
+    }
+
+    // Moves a finished output file or folder from the work directory into
+    // `dir`. An older one of the same name is moved aside as a backup first
+    // and put back when the move or `verify` fails, so one of the two is
+    // always in place. The backup is kept with `keep_backup`.
+    pub fn publish(
+        &self,
+        output: &Path,
+        dir: &str,
+        verify: impl Fn(&Path) -> io::Result<()>,
+        keep_backup: bool,
+    ) -> io::Result<PathBuf> {
+        let target = Path::new(dir).join(output.file_name().unwrap_or_default());
+        fs::create_dir_all(dir)?;
+        let mut backup = target.as_os_str().to_os_string();
+        backup.push(BACKUP_SUFFIX);
+        let backup = PathBuf::from(backup);
+        let replacing = target.exists();
+        if replacing {
+            remove_path(&backup)?;
+            fs::rename(&target, &backup)?;
+        }
+
+        if let Err(e) = move_path(output, &target).and_then(|()| verify(&target)) {
+            if replacing {
+                remove_path(&target)?;
+                fs::rename(&backup, &target)?;
+            }
+            return Err(e);
+        }
+        if replacing && !keep_backup {
+            remove_path(&backup)?;
+        }
+        Ok(target)
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+// Renames `from` over `to`. An existing directory at `to` is moved aside
+// first since rename() can't replace a non-empty directory.
+fn replace(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if to.is_dir() {
+        let old = to.with_extension("old");
+        let _ = fs::remove_dir_all(&old);
+        fs::rename(to, &old)?;
+        move_path(from, to)?;
+        return fs::remove_dir_all(&old);
+    }
+    move_path(from, to)
+}
+
+// Removes a file or folder, if there is one.
+fn remove_path(path: &Path) -> io::Result<()> {
+    let removed = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match removed {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+// rename(), falling back to copying and removing for an output directory on
+// another filesystem than the cache. The copy only gets its final name once
+// complete.
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let mut unfinished = to.as_os_str().to_os_string();
+            unfinished.push(UNFINISHED_SUFFIX);
+            let unfinished = PathBuf::from(unfinished);
+            if from.is_dir() {
+                fs::create_dir_all(&unfinished)?;
+                fs::write(unfinished.join(COPYING_MARKER), "")?;
+                copy_all(from, &unfinished)?;
+                fs::remove_file(unfinished.join(COPYING_MARKER))?;
+            } else {
+                copy_all(from, &unfinished)?;
+            }
+            fs::rename(&unfinished, to)?;
+            if from.is_dir() {
+                fs::remove_dir_all(from)
+            } else {
+                fs::remove_file(from)
+            }
+        }
+        result => result,
+    }
+}
+
+// Copies keep the modification time, which carries the release date.
+fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    if !from.is_dir() {
+        fs::copy(from, to)?;
+        let modified = FileTime::from_last_modification_time(&fs::metadata(from)?);
+        return filetime::set_file_mtime(to, modified);
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        copy_all(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+// Removes outputs a crashed run left half-copied into the output directory
+// `dir`: "<output>.tmp" for a CBZ, PDF or HTML output, or a reader folder.
+// Other ".tmp" names are the user's, even beside a file of the same name,
+// and ones touched within STALE_AGE may be another run's copy still in
+// progress. Returns what was removed.
+pub fn remove_unfinished_outputs(dir: &str) -> io::Result<Vec<PathBuf>> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(Vec::new());
+    };
+    let mut removed = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !is_unfinished_output(&path) || !is_stale(&entry) {
+            continue;
+        }
+        remove_path(&path)?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+fn is_unfinished_output(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let Some(output) = name.strip_suffix(UNFINISHED_SUFFIX) else {
+        return false;
+    };
+    if path.is_dir() {
+        return path.join(COPYING_MARKER).is_file() || path.join(READER_PAGE).is_file();
+    }
+    OUTPUT_EXTENSIONS
+        .iter()
+        .any(|extension| output.ends_with(&format!(".{}", extension)))
+}
+
+fn is_stale(entry: &fs::DirEntry) -> bool {
+    entry
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > STALE_AGE)
+}
+
+// Removes work directories of runs that never finished. Returns how many
+// were removed.
+pub fn remove_stale(cache_dir: &str) -> io::Result<usize> {
+    let Ok(entries) = fs::read_dir(Path::new(cache_dir).join(TMP_DIR)) else {
+        return Ok(0);
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        if is_stale(&entry) {
+            fs::remove_dir_all(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    fn age(path: &Path, by: Duration) {
+        let then = SystemTime::now() - by;
+        filetime::set_file_mtime(path, FileTime::from_system_time(then)).unwrap();
+    }
+
+    // An archive cut off after its first entry, as when the process is
+    // killed: the writer never gets to write the central directory.
+    fn crash_writing(path: &Path) {
+        let mut zip = ZipWriter::new(fs::File::create(path).unwrap());
+        zip.start_file("001.jpg", FileOptions::default()).unwrap();
+        zip.write_all(&[0xff; 4096]).unwrap();
+        std::mem::forget(zip);
+    }
+
+    #[test]
+    fn crash_leaves_no_final_output_and_the_next_run_removes_it() {
+        let dir = TestDir::new("crash-output");
+        let unfinished = dir.join("Series c1.cbz.tmp");
+        crash_writing(&unfinished);
+        assert!(zip::ZipArchive::new(fs::File::open(&unfinished).unwrap()).is_err());
+        assert!(!dir.join("Series c1.cbz").exists());
+
+        // Too recent to tell from another run's copy.
+        assert!(remove_unfinished_outputs(dir.str()).unwrap().is_empty());
+        assert!(unfinished.exists());
+
+        age(&unfinished, STALE_AGE * 2);
+        assert_eq!(
+            remove_unfinished_outputs(dir.str()).unwrap(),
+            vec![unfinished.clone()]
+        );
+        assert!(!unfinished.exists());
+    }
+
+    #[test]
+    fn only_names_this_tool_writes_are_removed() {
+        let dir = TestDir::new("unfinished-names");
+        let ours = ["a.cbz.tmp", "b.pdf.tmp", "c.html.tmp"];
+        // The user's own, also beside a file or backup of the same name.
+        let theirs = [
+            "foo.tmp",
+            "photo.jpg",
+            "f.epub.tmp",
+            "notes.txt.tmp",
+            "notes.txt",
+            "e.tmp",
+            "e",
+            "d.tmp",
+            "d.bak",
+        ];
+        for name in ours.iter().chain(&theirs) {
+            fs::write(dir.join(name), b"data").unwrap();
+        }
+        // Reader folders, one cut short before its page was copied, and a
+        // folder of the user's.
+        fs::create_dir(dir.join("Reader.tmp")).unwrap();
+        fs::write(dir.join("Reader.tmp").join(READER_PAGE), b"<html>").unwrap();
+        fs::create_dir(dir.join("Cut.tmp")).unwrap();
+        fs::write(dir.join("Cut.tmp").join(COPYING_MARKER), b"").unwrap();
+        fs::create_dir(dir.join("build.tmp")).unwrap();
+        let folders = ["Reader.tmp", "Cut.tmp", "build.tmp"];
+        for name in ours.iter().chain(&theirs).chain(&folders) {
+            age(&dir.join(name), STALE_AGE * 2);
+        }
+
+        let mut removed = remove_unfinished_outputs(dir.str()).unwrap();
+        removed.sort();
+        let mut expected: Vec<PathBuf> = ours
+            .iter()
+            .chain(&folders[..2])
+            .map(|name| dir.join(name))
+            .collect();
+        expected.sort();
+        assert_eq!(removed, expected);
+        for name in theirs.iter().chain(&["build.tmp"]) {
+            assert!(dir.join(name).exists(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn chapter_promotion_cut_short_leaves_the_old_chapter() {
+        let cache = TestDir::new("promote-cut-short");
+        let work = WorkDir::create(cache.str()).unwrap();
+        let manifest = |pages: usize| {
+            let data = format!(
+                r#"{{"manga_url": "m", "chapter_urls": ["c"], "pages": {}, "release_date": "2024-01-01T00:00:00Z", "release_date_estimated": false}}"#,
+                pages
+            );
+            serde_json::from_str::<Manifest>(&data).unwrap()
+        };
+        let page = |number: usize| {
+            fs::write(work.page(number), [number as u8; 16]).unwrap();
+            work.page(number)
+        };
+        let names: Vec<String> = (1..=3).map(|n| format!("{:03}.jpg", n)).collect();
+        let target = series_dir(cache.str(), "Series").join("c1");
+        let on_disk = |dir: &Path| {
+            let mut names: Vec<String> = fs::read_dir(dir)
+                .unwrap()
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let pages = [page(1), page(2)];
+        work.promote_chapter(
+            cache.str(),
+            "Series",
+            "c1",
+            &pages,
+            &names[..2],
+            &manifest(2),
+        )
+        .unwrap();
+        assert_eq!(on_disk(&target), ["001.jpg", "002.jpg", "manifest.json"]);
+
+        // The third page is gone, as when the run dies moving the pages.
+        let pages = [page(1), page(2), work.page(3)];
+        let promoted =
+            work.promote_chapter(cache.str(), "Series", "c1", &pages, &names, &manifest(3));
+        assert!(promoted.is_err());
+        assert_eq!(on_disk(&target), ["001.jpg", "002.jpg", "manifest.json"]);
+        let kept = Manifest::load(&target.to_string_lossy()).unwrap();
+        assert_eq!(kept.pages, 2);
+    }
+
+    #[test]
+    fn failed_verification_puts_the_original_back() {
+        let cache = TestDir::new("publish-verify");
+        let out = cache.join("out");
+        let work = WorkDir::create(cache.str()).unwrap();
+        let target = out.join("Series c1.cbz");
+        let backup = out.join("Series c1.cbz.bak");
+        fs::create_dir_all(&out).unwrap();
+        fs::write(&target, b"original").unwrap();
+
+        let output = Path::new(&work.path).join("Series c1.cbz");
+        fs::write(&output, b"broken").unwrap();
+        let refuse = |_: &Path| Err(io::Error::new(io::ErrorKind::InvalidData, "unreadable"));
+        let published = work.publish(&output, &out.to_string_lossy(), refuse, true);
+        assert_eq!(published.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&target).unwrap(), b"original");
+        assert!(!backup.exists());
+
+        // A verified output takes its place, the original kept only when asked.
+        for keep_backup in [true, false] {
+            fs::write(&output, b"replacement").unwrap();
+            let accept = |_: &Path| Ok(());
+            let published = work.publish(&output, &out.to_string_lossy(), accept, keep_backup);
+            assert_eq!(published.unwrap(), target);
+            assert_eq!(fs::read(&target).unwrap(), b"replacement");
+            assert_eq!(backup.exists(), keep_backup);
+        }
+    }
+
+    #[test]
+    fn failed_verification_of_a_reader_folder_puts_the_original_back() {
+        let cache = TestDir::new("publish-verify-folder");
+        let out = cache.join("out");
+        let work = WorkDir::create(cache.str()).unwrap();
+        let target = out.join("Series");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join(READER_PAGE), b"original").unwrap();
+
+        // Missing its page.
+        let output = Path::new(&work.path).join("Series");
+        fs::create_dir_all(&output).unwrap();
+        fs::write(output.join("001.jpg"), b"page").unwrap();
+        let verify = |folder: &Path| fs::metadata(folder.join(READER_PAGE)).map(|_| ());
+        let published = work.publish(&output, &out.to_string_lossy(), verify, false);
+        assert!(published.is_err());
+        assert_eq!(fs::read(target.join(READER_PAGE)).unwrap(), b"original");
+        assert!(!target.join("001.jpg").exists());
+        assert!(!out.join("Series.bak").exists());
+    }
+}
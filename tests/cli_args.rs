@@ -0,0 +1,182 @@
+// Which combinations of arguments manga-cli accepts, checked on the binary
+// as users run it.
+
+// Scenarios here use only part of the harness.
+#[allow(dead_code)]
+mod support;
+
+use std::fs;
+use support::Harness;
+
+// clap's exit code for argument errors, and manga-cli's for other failures
+// (error.rs).
+const EXIT_USAGE: i32 = 2;
+const EXIT_FAILURE: i32 = 1;
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn clear_alone_clears_and_exits() {
+    let harness = Harness::new("clear-alone");
+    let cache = harness.work().join(".cache/manga-cli");
+    fs::create_dir_all(cache.join("series")).unwrap();
+
+    let output = harness.cli().arg("--clear").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Cleared cache."));
+    assert!(!cache.exists());
+
+    let output = harness.cli().arg("--clear").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("No cache to clear."));
+}
+
+#[test]
+fn clear_with_a_name_needs_then_download() {
+    let harness = Harness::new("clear-name");
+    let cache = harness.work().join(".cache/manga-cli");
+    fs::create_dir_all(&cache).unwrap();
+    for args in [
+        vec!["--clear", "naruto"],
+        vec!["--clear", "--again"],
+        vec!["--clear", "--from-dir", "pages"],
+    ] {
+        let output = harness.cli().args(&args).output().unwrap();
+        assert_eq!(output.status.code(), Some(EXIT_USAGE), "{:?}", args);
+        assert!(stderr(&output).contains("--then-download"), "{:?}", args);
+        // Refused before anything was cleared.
+        assert!(cache.exists(), "{:?}", args);
+    }
+}
+
+#[test]
+fn then_download_needs_clear_and_something_to_download() {
+    let harness = Harness::new("then-download");
+    let output = harness
+        .cli()
+        .args(["--then-download", "naruto"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_USAGE));
+    assert!(stderr(&output).contains("--clear"));
+
+    let output = harness
+        .cli()
+        .args(["--clear", "--then-download"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_USAGE));
+    assert!(stderr(&output).contains("needs a manga name"));
+}
+
+#[test]
+fn download_flags_have_no_effect_with_clear() {
+    let harness = Harness::new("clear-flags");
+    for (args, warned) in [
+        (vec!["--clear", "--format", "cbz"], vec!["--format"]),
+        (vec!["--clear", "--viewer", "zathura"], vec!["--viewer"]),
+        (
+            vec!["--clear", "--format", "pdf", "--viewer", "zathura"],
+            vec!["--format", "--viewer"],
+        ),
+        (vec!["--clear"], vec![]),
+    ] {
+        let output = harness.cli().args(&args).output().unwrap();
+        assert!(output.status.success(), "{:?}: {}", args, stderr(&output));
+        for flag in ["--format", "--viewer"] {
+            let warning = format!("Warning: {} has no effect with --clear.", flag);
+            assert_eq!(
+                stdout(&output).contains(&warning),
+                warned.contains(&flag),
+                "{:?}: {}",
+                args,
+                stdout(&output)
+            );
+        }
+    }
+}
+
+#[test]
+fn stale_only_removes_stale_work_directories() {
+    let harness = Harness::new("clear-stale");
+    let series = harness.work().join(".cache/manga-cli/series");
+    fs::create_dir_all(&series).unwrap();
+    let output = harness.cli().args(["--clear", "--stale"]).output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Removed 0 stale work directories."));
+    assert!(series.exists());
+
+    let output = harness.cli().arg("--stale").output().unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_USAGE));
+}
+
+#[test]
+fn clear_then_download_does_both() {
+    let harness = Harness::new("clear-then-download");
+    let stale = harness.work().join(".cache/manga-cli/series/Old");
+    fs::create_dir_all(&stale).unwrap();
+    let output = harness
+        .download("1", "cbz")
+        .args(["--clear", "--then-download"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Cleared cache."));
+    assert!(!stdout(&output).contains("no effect"));
+    assert!(!stale.exists());
+    assert_eq!(harness.outputs_with("cbz").len(), 1);
+}
+
+#[test]
+fn a_name_is_needed_without_clear() {
+    let harness = Harness::new("no-name");
+    let output = harness.cli().output().unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_FAILURE));
+    assert!(stderr(&output).contains("No manga given"));
+}
+
+#[test]
+fn flags_the_source_ignores_are_refused_unless_lenient() {
+    let harness = Harness::new("unsupported-flags");
+    let output = harness
+        .download("1", "cbz")
+        .args(["--group", "Fixture Scans", "--lang", "es"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_FAILURE));
+    assert!(
+        stderr(&output).contains(
+            "manganelo doesn't support --group (only mangadex can), --lang (only mangadex can)"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert!(harness.outputs_with("cbz").is_empty());
+
+    let output = harness
+        .download("1", "cbz")
+        .args(["--group", "Fixture Scans", "--lenient"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stdout(&output).contains("manganelo doesn't support --group (only mangadex can); ignored.")
+    );
+    assert_eq!(harness.outputs_with("cbz").len(), 1);
+}
+
+#[test]
+fn config_defaults_the_source_ignores_are_not_refused() {
+    let harness = Harness::new("unsupported-defaults");
+    harness.configure("lang = [\"es\"]");
+    let output = harness.download("1", "cbz").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stdout(&output).contains("doesn't support"));
+    assert_eq!(harness.outputs_with("cbz").len(), 1);
+}
@@ -0,0 +1,613 @@
+// The whole flow against the fake site: search, download, packaging, retries
+// and checking the cache against the site. New features add a scenario here,
+// or a file of their own next to this one using `support`.
+
+mod support;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+use support::site::crafted_png;
+use support::{cbz_entries, cbz_images_decode, FakeSite, Harness, SLUG};
+
+// manga-cli's exit codes (error.rs).
+const EXIT_HTTP: i32 = 4;
+const EXIT_TOOL: i32 = 7;
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn imagemagick_installed() -> bool {
+    let Some(path) = env::var_os("PATH") else {
+        return false;
+    };
+    env::split_paths(&path).any(|dir| dir.join("magick").is_file() || dir.join("convert").is_file())
+}
+
+// Files left under a temporary name anywhere below `dir`.
+fn unfinished_files(dir: &Path) -> Vec<String> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            found.extend(unfinished_files(&path));
+        } else if path.to_string_lossy().ends_with(".tmp") {
+            found.push(path.display().to_string());
+        }
+    }
+    found
+}
+
+#[test]
+fn searches_and_downloads_chapters_into_one_cbz() {
+    let harness = Harness::new("cbz");
+    let output = harness.download("1-2", "cbz").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Chapter 2: 4/4 pages"));
+
+    let cbzs = harness.outputs_with("cbz");
+    assert_eq!(cbzs.len(), 1, "{:?}", cbzs);
+    assert!(cbzs[0].ends_with("Fixture Tales c1-2.cbz"));
+    let pages = cbz_entries(&cbzs[0])
+        .iter()
+        .filter(|name| name.ends_with(".png"))
+        .count();
+    assert_eq!(pages, 7);
+    assert!(cbz_images_decode(&cbzs[0]));
+
+    let folders = harness.chapter_folders();
+    assert_eq!(folders.len(), 2, "{:?}", folders);
+    for (folder, (chapter, pages)) in folders.iter().zip([(1, 3), (2, 4)]) {
+        let manifest = Harness::manifest(folder);
+        assert_eq!(manifest["manga_url"], FakeSite::series_url(SLUG));
+        assert_eq!(
+            manifest["chapter_urls"][0],
+            FakeSite::chapter_url(SLUG, chapter)
+        );
+        assert_eq!(manifest["pages"], pages);
+        assert_eq!(manifest["series_title"], "Fixture Tales");
+        assert_eq!(manifest["release_date_estimated"], false);
+    }
+    // Every page was fetched once.
+    for (chapter, pages) in [(1, 3), (2, 4)] {
+        for page in 1..=pages {
+            let path = FakeSite::image_path(SLUG, chapter, page);
+            assert_eq!(harness.site.requests(&path), 1, "{}", path);
+        }
+    }
+}
+
+#[test]
+fn builds_a_pdf_or_says_imagemagick_is_missing() {
+    let harness = Harness::new("pdf");
+    let output = harness.download("3", "pdf").output().unwrap();
+    if imagemagick_installed() {
+        assert!(output.status.success(), "{}", stderr(&output));
+        let pdfs = harness.outputs_with("pdf");
+        assert_eq!(pdfs.len(), 1, "{:?}", pdfs);
+        assert!(fs::read(&pdfs[0]).unwrap().starts_with(b"%PDF"));
+        return;
+    }
+
+    // Refused before anything is downloaded.
+    assert_eq!(output.status.code(), Some(EXIT_TOOL));
+    assert!(stderr(&output).contains("ImageMagick"));
+    assert_eq!(harness.site.requests(&FakeSite::image_path(SLUG, 3, 1)), 0);
+
+    let output = harness
+        .download("3", "pdf")
+        .args(["--fallback-format", "cbz"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("CBZ instead of PDF"));
+    assert_eq!(harness.outputs_with("cbz").len(), 1);
+    assert!(harness.outputs_with("pdf").is_empty());
+}
+
+#[test]
+fn failed_page_fails_the_run_and_the_next_run_finishes() {
+    let harness = Harness::new("failure");
+    let broken = FakeSite::image_path(SLUG, 2, 2);
+    harness.site.fail(&broken, usize::MAX);
+    let output = harness
+        .download("1-2", "cbz")
+        .args(["--retry-passes", "0"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_HTTP), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Chapter 2: 1 of 4 pages failed"));
+    assert!(harness.outputs_with("cbz").is_empty());
+    assert_eq!(unfinished_files(&harness.work()), Vec::<String>::new());
+
+    harness.site.fail(&broken, 0);
+    let output = harness.download("1-2", "cbz").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(harness.outputs_with("cbz").len(), 1);
+    assert_eq!(harness.chapter_folders().len(), 2);
+    // Once by the failed run, with no retries, and once by this one.
+    assert_eq!(harness.site.requests(&broken), 2);
+}
+
+#[test]
+fn retry_pass_fetches_only_the_missing_page() {
+    let harness = Harness::new("retry");
+    let broken = FakeSite::image_path(SLUG, 2, 2);
+    harness.site.fail(&broken, 1);
+    let output = harness
+        .download("2", "cbz")
+        .args(["--retry-passes", "1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("1 recovered on retry"));
+    assert_eq!(harness.site.requests(&broken), 2);
+    for page in [1, 3, 4] {
+        assert_eq!(
+            harness.site.requests(&FakeSite::image_path(SLUG, 2, page)),
+            1
+        );
+    }
+    let manifest = Harness::manifest(&harness.chapter_folders()[0]);
+    assert_eq!(manifest["pages"], 4);
+}
+
+#[test]
+fn diff_checks_the_cache_against_the_site() {
+    let harness = Harness::new("diff");
+    let output = harness.download("2", "cbz").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let series = FakeSite::series_url(SLUG);
+    let output = harness
+        .cli()
+        .args(["diff", "--pages", &series, "2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("4 same, 0 changed, 0 added, 0 removed"));
+
+    harness.site.set_pages(SLUG, 2, 5);
+    let output = harness
+        .cli()
+        .args(["diff", "--pages", &series, "2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("4 same, 0 changed, 1 added, 0 removed"));
+}
+
+#[test]
+fn existing_outputs_are_handled_before_downloading() {
+    let harness = Harness::new("conflict");
+    let first_page = FakeSite::image_path(SLUG, 1, 1);
+    let run = |action: &str| {
+        harness
+            .download("1", "cbz")
+            .args(["--on-conflict", action])
+            .output()
+            .unwrap()
+    };
+    let output = run("rename");
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    for _ in 0..3 {
+        let output = run("rename");
+        assert!(output.status.success(), "{}", stderr(&output));
+    }
+    let names = |harness: &Harness| -> Vec<String> {
+        harness
+            .outputs_with("cbz")
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    };
+    assert_eq!(
+        names(&harness),
+        [
+            "Fixture Tales c1 (1).cbz",
+            "Fixture Tales c1 (2).cbz",
+            "Fixture Tales c1 (3).cbz",
+            "Fixture Tales c1.cbz",
+        ]
+    );
+    // A gap is filled first.
+    fs::remove_file(harness.outputs().join("Fixture Tales c1 (2).cbz")).unwrap();
+    let output = run("rename");
+    assert!(stdout(&output).contains("saving as Fixture Tales c1 (2)"));
+    assert_eq!(names(&harness).len(), 4);
+
+    // Skipping and failing download nothing.
+    let requests = harness.site.requests(&first_page);
+    let output = run("skip");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("already exists, skipping"));
+    let output = run("fail");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("already exists"));
+    assert_eq!(harness.site.requests(&first_page), requests);
+}
+
+#[test]
+fn plain_output_has_no_escape_sequences() {
+    const ESC: char = '\x1b';
+    let harness = Harness::new("plain");
+    let Some((succeeded, rich)) = harness.download_in_terminal("1", "xterm", &[]) else {
+        return;
+    };
+    // Without --plain a terminal gets colors and a redrawn progress line, so
+    // the check below would see them.
+    assert!(succeeded, "{}", rich);
+    assert!(rich.contains(ESC), "{}", rich);
+
+    for (chapter, term, flags) in [
+        ("2", "xterm", &["--plain"][..]),
+        ("3", "dumb", &[][..]),
+        (
+            "1-2",
+            "xterm",
+            &["--plain", "--on-conflict", "overwrite"][..],
+        ),
+    ] {
+        let (succeeded, plain) = harness.download_in_terminal(chapter, term, flags).unwrap();
+        assert!(succeeded, "{}", plain);
+        assert!(
+            !plain.contains(ESC),
+            "TERM={} {:?}: {:?}",
+            term,
+            flags,
+            plain
+        );
+        // Nothing is left out: the page count and summary are still there.
+        assert!(plain.contains(" pages"), "{}", plain);
+        assert!(plain.contains("Fixture Tales"), "{}", plain);
+    }
+}
+
+#[test]
+fn oversized_image_fails_its_page_and_is_kept() {
+    let harness = Harness::new("bomb");
+    let bomb = FakeSite::image_path(SLUG, 1, 2);
+    harness.site.replace(&bomb, crafted_png(100_000, 100_000));
+    let output = harness
+        .download("1", "cbz")
+        .args(["--retry-passes", "0"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Chapter 1: 1 of 3 pages failed"));
+    assert!(harness.outputs_with("cbz").is_empty());
+
+    // Said with the URL, and the raw file is kept for a look.
+    let printed = stdout(&output);
+    let url = format!("{}{}", harness.site.url(), bomb);
+    assert!(
+        printed.contains(&format!("{} declares a 100000x100000 image", url)),
+        "{}",
+        printed
+    );
+    let rejected = harness.work().join(".cache/manga-cli/rejected");
+    let kept: Vec<_> = fs::read_dir(rejected)
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    assert_eq!(kept.len(), 1, "{:?}", kept);
+    assert_eq!(fs::read(&kept[0]).unwrap(), crafted_png(100_000, 100_000));
+    let name = kept[0].file_name().unwrap().to_string_lossy().into_owned();
+    assert!(name.ends_with("-2.png"), "{}", name);
+    assert!(printed.contains(&name), "{}", printed);
+}
+
+#[test]
+fn chapter_links_are_used_before_the_url_template() {
+    let harness = Harness::new("template");
+    harness.configure("chapter_url_template = \"{manga_url}/c{chapter}\"");
+    let output = harness.download("2", "cbz").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(harness.outputs_with("cbz").len(), 1);
+    let series = format!("/manga/{}", SLUG);
+    assert_eq!(harness.site.requests(&format!("{}/chapter-2", series)), 1);
+    assert_eq!(harness.site.requests(&format!("{}/c2", series)), 0);
+
+    // A chapter the list lacks is looked for where the template says.
+    let output = harness
+        .download("5-", "cbz")
+        .args(["--retry-passes", "0"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(harness.site.requests(&format!("{}/c5", series)) > 0);
+    assert_eq!(harness.site.requests(&format!("{}/chapter-5", series)), 0);
+}
+
+#[test]
+fn chapter_list_comes_from_the_endpoint_then_the_series_page() {
+    let endpoint = format!("/api/manga/{}/chapters", SLUG);
+    let listed = |harness: &Harness| {
+        let output = harness.download("1-3", "cbz").output().unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert!(harness.site.requests(&endpoint) > 0);
+        stdout(&output)
+    };
+
+    // Without the endpoint, as on the fake site, the page has all three.
+    let harness = Harness::new("list-fallback");
+    assert!(listed(&harness).contains("Chapters 1-3: 3 chapter(s)"));
+    let harness = Harness::new("list-failing");
+    harness.site.fail(&endpoint, usize::MAX);
+    assert!(listed(&harness).contains("Chapters 1-3: 3 chapter(s)"));
+
+    // What the endpoint lists is used over the page.
+    let harness = Harness::new("list-endpoint");
+    let chapters: Vec<String> = (1..=2)
+        .rev()
+        .map(|chapter| {
+            format!(
+                "{{\"chapter_name\": \"Chapter {0}: From the API\", \"chapter_slug\": \"chapter-{0}\"}}",
+                chapter
+            )
+        })
+        .collect();
+    let json = format!("{{\"data\": {{\"chapters\": [{}]}}}}", chapters.join(", "));
+    harness.site.replace(&endpoint, json.into_bytes());
+    let printed = listed(&harness);
+    assert!(
+        printed.contains("Chapters 1-3: 2 chapter(s)"),
+        "{}",
+        printed
+    );
+    assert_eq!(harness.chapter_folders().len(), 2);
+    let manifest = Harness::manifest(&harness.chapter_folders()[0]);
+    assert_eq!(manifest["chapter_urls"][0], FakeSite::chapter_url(SLUG, 1));
+}
+
+#[test]
+fn identical_neighbouring_pages_are_dropped_unless_asked_not_to() {
+    let pngs = |harness: &Harness| {
+        cbz_entries(&harness.outputs_with("cbz")[0])
+            .iter()
+            .filter(|name| name.ends_with(".png"))
+            .count()
+    };
+    for (flags, pages) in [(&[][..], 3), (&["--no-dedupe"][..], 4)] {
+        let harness = Harness::new("dedupe");
+        harness.site.replace(
+            &FakeSite::image_path(SLUG, 2, 3),
+            FakeSite::page_image(2, 2),
+        );
+        let output = harness.download("2", "cbz").args(flags).output().unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        let dropped = stdout(&output).contains("page 3 is identical to page 2, dropped it");
+        assert_eq!(dropped, flags.is_empty(), "{}", stdout(&output));
+        assert_eq!(pngs(&harness), pages, "{:?}", flags);
+    }
+}
+
+#[test]
+fn configured_headers_sit_between_the_source_and_the_request() {
+    let harness = Harness::new("headers");
+    harness.configure(
+        "[headers.manganelo.image]\nuser-agent = \"fixture-agent\"\nAccept = \"\"\nReferer = \"https://ignored.test/\"",
+    );
+    let broken = FakeSite::image_path(SLUG, 1, 2);
+    harness.site.fail(&broken, usize::MAX);
+    let output = harness
+        .download("1", "cbz")
+        .args(["--retry-passes", "0", "--print-request"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_HTTP), "{}", stderr(&output));
+
+    // The headers printed for the failed image, up to the next request.
+    let stderr = stderr(&output);
+    let request = format!("GET {}{}", harness.site.url(), broken);
+    let printed: Vec<&str> = stderr
+        .lines()
+        .skip_while(|line| *line != request)
+        .skip(1)
+        .take_while(|line| line.starts_with("  "))
+        .map(str::trim)
+        .collect();
+    let origin = support::site::SITE;
+    assert_eq!(
+        printed,
+        [
+            "User-Agent: fixture-agent".to_string(),
+            "Accept-Language: en-US,en;q=0.5".to_string(),
+            format!("Referer: {}/", origin),
+        ],
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn streamed_archive_missing_pages_is_not_taken_for_the_finished_one() {
+    let harness = Harness::new("stream-partial");
+    let broken = FakeSite::image_path(SLUG, 2, 2);
+    harness.site.fail(&broken, usize::MAX);
+    let output = harness
+        .download("1-2", "cbz")
+        .args([
+            "--stream-cbz",
+            "--retry-passes",
+            "0",
+            "--on-page-failure",
+            "skip",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let partial = harness.outputs().join("Fixture Tales c1-2.partial.cbz");
+    assert_eq!(harness.outputs_with("cbz"), std::slice::from_ref(&partial));
+    assert_eq!(cbz_entries(&partial).len(), 3 + 3 + 1);
+
+    harness.site.fail(&broken, 0);
+    let output = harness
+        .download("1-2", "cbz")
+        .args(["--stream-cbz", "--skip-existing"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stdout(&output).contains("already exists"));
+    let finished = harness.outputs().join("Fixture Tales c1-2.cbz");
+    assert_eq!(harness.outputs_with("cbz"), std::slice::from_ref(&finished));
+    assert_eq!(cbz_entries(&finished).len(), 3 + 4 + 1);
+}
+
+// Pages 1 and 2 of chapter 3 with their manifest, as versions from before
+// per-run work directories left them in the cache, and the CBZ made of them,
+// which names the series.
+fn old_cache_layout(harness: &Harness) {
+    let cache = harness.work().join(".cache/manga-cli");
+    fs::create_dir_all(&cache).unwrap();
+    for page in 1..=2 {
+        fs::write(
+            cache.join(format!("{}.jpg", page)),
+            FakeSite::page_image(3, page),
+        )
+        .unwrap();
+    }
+    let manifest = serde_json::json!({
+        "manga_url": FakeSite::series_url(SLUG),
+        "chapter_urls": [FakeSite::chapter_url(SLUG, 3)],
+        "pages": 2,
+        "release_date": "2024-01-03T00:00:00Z",
+        "release_date_estimated": false,
+    });
+    fs::write(cache.join("manifest.json"), manifest.to_string()).unwrap();
+    let mut cbz = zip::ZipWriter::new(fs::File::create(cache.join("output.cbz")).unwrap());
+    cbz.start_file("ComicInfo.xml", zip::write::FileOptions::default())
+        .unwrap();
+    let comic_info =
+        "<ComicInfo>\n  <Series>Fixture Tales</Series>\n  <Number>3</Number>\n</ComicInfo>";
+    cbz.write_all(comic_info.as_bytes()).unwrap();
+    cbz.finish().unwrap();
+}
+
+#[test]
+fn migrate_cache_sorts_the_old_layout_once() {
+    let harness = Harness::new("migrate-cache");
+    old_cache_layout(&harness);
+    let cache = harness.work().join(".cache/manga-cli");
+    // The series and chapter number offered are taken.
+    let output = harness
+        .cli()
+        .arg("migrate-cache")
+        .write_stdin("\n\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stdout(&output).contains("Found in .cache/manga-cli: 2 page(s), a manifest, 1 output(s)")
+    );
+    let chapter = cache.join("series/Fixture Tales/Chapter 3");
+    assert_eq!(harness.chapter_folders().len(), 1);
+    assert_eq!(harness.chapter_folders()[0], chapter);
+    assert_eq!(Harness::manifest(&chapter)["chapter_name"], "Chapter 3");
+    assert!(chapter.join("2.jpg").is_file());
+    assert!(!cache.join("1.jpg").exists());
+    assert!(cache.join("Fixture Tales c3.cbz").is_file());
+    assert!(!cache.join("output.cbz").exists());
+
+    let output = harness.cli().arg("migrate-cache").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Nothing to migrate"));
+
+    // A second copy of the chapter is parked rather than merged.
+    old_cache_layout(&harness);
+    let output = harness
+        .cli()
+        .arg("migrate-cache")
+        .write_stdin("\n\np\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("these pages are a second copy"));
+    let unsorted = cache.join("unsorted");
+    for name in ["1.jpg", "2.jpg", "manifest.json"] {
+        assert!(unsorted.join(name).is_file(), "{}", name);
+        assert!(!cache.join(name).exists(), "{}", name);
+    }
+    assert!(cache.join("Fixture Tales c3 (1).cbz").is_file());
+    assert_eq!(Harness::manifest(&chapter)["pages"], 2);
+}
+
+// Each chapter folder's manifest against the pages in it.
+fn assert_manifests_match_pages(harness: &Harness) {
+    for folder in harness.chapter_folders() {
+        let pages = fs::read_dir(&folder)
+            .unwrap()
+            .flatten()
+            .filter(|entry| entry.file_name() != "manifest.json")
+            .count();
+        assert_eq!(Harness::manifest(&folder)["pages"], pages, "{:?}", folder);
+    }
+}
+
+#[test]
+fn killed_run_leaves_manifests_matching_the_pages() {
+    let harness = Harness::new("killed");
+    let output = harness.download("1", "cbz").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // Killed while chapter 2 waits on its third page.
+    let stalled = FakeSite::image_path(SLUG, 2, 3);
+    harness.site.stall(&stalled, Duration::from_secs(60));
+    let mut run = harness.spawn_download("2", "cbz");
+    let started = Instant::now();
+    while harness.site.requests(&stalled) == 0 {
+        assert!(
+            started.elapsed() < Duration::from_secs(30),
+            "page 3 never requested"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+    thread::sleep(Duration::from_millis(200));
+    run.kill().unwrap();
+    run.wait().unwrap();
+
+    // Only the finished chapter has a folder, whose manifest is whole.
+    assert_eq!(harness.chapter_folders().len(), 1);
+    assert_manifests_match_pages(&harness);
+    assert!(unfinished_files(&harness.work().join(".cache/manga-cli/series")).is_empty());
+
+    harness.site.stall(&stalled, Duration::ZERO);
+    let output = harness.download("2", "cbz").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(harness.chapter_folders().len(), 2);
+    assert_manifests_match_pages(&harness);
+}
+
+#[test]
+fn series_going_by_an_existing_folders_alternative_title_is_offered_it() {
+    let harness = Harness::new("alt-title-merge");
+    let other = harness.work().join(".cache/manga-cli/series/Other Tales");
+    fs::create_dir_all(&other).unwrap();
+    // Only the site's alternative title names the folder.
+    let page = format!(
+        "<html><body>\n<div class=\"story-info-right\"><h1>Fixture Tales</h1>\n<table><tr><td class=\"table-label\">Alternative :</td><td class=\"table-value\">Other Tales ; Tales of Fixtures</td></tr></table></div>\n<ul class=\"row-content-chapter\">\n<li><a href=\"{}\">Chapter 1</a></li>\n</ul>\n</body></html>",
+        FakeSite::chapter_url(SLUG, 1)
+    );
+    harness
+        .site
+        .replace(&format!("/manga/{}", SLUG), page.into_bytes());
+    let output = harness.download("1", "cbz").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stdout(&output).contains(
+            "Fixture Tales looks like the existing series .cache/manga-cli/series/Other Tales"
+        ),
+        "{}",
+        stdout(&output)
+    );
+}
@@ -0,0 +1,233 @@
+// Runs the manga-cli binary against a fake site, each test in a folder of its
+// own. A scenario starts a Harness, runs commands through cli() and looks at
+// the files they leave behind.
+
+pub mod site;
+
+use assert_cmd::Command;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+
+pub use site::{FakeSite, Series};
+
+pub const SLUG: &str = "fixture-tales";
+pub const TITLE: &str = "Fixture Tales";
+
+pub struct Harness {
+    pub site: FakeSite,
+    root: PathBuf,
+}
+
+impl Harness {
+    // A fake site with one series of three chapters, of 3, 4 and 2 pages, and
+    // a fresh config, data and work folder named after the test.
+    pub fn new(test: &str) -> Harness {
+        Harness::with_series(
+            test,
+            vec![Series {
+                slug: SLUG,
+                title: TITLE,
+                chapters: vec![3, 4, 2],
+            }],
+        )
+    }
+
+    pub fn with_series(test: &str, series: Vec<Series>) -> Harness {
+        let root = env::temp_dir().join(format!("manga-cli-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let harness = Harness {
+            site: FakeSite::start(series),
+            root,
+        };
+        fs::create_dir_all(harness.work()).unwrap();
+        let config = harness.root.join("config").join("manga-cli");
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("config.toml"), "update_check = false\n").unwrap();
+        harness
+    }
+
+    // manga-cli in the work folder, talking to the fake site only. Flags come
+    // after these, so a scenario can add its own.
+    pub fn cli(&self) -> Command {
+        Command::from_std(self.process())
+    }
+
+    // download() started in the background, to be killed partway.
+    pub fn spawn_download(&self, chapters: &str, formats: &str) -> process::Child {
+        self.process()
+            .args(download_args(chapters, formats))
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .spawn()
+            .unwrap()
+    }
+
+    fn process(&self) -> process::Command {
+        let mut command = process::Command::new(assert_cmd::cargo::cargo_bin("manga-cli"));
+        self.isolate(&mut command)
+            .env("NO_COLOR", "1")
+            .arg("--plain")
+            .args(self.site_args());
+        command
+    }
+
+    // Adds `lines` to the config file.
+    pub fn configure(&self, lines: &str) {
+        let path = self.root.join("config/manga-cli/config.toml");
+        let config = fs::read_to_string(&path).unwrap();
+        fs::write(&path, config + lines + "\n").unwrap();
+    }
+
+    // cli() downloading `chapters` of the series into out/ by searching for
+    // it.
+    pub fn download(&self, chapters: &str, formats: &str) -> Command {
+        let mut command = self.cli();
+        command.args(download_args(chapters, formats));
+        command
+    }
+
+    // download() of CBZs run by script(1) in a pseudo-terminal, so manga-cli
+    // sees a terminal on stdout and stderr, with TERM set to `term` and
+    // neither NO_COLOR nor --plain unless `flags` has it. Returns whether it
+    // succeeded and everything the terminal was sent, or None where script
+    // isn't installed.
+    pub fn download_in_terminal(
+        &self,
+        chapters: &str,
+        term: &str,
+        flags: &[&str],
+    ) -> Option<(bool, String)> {
+        let program = assert_cmd::cargo::cargo_bin("manga-cli");
+        let mut words = vec![program.to_string_lossy().into_owned()];
+        words.extend(self.site_args());
+        words.extend(download_args(chapters, "cbz"));
+        words.extend(flags.iter().map(|flag| flag.to_string()));
+        let line = shlex::try_join(words.iter().map(String::as_str)).unwrap();
+        let mut command = process::Command::new("script");
+        self.isolate(&mut command)
+            .env_remove("NO_COLOR")
+            .env("TERM", term)
+            .args(["--quiet", "--return", "--command", &line, "/dev/null"])
+            .stdin(process::Stdio::null());
+        let output = command.output().ok()?;
+        let shown = String::from_utf8_lossy(&output.stdout).into_owned();
+        Some((output.status.success(), shown))
+    }
+
+    // Runs in the work folder with the test's own config and data, talking to
+    // the fake site only.
+    fn isolate<'a>(&self, command: &'a mut process::Command) -> &'a mut process::Command {
+        command
+            .current_dir(self.work())
+            .env("XDG_CONFIG_HOME", self.root.join("config"))
+            .env("XDG_DATA_HOME", self.root.join("data"))
+            .env_remove("HTTP_PROXY")
+            .env_remove("HTTPS_PROXY")
+            .env_remove("ALL_PROXY")
+            .env_remove("http_proxy")
+            .env_remove("https_proxy")
+            .env_remove("all_proxy")
+    }
+
+    fn site_args(&self) -> Vec<String> {
+        [
+            "--no-wizard",
+            "--source",
+            "manganelo",
+            "--mirror",
+            self.site.url(),
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    pub fn work(&self) -> PathBuf {
+        self.root.join("work")
+    }
+
+    pub fn outputs(&self) -> PathBuf {
+        self.work().join("out")
+    }
+
+    // Files of out/ with the extension, sorted.
+    pub fn outputs_with(&self, extension: &str) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs::read_dir(self.outputs())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|found| found == extension))
+            .collect();
+        files.sort();
+        files
+    }
+
+    // The series' chapter folders in the cache, sorted.
+    pub fn chapter_folders(&self) -> Vec<PathBuf> {
+        let series = self.work().join(".cache/manga-cli/series").join(TITLE);
+        let mut folders: Vec<PathBuf> = fs::read_dir(series)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.join("manifest.json").is_file())
+            .collect();
+        folders.sort();
+        folders
+    }
+
+    pub fn manifest(folder: &Path) -> Value {
+        let data = fs::read_to_string(folder.join("manifest.json")).unwrap();
+        serde_json::from_str(&data).unwrap()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        // Kept when the test failed, to look at.
+        if !std::thread::panicking() {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+}
+
+// download()'s arguments after cli()'s.
+fn download_args(chapters: &str, formats: &str) -> Vec<String> {
+    vec![
+        "--chapters".to_string(),
+        chapters.to_string(),
+        "--format".to_string(),
+        formats.to_string(),
+        "--output-dir".to_string(),
+        "out".to_string(),
+        "--match".to_string(),
+        format!("^{}$", TITLE),
+        "fixture tales".to_string(),
+    ]
+}
+
+// Names of the entries of a CBZ, in the archive's order.
+pub fn cbz_entries(path: &Path) -> Vec<String> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+    (0..archive.len())
+        .map(|i| archive.by_index(i).unwrap().name().to_string())
+        .collect()
+}
+
+// Whether every image of the CBZ decodes.
+pub fn cbz_images_decode(path: &Path) -> bool {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+    (0..archive.len()).all(|i| {
+        let mut entry = archive.by_index(i).unwrap();
+        if entry.name().ends_with(".xml") {
+            return true;
+        }
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        image::load_from_memory(&data).is_ok()
+    })
+}
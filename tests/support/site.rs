@@ -0,0 +1,334 @@
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Where the site's own links point. manga-cli is run with --mirror set to the
+// fake site, which moves them there, so stored URLs look like the real site's.
+pub const SITE: &str = "https://m.manganelo.com";
+
+pub struct Series {
+    pub slug: &'static str,
+    pub title: &'static str,
+    // Page counts of chapters 1, 2, ...
+    pub chapters: Vec<usize>,
+}
+
+#[derive(Default)]
+struct State {
+    series: Vec<Series>,
+    // Requests left to answer with 500, by path.
+    failing: HashMap<String, usize>,
+    // Requests answered, by path.
+    requests: HashMap<String, usize>,
+    // Bodies served in place of what the site would draw, by path.
+    replaced: HashMap<String, Vec<u8>>,
+    // How long answers wait before being sent, by path.
+    stalled: HashMap<String, Duration>,
+}
+
+// A miniature manganelo on 127.0.0.1: a search page, series pages with their
+// chapter list, reader pages and PNG pages drawn per request. Each connection
+// carries one request.
+#[derive(Clone)]
+pub struct FakeSite {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeSite {
+    pub fn start(series: Vec<Series>) -> FakeSite {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind the fake site");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let site = FakeSite {
+            url,
+            state: Arc::new(Mutex::new(State {
+                series,
+                ..State::default()
+            })),
+        };
+        let server = site.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = server.clone();
+                thread::spawn(move || server.serve(stream));
+            }
+        });
+        site
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn series_url(slug: &str) -> String {
+        format!("{}/manga/{}", SITE, slug)
+    }
+
+    pub fn chapter_url(slug: &str, chapter: usize) -> String {
+        format!("{}/chapter-{}", FakeSite::series_url(slug), chapter)
+    }
+
+    pub fn image_path(slug: &str, chapter: usize, page: usize) -> String {
+        format!("/images/{}/{}/{}.png", slug, chapter, page)
+    }
+
+    // The image the site serves as `page` of `chapter`.
+    pub fn page_image(chapter: usize, page: usize) -> Vec<u8> {
+        draw_page(chapter, page)
+    }
+
+    // Answers the next `times` requests for `path` with 500.
+    pub fn fail(&self, path: &str, times: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .failing
+            .insert(path.to_string(), times);
+    }
+
+    // Answers requests for `path` with `body` from now on, as a compromised
+    // mirror would.
+    pub fn replace(&self, path: &str, body: Vec<u8>) {
+        self.state
+            .lock()
+            .unwrap()
+            .replaced
+            .insert(path.to_string(), body);
+    }
+
+    // Holds answers to `path` back for `delay` from now on, after counting
+    // the request.
+    pub fn stall(&self, path: &str, delay: Duration) {
+        self.state
+            .lock()
+            .unwrap()
+            .stalled
+            .insert(path.to_string(), delay);
+    }
+
+    pub fn requests(&self, path: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.requests.get(path).copied().unwrap_or(0)
+    }
+
+    // Changes how many pages a chapter has from now on, as when a site
+    // replaces a chapter.
+    pub fn set_pages(&self, slug: &str, chapter: usize, pages: usize) {
+        let mut state = self.state.lock().unwrap();
+        let series = state
+            .series
+            .iter_mut()
+            .find(|series| series.slug == slug)
+            .expect("a series of the fake site");
+        series.chapters[chapter - 1] = pages;
+    }
+
+    fn serve(&self, mut stream: TcpStream) {
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).is_err() {
+            return;
+        }
+        // The headers aren't looked at; GETs have no body.
+        let mut line = String::new();
+        while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+            line.clear();
+        }
+        let target = request_line.split(' ').nth(1).unwrap_or("/").to_string();
+        let (status, content_type, body) = self.respond(&target);
+        let path = target.split('?').next().unwrap_or(&target);
+        let stalled = self.state.lock().unwrap().stalled.get(path).copied();
+        if let Some(delay) = stalled {
+            thread::sleep(delay);
+        }
+        let head = format!(
+            "HTTP/1.1 {} X\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        );
+        let _ = stream.write_all(head.as_bytes());
+        let _ = stream.write_all(&body);
+    }
+
+    fn respond(&self, target: &str) -> (u16, &'static str, Vec<u8>) {
+        let path = target.split('?').next().unwrap_or(target);
+        let mut state = self.state.lock().unwrap();
+        *state.requests.entry(path.to_string()).or_default() += 1;
+        if let Some(left) = state.failing.get_mut(path).filter(|left| **left > 0) {
+            *left -= 1;
+            return (500, "text/plain", b"injected failure".to_vec());
+        }
+        if let Some(body) = state.replaced.get(path) {
+            return (200, "application/octet-stream", body.clone());
+        }
+        let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let page = match parts.as_slice() {
+            ["search", "story", query] => Some(search_page(&state.series, query)),
+            ["manga", slug] => find(&state.series, slug).map(series_page),
+            ["manga", slug, chapter] => find(&state.series, slug)
+                .zip(number(chapter, "chapter-"))
+                .and_then(|(series, chapter)| reader_page(&self.url, series, chapter)),
+            ["images", slug, chapter, page] => {
+                let image = find(&state.series, slug)
+                    .zip(number(chapter, ""))
+                    .zip(page.strip_suffix(".png").and_then(|page| number(page, "")));
+                return match image {
+                    Some(((series, chapter), page))
+                        if series
+                            .chapters
+                            .get(chapter - 1)
+                            .is_some_and(|pages| page <= *pages) =>
+                    {
+                        (200, "image/png", draw_page(chapter, page))
+                    }
+                    _ => (404, "text/plain", Vec::new()),
+                };
+            }
+            _ => None,
+        };
+        match page {
+            Some(html) => (200, "text/html; charset=utf-8", html.into_bytes()),
+            None => (404, "text/plain", Vec::new()),
+        }
+    }
+}
+
+fn find<'a>(series: &'a [Series], slug: &str) -> Option<&'a Series> {
+    series.iter().find(|series| series.slug == slug)
+}
+
+fn number(text: &str, prefix: &str) -> Option<usize> {
+    text.strip_prefix(prefix)?.parse().ok().filter(|n| *n > 0)
+}
+
+// Search results are the series whose title has every word of the query.
+fn search_page(series: &[Series], query: &str) -> String {
+    let words: Vec<String> = query
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let results: String = series
+        .iter()
+        .filter(|series| {
+            let title = series.title.to_lowercase();
+            words.iter().all(|word| title.contains(word.as_str()))
+        })
+        .map(|series| {
+            format!(
+                "<div class=\"search-story-item\"><h3><a href=\"{}\">{}</a></h3></div>\n",
+                FakeSite::series_url(series.slug),
+                series.title
+            )
+        })
+        .collect();
+    format!("<html><body>\n{}</body></html>", results)
+}
+
+// The chapter list is only on the page; the list endpoint answers 404, as on
+// mirrors without it.
+fn series_page(series: &Series) -> String {
+    let chapters: String = (1..=series.chapters.len())
+        .rev()
+        .map(|chapter| {
+            format!(
+                "<li><a href=\"{}\">Chapter {}</a><span class=\"chapter-time\" title=\"2024-01-{:02}T00:00:00Z\"></span></li>\n",
+                FakeSite::chapter_url(series.slug, chapter),
+                chapter,
+                chapter
+            )
+        })
+        .collect();
+    format!(
+        "<html><body>\n<div class=\"story-info-right\"><h1>{}</h1></div>\n<ul class=\"row-content-chapter\">\n{}</ul>\n</body></html>",
+        series.title, chapters
+    )
+}
+
+fn reader_page(url: &str, series: &Series, chapter: usize) -> Option<String> {
+    let pages = *series.chapters.get(chapter - 1)?;
+    let images: String = (1..=pages)
+        .map(|page| {
+            format!(
+                "<img src=\"{}{}\">\n",
+                url,
+                FakeSite::image_path(series.slug, chapter, page)
+            )
+        })
+        .collect();
+    let next = if chapter < series.chapters.len() {
+        format!(
+            "<a class=\"navi-change-chapter-btn-next\" href=\"{}\">NEXT CHAPTER</a>\n",
+            FakeSite::chapter_url(series.slug, chapter + 1)
+        )
+    } else {
+        String::new()
+    };
+    Some(format!(
+        "<html><body>\n<div class=\"container-chapter-reader\">\n{}</div>\n{}</body></html>",
+        images, next
+    ))
+}
+
+// A page no other page looks like: stripes whose width and colour depend on
+// the chapter and page, with noise in the blue channel so the PNG is as large
+// as a real page's and neither duplicate nor promotion detection trips.
+fn draw_page(chapter: usize, page: usize) -> Vec<u8> {
+    let stripe = (chapter * 7 + page * 3) as u32 % 11 + 4;
+    let tint = [(chapter * 53 % 256) as u8, (page * 97 % 256) as u8];
+    let mut noise = (chapter * 1000 + page) as u32 | 1;
+    let image = RgbImage::from_fn(200, 300, |x, y| {
+        // xorshift32
+        noise ^= noise << 13;
+        noise ^= noise >> 17;
+        noise ^= noise << 5;
+        let blue = noise as u8;
+        if (x / stripe + y / stripe).is_multiple_of(2) {
+            Rgb([tint[0], tint[1], blue])
+        } else {
+            Rgb([255 - tint[0], 255 - tint[1], blue])
+        }
+    });
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .expect("encode a page");
+    png
+}
+
+// A PNG of a few dozen bytes whose header declares `width` x `height`, with
+// no image data.
+pub fn crafted_png(width: u32, height: u32) -> Vec<u8> {
+    let mut header = width.to_be_bytes().to_vec();
+    header.extend(height.to_be_bytes());
+    // 8-bit RGB, no interlacing.
+    header.extend([8, 2, 0, 0, 0]);
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (kind, data) in [(b"IHDR", &header[..]), (b"IDAT", &[]), (b"IEND", &[])] {
+        let chunk = [&kind[..], data].concat();
+        png.extend((data.len() as u32).to_be_bytes());
+        png.extend(&chunk);
+        png.extend(crc32(&chunk).to_be_bytes());
+    }
+    png
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}